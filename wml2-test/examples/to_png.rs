@@ -41,10 +41,7 @@ fn loader(filename: &String) -> Result<ImageBuffer, Box<dyn Error>> {
     let reader = BufReader::new(f);
     let mut image = ImageBuffer::new();
     image.set_animation(true);
-    let mut option = DecodeOptions {
-        debug_flag: 0x0,
-        drawer: &mut image,
-    };
+    let mut option = DecodeOptions::new(&mut image);
     image_reader(reader, &mut option)?;
     Ok(image)
 }
@@ -78,10 +78,7 @@ fn loader(filename: &std::path::PathBuf) -> Option<ImageBuffer> {
             let now = Instant::now();
             let mut image = ImageBuffer::new();
             image.set_verbose(write_log);
-            let mut option = DecodeOptions {
-                debug_flag: 0x00,
-                drawer: &mut image,
-            };
+            let mut option = DecodeOptions::new(&mut image);
             let r = image_reader(reader, &mut option);
             let eslaped_time = now.elapsed();
             match r {
@@ -16,7 +16,12 @@ use crate::warning::ImgWarnings;
 use bin_rs::io::*;
 
 use crate::bmp::header::Compressions;
-use crate::bmp::header::{BitmapHeader, ColorTable};
+use crate::bmp::header::{
+    BRA_BOTTOMUP, BitmapHeader, ColorProfile, ColorTable, LCS_CALIBRATED_RGB, color_space_name,
+    halftoning_name, intent_name,
+};
+use crate::bmp::warning::BMPWarning;
+use crate::decoder::ccitt::{self, Encoder};
 
 fn get_color(c: &Option<&Vec<ColorTable>>, idx: usize) -> ColorTable {
     if let Some(c) = c {
@@ -241,10 +246,16 @@ fn decode_rle<B: BinaryReader>(
         .map(|i| if i % 4 == 3 { 0xff } else { 0 })
         .collect();
     let mut y: usize = height - 1;
-    let rev_bytes = 8 / header.bit_count;
+    let rev_bytes = (8 / header.bit_count.max(1)).max(1);
+    // RLE4/RLE8 keep one palette index per pixel, OS/2 RLE24 BGR triples.
+    let (pixel_bytes, depth) = if header.bit_count == 24 {
+        (3, 24)
+    } else {
+        (1, 8)
+    };
     'y: loop {
         let mut x: usize = 0;
-        let mut buf: Vec<u8> = (0..(width + 1)).map(|_| 0).collect();
+        let mut buf: Vec<u8> = vec![0; (width + 1) * pixel_bytes];
         'x: loop {
             let data0 = reader.read_byte()?;
             let data1 = reader.read_byte()?;
@@ -262,17 +273,15 @@ fn decode_rle<B: BinaryReader>(
                     if data1 == 0 {
                         x += data0 as usize;
                     } else {
-                        convert_rgba32(&buf, &mut line, header, 8)?;
+                        convert_rgba32(&buf, &mut line, header, depth)?;
                         option.drawer.draw(0, y, width, 1, &line, None)?;
                         if y == 0 {
                             break;
                         }
                         y -= 1;
-                        buf = (0..((width + rev_bytes - 1) / rev_bytes))
-                            .map(|_| 0)
-                            .collect();
+                        buf = vec![0; (width + 1) * pixel_bytes];
                         for _ in 0..data1 as usize {
-                            convert_rgba32(&buf, &mut line, header, 8)?;
+                            convert_rgba32(&buf, &mut line, header, depth)?;
                             option.drawer.draw(0, y, width, 1, &line, None)?;
                             if y == 0 {
                                 break;
@@ -284,7 +293,11 @@ fn decode_rle<B: BinaryReader>(
                     }
                 }
 
-                let bytes = (data1 as usize + rev_bytes - 1) / rev_bytes; // pixel
+                let bytes = if header.bit_count == 24 {
+                    data1 as usize * 3
+                } else {
+                    (data1 as usize + rev_bytes - 1) / rev_bytes
+                }; // pixel
                 let rbytes = (bytes + 1) / 2 * 2; // even bytes
                 let rbuf = reader.read_bytes_as_vec(rbytes)?;
 
@@ -299,16 +312,26 @@ fn decode_rle<B: BinaryReader>(
                         }
                         x += 1;
                     }
-                } else if header.bit_count == 4 {
-                    for i in 0..bytes {
-                        if x >= buf.len() || i >= rbuf.len() {
+                } else if header.bit_count == 24 {
+                    for pixel in rbuf[..bytes].chunks_exact(3) {
+                        if x >= width {
                             break;
                         }
-                        buf[x] = rbuf[i] >> 4;
-                        if x + 1 < width {
-                            buf[x + 1] = rbuf[i] & 0xf;
-                            x += 2;
+                        buf[x * 3..x * 3 + 3].copy_from_slice(pixel);
+                        x += 1;
+                    }
+                } else if header.bit_count == 4 {
+                    // an odd count leaves the low nibble of the last byte unused
+                    for i in 0..data1 as usize {
+                        if x >= width || i / 2 >= rbuf.len() {
+                            break;
                         }
+                        buf[x] = if i % 2 == 0 {
+                            rbuf[i / 2] >> 4
+                        } else {
+                            rbuf[i / 2] & 0xf
+                        };
+                        x += 1;
                     }
                 } else {
                     return Err(Box::new(ImgError::new_const(
@@ -316,6 +339,16 @@ fn decode_rle<B: BinaryReader>(
                         "Unknwon".to_string(),
                     )));
                 }
+            } else if header.bit_count == 24 {
+                let pixel = [data1, reader.read_byte()?, reader.read_byte()?];
+                for _ in 0..data0 {
+                    if x >= width {
+                        // broken bmp
+                        break 'x;
+                    }
+                    buf[x * 3..x * 3 + 3].copy_from_slice(&pixel);
+                    x += 1;
+                }
             } else if header.bit_count == 8 {
                 for _ in 0..data0 {
                     if x >= buf.len() {
@@ -352,7 +385,7 @@ fn decode_rle<B: BinaryReader>(
                 )));
             }
         }
-        convert_rgba32(&buf, &mut line, header, 8)?;
+        convert_rgba32(&buf, &mut line, header, depth)?;
         if header.height > 0 {
             option.drawer.draw(0, y, width, 1, &line, None)?;
         } else {
@@ -369,6 +402,68 @@ fn decode_rle<B: BinaryReader>(
     Ok(None)
 }
 
+/// OS/2 2.x Huffman 1D: 1-bit rows coded as CCITT Group 3 1D with EOLs.
+/// White runs are palette index 0 and black runs index 1.
+fn decode_huffman_1d<B: BinaryReader>(
+    reader: &mut B,
+    header: &BitmapHeader,
+    option: &mut DecodeOptions,
+) -> Result<Option<ImgWarnings>, Error> {
+    let width = header.width.unsigned_abs() as usize;
+    let height = header.height.unsigned_abs() as usize;
+    if header.bit_count != 1 {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::NoSupportFormat,
+            format!(
+                "BMP Huffman 1D needs 1 bit pixels, not {}",
+                header.bit_count
+            ),
+        )));
+    }
+    let size = match &header.bitmap_info {
+        Windows(info) if info.bi_size_image > 0 => info.bi_size_image as usize,
+        _ => header.filesize.saturating_sub(header.image_offset),
+    };
+    if size == 0 {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::DecodeError,
+            "BMP Huffman 1D data size is unknown".to_string(),
+        )));
+    }
+    let buf = reader.read_bytes_as_vec(size)?;
+    let (data, damaged) = ccitt::decoder(&buf, width, height, Encoder::G31d, false)?;
+
+    option.drawer.init(width, height, InitOptions::new())?;
+    let color_table = header.color_table.as_ref();
+    let white = get_color(&color_table, 0);
+    let black = get_color(&color_table, 1);
+    let mut line = vec![0xff; width * 4];
+    for (y_, row) in data.chunks_exact(width.max(1)).take(height).enumerate() {
+        for (pixel, value) in line.chunks_exact_mut(4).zip(row) {
+            let color = if *value == 0 { white } else { black };
+            pixel[0] = color.red;
+            pixel[1] = color.green;
+            pixel[2] = color.blue;
+        }
+        let y = if header.height > 0 {
+            height - 1 - y_
+        } else {
+            y_
+        };
+        option.drawer.draw(0, y, width, 1, &line, None)?;
+    }
+    option.drawer.terminate(None)?;
+    if damaged {
+        return Ok(ImgWarnings::add(
+            None,
+            Box::new(BMPWarning::new(
+                "BMP Huffman 1D data is damaged".to_string(),
+            )),
+        ));
+    }
+    Ok(None)
+}
+
 fn get_shift(mask: u32) -> (u32, u32) {
     let mut temp = mask;
     let mut shift = 0;
@@ -534,6 +629,93 @@ fn decode_png<B: BinaryReader>(
     }
 }
 
+/// Sets the halftoning fields of an OS/2 2.x header as metadata.
+fn os2_metadata(header: &BitmapHeader, option: &mut DecodeOptions) -> Result<(), Error> {
+    let Some(os2) = header.os2_header() else {
+        return Ok(());
+    };
+    if os2.us_recording != BRA_BOTTOMUP {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::NoSupportFormat,
+            format!("unknown OS/2 BMP recording order {}", os2.us_recording),
+        )));
+    }
+    option.drawer.set_metadata(
+        "OS/2 header",
+        DataMap::Ascii("BITMAPINFOHEADER2".to_owned()),
+    )?;
+    option.drawer.set_metadata(
+        "halftoning",
+        DataMap::Ascii(halftoning_name(os2.us_rendering)),
+    )?;
+    if os2.us_rendering != 0 {
+        option.drawer.set_metadata(
+            "halftoning parameters",
+            DataMap::UIntAllay(vec![os2.c_size1 as u64, os2.c_size2 as u64]),
+        )?;
+    }
+    Ok(())
+}
+
+/// Sets the V4/V5 color space fields and the V5 profile as metadata.
+///
+/// A profile that cannot be read is reported as a warning.
+fn color_space_metadata<B: BinaryReader>(
+    reader: &mut B,
+    header: &BitmapHeader,
+    option: &mut DecodeOptions,
+) -> Result<Option<ImgWarnings>, Error> {
+    let Some(v4) = header.v4_header() else {
+        return Ok(None);
+    };
+    // 52 and 56 byte headers end before bV4CSType
+    if !matches!(header.bitmap_info, Windows(ref info) if info.bi_size >= 108) {
+        return Ok(None);
+    }
+    option.drawer.set_metadata(
+        "color space",
+        DataMap::Ascii(color_space_name(v4.b_v4_cstype)),
+    )?;
+    if v4.b_v4_cstype == LCS_CALIBRATED_RGB {
+        if let Some(endpoints) = &v4.b_v4_endpoints {
+            option.drawer.set_metadata(
+                "color space endpoints",
+                DataMap::FloatAllay(endpoints.to_f64()),
+            )?;
+        }
+        option
+            .drawer
+            .set_metadata("gamma", DataMap::FloatAllay(v4.gamma().to_vec()))?;
+    }
+    let Some(v5) = header.v5_header() else {
+        return Ok(None);
+    };
+    option.drawer.set_metadata(
+        "rendering intent",
+        DataMap::Ascii(intent_name(v5.b_v5_intent)),
+    )?;
+    match header.read_color_profile(reader) {
+        Ok(Some(ColorProfile::Embedded(profile))) => {
+            option
+                .drawer
+                .set_metadata("ICC Profile", DataMap::ICCProfile(profile))?;
+        }
+        Ok(Some(ColorProfile::Linked(name))) => {
+            option
+                .drawer
+                .set_metadata("linked profile", DataMap::Ascii(name))?;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            return Ok(ImgWarnings::add(
+                None,
+                Box::new(BMPWarning::new(format!("color profile is skipped: {err}"))),
+            ));
+        }
+    }
+    Ok(None)
+}
+
 pub fn decode<'decode, B: BinaryReader>(
     reader: &mut B,
     option: &mut DecodeOptions,
@@ -562,6 +744,9 @@ pub fn decode<'decode, B: BinaryReader>(
         option.drawer.verbose(&s, None)?;
     }
 
+    let warnings = color_space_metadata(reader, &header, option)?;
+    os2_metadata(&header, option)?;
+
     let offset = header.image_offset;
     reader.seek(std::io::SeekFrom::Start(offset as u64))?;
 
@@ -604,6 +789,18 @@ pub fn decode<'decode, B: BinaryReader>(
                     .drawer
                     .set_metadata("compression", DataMap::Ascii("PNG".to_owned()))?;
             }
+            Compressions::Os2Huffman1D => {
+                result = decode_huffman_1d(reader, &header, option);
+                option
+                    .drawer
+                    .set_metadata("compression", DataMap::Ascii("Huffman 1D".to_owned()))?;
+            }
+            Compressions::Os2RLE24 => {
+                result = decode_rle(reader, &header, option);
+                option
+                    .drawer
+                    .set_metadata("compression", DataMap::Ascii("RLE24".to_owned()))?;
+            }
         }
     } else {
         result = decode_rgb(reader, &header, option);
@@ -630,5 +827,5 @@ pub fn decode<'decode, B: BinaryReader>(
         .drawer
         .set_metadata("height", DataMap::UInt(header.height.unsigned_abs() as u64))?;
 
-    result
+    Ok(ImgWarnings::append(result?, warnings))
 }
@@ -3,18 +3,351 @@
 type Error = Box<dyn std::error::Error>;
 use super::header::*;
 use crate::draw::*;
+use crate::encoder::{bool_option, opaque_rgba};
 use crate::error::*;
+use crate::metadata::DataMap;
+use crate::quantize::{self, QuantizeOptions};
 use bin_rs::io::*;
+use std::collections::HashMap;
 
+const ICC_PROFILE_METADATA_KEY: &str = "ICC Profile";
+
+/// Info header written before the color table or bit masks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BmpHeaderVersion {
+    /// BITMAPINFOHEADER
+    Info,
+    /// BITMAPV4HEADER
+    V4,
+    /// BITMAPV5HEADER
+    V5,
+}
+
+impl BmpHeaderVersion {
+    fn size(self) -> u32 {
+        match self {
+            BmpHeaderVersion::Info => 40,
+            BmpHeaderVersion::V4 => 108,
+            BmpHeaderVersion::V5 => 124,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BmpSettings {
+    bit_count: u16,
+    header: BmpHeaderVersion,
+    /// `header` was given as an option
+    header_explicit: bool,
+    icc_profile: Option<Vec<u8>>,
+    rle: bool,
+    /// 16-bit output as R5G6B5 instead of X1R5G5B5
+    rgb565: bool,
+    top_down: bool,
+    quantize: QuantizeOptions,
+}
+
+impl BmpSettings {
+    fn is_indexed(&self) -> bool {
+        self.bit_count <= 8
+    }
+
+    /// BI_BITFIELDS for 565 and for 32-bit output with alpha.
+    fn is_bit_fields(&self) -> bool {
+        (self.bit_count == 16 && self.rgb565)
+            || (self.bit_count == 32 && self.header != BmpHeaderVersion::Info)
+    }
+
+    fn compression(&self) -> Compressions {
+        match (self.rle, self.bit_count) {
+            (true, 8) => Compressions::BiRLE8,
+            (true, _) => Compressions::BiRLE4,
+            _ if self.is_bit_fields() => Compressions::BiBitFileds,
+            _ => Compressions::BiRGB,
+        }
+    }
+
+    /// Red, green, blue and alpha masks.
+    fn masks(&self) -> [u32; 4] {
+        match self.bit_count {
+            16 if self.rgb565 => [0xf800, 0x07e0, 0x001f, 0],
+            16 => [0x7c00, 0x03e0, 0x001f, 0],
+            32 => [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000],
+            _ => [0; 4],
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Box::new(ImgError::new_const(ImgErrorKind::InvalidParameter, message))
+}
+
+fn number_option(value: &DataMap) -> Option<u64> {
+    match value {
+        DataMap::UInt(value) => Some(*value),
+        DataMap::SInt(value) => u64::try_from(*value).ok(),
+        DataMap::Ascii(value) => value.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn bmp_settings(options: Option<&HashMap<String, DataMap>>) -> Result<BmpSettings, Error> {
+    let get = |key: &str| options.and_then(|map| map.get(key));
+
+    let rle = match get("compression") {
+        None => None,
+        Some(DataMap::Ascii(value)) => match value.to_ascii_lowercase().as_str() {
+            "none" | "rgb" => None,
+            "rle" => Some(None),
+            "rle8" => Some(Some(8)),
+            "rle4" => Some(Some(4)),
+            _ => return Err(invalid(format!("unsupported BMP compression: {value}"))),
+        },
+        Some(_) => {
+            return Err(invalid(
+                "BMP compression must be `none`, `rle`, `rle4`, or `rle8`".to_string(),
+            ));
+        }
+    };
+
+    let bit_count = match get("bit_count").or_else(|| get("bits")) {
+        Some(value) => match number_option(value) {
+            Some(bits @ (1 | 4 | 8 | 16 | 24 | 32)) => bits as u16,
+            _ => {
+                return Err(invalid(
+                    "BMP bit_count must be 1, 4, 8, 16, 24, or 32".to_string(),
+                ));
+            }
+        },
+        None => match rle {
+            Some(Some(bits)) => bits,
+            Some(None) => 8,
+            None => 24,
+        },
+    };
+    if let Some(rle) = rle
+        && !matches!(
+            (rle, bit_count),
+            (None, 4 | 8) | (Some(4), 4) | (Some(8), 8)
+        )
+    {
+        return Err(invalid(format!(
+            "BMP RLE compression does not match bit_count {bit_count}"
+        )));
+    }
+
+    let icc_profile = match get("icc_profile") {
+        None => None,
+        Some(DataMap::ICCProfile(profile)) | Some(DataMap::Raw(profile)) => Some(profile.clone()),
+        Some(_) => {
+            return Err(invalid(
+                "BMP icc_profile must be DataMap::ICCProfile or DataMap::Raw".to_string(),
+            ));
+        }
+    };
+
+    let header = match get("header").or_else(|| get("bmp_header")) {
+        None => None,
+        Some(DataMap::Ascii(value)) => Some(match value.to_ascii_lowercase().as_str() {
+            "info" | "v3" | "bitmapinfoheader" => BmpHeaderVersion::Info,
+            "v4" | "bitmapv4header" => BmpHeaderVersion::V4,
+            "v5" | "bitmapv5header" => BmpHeaderVersion::V5,
+            _ => return Err(invalid(format!("unsupported BMP header: {value}"))),
+        }),
+        Some(value) => Some(match number_option(value) {
+            Some(40) => BmpHeaderVersion::Info,
+            Some(108) => BmpHeaderVersion::V4,
+            Some(124) => BmpHeaderVersion::V5,
+            _ => {
+                return Err(invalid(
+                    "BMP header must be `info`, `v4`, `v5`, 40, 108, or 124".to_string(),
+                ));
+            }
+        }),
+    };
+    if icc_profile.is_some() && header.is_some_and(|header| header != BmpHeaderVersion::V5) {
+        return Err(invalid(
+            "BMP icc_profile requires the v5 header".to_string(),
+        ));
+    }
+    let header_explicit = header.is_some();
+    let header = header.unwrap_or(if bit_count == 32 || icc_profile.is_some() {
+        BmpHeaderVersion::V5
+    } else {
+        BmpHeaderVersion::Info
+    });
+
+    let rgb565 = match get("rgb16") {
+        None => false,
+        Some(value) => match number_option(value) {
+            Some(555) => false,
+            Some(565) => true,
+            _ => return Err(invalid("BMP rgb16 must be 555 or 565".to_string())),
+        },
+    };
+    if rgb565 && bit_count != 16 {
+        return Err(invalid("BMP rgb16 requires bit_count 16".to_string()));
+    }
+
+    let top_down = match get("top_down") {
+        Some(value) => bool_option(value, "BMP top_down")?,
+        None => false,
+    };
+    if top_down && rle.is_some() {
+        return Err(invalid(
+            "BMP RLE compression cannot be written top-down".to_string(),
+        ));
+    }
+
+    let mut quantize = QuantizeOptions::default();
+    if bit_count <= 8 {
+        quantize = QuantizeOptions::from_options(options)?;
+        quantize.max_colors = quantize.max_colors.min(1 << bit_count);
+        quantize.alpha = false;
+        quantize.reserve_transparent = false;
+    }
+
+    Ok(BmpSettings {
+        bit_count,
+        header,
+        header_explicit,
+        icc_profile,
+        rle: rle.is_some(),
+        rgb565,
+        top_down,
+        quantize,
+    })
+}
+
+/// Packs one row of palette indices MSB first.
+fn pack_indices(indices: &[u8], bit_count: usize, row: &mut Vec<u8>) {
+    let per_byte = 8 / bit_count;
+    for chunk in indices.chunks(per_byte) {
+        let mut byte = 0u8;
+        for (i, index) in chunk.iter().enumerate() {
+            byte |= index << (8 - bit_count * (i + 1));
+        }
+        row.push(byte);
+    }
+}
+
+fn push_rgb_row(rgba: &[u8], settings: &BmpSettings, row: &mut Vec<u8>) {
+    for pixel in rgba.chunks_exact(4) {
+        let (red, green, blue, alpha) = (pixel[0], pixel[1], pixel[2], pixel[3]);
+        match settings.bit_count {
+            32 => row.extend_from_slice(&[blue, green, red, alpha]),
+            24 => row.extend_from_slice(&[blue, green, red]),
+            _ => {
+                let (red, blue) = ((red >> 3) as u16, (blue >> 3) as u16);
+                let color = if settings.rgb565 {
+                    red << 11 | ((green >> 2) as u16) << 5 | blue
+                } else {
+                    red << 10 | ((green >> 3) as u16) << 5 | blue
+                };
+                write_u16_le(color, row);
+            }
+        }
+    }
+}
+
+/// Length of the run of `indices[start]`, at most 255 pixels.
+fn run_length(indices: &[u8], start: usize) -> usize {
+    indices[start..]
+        .iter()
+        .take(255)
+        .take_while(|index| **index == indices[start])
+        .count()
+}
+
+/// Encodes one row as BI_RLE8 or BI_RLE4, followed by end of line.
+///
+/// Runs of three or more pixels use encoded mode; shorter runs are gathered
+/// into absolute mode, which needs at least three pixels.
+fn encode_rle_row(indices: &[u8], bit_count: usize, data: &mut Vec<u8>) {
+    let mut x = 0;
+    while x < indices.len() {
+        let run = run_length(indices, x);
+        if run >= 3 {
+            let value = if bit_count == 4 {
+                indices[x] << 4 | indices[x]
+            } else {
+                indices[x]
+            };
+            data.extend_from_slice(&[run as u8, value]);
+            x += run;
+            continue;
+        }
+        let start = x;
+        while x < indices.len() && x - start < 255 && run_length(indices, x) < 3 {
+            x += 1;
+        }
+        let literal = &indices[start..x.min(start + 255)];
+        if literal.len() < 3 {
+            for index in literal {
+                let value = if bit_count == 4 {
+                    index << 4 | index
+                } else {
+                    *index
+                };
+                data.extend_from_slice(&[1, value]);
+            }
+            continue;
+        }
+        data.extend_from_slice(&[0, literal.len() as u8]);
+        let before = data.len();
+        if bit_count == 4 {
+            pack_indices(literal, 4, data);
+        } else {
+            data.extend_from_slice(literal);
+        }
+        if (data.len() - before) % 2 == 1 {
+            data.push(0);
+        }
+    }
+    data.extend_from_slice(&[0, 0]);
+}
+
+/// Encodes an image as BMP.
+///
+/// Without options a 24-bit BITMAPINFOHEADER image is written.
+///
+/// Supported `EncodeOptions.options` keys:
+/// - `bit_count` (alias `bits`): `1`, `4`, `8`, `16`, `24` (default), or
+///   `32`. 1/4/8-bit output is indexed and uses the quantizer keys of
+///   [`QuantizeOptions::from_options`]. 32-bit output keeps alpha.
+/// - `header`: `info`, `v4`, or `v5`. 32-bit output defaults to `v5` with
+///   BI_BITFIELDS A8R8G8B8 masks; `info` writes 32-bit BI_RGB, whose alpha
+///   most readers ignore. V4/V5 headers declare sRGB unless a profile is
+///   embedded.
+/// - `rgb16`: `555` (default, BI_RGB) or `565` (BI_BITFIELDS) for 16-bit
+///   output
+/// - `compression`: `none` (default), `rle`, `rle4`, or `rle8`. RLE4 needs
+///   4-bit and RLE8 8-bit output; `rle` picks the one matching `bit_count`
+///   and defaults to 8-bit.
+/// - `top_down`: `true` stores rows top to bottom with a negative height.
+///   RLE images cannot be top-down.
+/// - `icc_profile`: ICC profile bytes (`DataMap::ICCProfile` or
+///   `DataMap::Raw`) embedded after the pixel data as PROFILE_EMBEDDED.
+///   This needs the `v5` header, which becomes the default.
+///
+/// Without `icc_profile`, the source's `ICC Profile` metadata is embedded
+/// only when the `v5` header is requested.
 pub fn encode(image: &mut EncodeOptions<'_>) -> Result<Vec<u8>, Error> {
+    let mut settings = bmp_settings(image.options.as_ref())?;
     let profile = image.drawer.encode_start(None)?;
     let width;
     let height;
-    let _background;
     if let Some(profile) = profile {
+        if settings.icc_profile.is_none()
+            && settings.header_explicit
+            && settings.header == BmpHeaderVersion::V5
+            && let Some(metadata) = &profile.metadata
+            && let Some(DataMap::ICCProfile(icc_profile)) = metadata.get(ICC_PROFILE_METADATA_KEY)
+        {
+            settings.icc_profile = Some(icc_profile.clone());
+        }
         width = profile.width as u32;
         height = profile.height as u32;
-        _background = profile.background;
     } else {
         return Err(Box::new(ImgError::new_const(
             ImgErrorKind::OutboundIndex,
@@ -27,69 +360,159 @@ pub fn encode(image: &mut EncodeOptions<'_>) -> Result<Vec<u8>, Error> {
             "BMP dimensions must be non-zero".to_string(),
         )));
     }
-    let bit_count = 24u32;
-    let raw_samples = width
+    if width > i32::MAX as u32 || height > i32::MAX as u32 {
+        return Err(invalid("BMP dimensions exceed i32".to_string()));
+    }
+    let bit_count = settings.bit_count as u32;
+    let row_size = width
         .checked_mul(bit_count)
         .and_then(|bits| bits.checked_add(31))
         .map(|bits| (bits / 32) * 4)
+        .ok_or_else(|| invalid("BMP row size overflow".to_string()))?;
+    row_size
+        .checked_mul(height)
+        .ok_or_else(|| invalid("BMP image size overflow".to_string()))?;
+
+    let (width, height) = (width as usize, height as usize);
+    let rgba = image
+        .drawer
+        .encode_pick(0, 0, width, height, None)?
         .ok_or_else(|| {
             Box::new(ImgError::new_const(
-                ImgErrorKind::InvalidParameter,
-                "BMP row size overflow".to_string(),
-            )) as Error
-        })?;
-    let gap = raw_samples
-        .checked_sub(width.checked_mul(bit_count / 8).ok_or_else(|| {
-            Box::new(ImgError::new_const(
-                ImgErrorKind::InvalidParameter,
-                "BMP row size overflow".to_string(),
-            )) as Error
-        })?)
-        .ok_or_else(|| {
-            Box::new(ImgError::new_const(
-                ImgErrorKind::InvalidParameter,
-                "BMP row gap overflow".to_string(),
+                ImgErrorKind::EncodeError,
+                "BMP source image is missing".to_string(),
             )) as Error
         })?;
-    let buffersize = raw_samples.checked_mul(height).ok_or_else(|| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "BMP image size overflow".to_string(),
-        )) as Error
-    })?;
-    let file_size = buffersize.checked_add(54).ok_or_else(|| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "BMP file size overflow".to_string(),
-        )) as Error
-    })?;
+    if rgba.len() < width * height * 4 {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::EncodeError,
+            "BMP source image is truncated".to_string(),
+        )));
+    }
+    let rgba = &rgba[..width * height * 4];
+
+    let (palette, indices) = if settings.is_indexed() {
+        let quantized = quantize::quantize(&opaque_rgba(rgba), width, height, &settings.quantize)?;
+        (quantized.palette, quantized.indices)
+    } else {
+        (vec![], vec![])
+    };
+
+    // Rows in file order: bottom-up unless top_down.
+    let rows: Vec<usize> = if settings.top_down {
+        (0..height).collect()
+    } else {
+        (0..height).rev().collect()
+    };
+    let mut pixels = Vec::with_capacity(row_size as usize * height);
+    for y in rows {
+        if settings.rle {
+            encode_rle_row(
+                &indices[y * width..(y + 1) * width],
+                bit_count as usize,
+                &mut pixels,
+            );
+            continue;
+        }
+        let start = pixels.len();
+        if settings.is_indexed() {
+            pack_indices(
+                &indices[y * width..(y + 1) * width],
+                bit_count as usize,
+                &mut pixels,
+            );
+        } else {
+            push_rgb_row(
+                &rgba[y * width * 4..(y + 1) * width * 4],
+                &settings,
+                &mut pixels,
+            );
+        }
+        pixels.resize(start + row_size as usize, 0);
+    }
+    if settings.rle {
+        // end of bitmap
+        pixels.extend_from_slice(&[0, 1]);
+    }
+
+    let compression = settings.compression();
+    let masks = settings.masks();
+    // BITMAPINFOHEADER keeps BI_BITFIELDS masks after the header.
+    let mask_size = if settings.header == BmpHeaderVersion::Info && settings.is_bit_fields() {
+        12
+    } else {
+        0
+    };
+    let header_size = settings.header.size();
+    let offbits = 14 + header_size + mask_size + palette.len() as u32 * 4;
+    let buffersize =
+        u32::try_from(pixels.len()).map_err(|_| invalid("BMP image size overflow".to_string()))?;
+    let icc_profile = settings.icc_profile.as_deref().unwrap_or_default();
+    let profile_size = u32::try_from(icc_profile.len())
+        .map_err(|_| invalid("BMP ICC profile is too large".to_string()))?;
+    // bV5ProfileData counts from the start of the info header.
+    let profile_data = if icc_profile.is_empty() {
+        0
+    } else {
+        offbits - 14 + buffersize
+    };
+    let file_size = buffersize
+        .checked_add(offbits)
+        .and_then(|size| size.checked_add(profile_size))
+        .ok_or_else(|| invalid("BMP file size overflow".to_string()))?;
 
     let bitmap_file_header = BitmapFileHeader {
         bf_type: 0x4d42,
         bf_size: file_size,
         bf_reserved1: 0,
         bf_reserved2: 0,
-        bf_offbits: 54,
+        bf_offbits: offbits,
     };
 
+    let v4_header = (settings.header != BmpHeaderVersion::Info).then(|| BitmapInfoV4 {
+        b_v4_red_mask: masks[0],
+        b_v4_green_mask: masks[1],
+        b_v4_blue_mask: masks[2],
+        b_v4_alpha_mask: masks[3],
+        b_v4_cstype: if icc_profile.is_empty() {
+            LCS_SRGB
+        } else {
+            PROFILE_EMBEDDED
+        },
+        b_v4_endpoints: None,
+        b_v4_gamma_red: 0,
+        b_v4_gamma_green: 0,
+        b_v4_gamma_blue: 0,
+    });
+    let v5_header = (settings.header == BmpHeaderVersion::V5).then_some(BitmapInfoV5 {
+        b_v5_intent: LCS_GM_IMAGES,
+        b_v5_profile_data: profile_data,
+        b_v5_profile_size: profile_size,
+        b_v5_reserved: 0,
+    });
+    let bi_height = if settings.top_down {
+        (height as i32).wrapping_neg() as u32
+    } else {
+        height as u32
+    };
     let header = BitmapWindowsInfo {
-        bi_size: 40,
-        bi_width: width,
-        bi_height: height,
+        bi_size: header_size,
+        bi_width: width as u32,
+        bi_height,
         bi_plane: 1,
-        bi_bit_count: bit_count as u16,
-        bi_compression: Compressions::BiRGB as u32,
+        bi_bit_count: settings.bit_count,
+        bi_compression: compression as u32,
         bi_size_image: buffersize,
         bi_xpels_per_meter: 0,
         bi_ypels_per_meter: 0,
-        bi_clr_used: 0,
+        bi_clr_used: palette.len() as u32,
         bi_clr_importation: 0,
-        b_v4_header: None,
-        b_v5_header: None,
+        b_v4_header: v4_header,
+        b_v5_header: v5_header,
+        b_os2_header: None,
     };
 
-    let mut data: Vec<u8> =
-        Vec::with_capacity((bitmap_file_header.bf_offbits + header.bi_size_image) as usize);
+    let mut data: Vec<u8> = Vec::with_capacity(file_size as usize);
     write_u16_le(bitmap_file_header.bf_type, &mut data);
     write_u32_le(bitmap_file_header.bf_size, &mut data);
     write_u16_le(bitmap_file_header.bf_reserved1, &mut data);
@@ -108,49 +531,34 @@ pub fn encode(image: &mut EncodeOptions<'_>) -> Result<Vec<u8>, Error> {
     write_u32_le(header.bi_clr_used, &mut data);
     write_u32_le(header.bi_clr_importation, &mut data);
 
-    for y in 0..height {
-        let bmp_y = height - y - 1;
-        let buf = image
-            .drawer
-            .encode_pick(0, bmp_y as usize, width as usize, 1, None)?
-            .ok_or_else(|| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::EncodeError,
-                    "BMP source row is missing".to_string(),
-                )) as Error
-            })?;
-        let expected_row_len = usize::try_from(width)
-            .ok()
-            .and_then(|w| w.checked_mul(4))
-            .ok_or_else(|| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::InvalidParameter,
-                    "BMP source row size overflow".to_string(),
-                )) as Error
-            })?;
-        if buf.len() < expected_row_len {
-            return Err(Box::new(ImgError::new_const(
-                ImgErrorKind::EncodeError,
-                "BMP source row is truncated".to_string(),
-            )));
-        }
-        let mut ptr = 0;
-        for _ in 0..width {
-            let blue = buf[ptr];
-            let green = buf[ptr + 1];
-            let red = buf[ptr + 2];
-            //            let alpha = buf[ptr+3];
-
-            data.push(red);
-            data.push(green);
-            data.push(blue);
-            //            data.push(alpha);
-            ptr += 4;
-        }
-        for _ in 0..gap {
-            data.push(0);
+    if let Some(v4) = &header.b_v4_header {
+        write_u32_le(v4.b_v4_red_mask, &mut data);
+        write_u32_le(v4.b_v4_green_mask, &mut data);
+        write_u32_le(v4.b_v4_blue_mask, &mut data);
+        write_u32_le(v4.b_v4_alpha_mask, &mut data);
+        write_u32_le(v4.b_v4_cstype, &mut data);
+        // CIEXYZTRIPLE endpoints, unused for sRGB
+        data.resize(data.len() + 36, 0);
+        write_u32_le(v4.b_v4_gamma_red, &mut data);
+        write_u32_le(v4.b_v4_gamma_green, &mut data);
+        write_u32_le(v4.b_v4_gamma_blue, &mut data);
+    } else if mask_size > 0 {
+        for mask in &masks[..3] {
+            write_u32_le(*mask, &mut data);
         }
     }
+    if let Some(v5) = &header.b_v5_header {
+        write_u32_le(v5.b_v5_intent, &mut data);
+        write_u32_le(v5.b_v5_profile_data, &mut data);
+        write_u32_le(v5.b_v5_profile_size, &mut data);
+        write_u32_le(v5.b_v5_reserved, &mut data);
+    }
+    for color in &palette {
+        data.extend_from_slice(&[color[2], color[1], color[0], 0]);
+    }
+    data.extend_from_slice(&pixels);
+    data.extend_from_slice(icc_profile);
+
     image.drawer.encode_end(None)?;
     Ok(data)
 }
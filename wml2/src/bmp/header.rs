@@ -10,6 +10,7 @@ type Error = Box<dyn std::error::Error>;
 
 use crate::error::ImgError;
 use crate::error::ImgErrorKind;
+use bin_rs::io::{read_u16_le, read_u32_le};
 use bin_rs::reader::BinaryReader;
 use std::io::SeekFrom;
 
@@ -53,6 +54,20 @@ pub struct BitmapWindowsInfo {
     pub bi_clr_importation: u32,
     pub b_v4_header: Option<BitmapInfoV4>,
     pub b_v5_header: Option<BitmapInfoV5>,
+    pub b_os2_header: Option<BitmapInfoOs2>,
+}
+
+/// Fields after the first 40 bytes of an OS/2 2.x BITMAPINFOHEADER2.
+#[derive(Debug, Default)]
+pub struct BitmapInfoOs2 {
+    pub us_units: u16,
+    pub us_reserved: u16,
+    pub us_recording: u16,
+    pub us_rendering: u16,
+    pub c_size1: u32,
+    pub c_size2: u32,
+    pub ul_color_encoding: u32,
+    pub ul_identifier: u32,
 }
 
 #[derive(Debug)]
@@ -121,9 +136,179 @@ pub enum Compressions {
     BiBitFileds = 3,
     BiJpeg = 4,
     BiPng = 5,
+    /// OS/2 2.x BCA_HUFFMAN1D, stored as 3
+    Os2Huffman1D = 0x103,
+    /// OS/2 2.x BCA_RLE24, stored as 4
+    Os2RLE24 = 0x104,
+}
+
+/// usRecording BRA_BOTTOMUP, the only OS/2 2.x row order
+pub const BRA_BOTTOMUP: u16 = 0;
+
+/// Returns a readable name of an OS/2 2.x usRendering value.
+pub fn halftoning_name(rendering: u16) -> String {
+    match rendering {
+        0 => "None".to_string(),
+        1 => "Error diffusion".to_string(),
+        2 => "PANDA".to_string(),
+        3 => "Super-circle".to_string(),
+        _ => format!("Unknown ({rendering})"),
+    }
+}
+
+/// OS/2 2.x BITMAPINFOHEADER2 sizes. The header may be cut after any field
+/// from 16 bytes on; sizes shared with Windows headers are read as Windows.
+fn is_os2_v2_size(bi_size: u32) -> bool {
+    bi_size == 64
+        || ((16..64).contains(&bi_size)
+            && bi_size.is_multiple_of(2)
+            && !matches!(bi_size, 40 | 52 | 56))
+}
+
+/// bV4CSType: calibrated RGB using the endpoints and gamma fields.
+pub const LCS_CALIBRATED_RGB: u32 = 0;
+/// bV4CSType: `sRGB`
+pub const LCS_SRGB: u32 = 0x7352_4742;
+/// bV4CSType: `Win `, the system default color space
+pub const LCS_WINDOWS_COLOR_SPACE: u32 = 0x5769_6e20;
+/// bV5CSType: `LINK`, bV5ProfileData points to a profile file name
+pub const PROFILE_LINKED: u32 = 0x4c49_4e4b;
+/// bV5CSType: `MBED`, bV5ProfileData points to an ICC profile
+pub const PROFILE_EMBEDDED: u32 = 0x4d42_4544;
+
+/// bV5Intent values
+pub const LCS_GM_BUSINESS: u32 = 1;
+pub const LCS_GM_GRAPHICS: u32 = 2;
+pub const LCS_GM_IMAGES: u32 = 4;
+pub const LCS_GM_ABS_COLORIMETRIC: u32 = 8;
+
+/// Upper bound for a profile referenced by bV5ProfileData.
+const MAX_PROFILE_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Returns a readable name of a bV4CSType value.
+pub fn color_space_name(cstype: u32) -> String {
+    match cstype {
+        LCS_CALIBRATED_RGB => "Calibrated RGB".to_string(),
+        LCS_SRGB => "sRGB".to_string(),
+        LCS_WINDOWS_COLOR_SPACE => "Windows color space".to_string(),
+        PROFILE_LINKED => "Linked profile".to_string(),
+        PROFILE_EMBEDDED => "Embedded profile".to_string(),
+        _ => format!("Unknown ({cstype:#010x})"),
+    }
+}
+
+/// Returns a readable name of a bV5Intent value.
+pub fn intent_name(intent: u32) -> String {
+    match intent {
+        LCS_GM_BUSINESS => "Saturation".to_string(),
+        LCS_GM_GRAPHICS => "Relative colorimetric".to_string(),
+        LCS_GM_IMAGES => "Perceptual".to_string(),
+        LCS_GM_ABS_COLORIMETRIC => "Absolute colorimetric".to_string(),
+        _ => format!("Unknown ({intent})"),
+    }
+}
+
+impl CieXYZ {
+    /// X, Y and Z as FXPT2DOT30 fixed point values.
+    pub fn to_f64(&self) -> [f64; 3] {
+        let scale = (1u32 << 30) as f64;
+        [
+            self.ciexyz_x as f64 / scale,
+            self.ciexyz_y as f64 / scale,
+            self.ciexyz_z as f64 / scale,
+        ]
+    }
+}
+
+impl CieXYZTriple {
+    /// Red, green and blue endpoints as nine XYZ values.
+    pub fn to_f64(&self) -> Vec<f64> {
+        [&self.ciexyz_red, &self.ciexyz_green, &self.ciexyz_blue]
+            .iter()
+            .flat_map(|xyz| xyz.to_f64())
+            .collect()
+    }
+}
+
+impl BitmapInfoV4 {
+    /// Red, green and blue gamma as FXPT16DOT16 fixed point values.
+    pub fn gamma(&self) -> [f64; 3] {
+        [
+            self.b_v4_gamma_red as f64 / 65536.0,
+            self.b_v4_gamma_green as f64 / 65536.0,
+            self.b_v4_gamma_blue as f64 / 65536.0,
+        ]
+    }
+}
+
+/// Profile referenced by a BITMAPV5HEADER.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorProfile {
+    /// PROFILE_EMBEDDED ICC profile data
+    Embedded(Vec<u8>),
+    /// PROFILE_LINKED profile file name
+    Linked(String),
 }
 
 impl BitmapHeader {
+    /// Returns the BITMAPV4HEADER fields, also present in V5 headers.
+    pub fn v4_header(&self) -> Option<&BitmapInfoV4> {
+        match &self.bitmap_info {
+            BitmapInfo::Windows(info) => info.b_v4_header.as_ref(),
+            BitmapInfo::Os2(..) => None,
+        }
+    }
+
+    /// Returns the BITMAPV5HEADER fields.
+    pub fn v5_header(&self) -> Option<&BitmapInfoV5> {
+        match &self.bitmap_info {
+            BitmapInfo::Windows(info) => info.b_v5_header.as_ref(),
+            BitmapInfo::Os2(..) => None,
+        }
+    }
+
+    /// Returns the OS/2 2.x BITMAPINFOHEADER2 fields.
+    pub fn os2_header(&self) -> Option<&BitmapInfoOs2> {
+        match &self.bitmap_info {
+            BitmapInfo::Windows(info) => info.b_os2_header.as_ref(),
+            BitmapInfo::Os2(..) => None,
+        }
+    }
+
+    /// Reads the embedded or linked profile of a V5 header.
+    ///
+    /// bV5ProfileData is an offset from the start of the info header.
+    pub fn read_color_profile<B: BinaryReader>(
+        &self,
+        reader: &mut B,
+    ) -> Result<Option<ColorProfile>, Error> {
+        let (Some(v4), Some(v5)) = (self.v4_header(), self.v5_header()) else {
+            return Ok(None);
+        };
+        if !matches!(v4.b_v4_cstype, PROFILE_EMBEDDED | PROFILE_LINKED) || v5.b_v5_profile_size == 0
+        {
+            return Ok(None);
+        }
+        if v5.b_v5_profile_size > MAX_PROFILE_SIZE {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::DecodeError,
+                "BMP color profile is too large".to_string(),
+            )));
+        }
+        reader.seek(SeekFrom::Start(14 + v5.b_v5_profile_data as u64))?;
+        let data = reader.read_bytes_as_vec(v5.b_v5_profile_size as usize)?;
+        if v4.b_v4_cstype == PROFILE_EMBEDDED {
+            return Ok(Some(ColorProfile::Embedded(data)));
+        }
+        // NUL terminated file name, read as Latin-1
+        let name = data
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect();
+        Ok(Some(ColorProfile::Linked(name)))
+    }
+
     pub fn new<B: BinaryReader>(reader: &mut B, _opt: usize) -> Result<Self, Error> {
         let mut read_size;
         let b = reader.read_byte()?;
@@ -156,6 +341,7 @@ impl BitmapHeader {
         let clut_offset;
         match bi_size {
             12 | 40 | 52 | 56 | 108 | 124 => {}
+            _ if is_os2_v2_size(bi_size) => {}
             _ => {
                 return Err(Box::new(ImgError::new_const(
                     ImgErrorKind::DecodeError,
@@ -181,6 +367,55 @@ impl BitmapHeader {
             clut_size = 0;
             clut_offset = 12 + 14;
             bitmap_info = BitmapInfo::Os2(os2header);
+        } else if is_os2_v2_size(bi_size) {
+            // Missing fields of a short header are zero.
+            let mut fields = reader.read_bytes_as_vec(bi_size as usize - 4)?;
+            fields.resize(60, 0);
+            read_size += bi_size - 4;
+            let info_header = BitmapWindowsInfo {
+                bi_size,
+                bi_width: read_u32_le(&fields, 0),
+                bi_height: read_u32_le(&fields, 4),
+                bi_plane: read_u16_le(&fields, 8),
+                bi_bit_count: read_u16_le(&fields, 10),
+                bi_compression: read_u32_le(&fields, 12),
+                bi_size_image: read_u32_le(&fields, 16),
+                bi_xpels_per_meter: read_u32_le(&fields, 20),
+                bi_ypels_per_meter: read_u32_le(&fields, 24),
+                bi_clr_used: read_u32_le(&fields, 28),
+                bi_clr_importation: read_u32_le(&fields, 32),
+                b_v4_header: None,
+                b_v5_header: None,
+                b_os2_header: Some(BitmapInfoOs2 {
+                    us_units: read_u16_le(&fields, 36),
+                    us_reserved: read_u16_le(&fields, 38),
+                    us_recording: read_u16_le(&fields, 40),
+                    us_rendering: read_u16_le(&fields, 42),
+                    c_size1: read_u32_le(&fields, 44),
+                    c_size2: read_u32_le(&fields, 48),
+                    ul_color_encoding: read_u32_le(&fields, 52),
+                    ul_identifier: read_u32_le(&fields, 56),
+                }),
+            };
+
+            bit_per_count = info_header.bi_bit_count as usize;
+            clut_size = if bit_per_count <= 8 {
+                info_header.bi_clr_used as usize
+            } else {
+                0
+            };
+            clut_offset = bi_size as u64 + 14;
+            width = info_header.bi_width as i32;
+            height = info_header.bi_height as i32;
+            compression = match info_header.bi_compression {
+                0 => Some(Compressions::BiRGB),
+                1 => Some(Compressions::BiRLE8),
+                2 => Some(Compressions::BiRLE4),
+                3 => Some(Compressions::Os2Huffman1D),
+                4 => Some(Compressions::Os2RLE24),
+                _ => None,
+            };
+            bitmap_info = BitmapInfo::Windows(info_header);
         } else {
             let mut info_header = BitmapWindowsInfo {
                 bi_size,
@@ -196,6 +431,7 @@ impl BitmapHeader {
                 bi_clr_importation: reader.read_u32_le()?, //40
                 b_v4_header: None,
                 b_v5_header: None,
+                b_os2_header: None,
             };
 
             read_size += 40 - 4;
@@ -320,6 +556,9 @@ impl BitmapHeader {
                 0 => Some(Compressions::BiRGB),
                 1 => Some(Compressions::BiRLE8),
                 2 => Some(Compressions::BiRLE4),
+                // bit fields cannot describe 1-bit pixels, so this is an
+                // OS/2 2.x bitmap cut to 40 bytes
+                3 if bit_per_count == 1 => Some(Compressions::Os2Huffman1D),
                 3 => Some(Compressions::BiBitFileds),
                 4 => Some(Compressions::BiJpeg),
                 5 => Some(Compressions::BiPng),
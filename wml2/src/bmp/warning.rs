@@ -1,6 +1,7 @@
 //! BMP-specific warning types.
 
-use crate::warning::WarningKind;
+use crate::warning::{ImgWarning, WarningKind};
+use std::fmt::*;
 
 #[derive(Debug)]
 pub enum BMPWarningKind {
@@ -20,3 +21,26 @@ impl WarningKind for BMPWarningKind {
         }
     }
 }
+
+pub struct BMPWarning {
+    message: String,
+}
+
+impl ImgWarning for BMPWarning {}
+impl Debug for BMPWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        std::fmt::Display::fmt(&self.message, f)
+    }
+}
+
+impl Display for BMPWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", &self.message)
+    }
+}
+
+impl BMPWarning {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
@@ -1,4 +1,5 @@
-//! RGB and RGBA color primitives used across decoders and encoders.
+//! RGB and RGBA color primitives and colorimetry helpers used across decoders
+//! and encoders.
 
 /// An RGB color without alpha.
 #[derive(Debug, Clone)]
@@ -314,3 +315,178 @@ const PC98COLOR16: [RGB; 16] = [
     RGB{red:0xff,green:0xff,blue:0xff},    // 15 white
 ];
 */
+
+/// CIE 1931 xy chromaticity of the D65 white point used by sRGB.
+pub const D65_WHITE_POINT: (f64, f64) = (0.3127, 0.3290);
+
+/// CIE 1931 xy chromaticity of the D50 white point used by CIELab and ICC.
+pub const D50_WHITE_POINT: (f64, f64) = (0.3457, 0.3585);
+
+/// CIE 1931 xy chromaticities of the sRGB red, green and blue primaries.
+pub const SRGB_PRIMARIES: [(f64, f64); 3] = [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06)];
+
+/// A row-major 3x3 matrix used for linear RGB and XYZ conversions.
+pub type Matrix3 = [[f64; 3]; 3];
+
+/// Converts an sRGB-encoded value in `0.0..=1.0` to linear light.
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear-light value in `0.0..=1.0` to the sRGB transfer curve.
+pub fn linear_to_srgb(value: f64) -> f64 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Multiplies two 3x3 matrices (`a * b`).
+pub fn multiply_matrix3(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut result = [[0.0; 3]; 3];
+    for (row, result_row) in result.iter_mut().enumerate() {
+        for (col, value) in result_row.iter_mut().enumerate() {
+            *value = (0..3).map(|i| a[row][i] * b[i][col]).sum();
+        }
+    }
+    result
+}
+
+/// Applies a 3x3 matrix to a column vector.
+pub fn transform_matrix3(matrix: &Matrix3, vector: [f64; 3]) -> [f64; 3] {
+    [
+        matrix[0][0] * vector[0] + matrix[0][1] * vector[1] + matrix[0][2] * vector[2],
+        matrix[1][0] * vector[0] + matrix[1][1] * vector[1] + matrix[1][2] * vector[2],
+        matrix[2][0] * vector[0] + matrix[2][1] * vector[1] + matrix[2][2] * vector[2],
+    ]
+}
+
+/// Inverts a 3x3 matrix, returning `None` when it is singular.
+pub fn invert_matrix3(m: &Matrix3) -> Option<Matrix3> {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    if det.abs() < 1e-12 || !det.is_finite() {
+        return None;
+    }
+    let inv = 1.0 / det;
+    Some([
+        [
+            (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv,
+            (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv,
+            (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv,
+        ],
+        [
+            (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv,
+            (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv,
+            (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv,
+        ],
+        [
+            (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv,
+            (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv,
+            (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv,
+        ],
+    ])
+}
+
+/// Converts an xy chromaticity to XYZ with `Y = 1`.
+pub fn xy_to_xyz(xy: (f64, f64)) -> Option<[f64; 3]> {
+    let (x, y) = xy;
+    if y <= 0.0 || !x.is_finite() || !y.is_finite() {
+        return None;
+    }
+    Some([x / y, 1.0, (1.0 - x - y) / y])
+}
+
+/// Builds the linear RGB to XYZ matrix for the given white point and primaries.
+pub fn rgb_to_xyz_matrix(white: (f64, f64), primaries: [(f64, f64); 3]) -> Option<Matrix3> {
+    let red = xy_to_xyz(primaries[0])?;
+    let green = xy_to_xyz(primaries[1])?;
+    let blue = xy_to_xyz(primaries[2])?;
+    let white = xy_to_xyz(white)?;
+    let primaries = [
+        [red[0], green[0], blue[0]],
+        [red[1], green[1], blue[1]],
+        [red[2], green[2], blue[2]],
+    ];
+    let scale = transform_matrix3(&invert_matrix3(&primaries)?, white);
+    let mut matrix = primaries;
+    for row in matrix.iter_mut() {
+        for (value, scale) in row.iter_mut().zip(scale) {
+            *value *= scale;
+        }
+    }
+    Some(matrix)
+}
+
+/// Builds a Bradford chromatic adaptation matrix between two white points.
+pub fn bradford_adaptation(source: (f64, f64), destination: (f64, f64)) -> Option<Matrix3> {
+    const BRADFORD: Matrix3 = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let source = transform_matrix3(&BRADFORD, xy_to_xyz(source)?);
+    let destination = transform_matrix3(&BRADFORD, xy_to_xyz(destination)?);
+    if source.iter().any(|value| value.abs() < 1e-12) {
+        return None;
+    }
+    let scale = [
+        [destination[0] / source[0], 0.0, 0.0],
+        [0.0, destination[1] / source[1], 0.0],
+        [0.0, 0.0, destination[2] / source[2]],
+    ];
+    let inverse = invert_matrix3(&BRADFORD)?;
+    Some(multiply_matrix3(
+        &inverse,
+        &multiply_matrix3(&scale, &BRADFORD),
+    ))
+}
+
+/// Builds the matrix converting linear RGB with the given colorimetry to linear sRGB.
+///
+/// The source white point is adapted to D65 with the Bradford transform, which
+/// corresponds to a relative colorimetric conversion.
+pub fn rgb_to_srgb_matrix(white: (f64, f64), primaries: [(f64, f64); 3]) -> Option<Matrix3> {
+    let source = rgb_to_xyz_matrix(white, primaries)?;
+    let adaptation = bradford_adaptation(white, D65_WHITE_POINT)?;
+    let srgb = invert_matrix3(&rgb_to_xyz_matrix(D65_WHITE_POINT, SRGB_PRIMARIES)?)?;
+    Some(multiply_matrix3(
+        &srgb,
+        &multiply_matrix3(&adaptation, &source),
+    ))
+}
+
+/// Builds the matrix converting XYZ relative to `white` to linear sRGB.
+pub fn xyz_to_srgb_matrix(white: (f64, f64)) -> Option<Matrix3> {
+    let adaptation = bradford_adaptation(white, D65_WHITE_POINT)?;
+    let srgb = invert_matrix3(&rgb_to_xyz_matrix(D65_WHITE_POINT, SRGB_PRIMARIES)?)?;
+    Some(multiply_matrix3(&srgb, &adaptation))
+}
+
+/// Converts CIE L*a*b* to XYZ relative to the white point `white`, with
+/// `Y = 1` for white.
+pub fn lab_to_xyz(lab: [f64; 3], white: [f64; 3]) -> [f64; 3] {
+    const DELTA: f64 = 6.0 / 29.0;
+    let inverse = |t: f64| {
+        if t > DELTA {
+            t * t * t
+        } else {
+            3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+        }
+    };
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+    [
+        white[0] * inverse(fx),
+        white[1] * inverse(fy),
+        white[2] * inverse(fz),
+    ]
+}
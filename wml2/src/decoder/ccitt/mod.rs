@@ -4,8 +4,7 @@
 mod black;
 mod white;
 type Error = Box<dyn std::error::Error>;
-use crate::error::ImgError;
-use crate::error::ImgErrorKind;
+use crate::error::{ImgError, ImgErrorKind};
 pub use black::black_tree;
 pub use white::white_tree;
 
@@ -18,6 +17,10 @@ const TERMINATE:u8 = 2;
 */
 const BLACK: u8 = 0xff;
 
+// 2D extension 0000001 111 and its 1D form switch to uncompressed mode.
+const UNCOMPRESSED: usize = 7;
+const UNCOMPRESSED_1D: usize = 0b0000_0000_1111;
+
 #[derive(PartialEq, Debug)]
 pub enum Encoder {
     HuffmanRLE,
@@ -43,6 +46,7 @@ pub enum Mode {
     Vr(usize),
     Vl(usize),
     Ext2D(usize),
+    Run,
     EOL,
     None,
 }
@@ -77,35 +81,27 @@ impl BitReader {
     }
 
     fn look_bits_msb(&mut self, size: usize) -> Result<usize, Error> {
-        while self.left_bits <= 24 {
-            if self.ptr >= self.buffer.len() {
-                if self.left_bits < size {
-                    self.warning = true;
-                    if size >= 12 {
-                        return Ok(0x1); // send EOL
-                    } else {
-                        return Ok(0x0);
-                    }
-                }
-                break;
-            }
-
+        while self.left_bits <= 24 && self.ptr < self.buffer.len() {
             self.last_byte = (self.last_byte << 8) | (self.buffer[self.ptr] as u32);
             self.ptr += 1;
             self.left_bits += 8;
         }
-        if self.left_bits < size {
+        Ok(self.peek_bits(size))
+    }
+
+    /// Returns the next `size` buffered bits. The last bits of the data are
+    /// padded with zeros, so a code ending at the very end still matches.
+    fn peek_bits(&mut self, size: usize) -> usize {
+        if self.left_bits >= size {
+            return ((self.last_byte >> (self.left_bits - size)) & ((1 << size) - 1)) as usize;
+        }
+        if self.left_bits == 0 {
             self.warning = true;
-            if size >= 12 {
-                return Ok(0x1);
-            } else {
-                return Ok(0x0);
-            }
+            // send EOL
+            return if size >= 12 { 0x1 } else { 0x0 };
         }
-
-        let bits = (self.last_byte >> (self.left_bits - size)) & ((1 << size) - 1);
-
-        Ok(bits as usize)
+        let rest = self.last_byte & ((1 << self.left_bits) - 1);
+        (rest << (size - self.left_bits)) as usize
     }
 
     fn skip_bits(&mut self, size: usize) {
@@ -122,34 +118,12 @@ impl BitReader {
     }
 
     fn look_bits_lsb(&mut self, size: usize) -> Result<usize, Error> {
-        while self.left_bits <= 24 {
-            if self.ptr >= self.buffer.len() {
-                if self.left_bits < size {
-                    self.warning = true;
-                    if size >= 12 {
-                        return Ok(0x1); // send EOL
-                    } else {
-                        return Ok(0x0);
-                    }
-                }
-                break;
-            }
+        while self.left_bits <= 24 && self.ptr < self.buffer.len() {
             self.last_byte = (self.last_byte << 8) | (self.buffer[self.ptr].reverse_bits() as u32);
             self.ptr += 1;
             self.left_bits += 8;
         }
-        if self.left_bits < size {
-            self.warning = true;
-            if size >= 12 {
-                return Ok(0x1);
-            } else {
-                return Ok(0x0);
-            }
-        }
-
-        let bits = (self.last_byte >> (self.left_bits - size)) & ((1 << size) - 1);
-
-        Ok(bits as usize)
+        Ok(self.peek_bits(size))
     }
 
     fn get_bits(&mut self, size: usize) -> Result<usize, Error> {
@@ -347,7 +321,8 @@ impl BitReader {
         }
 
         if bits == 10 {
-            self.skip_bits(10);
+            // 0000001 is followed by a 3-bit extension number.
+            self.skip_bits(7);
             let n = self.look_bits(3)?;
             self.skip_bits(3);
             return Ok(Mode::Ext2D(n));
@@ -362,6 +337,60 @@ impl BitReader {
     }
 }
 
+// Uncompressed mode (T.4 Annex A): 1..00001 are up to four whites and a
+// black, 000001 is five whites, and 0000001..00000000001 exit after up to
+// four whites. The bit after the exit code gives the next color.
+// Returns false on an invalid code.
+fn uncompressed(
+    reader: &mut BitReader,
+    codes: &mut Vec<usize>,
+    a0: &mut usize,
+    width: usize,
+) -> Result<bool, Error> {
+    loop {
+        let bits = reader.look_bits(11)?;
+        let zeros = (0..11).take_while(|i| bits & (0x400 >> i) == 0).count();
+        match zeros {
+            0..=4 => {
+                reader.skip_bits(zeros + 1);
+                put_pixels(codes, a0, WHITE, zeros, width);
+                put_pixels(codes, a0, BLACK, 1, width);
+            }
+            5 => {
+                reader.skip_bits(6);
+                put_pixels(codes, a0, WHITE, 5, width);
+            }
+            6..=10 => {
+                reader.skip_bits(zeros + 1);
+                put_pixels(codes, a0, WHITE, zeros - 6, width);
+                let color = if reader.get_bits(1)? == 1 {
+                    BLACK
+                } else {
+                    WHITE
+                };
+                set_color(codes, *a0, color);
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        }
+    }
+}
+
+fn put_pixels(codes: &mut Vec<usize>, a0: &mut usize, color: u8, count: usize, width: usize) {
+    if count > 0 {
+        set_color(codes, *a0, color);
+        *a0 = (*a0 + count).min(width);
+    }
+}
+
+// An odd number of changes means black is the current color.
+fn set_color(codes: &mut Vec<usize>, a0: usize, color: u8) {
+    let is_black = codes.len() & 0x1 == 1;
+    if is_black != (color == BLACK) {
+        codes.push(a0);
+    }
+}
+
 // Tiff independ
 pub fn decoder(
     buf: &[u8],
@@ -370,7 +399,16 @@ pub fn decoder(
     encoding: Encoder,
     is_lsb: bool,
 ) -> Result<(Vec<u8>, bool), Error> {
-    let mut data = Vec::with_capacity(width * height);
+    let size = width.checked_mul(height).ok_or_else(|| {
+        Box::new(ImgError::new_const(
+            ImgErrorKind::OutOfMemory,
+            format!("CCITT image {}x{} is too large", width, height),
+        )) as Error
+    })?;
+    // Every row takes at least one bit, so a short buffer cannot fill a tall
+    // image.
+    let rows = height.min(buf.len().saturating_mul(8));
+    let mut data = Vec::with_capacity(width * rows);
     let white = white_tree();
     let black = black_tree();
 
@@ -408,50 +446,77 @@ pub fn decoder(
     cur_codes.push(TERMINATE);
     */
     // fast code
-    let mut codes = vec![0, 0, width];
+    // The row end is listed twice so b1 and b2 both resolve past the last
+    // change of the reference row.
+    let mut codes = vec![0, 0, width, width];
+    // A damaged G4 stream has no EOL to resynchronise on.
+    let mut lost = false;
 
     loop {
         let mut a0 = 0;
         let mut eol = false;
+        let mut damaged = false;
 
         // test code
         let pre_codes = codes;
-        codes = Vec::with_capacity(width + 2);
+        codes = Vec::with_capacity(width + 4);
         codes.push(0);
         codes.push(0);
         let mut codes_ptr = 1;
 
-        while a0 < width && !eol {
-            let mode = if is2d { reader.mode()? } else { Mode::Horiz };
+        while a0 < width && !eol && !damaged {
+            let mode = if is2d {
+                reader.mode()?
+            } else if reader.look_bits(12)? == UNCOMPRESSED_1D {
+                reader.skip_bits(12);
+                Mode::Ext2D(UNCOMPRESSED)
+            } else {
+                Mode::Run
+            };
+            // a0 starts on an imaginary white pixel before the row, so a
+            // reference change at 0 still lies to its right.
+            let row_start = a0 == 0 && codes.len() == 2;
             match mode {
-                Mode::Horiz => {
-                    let (mut len1, mut len2);
-                    if codes.len() & 0x1 == 0 {
-                        len1 = reader.run_len(&white)?;
-                        len2 = reader.run_len(&black)?;
+                Mode::Run => {
+                    let tree = if codes.len() & 0x1 == 0 {
+                        &white
                     } else {
-                        len1 = reader.run_len(&black)?;
-                        len2 = reader.run_len(&white)?;
-                    }
-                    if len1 == EOL {
-                        // EOL
+                        &black
+                    };
+                    let len = reader.run_len(tree)?;
+                    if len == EOL {
                         eol = true;
-                        len1 = 0;
+                    } else {
+                        a0 += len as usize;
+                        codes.push(a0);
                     }
-                    if len2 == EOL {
-                        // EOL
+                }
+                Mode::Horiz => {
+                    let (first, second) = if codes.len() & 0x1 == 0 {
+                        (&white, &black)
+                    } else {
+                        (&black, &white)
+                    };
+                    let len1 = reader.run_len(first)?;
+                    let len2 = if len1 == EOL {
+                        EOL
+                    } else {
+                        reader.run_len(second)?
+                    };
+                    if len1 == EOL || len2 == EOL {
                         eol = true;
-                        len2 = 0;
+                    } else {
+                        a0 += len1 as usize;
+                        codes.push(a0);
+                        a0 += len2 as usize;
+                        codes.push(a0);
                     }
-
-                    a0 += len1 as usize;
-                    codes.push(a0);
-                    a0 += len2 as usize;
-                    codes.push(a0);
                 }
                 Mode::Pass => {
                     codes_ptr += 1;
-                    while codes_ptr < pre_codes.len() && a0 >= pre_codes[codes_ptr] {
+                    while codes_ptr < pre_codes.len()
+                        && (a0 > pre_codes[codes_ptr] || (a0 == pre_codes[codes_ptr] && !row_start))
+                    {
                         codes_ptr += 2;
                     }
                     codes_ptr += 1;
@@ -464,7 +529,9 @@ pub fn decoder(
                 Mode::V => {
                     codes_ptr += 1;
 
-                    while codes_ptr < pre_codes.len() && a0 >= pre_codes[codes_ptr] {
+                    while codes_ptr < pre_codes.len()
+                        && (a0 > pre_codes[codes_ptr] || (a0 == pre_codes[codes_ptr] && !row_start))
+                    {
                         codes_ptr += 2;
                     }
 
@@ -479,7 +546,9 @@ pub fn decoder(
                 Mode::Vr(n) => {
                     codes_ptr += 1;
 
-                    while codes_ptr < pre_codes.len() && a0 >= pre_codes[codes_ptr] {
+                    while codes_ptr < pre_codes.len()
+                        && (a0 > pre_codes[codes_ptr] || (a0 == pre_codes[codes_ptr] && !row_start))
+                    {
                         codes_ptr += 2;
                     }
 
@@ -495,7 +564,9 @@ pub fn decoder(
                 Mode::Vl(n) => {
                     codes_ptr += 1;
 
-                    while codes_ptr < pre_codes.len() && a0 >= pre_codes[codes_ptr] {
+                    while codes_ptr < pre_codes.len()
+                        && (a0 > pre_codes[codes_ptr] || (a0 == pre_codes[codes_ptr] && !row_start))
+                    {
                         codes_ptr += 2;
                     }
                     let b1 = pre_codes.get(codes_ptr).copied().unwrap_or_else(|| {
@@ -503,17 +574,26 @@ pub fn decoder(
                         width
                     });
                     let a1 = b1.saturating_sub(n);
-                    a0 = a1;
+                    if a1 < a0 {
+                        damaged = true;
+                    }
+                    a0 = a1.max(a0);
                     codes.push(a0);
 
                     codes_ptr = codes_ptr.saturating_sub(2);
                 }
-                Mode::Ext2D(n) => {
-                    let message = format!("not support 2D Ext({}) for CCITT decoder", n);
-                    return Err(Box::new(ImgError::new_const(
-                        ImgErrorKind::DecodeError,
-                        message,
-                    )));
+                Mode::Ext2D(UNCOMPRESSED) => {
+                    if !uncompressed(&mut reader, &mut codes, &mut a0, width)? {
+                        damaged = true;
+                    }
+                    // The exit code may leave either color current, so move
+                    // codes_ptr back onto the parity of that color.
+                    if (codes_ptr + codes.len()) & 0x1 == 0 {
+                        codes_ptr = if codes_ptr > 0 { codes_ptr - 1 } else { 1 };
+                    }
+                }
+                Mode::Ext2D(_) => {
+                    damaged = true;
                 }
                 Mode::None => {
                     // fill?
@@ -525,59 +605,56 @@ pub fn decoder(
                     break;
                 }
             }
+            if a0 > width {
+                damaged = true;
+            }
         }
         if encoding == Encoder::G4 {
             // G4 Tiff encoding does have eight EOLs at the end.
-            if eol {
+            if eol && a0 == 0 && codes.len() == 2 {
                 // EOBF uncheck
                 break;
             }
-            /*
-                if reader.look_bits(12)? == 1 {  // EOL?
-                    reader.skip_bits(12);
-                } else {
-                    warning!("unexcept eol");
-                }
-                reader.flush();
-            */
+            if eol || damaged {
+                lost = true;
+            }
+        }
+        if damaged || (eol && a0 < width) {
+            reader.warning = true;
         }
 
         codes.push(width);
 
+        // Draw exactly one row even if the codes run short or past the end.
+        let offset = data.len();
         let mut color = WHITE;
 
-        for i in 2..codes.len() {
-            for _ in codes[i - 1]..codes[i] {
-                data.push(color);
+        for &change in &codes[2..] {
+            let end = offset + change.min(width);
+            if end > data.len() {
+                data.resize(end, color);
             }
             color ^= BLACK;
         }
-        // slow code
-        /*
-                for i in 0..width {
-                    if cur_codes[i] == BLACK {
-                        data.push(BLACK);
-                    } else {
-                        data.push(0);
-                    }
-                }
-                ref_codes = cur_codes.clone();
-                cur_codes = vec![UNDEF;width];
-                cur_codes.push(TERMINATE);
-        */
+        data.resize(offset + width, WHITE);
+        codes.push(width);
         y += 1;
 
-        if y >= height {
+        if y >= height || lost {
             break;
         } // G3 Tiff encoding does not have EOL at the end.
 
         if (encoding == Encoder::G31d || encoding == Encoder::G32d) && !eol {
+            // A damaged row resynchronises on the next EOL.
             loop {
                 if reader.look_bits(12)? == 1 {
                     // EOL?
                     reader.skip_bits(12);
                     break;
                 }
+                if reader.look_bits(1)? == 1 {
+                    reader.warning = true;
+                }
                 reader.skip_bits(1); // fill
             }
         }
@@ -592,6 +669,11 @@ pub fn decoder(
         }
     }
 
+    if data.len() < size {
+        reader.warning = true;
+        data.resize(size, WHITE);
+    }
+
     Ok((data, reader.warning))
 }
 
@@ -607,4 +689,86 @@ mod tests {
         assert_eq!(value, EOL);
         assert!(reader.warning);
     }
+
+    /// Packs a string of '0' and '1' MSB first, ignoring spaces.
+    fn bits(code: &str) -> Vec<u8> {
+        let code: Vec<u8> = code.bytes().filter(|b| *b != b' ').collect();
+        let mut buf = vec![0; code.len().div_ceil(8)];
+        for (i, bit) in code.iter().enumerate() {
+            if *bit == b'1' {
+                buf[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn decodes_1d_uncompressed_mode() {
+        // EOL, uncompressed entry, B, WB, 5W, exit with a white T bit.
+        let buf = bits("000000000001 000000001111 1 01 000001 0000001 0");
+        let (data, warning) = decoder(&buf, 8, 1, Encoder::G31d, false).unwrap();
+
+        assert_eq!(
+            data,
+            [BLACK, WHITE, BLACK, WHITE, WHITE, WHITE, WHITE, WHITE]
+        );
+        assert!(!warning);
+    }
+
+    #[test]
+    fn rejects_overflowing_image_size() {
+        let buf = bits("1");
+        assert!(decoder(&buf, usize::MAX / 2, 3, Encoder::G4, false).is_err());
+    }
+
+    #[test]
+    fn decodes_2d_uncompressed_mode_and_keeps_the_reference_row() {
+        let buf = bits(concat!(
+            // row 0: uncompressed WB, exit after one white into black,
+            // then horizontal black 2 white 3
+            "0000001111 01 00000001 1 001 11 1000 ",
+            // row 1: V0, uncompressed B, exit into white, then V0 V0 V0
+            "1 0000001111 1 0000001 0 1 1 1 ",
+            "000000000001 000000000001",
+        ));
+        let (data, warning) = decoder(&buf, 8, 2, Encoder::G4, false).unwrap();
+        let row = [WHITE, BLACK, WHITE, BLACK, BLACK, WHITE, WHITE, WHITE];
+
+        assert_eq!(&data[..8], row);
+        assert_eq!(&data[8..], row);
+        assert!(!warning);
+    }
+
+    #[test]
+    fn damaged_g3_row_resyncs_at_the_next_eol() {
+        let buf = bits(concat!(
+            "000000000001 10011 ",
+            // white 2, black 5, white 4 runs past the row end
+            "000000000001 0111 0011 1011 101 ",
+            "000000000001 1011 011",
+        ));
+        let (data, warning) = decoder(&buf, 8, 3, Encoder::G31d, false).unwrap();
+
+        assert_eq!(&data[..8], [WHITE; 8]);
+        assert_eq!(
+            &data[8..16],
+            [WHITE, WHITE, BLACK, BLACK, BLACK, BLACK, BLACK, WHITE]
+        );
+        assert_eq!(
+            &data[16..],
+            [WHITE, WHITE, WHITE, WHITE, BLACK, BLACK, BLACK, BLACK]
+        );
+        assert!(warning);
+    }
+
+    #[test]
+    fn truncated_g4_fills_the_missing_rows() {
+        // One horizontal row of black 8, then the data ends.
+        let buf = bits("001 00110101 000101");
+        let (data, warning) = decoder(&buf, 8, 3, Encoder::G4, false).unwrap();
+
+        assert_eq!(data.len(), 24);
+        assert_eq!(&data[..8], [BLACK; 8]);
+        assert!(warning);
+    }
 }
@@ -3,11 +3,21 @@
 type Error = Box<dyn std::error::Error>;
 use crate::error::ImgError;
 use crate::error::ImgErrorKind;
-use bin_rs::io::read_byte;
 
 const MAX_TABLE: usize = 4096;
 const MAX_CBL: usize = 12;
 
+/// A damaged-stream condition that a lenient decoder recovered from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LzwRecovery {
+    /// A code beyond the current table ended the stream; the data decoded
+    /// before it is returned.
+    InvalidCode { code: usize, table_size: usize },
+    /// Codes kept arriving after the table was full without a clear code;
+    /// no further entries were added.
+    TableOverflow,
+}
+
 pub struct Lzwdecode {
     buffer: Vec<u8>,
     cbl: usize,
@@ -24,6 +34,9 @@ pub struct Lzwdecode {
     is_init: bool,
     is_lsb: bool,
     is_tiff: usize,
+    is_lenient: bool,
+    is_end: bool,
+    recoveries: Vec<LzwRecovery>,
 }
 
 impl Lzwdecode {
@@ -65,22 +78,60 @@ impl Lzwdecode {
             is_init: false,
             is_lsb,  // GIF Must True
             is_tiff, // if tiff set 1
+            is_lenient: false,
+            is_end: false,
+            recoveries: Vec::new(),
         }
     }
 
+    /// In lenient mode invalid codes end the stream instead of failing, and
+    /// the conditions are collected in [`Lzwdecode::recoveries`].
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.is_lenient = lenient;
+    }
+
+    /// Returns whether an end code (or, when lenient, an invalid code) ended
+    /// the stream.
+    pub fn is_end(&self) -> bool {
+        self.is_end
+    }
+
+    /// Conditions recovered from so far.
+    pub fn recoveries(&self) -> &[LzwRecovery] {
+        &self.recoveries
+    }
+
+    /// Records an invalid code. Strict decoders fail; lenient decoders end
+    /// the stream.
+    fn invalid_code(&mut self, code: usize) -> Result<(), Error> {
+        let table_size = self.dic.len();
+        if !self.is_lenient {
+            let message = format!(
+                "Over table in LZW.Table size is {},but code is {}",
+                table_size, code
+            );
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::IllegalData,
+                message,
+            )));
+        }
+        self.recoveries
+            .push(LzwRecovery::InvalidCode { code, table_size });
+        self.is_end = true;
+        Ok(())
+    }
+
     // use 32bit
     fn fill_bits(&mut self) {
         self.clear_dic();
         self.last_byte = 0;
         let ptr = self.ptr;
+        // Short streams (tiny frames) are padded with zero bits.
+        let byte = |offset: usize| self.buffer.get(ptr + offset).copied().unwrap_or(0) as u32;
         if self.is_lsb {
-            self.last_byte = read_byte(&self.buffer, ptr) as u32;
-            self.last_byte |= (read_byte(&self.buffer, ptr + 1) as u32) << 8;
-            self.last_byte |= (read_byte(&self.buffer, ptr + 2) as u32) << 16;
+            self.last_byte = byte(0) | (byte(1) << 8) | (byte(2) << 16);
         } else {
-            self.last_byte = (read_byte(&self.buffer, ptr) as u32) << 16;
-            self.last_byte |= (read_byte(&self.buffer, ptr + 1) as u32) << 8;
-            self.last_byte |= read_byte(&self.buffer, ptr + 2) as u32;
+            self.last_byte = (byte(0) << 16) | (byte(1) << 8) | byte(2);
         }
         self.left_bits = 24;
         self.ptr = 3;
@@ -169,6 +220,9 @@ impl Lzwdecode {
         self.prev_code = self.clear; // NULL
 
         loop {
+            if self.is_end {
+                return Ok(data);
+            }
             let res = self.get_bits(); // GIF Lsb only Tiff use Lsb or Msb
             // If data is shotage,it returns values and waits a next buffer.
             let code = if let Ok(code) = res {
@@ -183,65 +237,37 @@ impl Lzwdecode {
                 }
                 self.clear_dic();
             } else if code == self.end {
-                let prev = self.dic.get(self.prev_code).ok_or_else(|| {
-                    Box::new(ImgError::new_const(
-                        ImgErrorKind::IllegalData,
-                        "invalid previous LZW code".to_string(),
-                    )) as Error
-                })?;
-                for p in prev {
+                for p in &self.dic[self.prev_code] {
                     data.push(*p);
                 }
+                self.is_end = true;
+                return Ok(data);
+            } else if code > self.dic.len()
+                || (code == self.dic.len() && self.dic[self.prev_code].is_empty())
+            {
+                // The previous entry is flushed before giving up so that the
+                // caller keeps every pixel decoded so far.
+                for p in &self.dic[self.prev_code] {
+                    data.push(*p);
+                }
+                self.invalid_code(code)?;
                 return Ok(data);
-            } else if code > self.dic.len() {
-                let message = format!(
-                    "Over table in LZW.Table size is {},but code is {}",
-                    self.dic.len(),
-                    code
-                );
-                return Err(Box::new(ImgError::new_const(
-                    ImgErrorKind::IllegalData,
-                    message,
-                )));
             } else {
-                let append_code;
-                if code == self.dic.len() {
-                    append_code = *self
-                        .dic
-                        .get(self.prev_code)
-                        .and_then(|value| value.first())
-                        .ok_or_else(|| {
-                            Box::new(ImgError::new_const(
-                                ImgErrorKind::IllegalData,
-                                "invalid previous LZW code".to_string(),
-                            )) as Error
-                        })?;
+                let append_code = if code == self.dic.len() {
+                    self.dic[self.prev_code][0]
                 } else {
-                    append_code = *self
-                        .dic
-                        .get(code)
-                        .and_then(|value| value.first())
-                        .ok_or_else(|| {
-                            Box::new(ImgError::new_const(
-                                ImgErrorKind::IllegalData,
-                                "invalid LZW code".to_string(),
-                            )) as Error
-                        })?;
-                }
+                    self.dic[code][0]
+                };
                 if self.prev_code != self.end && self.prev_code != self.clear {
-                    let mut table: Vec<u8> = Vec::new();
-                    let prev = self.dic.get(self.prev_code).ok_or_else(|| {
-                        Box::new(ImgError::new_const(
-                            ImgErrorKind::IllegalData,
-                            "invalid previous LZW code".to_string(),
-                        )) as Error
-                    })?;
-                    for p in prev {
-                        data.push(*p);
-                        table.push(*p);
+                    let prev = &self.dic[self.prev_code];
+                    data.extend_from_slice(prev);
+                    if self.dic.len() < self.max_table {
+                        let mut table = prev.clone();
+                        table.push(append_code);
+                        self.dic.push(table);
+                    } else if !self.recoveries.contains(&LzwRecovery::TableOverflow) {
+                        self.recoveries.push(LzwRecovery::TableOverflow);
                     }
-                    table.push(append_code);
-                    self.dic.push(table);
                     // Tiff LZW is increment entry value before next loop.
                     let next = self.dic.len() + self.is_tiff;
                     if next == self.bit_mask as usize + 1
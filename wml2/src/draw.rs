@@ -75,6 +75,22 @@ pub trait DrawCallback: Sync + Send {
         key: &str,
         value: DataMap,
     ) -> Result<Option<CallbackResponse>, Error>;
+    /// Writes unnormalized samples of a high-bit-depth image into a
+    /// rectangle, `channels` values per pixel.
+    ///
+    /// Decoders only call this when asked for them, e.g. the TIFF
+    /// `high_bit_depth` option. Targets that only need RGBA can ignore it.
+    fn draw_samples(
+        &mut self,
+        _start_x: usize,
+        _start_y: usize,
+        _width: usize,
+        _height: usize,
+        _channels: usize,
+        _data: &[f64],
+    ) -> Result<Option<CallbackResponse>, Error> {
+        Ok(None)
+    }
 }
 
 /// Supplies image data to encoders.
@@ -97,6 +113,14 @@ pub trait PickCallback: Sync + Send {
     fn encode_end(&mut self, _: Option<EndOptions>) -> Result<(), Error>;
     /// Returns source metadata, if available.
     fn metadata(&mut self) -> Result<Option<HashMap<String, DataMap>>, Error>;
+    /// Returns the next page for multi-page encoders, or `None` once the
+    /// source has no more pages.
+    ///
+    /// Sources that do not override this are encoded as a single image read
+    /// through [`Self::encode_pick`].
+    fn encode_next_page(&mut self) -> Result<Option<EncodePage>, Error> {
+        Ok(None)
+    }
 }
 
 /// Encoder-specific startup options.
@@ -368,6 +392,14 @@ pub struct AnimationLayer {
     pub control: NextOptions,
 }
 
+/// Unnormalized samples received through [`DrawCallback::draw_samples`].
+pub struct SampleBuffer {
+    /// Values per pixel.
+    pub channels: usize,
+    /// Interleaved samples in row-major order.
+    pub buffer: Vec<f64>,
+}
+
 #[allow(unused)]
 /// Default in-memory RGBA image store used by decoders and encoders.
 pub struct ImageBuffer {
@@ -390,6 +422,8 @@ pub struct ImageBuffer {
     fnverbose: fn(&str) -> Result<Option<CallbackResponse>, Error>,
     /// Arbitrary metadata collected during decode.
     pub metadata: Option<HashMap<String, DataMap>>,
+    /// High-bit-depth samples of the base canvas, if the decoder sent any.
+    pub samples: Option<SampleBuffer>,
 }
 
 fn default_verbose(_: &str) -> Result<Option<CallbackResponse>, Error> {
@@ -422,6 +456,7 @@ impl ImageBuffer {
             first_wait_time: None,
             fnverbose: default_verbose,
             metadata: None,
+            samples: None,
         }
     }
 
@@ -438,6 +473,7 @@ impl ImageBuffer {
             first_wait_time: None,
             fnverbose: default_verbose,
             metadata: None,
+            samples: None,
         }
     }
 
@@ -467,6 +503,7 @@ impl DrawCallback for ImageBuffer {
         let buffersize = checked_rgba_len(width, height, "image")?;
         self.width = width;
         self.height = height;
+        self.samples = None;
         if let Some(option) = option {
             self.background_color = option.background;
             if option.animation {
@@ -688,6 +725,123 @@ impl DrawCallback for ImageBuffer {
 
         Ok(None)
     }
+
+    /// Stores high-bit-depth samples of the base canvas.
+    fn draw_samples(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        data: &[f64],
+    ) -> Result<Option<CallbackResponse>, Error> {
+        if start_x >= self.width || start_y >= self.height || channels == 0 {
+            return Ok(None);
+        }
+        let len = self
+            .width
+            .checked_mul(self.height)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .ok_or_else(|| {
+                Box::new(ImgError::new_const(
+                    ImgErrorKind::InvalidParameter,
+                    "sample buffer size overflow".to_string(),
+                )) as Error
+            })?;
+        if self
+            .samples
+            .as_ref()
+            .is_none_or(|samples| samples.channels != channels)
+        {
+            self.samples = Some(SampleBuffer {
+                channels,
+                buffer: vec![0.0; len],
+            });
+        }
+        let Some(samples) = self.samples.as_mut() else {
+            return Ok(None);
+        };
+        let w = width.min(self.width - start_x);
+        let h = height.min(self.height - start_y);
+        for y in 0..h {
+            let src = y * width * channels;
+            let dest = ((start_y + y) * self.width + start_x) * channels;
+            let row = data.get(src..src + w * channels).ok_or_else(|| {
+                Box::new(ImgError::new_const(
+                    ImgErrorKind::OutboundIndex,
+                    "decoder buffer in draw_samples".to_string(),
+                )) as Error
+            })?;
+            samples.buffer[dest..dest + w * channels].copy_from_slice(row);
+        }
+        Ok(None)
+    }
+}
+
+/// Copies the `(start_x, start_y, width, height)` rectangle out of an RGBA
+/// buffer, filling the part outside the image with zeros.
+fn pick_rgba(
+    buffer: &[u8],
+    image_width: usize,
+    image_height: usize,
+    (start_x, start_y, width, height): (usize, usize, usize, usize),
+) -> Result<Option<Vec<u8>>, Error> {
+    let buffersize = checked_rgba_len(width, height, "pick")?;
+    let mut data = Vec::with_capacity(buffersize);
+
+    if start_x >= image_width || start_y >= image_height {
+        return Ok(None);
+    }
+    let requested_end_x = start_x.checked_add(width).ok_or_else(|| {
+        Box::new(ImgError::new_const(
+            ImgErrorKind::OutboundIndex,
+            "pick rectangle x overflow".to_string(),
+        )) as Error
+    })?;
+    let requested_end_y = start_y.checked_add(height).ok_or_else(|| {
+        Box::new(ImgError::new_const(
+            ImgErrorKind::OutboundIndex,
+            "pick rectangle y overflow".to_string(),
+        )) as Error
+    })?;
+    let w = image_width.min(requested_end_x) - start_x;
+    let h = image_height.min(requested_end_y) - start_y;
+
+    for y in 0..h {
+        let scanline_src = (start_y + y) * image_width * 4;
+        for x in 0..w {
+            let offset_src = scanline_src + (start_x + x) * 4;
+            if offset_src + 3 >= buffer.len() {
+                return Err(Box::new(ImgError::new_const(
+                    ImgErrorKind::OutboundIndex,
+                    "Image buffer in pick".to_string(),
+                )));
+            }
+            data.push(buffer[offset_src]);
+            data.push(buffer[offset_src + 1]);
+            data.push(buffer[offset_src + 2]);
+            data.push(buffer[offset_src + 3]);
+        }
+        for _ in w..width {
+            // 0 fill
+            data.push(0x00);
+            data.push(0x00);
+            data.push(0x00);
+            data.push(0x00);
+        }
+    }
+    for _ in h..height {
+        // 0 fill
+        for _ in 0..width {
+            data.push(0x00);
+            data.push(0x00);
+            data.push(0x00);
+            data.push(0x00);
+        }
+    }
+
+    Ok(Some(data))
 }
 
 impl PickCallback for ImageBuffer {
@@ -728,73 +882,142 @@ impl PickCallback for ImageBuffer {
         height: usize,
         _: Option<PickOptions>,
     ) -> Result<Option<Vec<u8>>, Error> {
-        if self.buffer.is_none() {
-            return Err(Box::new(ImgError::new_const(
-                ImgErrorKind::NotInitializedImageBuffer,
-                "in pick".to_string(),
-            )));
-        }
-        let buffersize = checked_rgba_len(width, height, "pick")?;
-        let mut data = Vec::with_capacity(buffersize);
         let buffer = self.buffer.as_ref().ok_or_else(|| {
             Box::new(ImgError::new_const(
                 ImgErrorKind::NotInitializedImageBuffer,
                 "in pick".to_string(),
             )) as Error
         })?;
+        pick_rgba(
+            buffer,
+            self.width,
+            self.height,
+            (start_x, start_y, width, height),
+        )
+    }
 
-        if start_x >= self.width || start_y >= self.height {
-            return Ok(None);
+    /// Finalizes encoding.
+    fn encode_end(&mut self, _: Option<EndOptions>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Returns stored metadata.
+    fn metadata(&mut self) -> Result<Option<HashMap<String, DataMap>>, Error> {
+        if let Some(hashmap) = &self.metadata {
+            Ok(Some(hashmap.clone()))
+        } else {
+            Ok(None)
         }
-        let requested_end_x = start_x.checked_add(width).ok_or_else(|| {
-            Box::new(ImgError::new_const(
-                ImgErrorKind::OutboundIndex,
-                "pick rectangle x overflow".to_string(),
-            )) as Error
-        })?;
-        let requested_end_y = start_y.checked_add(height).ok_or_else(|| {
-            Box::new(ImgError::new_const(
-                ImgErrorKind::OutboundIndex,
-                "pick rectangle y overflow".to_string(),
-            )) as Error
-        })?;
-        let w = self.width.min(requested_end_x) - start_x;
-        let h = self.height.min(requested_end_y) - start_y;
+    }
+}
 
-        for y in 0..h {
-            let scanline_src = (start_y + y) * self.width * 4;
-            for x in 0..w {
-                let offset_src = scanline_src + (start_x + x) * 4;
-                if offset_src + 3 >= buffer.len() {
-                    return Err(Box::new(ImgError::new_const(
-                        ImgErrorKind::OutboundIndex,
-                        "Image buffer in pick".to_string(),
-                    )));
-                }
-                data.push(buffer[offset_src]);
-                data.push(buffer[offset_src + 1]);
-                data.push(buffer[offset_src + 2]);
-                data.push(buffer[offset_src + 3]);
-            }
-            for _ in w..width {
-                // 0 fill
-                data.push(0x00);
-                data.push(0x00);
-                data.push(0x00);
-                data.push(0x00);
-            }
+/// One page handed to multi-page encoders by
+/// [`PickCallback::encode_next_page`].
+#[derive(Debug, Clone)]
+pub struct EncodePage {
+    /// Page width in pixels.
+    pub width: usize,
+    /// Page height in pixels.
+    pub height: usize,
+    /// RGBA pixels, `width * height * 4` bytes.
+    pub buffer: Vec<u8>,
+    /// Encoder options for this page only. Keys given here override
+    /// [`EncodeOptions::options`].
+    pub options: Option<HashMap<String, DataMap>>,
+}
+
+impl EncodePage {
+    /// Creates a page without page-specific options.
+    pub fn new(width: usize, height: usize, buffer: Vec<u8>) -> Self {
+        Self {
+            width,
+            height,
+            buffer,
+            options: None,
         }
-        for _ in h..height {
-            // 0 fill
-            for _ in 0..width {
-                data.push(0x00);
-                data.push(0x00);
-                data.push(0x00);
-                data.push(0x00);
-            }
+    }
+
+    /// Sets a page-specific encoder option.
+    pub fn with_option(mut self, key: &str, value: DataMap) -> Self {
+        self.options
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value);
+        self
+    }
+}
+
+/// [`PickCallback`] that feeds pages from an iterator one by one.
+///
+/// Multi-page encoders such as TIFF pull every page through
+/// [`PickCallback::encode_next_page`], so pages can be produced lazily by a
+/// scanner or document pipeline. Single-image encoders see the first page.
+pub struct PageSource<I> {
+    pages: I,
+    first: Option<EncodePage>,
+    started: bool,
+    metadata: Option<HashMap<String, DataMap>>,
+}
+
+impl<I: Iterator<Item = EncodePage> + Send + Sync> PageSource<I> {
+    /// Wraps a page iterator.
+    pub fn new(pages: I) -> Self {
+        Self {
+            pages,
+            first: None,
+            started: false,
+            metadata: None,
         }
+    }
 
-        Ok(Some(data))
+    /// Sets source metadata, such as `EXIF` or `ICC Profile`, that encoders
+    /// write with the first page.
+    pub fn with_metadata(mut self, metadata: HashMap<String, DataMap>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    fn peek_first(&mut self) -> Option<&EncodePage> {
+        if !self.started {
+            self.started = true;
+            self.first = self.pages.next();
+        }
+        self.first.as_ref()
+    }
+}
+
+impl<I: Iterator<Item = EncodePage> + Send + Sync> PickCallback for PageSource<I> {
+    /// Reports the first page as the image profile.
+    fn encode_start(&mut self, _: Option<EncoderOptions>) -> Result<Option<ImageProfiles>, Error> {
+        let (width, height) = self
+            .peek_first()
+            .map(|page| (page.width, page.height))
+            .unwrap_or((0, 0));
+        Ok(Some(ImageProfiles {
+            width,
+            height,
+            background: None,
+            metadata: self.metadata.clone(),
+        }))
+    }
+
+    /// Reads an RGBA rectangle from the first page.
+    fn encode_pick(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        _: Option<PickOptions>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let Some(page) = self.peek_first() else {
+            return Ok(None);
+        };
+        pick_rgba(
+            &page.buffer,
+            page.width,
+            page.height,
+            (start_x, start_y, width, height),
+        )
     }
 
     /// Finalizes encoding.
@@ -802,22 +1025,54 @@ impl PickCallback for ImageBuffer {
         Ok(())
     }
 
-    /// Returns stored metadata.
+    /// Returns the metadata given to [`PageSource::with_metadata`].
     fn metadata(&mut self) -> Result<Option<HashMap<String, DataMap>>, Error> {
-        if let Some(hashmap) = &self.metadata {
-            Ok(Some(hashmap.clone()))
-        } else {
-            Ok(None)
+        Ok(self.metadata.clone())
+    }
+
+    /// Hands out the pages in iterator order.
+    fn encode_next_page(&mut self) -> Result<Option<EncodePage>, Error> {
+        if self.started
+            && let Some(page) = self.first.take()
+        {
+            return Ok(Some(page));
         }
+        self.started = true;
+        Ok(self.pages.next())
     }
 }
 
 /// Decoder configuration.
+///
+/// Build it with [`DecodeOptions::new`] so that new settings can be added
+/// without breaking callers.
+#[non_exhaustive]
 pub struct DecodeOptions<'a> {
     /// Enables format-specific verbose output when non-zero.
     pub debug_flag: usize,
     /// Destination callback implementation.
     pub drawer: &'a mut dyn DrawCallback,
+    /// Decoder-specific options such as PNG `color_correction`.
+    pub options: Option<HashMap<String, DataMap>>,
+}
+
+impl<'a> DecodeOptions<'a> {
+    /// Creates decoder options that draw into `drawer` with no extra settings.
+    pub fn new(drawer: &'a mut dyn DrawCallback) -> Self {
+        Self {
+            debug_flag: 0,
+            drawer,
+            options: None,
+        }
+    }
+
+    /// Sets a decoder-specific option.
+    pub fn with_option(mut self, key: &str, value: DataMap) -> Self {
+        self.options
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value);
+        self
+    }
 }
 
 /// Encoder configuration.
@@ -871,6 +1126,7 @@ pub fn image_from_file(filename: String) -> Result<ImageBuffer, Error> {
     let mut option = DecodeOptions {
         debug_flag: 0x00,
         drawer: &mut image,
+        options: None,
     };
     let _ = image_reader(reader, &mut option)?;
     Ok(image)
@@ -994,6 +1250,7 @@ pub fn image_load(buffer: &[u8]) -> Result<ImageBuffer, Error> {
     let mut option = DecodeOptions {
         debug_flag: 0,
         drawer: &mut ib,
+        options: None,
     };
     let mut reader = BytesReader::new(buffer);
 
@@ -1012,10 +1269,7 @@ pub fn image_load(buffer: &[u8]) -> Result<ImageBuffer, Error> {
 /// let png = image_to(&mut source, ImageFormat::Png, None).unwrap();
 ///
 /// let mut target = ImageBuffer::new();
-/// let mut options = DecodeOptions {
-///     debug_flag: 0,
-///     drawer: &mut target,
-/// };
+/// let mut options = DecodeOptions::new(&mut target);
 /// image_loader(&png, &mut options).unwrap();
 /// assert_eq!(target.width, 1);
 /// assert_eq!(target.height, 1);
@@ -1042,10 +1296,7 @@ pub fn image_loader(
 /// let png = image_to(&mut source, ImageFormat::Png, None).unwrap();
 ///
 /// let mut target = ImageBuffer::new();
-/// let mut options = DecodeOptions {
-///     debug_flag: 0,
-///     drawer: &mut target,
-/// };
+/// let mut options = DecodeOptions::new(&mut target);
 /// image_reader(Cursor::new(png), &mut options).unwrap();
 /// assert_eq!(target.width, 1);
 /// assert_eq!(target.height, 1);
@@ -1089,8 +1340,368 @@ pub fn image_writer<W: Write>(
     Ok(None)
 }
 
+/// Reads the `page` (or `frame`) decode option: the zero-based TIFF page,
+/// ICO entry or animation frame to decode.
+pub(crate) fn page_option(
+    options: Option<&HashMap<String, DataMap>>,
+) -> Result<Option<usize>, Error> {
+    index_option(options, &["page", "frame"])
+}
+
+/// Reads the first of `keys` as a non-negative index.
+pub(crate) fn index_option(
+    options: Option<&HashMap<String, DataMap>>,
+    keys: &[&str],
+) -> Result<Option<usize>, Error> {
+    let Some(value) = options.and_then(|map| keys.iter().find_map(|key| map.get(*key))) else {
+        return Ok(None);
+    };
+    let index = match value {
+        DataMap::UInt(index) => usize::try_from(*index).ok(),
+        DataMap::SInt(index) => usize::try_from(*index).ok(),
+        DataMap::Ascii(index) => index.trim().parse().ok(),
+        _ => None,
+    };
+    index.map(Some).ok_or_else(|| {
+        Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            format!("{} must be a non-negative integer", keys[0]),
+        )) as Error
+    })
+}
+
+/// Returns `options` without the page selector, for nested decoders.
+pub(crate) fn options_without_page(
+    options: Option<&HashMap<String, DataMap>>,
+) -> Option<HashMap<String, DataMap>> {
+    options.map(|map| {
+        map.iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "page" | "frame"))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    })
+}
+
+pub(crate) fn page_out_of_range(page: usize, count: usize) -> Error {
+    Box::new(ImgError::new_const(
+        ImgErrorKind::OutboundIndex,
+        format!("page {page} is out of range ({count} pages)"),
+    ))
+}
+
+/// Keeps animation frames up to `last` and aborts the decoder after it.
+struct FrameCollector {
+    image: ImageBuffer,
+    last: usize,
+    done: bool,
+}
+
+impl DrawCallback for FrameCollector {
+    fn init(
+        &mut self,
+        width: usize,
+        height: usize,
+        option: Option<InitOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.image.init(width, height, option)
+    }
+
+    fn draw(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        data: &[u8],
+        option: Option<DrawOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+        self.image
+            .draw(start_x, start_y, width, height, data, option)
+    }
+
+    fn terminate(
+        &mut self,
+        _: Option<TerminateOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        Ok(None)
+    }
+
+    fn next(&mut self, next: Option<NextOptions>) -> Result<Option<CallbackResponse>, Error> {
+        let frames = self
+            .image
+            .animation
+            .as_ref()
+            .map_or(0, |frames| frames.len());
+        if self.done || frames > self.last {
+            self.done = true;
+            return Ok(Some(CallbackResponse::abort()));
+        }
+        self.image.next(next)
+    }
+
+    fn verbose(
+        &mut self,
+        verbose: &str,
+        option: Option<VerboseOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.image.verbose(verbose, option)
+    }
+
+    fn set_metadata(
+        &mut self,
+        key: &str,
+        value: DataMap,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.image.set_metadata(key, value)
+    }
+
+    fn draw_samples(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        data: &[f64],
+    ) -> Result<Option<CallbackResponse>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+        self.image
+            .draw_samples(start_x, start_y, width, height, channels, data)
+    }
+}
+
+/// Counts animation frames without keeping any pixels.
+struct FrameCounter {
+    frames: usize,
+}
+
+impl DrawCallback for FrameCounter {
+    fn init(
+        &mut self,
+        _: usize,
+        _: usize,
+        _: Option<InitOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        Ok(None)
+    }
+
+    fn draw(
+        &mut self,
+        _: usize,
+        _: usize,
+        _: usize,
+        _: usize,
+        _: &[u8],
+        _: Option<DrawOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        Ok(None)
+    }
+
+    fn terminate(
+        &mut self,
+        _: Option<TerminateOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        Ok(None)
+    }
+
+    fn next(&mut self, _: Option<NextOptions>) -> Result<Option<CallbackResponse>, Error> {
+        self.frames += 1;
+        Ok(Some(CallbackResponse::cont()))
+    }
+
+    fn verbose(
+        &mut self,
+        _: &str,
+        _: Option<VerboseOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        Ok(None)
+    }
+
+    fn set_metadata(&mut self, _: &str, _: DataMap) -> Result<Option<CallbackResponse>, Error> {
+        Ok(None)
+    }
+}
+
+fn source_over(dst: &mut [u8], src: &[u8]) {
+    let src_alpha = src[3] as u32;
+    if src_alpha == 0 {
+        return;
+    }
+    if src_alpha == 255 {
+        dst.copy_from_slice(src);
+        return;
+    }
+
+    let dst_alpha = dst[3] as u32;
+    let out_alpha = src_alpha + ((dst_alpha * (255 - src_alpha) + 127) / 255);
+    if out_alpha == 0 {
+        dst.copy_from_slice(&[0, 0, 0, 0]);
+        return;
+    }
+
+    for channel in 0..3 {
+        let src_premul = src[channel] as u32 * src_alpha;
+        let dst_premul = dst[channel] as u32 * dst_alpha;
+        let out_premul = src_premul + ((dst_premul * (255 - src_alpha) + 127) / 255);
+        dst[channel] = ((out_premul * 255 + (out_alpha / 2)) / out_alpha) as u8;
+    }
+    dst[3] = out_alpha as u8;
+}
+
+/// Calls `f` with the canvas and layer byte offsets of every pixel of
+/// `layer` that lies on the canvas.
+fn for_layer_pixels(
+    width: usize,
+    height: usize,
+    layer: &AnimationLayer,
+    mut f: impl FnMut(usize, usize),
+) {
+    for y in 0..layer.height {
+        let canvas_y = layer.start_y as i64 + y as i64;
+        if canvas_y < 0 || canvas_y >= height as i64 {
+            continue;
+        }
+        for x in 0..layer.width {
+            let canvas_x = layer.start_x as i64 + x as i64;
+            if canvas_x < 0 || canvas_x >= width as i64 {
+                continue;
+            }
+            f(
+                (canvas_y as usize * width + canvas_x as usize) * 4,
+                (y * layer.width + x) * 4,
+            );
+        }
+    }
+}
+
+/// Composes animation layers `0..=last`, applying the dispose operation of
+/// every layer before `last`.
+fn compose_frame(image: &ImageBuffer, layers: &[AnimationLayer], last: usize) -> Vec<u8> {
+    let (width, height) = (image.width, image.height);
+    let background = image.background_color.as_ref().map_or([0; 4], |color| {
+        [color.red, color.green, color.blue, color.alpha]
+    });
+    let mut canvas = background.repeat(width * height);
+    for (index, layer) in layers.iter().enumerate().take(last + 1) {
+        let dispose = &layer.control.dispose_option;
+        let previous = matches!(dispose, Some(NextDispose::Previous)).then(|| canvas.clone());
+        let blend = matches!(layer.control.blend, Some(NextBlend::Source));
+        for_layer_pixels(width, height, layer, |dst, src| {
+            let (Some(dst), Some(src)) =
+                (canvas.get_mut(dst..dst + 4), layer.buffer.get(src..src + 4))
+            else {
+                return;
+            };
+            if blend {
+                source_over(dst, src);
+            } else {
+                dst.copy_from_slice(src);
+            }
+        });
+        if index == last {
+            break;
+        }
+        match (dispose, previous) {
+            (Some(NextDispose::Background), _) => {
+                for_layer_pixels(width, height, layer, |dst, _| {
+                    canvas[dst..dst + 4].copy_from_slice(&background);
+                });
+            }
+            (Some(NextDispose::Previous), Some(previous)) => canvas = previous,
+            _ => {}
+        }
+    }
+    canvas
+}
+
+/// Decodes animation frame `page` as a still image. Earlier frames are
+/// decoded for composition, and the decoder stops after the selected one.
+fn decode_frame<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    page: usize,
+) -> Result<Option<ImgWarnings>, Error> {
+    let mut collector = FrameCollector {
+        image: ImageBuffer::new(),
+        last: page,
+        done: false,
+    };
+    let mut frame_option = DecodeOptions {
+        debug_flag: option.debug_flag,
+        drawer: &mut collector,
+        options: options_without_page(option.options.as_ref()),
+    };
+    let warnings = image_decoder(reader, &mut frame_option)?;
+    let image = collector.image;
+
+    let canvas = match &image.animation {
+        Some(layers) if page < layers.len() => compose_frame(&image, layers, page),
+        Some(layers) if !layers.is_empty() => return Err(page_out_of_range(page, layers.len())),
+        _ if page == 0 => image.buffer.clone().ok_or_else(|| {
+            Box::new(ImgError::new_const(
+                ImgErrorKind::NotInitializedImageBuffer,
+                "decoder did not produce an image".to_string(),
+            )) as Error
+        })?,
+        _ => return Err(page_out_of_range(page, 1)),
+    };
+    option.drawer.init(
+        image.width,
+        image.height,
+        Some(InitOptions {
+            loop_count: 1,
+            background: image.background_color.clone(),
+            animation: false,
+        }),
+    )?;
+    option
+        .drawer
+        .draw(0, 0, image.width, image.height, &canvas, None)?;
+    if let Some(metadata) = &image.metadata {
+        for (key, value) in metadata {
+            option.drawer.set_metadata(key, value.clone())?;
+        }
+    }
+    option.drawer.terminate(None)?;
+    Ok(warnings)
+}
+
+/// Returns the number of pages in an encoded image: TIFF pages, ICO
+/// entries, or animation frames. Still images have one page.
+///
+/// TIFF and ICO only read their directories. Animated formats are decoded
+/// without keeping any pixels.
+pub fn image_page_count(buffer: &[u8]) -> Result<usize, Error> {
+    let mut reader = BytesReader::new(buffer);
+    match format_check(buffer) {
+        #[cfg(feature = "tiff")]
+        ImageFormat::Tiff => crate::tiff::decoder::page_count(&mut reader),
+        #[cfg(feature = "ico")]
+        ImageFormat::Ico => crate::ico::decoder::page_count(&mut reader),
+        _ => {
+            let mut counter = FrameCounter { frames: 0 };
+            let mut option = DecodeOptions {
+                debug_flag: 0,
+                drawer: &mut counter,
+                options: None,
+            };
+            image_decoder(&mut reader, &mut option)?;
+            Ok(counter.frames.max(1))
+        }
+    }
+}
+
 /// Detects the input format and dispatches to the matching decoder.
 ///
+/// The `page` (or `frame`) option selects one zero-based TIFF page, ICO
+/// entry or animation frame. Animation frames are composed onto the canvas
+/// and delivered as a still image.
+///
 /// # Examples
 /// ```rust
 /// use bin_rs::reader::BytesReader;
@@ -1102,10 +1713,7 @@ pub fn image_writer<W: Write>(
 ///
 /// let mut reader = BytesReader::new(&png);
 /// let mut target = ImageBuffer::new();
-/// let mut options = DecodeOptions {
-///     debug_flag: 0,
-///     drawer: &mut target,
-/// };
+/// let mut options = DecodeOptions::new(&mut target);
 /// image_decoder(&mut reader, &mut options).unwrap();
 /// assert_eq!(target.width, 1);
 /// assert_eq!(target.height, 1);
@@ -1127,6 +1735,12 @@ pub fn image_decoder<B: BinaryReader>(
     let format = format_check(&buffer);
 
     use crate::util::ImageFormat::*;
+    // TIFF and ICO pick the page from their own directories.
+    if let Some(page) = page_option(option.options.as_ref())?
+        && !matches!(format, Tiff | Ico)
+    {
+        return decode_frame(reader, option, page);
+    }
     match format {
         #[cfg(feature = "jpeg")]
         Jpeg => {
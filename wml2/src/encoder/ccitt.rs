@@ -0,0 +1,568 @@
+//! CCITT fax encoder for bilevel images.
+//!
+//! Supports Modified Huffman, T.4 one- and two-dimensional (Group 3) and
+//! T.6 (Group 4) coding. Input rows are packed one bit per pixel, most
+//! significant bit first, with 1 as black, as in 1-bit WhiteIsZero TIFF.
+//! Each input row starts on a byte boundary.
+
+type Error = Box<dyn std::error::Error>;
+
+use crate::decoder::ccitt::{HuffmanTree, black_tree, white_tree};
+use crate::error::{ImgError, ImgErrorKind};
+
+/// CCITT coding scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Modified Huffman: one-dimensional rows without EOLs, each starting
+    /// on a byte boundary (TIFF Compression 2).
+    HuffmanRle,
+    /// T.4 one-dimensional coding (TIFF Compression 3).
+    G3OneD,
+    /// T.4 two-dimensional coding. Every `k`th row is coded
+    /// one-dimensionally to stop errors spreading (TIFF Compression 3 with
+    /// T4Options bit 0).
+    G3TwoD { k: usize },
+    /// T.6 two-dimensional coding (TIFF Compression 4).
+    G4,
+}
+
+/// Settings for [`encode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CcittOptions {
+    /// Coding scheme.
+    pub encoding: Encoding,
+    /// Writes an EOL before each Group 3 row. Two-dimensional coding always
+    /// writes them, since the 1D/2D tag bit follows the EOL.
+    pub eol: bool,
+    /// Inserts fill bits so every EOL ends on a byte boundary (T4Options
+    /// bit 2).
+    pub byte_align: bool,
+    /// Ends Group 3 data with RTC (six EOLs) and Group 4 data with EOFB.
+    pub end_marker: bool,
+    /// Writes bits least significant first (FillOrder 2).
+    pub is_lsb: bool,
+}
+
+impl Default for CcittOptions {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::G4,
+            eol: true,
+            byte_align: false,
+            end_marker: true,
+            is_lsb: false,
+        }
+    }
+}
+
+impl CcittOptions {
+    /// Default settings for `encoding`.
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            ..Self::default()
+        }
+    }
+}
+
+/// `(code, bits)` of the Modified Huffman run codes of one color, taken from
+/// the decoder's lookup tables.
+struct RunCodes {
+    /// Terminating codes for runs of 0 to 63.
+    terminating: [(u16, u8); 64],
+    /// Make-up codes for runs of 64 to 2560, in steps of 64.
+    makeup: [(u16, u8); 40],
+}
+
+impl RunCodes {
+    /// Inverts `tree`. An entry of `bits` bits at index `pos` of a table
+    /// looked up with `width` bits has the code `pos >> (width - bits)`.
+    fn new(tree: &HuffmanTree) -> Self {
+        let mut codes = Self {
+            terminating: [(0, 0); 64],
+            makeup: [(0, 0); 40],
+        };
+        let tables = [
+            (&tree.matrix, tree.working_bits),
+            (&tree.append, tree.max_bits),
+        ];
+        for (table, width) in tables {
+            for (pos, &(bits, run)) in table.iter().enumerate() {
+                if bits <= 0 || run < 0 {
+                    continue;
+                }
+                let code = ((pos >> (width - bits as usize)) as u16, bits as u8);
+                match run {
+                    0..64 => codes.terminating[run as usize] = code,
+                    64..=2560 if run % 64 == 0 => codes.makeup[run as usize / 64 - 1] = code,
+                    _ => {}
+                }
+            }
+        }
+        codes
+    }
+}
+
+const EOL: (u16, u8) = (0b000000000001, 12);
+const PASS: (u16, u8) = (0b0001, 4);
+const HORIZONTAL: (u16, u8) = (0b001, 3);
+/// Vertical mode codes for `a1 - b1` of -3 to 3.
+const VERTICAL: [(u16, u8); 7] = [
+    (0b0000010, 7),
+    (0b000010, 6),
+    (0b010, 3),
+    (0b1, 1),
+    (0b011, 3),
+    (0b000011, 6),
+    (0b0000011, 7),
+];
+
+/// Most significant bit first bit writer.
+struct BitWriter {
+    data: Vec<u8>,
+    buffer: u32,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            data: vec![],
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn put(&mut self, (code, bits): (u16, u8)) {
+        self.buffer = (self.buffer << bits) | code as u32;
+        self.bits += bits as usize;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.data.push((self.buffer >> self.bits) as u8);
+        }
+    }
+
+    /// Pads with zero bits to the next byte boundary.
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.put((0, (8 - self.bits) as u8));
+        }
+    }
+
+    /// Writes an EOL, first padding with fill bits when it has to end on a
+    /// byte boundary.
+    fn put_eol(&mut self, byte_align: bool) {
+        let fill = (8 - (self.bits + EOL.1 as usize) % 8) % 8;
+        if byte_align && fill > 0 {
+            self.put((0, fill as u8));
+        }
+        self.put(EOL);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.data
+    }
+}
+
+/// Writes a run as make-up codes followed by a terminating code. `codes`
+/// holds the white codes, then the black codes.
+fn put_run(writer: &mut BitWriter, codes: &[RunCodes; 2], mut run: usize, black: bool) {
+    let codes = &codes[usize::from(black)];
+    while run >= 2560 {
+        writer.put(codes.makeup[39]);
+        run -= 2560;
+    }
+    if run >= 64 {
+        writer.put(codes.makeup[run / 64 - 1]);
+        run %= 64;
+    }
+    writer.put(codes.terminating[run]);
+}
+
+/// A packed bilevel row. Pixels beyond the width read as white.
+struct Row<'a> {
+    data: &'a [u8],
+    width: usize,
+}
+
+impl Row<'_> {
+    fn pixel(&self, x: usize) -> bool {
+        x < self.width && self.data[x / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    /// First position at or after `start` whose color is not `color`.
+    fn find_change(&self, start: usize, color: bool) -> usize {
+        (start..self.width)
+            .find(|&x| self.pixel(x) != color)
+            .unwrap_or(self.width)
+    }
+}
+
+/// Codes a row as alternating white and black runs.
+fn encode_1d_row(writer: &mut BitWriter, codes: &[RunCodes; 2], row: &Row<'_>) {
+    let mut x = 0;
+    let mut black = false;
+    while x < row.width {
+        let end = row.find_change(x, black);
+        put_run(writer, codes, end - x, black);
+        x = end;
+        black = !black;
+    }
+    if row.width == 0 {
+        put_run(writer, codes, 0, false);
+    }
+}
+
+/// Codes a row against the reference row with pass, vertical and horizontal
+/// modes (T.4 section 4.2 and T.6).
+fn encode_2d_row(
+    writer: &mut BitWriter,
+    codes: &[RunCodes; 2],
+    row: &Row<'_>,
+    reference: &Row<'_>,
+) {
+    let width = row.width;
+    let mut a0 = 0;
+    let mut a1 = if row.pixel(0) {
+        0
+    } else {
+        row.find_change(0, false)
+    };
+    let mut b1 = if reference.pixel(0) {
+        0
+    } else {
+        reference.find_change(0, false)
+    };
+    loop {
+        let b2 = if b1 >= width {
+            width
+        } else {
+            reference.find_change(b1, reference.pixel(b1))
+        };
+        if b2 < a1 {
+            writer.put(PASS);
+            a0 = b2;
+        } else if a1.abs_diff(b1) <= 3 {
+            writer.put(VERTICAL[(a1 as isize - b1 as isize + 3) as usize]);
+            a0 = a1;
+        } else {
+            let a2 = if a1 >= width {
+                width
+            } else {
+                row.find_change(a1, row.pixel(a1))
+            };
+            // The imaginary pixel before the row is white.
+            let black = a0 + a1 != 0 && row.pixel(a0);
+            writer.put(HORIZONTAL);
+            put_run(writer, codes, a1 - a0, black);
+            put_run(writer, codes, a2 - a1, !black);
+            a0 = a2;
+        }
+        if a0 >= width {
+            break;
+        }
+        let color = row.pixel(a0);
+        a1 = row.find_change(a0, color);
+        b1 = reference.find_change(a0, !color);
+        b1 = reference.find_change(b1, color);
+    }
+}
+
+/// Encodes `height` packed rows of `width` pixels.
+pub fn encode(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    options: &CcittOptions,
+) -> Result<Vec<u8>, Error> {
+    let row_len = width.div_ceil(8);
+    if data.len() < row_len * height {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "CCITT bilevel buffer is too short".to_string(),
+        )));
+    }
+    if options.encoding == (Encoding::G3TwoD { k: 0 }) {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "CCITT 2D coding needs k of at least 1".to_string(),
+        )));
+    }
+    let white = vec![0; row_len];
+    let mut reference = Row {
+        data: &white,
+        width,
+    };
+    let codes = [RunCodes::new(&white_tree()), RunCodes::new(&black_tree())];
+    let mut writer = BitWriter::new();
+    for y in 0..height {
+        let row = Row {
+            data: &data[y * row_len..(y + 1) * row_len],
+            width,
+        };
+        match options.encoding {
+            Encoding::HuffmanRle => {
+                encode_1d_row(&mut writer, &codes, &row);
+                writer.align();
+            }
+            Encoding::G3OneD => {
+                if options.eol {
+                    writer.put_eol(options.byte_align);
+                }
+                encode_1d_row(&mut writer, &codes, &row);
+            }
+            Encoding::G3TwoD { k } => {
+                writer.put_eol(options.byte_align);
+                let one_dimensional = y % k == 0;
+                writer.put((u16::from(one_dimensional), 1));
+                if one_dimensional {
+                    encode_1d_row(&mut writer, &codes, &row);
+                } else {
+                    encode_2d_row(&mut writer, &codes, &row, &reference);
+                }
+            }
+            Encoding::G4 => encode_2d_row(&mut writer, &codes, &row, &reference),
+        }
+        reference = row;
+    }
+
+    if options.end_marker {
+        match options.encoding {
+            Encoding::HuffmanRle => {}
+            Encoding::G3OneD => {
+                for _ in 0..6 {
+                    writer.put_eol(options.byte_align);
+                }
+            }
+            Encoding::G3TwoD { .. } => {
+                for _ in 0..6 {
+                    writer.put_eol(options.byte_align);
+                    writer.put((1, 1));
+                }
+            }
+            Encoding::G4 => {
+                // EOFB
+                writer.put(EOL);
+                writer.put(EOL);
+            }
+        }
+    }
+
+    let mut encoded = writer.finish();
+    if options.is_lsb {
+        for byte in &mut encoded {
+            *byte = byte.reverse_bits();
+        }
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CcittOptions, Encoding, RunCodes, encode};
+    use crate::decoder::ccitt::{Encoder, black_tree, decoder, white_tree};
+
+    #[test]
+    fn run_codes_cover_every_run_length() {
+        for tree in [white_tree(), black_tree()] {
+            let codes = RunCodes::new(&tree);
+            assert!(codes.terminating.iter().all(|&(_, bits)| bits > 0));
+            assert!(codes.makeup.iter().all(|&(_, bits)| bits > 0));
+        }
+        // White run 0 is 00110101, black make-up 2560 is 000000011111.
+        assert_eq!(RunCodes::new(&white_tree()).terminating[0], (0b00110101, 8));
+        assert_eq!(
+            RunCodes::new(&black_tree()).makeup[39],
+            (0b000000011111, 12)
+        );
+    }
+
+    /// Rows with runs of every length class: terminating, make-up, and
+    /// extended make-up codes.
+    fn patterned_rows(width: usize, height: usize) -> Vec<u8> {
+        let row_len = width.div_ceil(8);
+        let mut data = vec![0; row_len * height];
+        for y in 0..height {
+            let mut x = 0;
+            let mut black = y % 2 == 1;
+            let mut run = 1 + y * 7;
+            while x < width {
+                for pixel in x..(x + run).min(width) {
+                    if black {
+                        data[y * row_len + pixel / 8] |= 0x80 >> (pixel % 8);
+                    }
+                }
+                x += run;
+                black = !black;
+                run = (run * 5 + y) % 2700 + 1;
+            }
+        }
+        data
+    }
+
+    fn unpack(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let row_len = width.div_ceil(8);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let black = data[y * row_len + x / 8] & (0x80 >> (x % 8)) != 0;
+                pixels.push(if black { 0xff } else { 0 });
+            }
+        }
+        pixels
+    }
+
+    fn roundtrip(width: usize, height: usize, options: CcittOptions, decoding: Encoder) {
+        let data = patterned_rows(width, height);
+        let encoded = encode(&data, width, height, &options).unwrap();
+        let (decoded, warning) =
+            decoder(&encoded, width, height, decoding, options.is_lsb).unwrap();
+        assert!(!warning, "{options:?}");
+        assert_eq!(decoded, unpack(&data, width, height), "{options:?}");
+    }
+
+    #[test]
+    fn noisy_rows_roundtrip_against_decoder() {
+        let mut seed = 12345_u32;
+        for width in [1_usize, 2, 7, 8, 9, 31, 64, 65, 200, 1729] {
+            for density in [2, 5, 17, 97] {
+                let height = 20;
+                let row_len = width.div_ceil(8);
+                let mut data = vec![0; row_len * height];
+                let mut black = false;
+                for y in 0..height {
+                    for x in 0..width {
+                        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                        if (seed >> 16) % density == 0 {
+                            black = !black;
+                        }
+                        if black {
+                            data[y * row_len + x / 8] |= 0x80 >> (x % 8);
+                        }
+                    }
+                }
+                for (encoding, decoding) in [
+                    (Encoding::HuffmanRle, Encoder::HuffmanRLE),
+                    (Encoding::G3OneD, Encoder::G31d),
+                    (Encoding::G3TwoD { k: 4 }, Encoder::G32d),
+                    (Encoding::G4, Encoder::G4),
+                ] {
+                    let options = CcittOptions::new(encoding);
+                    let encoded = encode(&data, width, height, &options).unwrap();
+                    let (decoded, warning) =
+                        decoder(&encoded, width, height, decoding, false).unwrap();
+                    assert!(!warning, "{width} {density} {encoding:?}");
+                    assert_eq!(decoded, unpack(&data, width, height));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn modified_huffman_roundtrips_against_decoder() {
+        let options = CcittOptions::new(Encoding::HuffmanRle);
+        roundtrip(3000, 24, options, Encoder::HuffmanRLE);
+        roundtrip(13, 5, options, Encoder::HuffmanRLE);
+    }
+
+    #[test]
+    fn group3_one_dimensional_roundtrips_against_decoder() {
+        for byte_align in [false, true] {
+            for end_marker in [false, true] {
+                let options = CcittOptions {
+                    encoding: Encoding::G3OneD,
+                    byte_align,
+                    end_marker,
+                    ..CcittOptions::default()
+                };
+                roundtrip(3000, 24, options, Encoder::G31d);
+                roundtrip(13, 5, options, Encoder::G31d);
+            }
+        }
+    }
+
+    #[test]
+    fn group3_two_dimensional_roundtrips_against_decoder() {
+        for k in [1, 2, 4, 100] {
+            for byte_align in [false, true] {
+                let options = CcittOptions {
+                    encoding: Encoding::G3TwoD { k },
+                    byte_align,
+                    ..CcittOptions::default()
+                };
+                roundtrip(3000, 24, options, Encoder::G32d);
+                roundtrip(13, 5, options, Encoder::G32d);
+            }
+        }
+    }
+
+    #[test]
+    fn group4_roundtrips_against_decoder() {
+        for end_marker in [false, true] {
+            let options = CcittOptions {
+                end_marker,
+                ..CcittOptions::default()
+            };
+            roundtrip(3000, 24, options, Encoder::G4);
+            roundtrip(13, 5, options, Encoder::G4);
+        }
+    }
+
+    #[test]
+    fn lsb_fill_order_roundtrips_against_decoder() {
+        for (encoding, decoding) in [
+            (Encoding::G3OneD, Encoder::G31d),
+            (Encoding::G4, Encoder::G4),
+        ] {
+            let options = CcittOptions {
+                encoding,
+                is_lsb: true,
+                ..CcittOptions::default()
+            };
+            roundtrip(100, 7, options, decoding);
+        }
+    }
+
+    #[test]
+    fn byte_aligned_eols_end_on_byte_boundaries() {
+        let options = CcittOptions {
+            encoding: Encoding::G3OneD,
+            byte_align: true,
+            end_marker: false,
+            ..CcittOptions::default()
+        };
+        let width = 13;
+        let data = patterned_rows(width, 5);
+        let encoded = encode(&data, width, 5, &options).unwrap();
+        // Every EOL is `0000 0000 0000 0001` with the last bit in a byte's
+        // lowest position, so each row starts on a byte boundary.
+        let eol_ends = encoded
+            .windows(2)
+            .filter(|pair| pair[0] & 0x0f == 0 && pair[1] == 0x01)
+            .count();
+        assert_eq!(eol_ends, 5);
+    }
+
+    #[test]
+    fn group4_codes_identical_rows_in_vertical_mode() {
+        let width = 64;
+        let mut data = vec![0; 8 * 3];
+        for row in data.chunks_mut(8) {
+            row[2] = 0xff;
+        }
+        let options = CcittOptions::new(Encoding::G4);
+        let encoded = encode(&data, width, 3, &options).unwrap();
+        let single = encode(&data[..8], width, 1, &options).unwrap();
+        // A repeated row is three V(0) codes of one bit each.
+        assert!(encoded.len() <= single.len() + 1);
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let options = CcittOptions::new(Encoding::G4);
+        assert!(encode(&[0; 3], 16, 2, &options).is_err());
+        let options = CcittOptions::new(Encoding::G3TwoD { k: 0 });
+        assert!(encode(&[0; 4], 16, 2, &options).is_err());
+    }
+}
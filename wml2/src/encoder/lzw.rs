@@ -195,7 +195,7 @@ pub fn encode_tiff(data: &[u8], is_lsb: bool) -> Result<Vec<u8>, Error> {
 #[cfg(test)]
 mod tests {
     use super::{encode_gif, encode_tiff};
-    use crate::decoder::lzw::Lzwdecode;
+    use crate::decoder::lzw::{LzwRecovery, Lzwdecode};
 
     fn patterned_bytes() -> Vec<u8> {
         let mut bytes = Vec::with_capacity(4096);
@@ -271,4 +271,68 @@ mod tests {
         let decoded = decoder.decode(&encoded).unwrap();
         assert_eq!(decoded, source);
     }
+
+    /// Packs `(code, width)` pairs LSB first, as GIF does.
+    fn pack_lsb(codes: &[(usize, usize)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let (mut acc, mut bits) = (0_u64, 0);
+        for &(code, width) in codes {
+            acc |= (code as u64) << bits;
+            bits += width;
+            while bits >= 8 {
+                bytes.push(acc as u8);
+                acc >>= 8;
+                bits -= 8;
+            }
+        }
+        if bits > 0 {
+            bytes.push(acc as u8);
+        }
+        bytes
+    }
+
+    #[test]
+    fn gif_lzw_invalid_code_fails_strict_and_ends_lenient() {
+        // clear, 0, then a code beyond the 6-entry table.
+        let encoded = pack_lsb(&[(4, 3), (0, 3), (7, 3)]);
+        assert!(Lzwdecode::gif(2).decode(&encoded).is_err());
+
+        let mut decoder = Lzwdecode::gif(2);
+        decoder.set_lenient(true);
+        let decoded = decoder.decode(&encoded).unwrap();
+        assert_eq!(decoded, vec![0]);
+        assert!(decoder.is_end());
+        assert_eq!(
+            decoder.recoveries(),
+            &[LzwRecovery::InvalidCode {
+                code: 7,
+                table_size: 6
+            }]
+        );
+    }
+
+    #[test]
+    fn gif_lzw_tolerates_codes_after_full_table() {
+        // Without a clear code the table fills at 4096 entries and further
+        // codes must be decoded without growing it.
+        let mut codes = vec![(4, 3)];
+        let (mut table, mut width) = (6, 3);
+        for i in 0..5000 {
+            codes.push((0, width));
+            if i > 0 && table < 4096 {
+                table += 1;
+                if table == 1 << width && width < 12 {
+                    width += 1;
+                }
+            }
+        }
+        codes.push((5, width));
+
+        let mut decoder = Lzwdecode::gif(2);
+        decoder.set_lenient(true);
+        let decoded = decoder.decode(&pack_lsb(&codes)).unwrap();
+        assert_eq!(decoded, vec![0; 5000]);
+        assert!(decoder.is_end());
+        assert_eq!(decoder.recoveries(), &[LzwRecovery::TableOverflow]);
+    }
 }
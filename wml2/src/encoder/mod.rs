@@ -1,3 +1,46 @@
 //! Shared low-level encoder utilities.
 
+pub mod ccitt;
 pub mod lzw;
+pub mod packbits;
+
+#[cfg(any(feature = "bmp", feature = "tiff"))]
+type Error = Box<dyn std::error::Error>;
+
+#[cfg(any(feature = "bmp", feature = "tiff"))]
+use crate::error::{ImgError, ImgErrorKind};
+#[cfg(any(feature = "bmp", feature = "tiff"))]
+use crate::metadata::DataMap;
+
+/// Reads an on/off encoder option. `name` prefixes error messages, e.g.
+/// `"BMP top_down"`.
+#[cfg(any(feature = "bmp", feature = "tiff"))]
+pub(crate) fn bool_option(value: &DataMap, name: &str) -> Result<bool, Error> {
+    match value {
+        DataMap::UInt(value) => Ok(*value != 0),
+        DataMap::SInt(value) => Ok(*value != 0),
+        DataMap::Ascii(value) => match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(true),
+            "false" | "no" | "off" | "0" => Ok(false),
+            _ => Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                format!("unsupported {name} option: {value}"),
+            ))),
+        },
+        _ => Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            format!("{name} must be `true`, `false`, 0, or 1"),
+        ))),
+    }
+}
+
+/// Copies RGBA pixels with every alpha set to 0xff, for indexed output
+/// that has no transparency.
+#[cfg(any(feature = "bmp", feature = "tiff"))]
+pub(crate) fn opaque_rgba(rgba: &[u8]) -> Vec<u8> {
+    let mut opaque = rgba.to_vec();
+    for pixel in opaque.chunks_exact_mut(4) {
+        pixel[3] = 0xff;
+    }
+    opaque
+}
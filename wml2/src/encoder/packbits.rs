@@ -0,0 +1,41 @@
+//! PackBits encoder for TIFF.
+
+fn push_literal(out: &mut Vec<u8>, literal: &[u8]) {
+    for chunk in literal.chunks(128) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn encode_row(out: &mut Vec<u8>, row: &[u8]) {
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i < row.len() {
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+        // A run of two only pays off when it does not split a literal.
+        if run >= 3 || (run == 2 && i == literal_start) {
+            push_literal(out, &row[literal_start..i]);
+            out.push((257 - run) as u8);
+            out.push(row[i]);
+            literal_start = i + run;
+        }
+        i += run;
+    }
+    push_literal(out, &row[literal_start..]);
+}
+
+/// Encodes `data` with PackBits. Each row of `row_len` bytes is packed on
+/// its own, as TIFF requires.
+pub fn encode(data: &[u8], row_len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 128 + 1);
+    if row_len == 0 {
+        return out;
+    }
+    for row in data.chunks(row_len) {
+        encode_row(&mut out, row);
+    }
+    out
+}
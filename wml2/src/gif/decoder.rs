@@ -2,9 +2,11 @@
 
 type Error = Box<dyn std::error::Error>;
 
+use super::font;
 use super::header::*;
+use super::warning::{GifWarning, GifWarningKind};
 use crate::color::RGBA;
-use crate::decoder::lzw::Lzwdecode;
+use crate::decoder::lzw::{LzwRecovery, Lzwdecode};
 use crate::draw::*;
 use crate::error::ImgError;
 use crate::error::ImgErrorKind;
@@ -17,6 +19,8 @@ const SEPARATOR: u8 = b','; // 0x2c
 const EXTEND_BLOCK: u8 = b'!'; // 0x21
 const COMMENT_LABEL: u8 = 0xfe;
 const GRAPHIC_CONTROLE: u8 = 0xf9;
+const PLAIN_TEXT_LABEL: u8 = 0x01;
+const APPLICATION_LABEL: u8 = 0xff;
 const END_MARKER: u8 = b';'; // 0x3c
 const END: u8 = 0x00;
 
@@ -66,20 +70,666 @@ fn draw_frame(
     Ok(())
 }
 
+/// Animation and graphic control state carried between blocks.
+struct FrameState {
+    loop_count: u32,
+    delay_time: u16,
+    dispose_method: u8,
+    is_transparent: bool,
+    transparent_color: usize,
+    is_inited: bool,
+    is_first: bool,
+    frame_count: usize,
+    comment_count: usize,
+    plain_text_count: usize,
+}
+
+/// What the block loop does after a block.
+enum Flow {
+    Continue,
+    End,
+    Abort,
+}
+
+fn add_warning(warnings: &mut Option<ImgWarnings>, kind: GifWarningKind, message: String) {
+    *warnings = ImgWarnings::add(
+        warnings.take(),
+        Box::new(GifWarning::new_const(kind, message)),
+    );
+}
+
+fn copy_rect(rect: &ImageRect) -> ImageRect {
+    ImageRect {
+        width: rect.width,
+        height: rect.height,
+        start_x: rect.start_x,
+        start_y: rect.start_y,
+    }
+}
+
+/// Initializes the drawer on the first rendering block and announces every
+/// later block as a new animation frame. Returns `false` when the drawer
+/// aborts.
+fn begin_frame(
+    option: &mut DecodeOptions,
+    header: &GifHeader,
+    state: &mut FrameState,
+    rect: &ImageRect,
+) -> Result<bool, Error> {
+    if !state.is_inited {
+        let background = if header.color_table.is_empty() {
+            None
+        } else {
+            header
+                .color_table
+                .get(header.scd.color_index as usize)
+                .cloned()
+        };
+        let init = InitOptions {
+            loop_count: state.loop_count,
+            background,
+            animation: true,
+        };
+        option
+            .drawer
+            .init(header.width, header.height, Some(init))?;
+        state.is_inited = true;
+    }
+
+    if !state.is_first {
+        let opt = NextOptions {
+            flag: NextOption::Next,
+            await_time: gif_delay_ms(state.delay_time as usize),
+            image_rect: Some(copy_rect(rect)),
+            dispose_option: Some(gif_dispose_option(state.dispose_method)),
+            blend: Some(gif_blend_option(state.is_transparent)),
+        };
+        let result = option.drawer.next(Some(opt))?;
+        if let Some(response) = result
+            && response.response == ResponseCommand::Abort
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Draws a decoded rendering block. The first block is drawn on the canvas
+/// and replayed as animation frame 0.
+fn finish_frame(
+    option: &mut DecodeOptions,
+    state: &mut FrameState,
+    rect: &ImageRect,
+    frame_buffer: &[u8],
+) -> Result<(), Error> {
+    let (width, height) = (rect.width, rect.height);
+    if state.is_first {
+        draw_frame(
+            option,
+            rect.start_x as usize,
+            rect.start_y as usize,
+            width,
+            height,
+            frame_buffer,
+        )?;
+
+        let opt = NextOptions {
+            flag: NextOption::Continue,
+            await_time: gif_delay_ms(state.delay_time as usize),
+            image_rect: Some(copy_rect(rect)),
+            dispose_option: Some(gif_dispose_option(state.dispose_method)),
+            blend: Some(gif_blend_option(state.is_transparent)),
+        };
+        let result = option.drawer.next(Some(opt))?;
+        if let Some(response) = result
+            && response.response == ResponseCommand::Continue
+        {
+            draw_frame(option, 0, 0, width, height, frame_buffer)?;
+        }
+        state.is_first = false;
+    } else {
+        draw_frame(option, 0, 0, width, height, frame_buffer)?;
+    }
+    Ok(())
+}
+
+/// Reads data sub-blocks up to the block terminator.
+fn read_sub_blocks<B: BinaryReader>(reader: &mut B) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    loop {
+        let len = reader.read_byte()? as usize;
+        if len == 0 {
+            return Ok(data);
+        }
+        data.append(&mut reader.read_bytes_as_vec(len)?);
+    }
+}
+
+/// Reads sub-blocks keeping their length bytes, which recovers raw payloads
+/// such as XMP that are stored without sub-block framing.
+fn read_raw_sub_blocks<B: BinaryReader>(reader: &mut B) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    loop {
+        let len = reader.read_byte()?;
+        if len == 0 {
+            return Ok(data);
+        }
+        data.push(len);
+        data.append(&mut reader.read_bytes_as_vec(len as usize)?);
+    }
+}
+
+/// Renders a plain text extension with the built-in font. Colors come from
+/// the global color table; a transparent background index leaves the
+/// background clear.
+fn render_plain_text(
+    header: &GifHeader,
+    state: &FrameState,
+    grid: &[u8],
+    text: &[u8],
+    warnings: &mut Option<ImgWarnings>,
+) -> Option<(ImageRect, Vec<u8>)> {
+    let left = u16::from_le_bytes([grid[0], grid[1]]) as usize;
+    let top = u16::from_le_bytes([grid[2], grid[3]]) as usize;
+    let width = u16::from_le_bytes([grid[4], grid[5]]) as usize;
+    let height = u16::from_le_bytes([grid[6], grid[7]]) as usize;
+    let (cell_width, cell_height) = (grid[8] as usize, grid[9] as usize);
+    let foreground = header.color_table.get(grid[10] as usize)?;
+    let background = header.color_table.get(grid[11] as usize)?;
+    if width == 0 || height == 0 || cell_width == 0 || cell_height == 0 {
+        return None;
+    }
+    // Only the part of the grid on the logical screen is drawn.
+    let visible_width = width.min(header.width.saturating_sub(left));
+    let visible_height = height.min(header.height.saturating_sub(top));
+    if visible_width == 0 || visible_height == 0 {
+        add_warning(
+            warnings,
+            GifWarningKind::MalformedBlock,
+            format!(
+                "plain text grid {}x{} at ({}, {}) is outside the logical screen",
+                width, height, left, top
+            ),
+        );
+        return None;
+    }
+
+    let background = if state.is_transparent && state.transparent_color == grid[11] as usize {
+        [0, 0, 0, 0]
+    } else {
+        [background.red, background.green, background.blue, 0xff]
+    };
+    let foreground = [foreground.red, foreground.green, foreground.blue, 0xff];
+    let columns = width / cell_width;
+    let rows = height / cell_height;
+    let mut frame_buffer = Vec::with_capacity(visible_width * visible_height * 4);
+    for _ in 0..visible_width * visible_height {
+        frame_buffer.extend_from_slice(&background);
+    }
+
+    for (index, &ch) in text.iter().take(columns * rows).enumerate() {
+        let (cell_x, cell_y) = (index % columns * cell_width, index / columns * cell_height);
+        if cell_x >= visible_width || cell_y >= visible_height {
+            continue;
+        }
+        for y in 0..cell_height.min(visible_height - cell_y) {
+            for x in 0..cell_width.min(visible_width - cell_x) {
+                if font::glyph_pixel(ch, x, y, cell_width, cell_height) {
+                    let offset = ((cell_y + y) * visible_width + cell_x + x) * 4;
+                    frame_buffer[offset..offset + 4].copy_from_slice(&foreground);
+                }
+            }
+        }
+    }
+
+    let rect = ImageRect {
+        width: visible_width,
+        height: visible_height,
+        start_x: left as i32,
+        start_y: top as i32,
+    };
+    Some((rect, frame_buffer))
+}
+
+/// Stores XMP and ICC application extensions as metadata.
+fn application_metadata(
+    option: &mut DecodeOptions,
+    identifier: &str,
+    payload: Vec<u8>,
+) -> Result<(), Error> {
+    match identifier {
+        "XMP DataXMP" => {
+            // The payload ends with a 258-byte "magic trailer" that lets
+            // readers skip the unframed XMP packet as sub-blocks.
+            let end = payload
+                .windows(3)
+                .position(|window| window == [0x01, 0xff, 0xfe])
+                .unwrap_or(payload.len());
+            let xmp = &payload[..end];
+            match String::from_utf8(xmp.to_vec()) {
+                Ok(xmp) => {
+                    option.drawer.set_metadata("XMP", DataMap::Ascii(xmp))?;
+                }
+                Err(_) => {
+                    option
+                        .drawer
+                        .set_metadata("XMP Raw", DataMap::Raw(xmp.to_vec()))?;
+                }
+            }
+        }
+        "ICCRGBG1012" => {
+            option
+                .drawer
+                .set_metadata("ICC Profile", DataMap::ICCProfile(payload))?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Reads image data sub-blocks. A stream that ends early returns the bytes
+/// read so far together with `true`.
+fn read_image_sub_blocks<B: BinaryReader>(reader: &mut B) -> (Vec<u8>, bool) {
+    let mut data = Vec::new();
+    loop {
+        let Ok(len) = reader.read_byte() else {
+            return (data, true);
+        };
+        if len == 0 {
+            return (data, false);
+        }
+        match reader.read_bytes_as_vec(len as usize) {
+            Ok(mut block) => data.append(&mut block),
+            Err(_) => {
+                while let Ok(byte) = reader.read_byte() {
+                    data.push(byte);
+                }
+                return (data, true);
+            }
+        }
+    }
+}
+
+/// Maps the `row`-th stored row of an interlaced image to its position.
+fn interlaced_row(row: usize, height: usize) -> usize {
+    let mut row = row;
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        let count = height.saturating_sub(start).div_ceil(step);
+        if row < count {
+            return start + row * step;
+        }
+        row -= count;
+    }
+    row
+}
+
+/// Converts color indices to RGBA. Pixels missing from a short stream or
+/// outside the color table become `background`; the flag reports indices
+/// outside the table.
+fn frame_pixels(
+    data: &[u8],
+    color_table: &[RGBA],
+    width: usize,
+    height: usize,
+    is_interlace: bool,
+    background: [u8; 4],
+) -> (Vec<u8>, bool) {
+    let mut frame_buffer = Vec::with_capacity(width * height * 4);
+    for _ in 0..width * height {
+        frame_buffer.extend_from_slice(&background);
+    }
+    let mut invalid_index = false;
+    for row in 0..height {
+        let y = if is_interlace {
+            interlaced_row(row, height)
+        } else {
+            row
+        };
+        let Some(indices) = data.get(row * width..) else {
+            break;
+        };
+        for (x, &index) in indices.iter().take(width).enumerate() {
+            let Some(color) = color_table.get(index as usize) else {
+                invalid_index = true;
+                continue;
+            };
+            let offset = (y * width + x) * 4;
+            frame_buffer[offset..offset + 4].copy_from_slice(&[
+                color.red,
+                color.green,
+                color.blue,
+                color.alpha,
+            ]);
+        }
+    }
+    (frame_buffer, invalid_index)
+}
+
+fn is_block_start<B: BinaryReader>(reader: &mut B, header: &GifHeader) -> bool {
+    let peek = (1..=10)
+        .rev()
+        .find_map(|len| reader.read_bytes_no_move(len).ok())
+        .unwrap_or_default();
+    match peek.as_slice() {
+        [SEPARATOR, descriptor @ ..] if descriptor.len() == 9 => {
+            let value = |i: usize| u16::from_le_bytes([descriptor[i], descriptor[i + 1]]) as usize;
+            let (left, top, width, height) = (value(0), value(2), value(4), value(6));
+            width > 0 && height > 0 && left + width <= header.width && top + height <= header.height
+        }
+        [EXTEND_BLOCK, GRAPHIC_CONTROLE, 0x04, ..]
+        | [EXTEND_BLOCK, APPLICATION_LABEL, 0x0b, ..]
+        | [EXTEND_BLOCK, PLAIN_TEXT_LABEL, 0x0c, ..]
+        | [EXTEND_BLOCK, COMMENT_LABEL, ..] => true,
+        [END_MARKER] => true,
+        _ => false,
+    }
+}
+
+/// Skips bytes up to the next plausible block introducer. Returns the number
+/// of bytes skipped, or `None` at the end of the stream.
+fn resync<B: BinaryReader>(reader: &mut B, header: &GifHeader) -> Option<usize> {
+    let mut skipped = 0;
+    while !is_block_start(reader, header) {
+        reader.read_byte().ok()?;
+        skipped += 1;
+    }
+    Some(skipped)
+}
+
+fn decode_extension<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &mut GifHeader,
+    state: &mut FrameState,
+    warnings: &mut Option<ImgWarnings>,
+) -> Result<Flow, Error> {
+    let ext = reader.read_byte()?;
+    match ext {
+        END => {}
+        COMMENT_LABEL => {
+            let mut s = "".to_string();
+            state.comment_count += 1;
+            loop {
+                let len = reader.read_byte()? as usize;
+                if len == 0 {
+                    break;
+                }
+                let comment = reader.read_ascii_string(len)?;
+                s = s.to_owned() + &comment;
+            }
+            if option.debug_flag > 0 {
+                option
+                    .drawer
+                    .verbose(&("Comment: ".to_owned() + &s), None)?;
+            }
+            let string = format!("comment:{}", state.comment_count);
+            option
+                .drawer
+                .set_metadata(&string, DataMap::Ascii(s.to_string()))?;
+        }
+        GRAPHIC_CONTROLE => {
+            let _len = reader.read_byte()? as usize; //5
+            let flag = reader.read_byte()?;
+            state.delay_time = reader.read_u16_le()?;
+
+            if state.is_transparent && state.transparent_color < header.color_table.len() {
+                header.color_table[state.transparent_color].alpha = 0xff;
+            }
+
+            state.is_transparent = flag & 0x1 == 1;
+            state.dispose_method = (flag >> 2) & 0x07;
+
+            state.transparent_color = reader.read_byte()? as usize;
+            if option.debug_flag > 0 {
+                let s = format!(
+                    "Grahic Control {} delay {}ms  transpearent {:?}",
+                    flag, state.delay_time, state.is_transparent
+                );
+                option.drawer.verbose(&s, None)?;
+            }
+            reader.read_byte()?; // is 00
+        }
+        PLAIN_TEXT_LABEL => {
+            let len = reader.read_byte()? as usize;
+            let grid = reader.read_bytes_as_vec(len)?;
+            let text = read_sub_blocks(reader)?;
+            if grid.len() < 12 {
+                return Ok(Flow::Continue);
+            }
+            state.plain_text_count += 1;
+            option.drawer.set_metadata(
+                &format!("plain_text:{}", state.plain_text_count),
+                DataMap::Ascii(String::from_utf8_lossy(&text).to_string()),
+            )?;
+            let Some((rect, frame_buffer)) =
+                render_plain_text(header, state, &grid, &text, warnings)
+            else {
+                return Ok(Flow::Continue);
+            };
+            if !begin_frame(option, header, state, &rect)? {
+                return Ok(Flow::Abort);
+            }
+            finish_frame(option, state, &rect, &frame_buffer)?;
+        }
+        APPLICATION_LABEL => {
+            // Netscape2.0 (Animation Flag)
+            let len = reader.read_byte()? as usize;
+            if len == 0 {
+                return Ok(Flow::Continue);
+            }
+            let buf = reader.read_bytes_as_vec(len)?;
+            let s = read_ascii_string(&buf, 0, len);
+            if s != "NETSCAPE2.0" {
+                let payload = if s == "XMP DataXMP" {
+                    read_raw_sub_blocks(reader)?
+                } else {
+                    read_sub_blocks(reader)?
+                };
+                application_metadata(option, &s, payload)?;
+                return Ok(Flow::Continue);
+            }
+            loop {
+                let len = reader.read_byte()? as usize;
+                if len == 0 {
+                    break;
+                }
+                option
+                    .drawer
+                    .set_metadata("Animation GIF", DataMap::Ascii("NETSCAPE2.0".to_string()))?;
+                if option.debug_flag > 0 {
+                    option
+                        .drawer
+                        .verbose(&("Animation tag: ".to_owned() + &s), None)?;
+                }
+                let id = reader.read_byte()?;
+                if id == 0x01 && len >= 3 {
+                    state.loop_count = reader.read_u16_le()? as u32;
+                    if len > 3 {
+                        reader.skip_ptr(len - 3)?;
+                    }
+                } else {
+                    reader.skip_ptr(len.saturating_sub(1))?;
+                }
+            }
+        }
+        _ => loop {
+            let len = reader.read_byte()? as usize;
+            if len == 0 {
+                break;
+            }
+            reader.skip_ptr(len)?;
+        },
+    }
+    Ok(Flow::Continue)
+}
+
+fn decode_image<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &mut GifHeader,
+    state: &mut FrameState,
+    warnings: &mut Option<ImgWarnings>,
+) -> Result<Flow, Error> {
+    let lscd = GifLscd::new(reader)?;
+    let rect = ImageRect {
+        width: lscd.xsize as usize,
+        height: lscd.ysize as usize,
+        start_x: lscd.xstart as i32,
+        start_y: lscd.ystart as i32,
+    };
+    if !begin_frame(option, header, state, &rect)? {
+        return Ok(Flow::Abort);
+    }
+    state.frame_count += 1;
+    let frame = state.frame_count;
+
+    let has_local_palette;
+    let mut local_color_table = Vec::new();
+    if lscd.field & 0x80 == 0x80 {
+        has_local_palette = true;
+        let color_table_size = (1 << ((lscd.field & 0x07) + 1)) as usize;
+        for _ in 0..color_table_size {
+            let color = RGBA {
+                red: reader.read_byte()?,
+                green: reader.read_byte()?,
+                blue: reader.read_byte()?,
+                alpha: 0xff,
+            };
+            local_color_table.push(color);
+        }
+        if state.is_transparent
+            && let Some(color) = local_color_table.get_mut(state.transparent_color)
+        {
+            color.alpha = 0x00;
+        }
+    } else {
+        has_local_palette = false;
+        if state.is_transparent && state.transparent_color < header.color_table.len() {
+            header.color_table[state.transparent_color].alpha = 0x00;
+        }
+    }
+
+    // LZW block
+    let lzw_min_bits = reader.read_byte()? as usize;
+    let (buf, truncated) = read_image_sub_blocks(reader);
+    let data = if (1..=11).contains(&lzw_min_bits) {
+        let mut decoder = Lzwdecode::gif(lzw_min_bits);
+        decoder.set_lenient(true);
+        let data = decoder.decode(&buf)?;
+        for recovery in decoder.recoveries() {
+            let (kind, message) = match recovery {
+                LzwRecovery::InvalidCode { code, table_size } => (
+                    GifWarningKind::InvalidCode,
+                    format!(
+                        "frame {}: code {} exceeds table size {}",
+                        frame, code, table_size
+                    ),
+                ),
+                LzwRecovery::TableOverflow => (
+                    GifWarningKind::CodeTableOverflow,
+                    format!("frame {}: codes continue after the table is full", frame),
+                ),
+            };
+            add_warning(warnings, kind, message);
+        }
+        data
+    } else {
+        add_warning(
+            warnings,
+            GifWarningKind::InvalidCode,
+            format!(
+                "frame {}: LZW minimum code size {} is out of range",
+                frame, lzw_min_bits
+            ),
+        );
+        Vec::new()
+    };
+    let color_table = if has_local_palette {
+        &local_color_table
+    } else {
+        &header.color_table
+    };
+
+    let width = lscd.xsize as usize;
+    let height = lscd.ysize as usize;
+    if option.debug_flag > 0 {
+        option.drawer.verbose(&format!("{:?}", lscd), None)?;
+        option.drawer.verbose(
+            &format!(
+                "{} {} {} data length {}",
+                width,
+                height,
+                width * height,
+                data.len()
+            ),
+            None,
+        )?;
+    }
+
+    let background = header
+        .color_table
+        .get(header.scd.color_index as usize)
+        .map(|color| [color.red, color.green, color.blue, color.alpha])
+        .unwrap_or([0, 0, 0, 0]);
+    let is_interlace = (lscd.field & 0x40) == 0x40;
+    let (frame_buffer, invalid_index) =
+        frame_pixels(&data, color_table, width, height, is_interlace, background);
+    if data.len() < width * height {
+        add_warning(
+            warnings,
+            GifWarningKind::MissingRows,
+            format!(
+                "frame {}: {} of {} rows decoded, the rest is padded with the background",
+                frame,
+                data.len() / width,
+                height
+            ),
+        );
+    }
+    if invalid_index {
+        add_warning(
+            warnings,
+            GifWarningKind::InvalidColorIndex,
+            format!("frame {}: color index outside the color table", frame),
+        );
+    }
+
+    finish_frame(option, state, &rect, &frame_buffer)?;
+    if truncated {
+        add_warning(
+            warnings,
+            GifWarningKind::TruncatedData,
+            format!("frame {}: image data ends before its terminator", frame),
+        );
+        return Ok(Flow::End);
+    }
+    Ok(Flow::Continue)
+}
+
+/// Decodes a GIF stream.
+///
+/// Damaged streams are decoded as far as possible: a truncated or corrupt
+/// frame is rendered with its missing rows padded with the background, and
+/// unknown data between blocks is skipped up to the next block. Each
+/// recovery is reported as a [`GifWarning`].
 pub fn decode<'decode, B: BinaryReader>(
     reader: &mut B,
     option: &mut DecodeOptions,
 ) -> Result<Option<ImgWarnings>, Error> {
     let mut header = GifHeader::new(reader, option.debug_flag)?;
-    let mut comment = "".to_string();
-    let mut is_transparent = false;
-    let mut transparent_color = 0x00;
-    let mut delay_time = 0;
-    let mut dispose_method = 0;
-    let mut loop_count = 0;
-    let mut is_inited = false;
-    let mut is_first = true;
-    let warnings: Option<ImgWarnings> = None;
+    let mut state = FrameState {
+        loop_count: 0,
+        delay_time: 0,
+        dispose_method: 0,
+        is_transparent: false,
+        transparent_color: 0,
+        is_inited: false,
+        is_first: true,
+        frame_count: 0,
+        comment_count: 0,
+        plain_text_count: 0,
+    };
+    let mut warnings: Option<ImgWarnings> = None;
 
     if option.debug_flag > 0 {
         option.drawer.verbose(&format!("{:?}", &header), None)?;
@@ -96,294 +746,58 @@ pub fn decode<'decode, B: BinaryReader>(
     option
         .drawer
         .set_metadata("height", DataMap::UInt(header.height as u64))?;
-    let mut comment_count = 0;
 
     loop {
-        let c = reader.read_byte()?;
-
-        match c {
+        let result: Result<Flow, Error> = match reader.read_byte() {
             // BLOCK LOOP
-            END => {}
-            EXTEND_BLOCK => {
-                let ext = reader.read_byte()?;
-                match ext {
-                    END => {}
-                    COMMENT_LABEL => {
-                        let mut s = "".to_string();
-                        comment_count += 1;
-                        loop {
-                            let len = reader.read_byte()? as usize;
-                            if len == 0 {
-                                break;
-                            }
-                            if ext == COMMENT_LABEL {
-                                let comment = reader.read_ascii_string(len)?;
-                                s = s.to_owned() + &comment;
-                            }
-                        }
-                        if option.debug_flag > 0 {
-                            option
-                                .drawer
-                                .verbose(&("Comment: ".to_owned() + &s), None)?;
-                        }
-                        let string = format!("comment:{}", comment_count);
-                        option
-                            .drawer
-                            .set_metadata(&string, DataMap::Ascii(s.to_string()))?;
-                        comment += &s;
-                    }
-                    GRAPHIC_CONTROLE => {
-                        let _len = reader.read_byte()? as usize; //5
-                        let flag = reader.read_byte()?;
-                        delay_time = reader.read_u16_le()?;
-
-                        if is_transparent && transparent_color < header.color_table.len() {
-                            header.color_table[transparent_color].alpha = 0xff;
-                        }
-
-                        is_transparent = flag & 0x1 == 1;
-                        dispose_method = (flag >> 2) & 0x07;
-
-                        transparent_color = reader.read_byte()? as usize;
-                        if option.debug_flag > 0 {
-                            let s = format!(
-                                "Grahic Control {} delay {}ms  transpearent {:?}",
-                                flag, delay_time, is_transparent
-                            );
-                            option.drawer.verbose(&s, None)?;
-                        }
-                        reader.read_byte()?; // is 00
-                    }
-                    0xff => {
-                        // Netscape2.0 (Animation Flag)
-                        let len = reader.read_byte()? as usize;
-                        if len == 0 {
-                            break;
-                        }
-                        let buf = reader.read_bytes_as_vec(len)?;
-                        let s = read_ascii_string(&buf, 0, len);
-                        loop {
-                            let len = reader.read_byte()? as usize;
-                            if len == 0 {
-                                break;
-                            }
-                            if s == "NETSCAPE2.0" {
-                                option.drawer.set_metadata(
-                                    "Animation GIF",
-                                    DataMap::Ascii("NETSCAPE2.0".to_string()),
-                                )?;
-                                if option.debug_flag > 0 {
-                                    option
-                                        .drawer
-                                        .verbose(&("Animation tag: ".to_owned() + &s), None)?;
-                                }
-                                let id = reader.read_byte()?;
-                                if id == 0x01 && len >= 3 {
-                                    loop_count = reader.read_u16_le()? as u32;
-                                    if len > 3 {
-                                        reader.skip_ptr(len - 3)?;
-                                    }
-                                } else {
-                                    reader.skip_ptr(len.saturating_sub(1))?;
-                                }
-                            } else {
-                                reader.skip_ptr(len)?;
-                            }
-                        }
-                    }
-                    _ => loop {
-                        let len = reader.read_byte()? as usize;
-                        if len == 0 {
-                            break;
-                        }
-                        reader.skip_ptr(len)?;
-                    },
-                }
+            Ok(END) => Ok(Flow::Continue),
+            Ok(EXTEND_BLOCK) => {
+                decode_extension(reader, option, &mut header, &mut state, &mut warnings)
             }
-
-            SEPARATOR => {
-                let lscd = GifLscd::new(reader)?;
-                if !is_inited {
-                    let background = if header.color_table.is_empty() {
-                        None
-                    } else {
-                        Some(header.color_table[header.scd.color_index as usize].clone())
-                    };
-                    let init = InitOptions {
-                        loop_count,
-                        background,
-                        animation: true,
-                    };
-                    option
-                        .drawer
-                        .init(header.width, header.height, Some(init))?;
-                    is_inited = true;
-                }
-
-                if !is_first {
-                    let rect = ImageRect {
-                        width: lscd.xsize as usize,
-                        height: lscd.ysize as usize,
-                        start_x: lscd.xstart as i32,
-                        start_y: lscd.ystart as i32,
-                    };
-
-                    let await_time = gif_delay_ms(delay_time as usize);
-
-                    let opt = NextOptions {
-                        flag: NextOption::Next,
-                        await_time,
-                        image_rect: Some(rect),
-                        dispose_option: Some(gif_dispose_option(dispose_method)),
-                        blend: Some(gif_blend_option(is_transparent)),
-                    };
-
-                    let result = option.drawer.next(Some(opt))?;
-                    if let Some(response) = result {
-                        if response.response == ResponseCommand::Abort {
-                            return Ok(None);
-                        }
-                    }
-                }
-                let has_local_palette;
-                let mut local_color_table = Vec::new();
-                if lscd.field & 0x80 == 0x80 {
-                    has_local_palette = true;
-                    let color_table_size = (1 << ((lscd.field & 0x07) + 1)) as usize;
-                    for _ in 0..color_table_size {
-                        let color = RGBA {
-                            red: reader.read_byte()?,
-                            green: reader.read_byte()?,
-                            blue: reader.read_byte()?,
-                            alpha: 0xff,
-                        };
-                        local_color_table.push(color);
-                    }
-                    if is_transparent {
-                        local_color_table[transparent_color].alpha = 0x00;
-                    }
-                } else {
-                    has_local_palette = false;
-                    if is_transparent && transparent_color < header.color_table.len() {
-                        header.color_table[transparent_color].alpha = 0x00;
+            Ok(SEPARATOR) => decode_image(reader, option, &mut header, &mut state, &mut warnings),
+            Ok(END_MARKER) => Ok(Flow::End),
+            Ok(c) => {
+                let offset = reader.offset()?.saturating_sub(1);
+                match resync(reader, &header) {
+                    Some(skipped) => {
+                        add_warning(
+                            &mut warnings,
+                            GifWarningKind::MalformedBlock,
+                            format!(
+                                "skipped {} bytes from offset {} (0x{:02x})",
+                                skipped + 1,
+                                offset,
+                                c
+                            ),
+                        );
+                        Ok(Flow::Continue)
                     }
-                    // header.color_table[transparent_color].alpha = 0xff;
-                }
-                // LZW block
-                let lzw_min_bits = reader.read_byte()? as usize;
-                let mut buf: Vec<u8> = Vec::new();
-                'lzw_read: loop {
-                    let len = reader.read_byte()? as usize;
-                    if len == 0 {
-                        break 'lzw_read;
-                    }
-                    buf.append(&mut reader.read_bytes_as_vec(len)?);
-                }
-                let mut decoder = Lzwdecode::gif(lzw_min_bits);
-                let data = decoder.decode(&buf)?;
-                let color_table = if has_local_palette {
-                    &local_color_table
-                } else {
-                    &header.color_table
-                };
-
-                let width = lscd.xsize as usize;
-                let height = lscd.ysize as usize;
-                if option.debug_flag > 0 {
-                    option.drawer.verbose(&format!("{:?}", lscd), None)?;
-                    option.drawer.verbose(
-                        &format!(
-                            "{} {} {} data length {}",
-                            width,
-                            height,
-                            width * height,
-                            data.len()
-                        ),
-                        None,
-                    )?;
-                }
-
-                let interlace_start_y = [0, 4, 2, 1];
-                let interlace_delta_y = [8, 8, 4, 2];
-                let mut interlace_mode = 0;
-                let mut interlace_y = interlace_start_y[interlace_mode];
-                let is_interlace = (lscd.field & 0x40) == 0x40;
-                let mut frame_buffer = vec![0_u8; width * height * 4];
-
-                for y in 0..height {
-                    let mut line: Vec<u8> = vec![0; width * 4];
-                    let offset = y * width;
-                    for x in 0..width {
-                        let color = data[offset + x] as usize;
-                        line[x * 4] = color_table[color].red;
-                        line[x * 4 + 1] = color_table[color].green;
-                        line[x * 4 + 2] = color_table[color].blue;
-                        line[x * 4 + 3] = color_table[color].alpha;
-                    }
-                    if is_interlace {
-                        let offset = interlace_y * width * 4;
-                        frame_buffer[offset..offset + width * 4].copy_from_slice(&line);
-                        if interlace_y == 16 {
-                            interlace_y += 8;
-                        } else {
-                            interlace_y += interlace_delta_y[interlace_mode];
-                        }
-                        if interlace_y >= height {
-                            interlace_mode += 1;
-                            if interlace_mode >= interlace_start_y.len() {
-                                break;
-                            }
-                            interlace_y = interlace_start_y[interlace_mode];
-                        }
-                    } else {
-                        let offset = y * width * 4;
-                        frame_buffer[offset..offset + width * 4].copy_from_slice(&line);
-                    }
-                }
-
-                if is_first {
-                    draw_frame(
-                        option,
-                        lscd.xstart as usize,
-                        lscd.ystart as usize,
-                        width,
-                        height,
-                        &frame_buffer,
-                    )?;
-
-                    let opt = NextOptions {
-                        flag: NextOption::Continue,
-                        await_time: gif_delay_ms(delay_time as usize),
-                        image_rect: Some(ImageRect {
-                            width,
-                            height,
-                            start_x: lscd.xstart as i32,
-                            start_y: lscd.ystart as i32,
-                        }),
-                        dispose_option: Some(gif_dispose_option(dispose_method)),
-                        blend: Some(gif_blend_option(is_transparent)),
-                    };
-                    let result = option.drawer.next(Some(opt))?;
-                    if let Some(response) = result {
-                        if response.response == ResponseCommand::Continue {
-                            draw_frame(option, 0, 0, width, height, &frame_buffer)?;
-                        }
-                    }
-                    is_first = false;
-                } else {
-                    draw_frame(option, 0, 0, width, height, &frame_buffer)?;
+                    None => Err(Box::new(ImgError::new_const(
+                        ImgErrorKind::IllegalData,
+                        "read error in gif decode".to_string(),
+                    ))),
                 }
             }
-            END_MARKER => {
+            Err(err) => Err(err.into()),
+        };
+        match result {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::End) => break,
+            Ok(Flow::Abort) => return Ok(None),
+            // Frames drawn before the damage are kept.
+            Err(err) if state.is_inited => {
+                add_warning(
+                    &mut warnings,
+                    GifWarningKind::TruncatedData,
+                    format!(
+                        "decoding stopped after frame {}: {}",
+                        state.frame_count, err
+                    ),
+                );
                 break;
             }
-            _ => {
-                return Err(Box::new(ImgError::new_const(
-                    ImgErrorKind::IllegalData,
-                    "read error in gif decode".to_string(),
-                )));
-            }
-        };
+            Err(err) => return Err(err),
+        }
     }
 
     Ok(warnings)
@@ -391,7 +805,7 @@ pub fn decode<'decode, B: BinaryReader>(
 
 #[cfg(test)]
 mod tests {
-    use super::gif_delay_ms;
+    use super::{gif_delay_ms, interlaced_row};
 
     #[test]
     fn zero_and_one_centisecond_delays_are_normalized() {
@@ -399,4 +813,17 @@ mod tests {
         assert_eq!(gif_delay_ms(1), 100);
         assert_eq!(gif_delay_ms(2), 20);
     }
+
+    #[test]
+    fn interlaced_rows_cover_every_line_once() {
+        for height in 1..40 {
+            let mut rows: Vec<usize> = (0..height).map(|row| interlaced_row(row, height)).collect();
+            assert_eq!(interlaced_row(0, height), 0);
+            rows.sort_unstable();
+            assert_eq!(rows, (0..height).collect::<Vec<_>>(), "height {height}");
+        }
+        assert_eq!(interlaced_row(1, 16), 8);
+        assert_eq!(interlaced_row(2, 16), 4);
+        assert_eq!(interlaced_row(5, 16), 6);
+    }
 }
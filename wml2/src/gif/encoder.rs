@@ -8,12 +8,20 @@ use crate::draw::{
 use crate::encoder::lzw::encode_gif;
 use crate::error::{ImgError, ImgErrorKind};
 use crate::metadata::DataMap;
+use crate::quantize::{self, ColorHistogram, QuantizeOptions};
 use bin_rs::io::{write_bytes, write_u16_le};
 use std::collections::HashMap;
 
 type Error = Box<dyn std::error::Error>;
 
 const TRANSPARENT_INDEX: u8 = 0;
+/// Packed `0xRRGGBB` color, or `TRANSPARENT_PIXEL` for fully transparent.
+const TRANSPARENT_PIXEL: u32 = u32::MAX;
+const DISPOSE_NONE: u8 = 1;
+const DISPOSE_BACKGROUND: u8 = 2;
+
+/// x, y, width, height
+type FrameRect = (usize, usize, usize, usize);
 
 #[derive(Debug)]
 struct AnimationFrame {
@@ -42,12 +50,24 @@ struct QuantizedFrame {
     min_code_size: usize,
 }
 
-#[derive(Clone, Copy, Default)]
-struct HistogramBin {
-    count: u32,
-    sum_r: u64,
-    sum_g: u64,
-    sum_b: u64,
+/// Encoder settings read from `EncodeOptions.options`.
+struct GifOptions {
+    optimize: bool,
+    interlace: bool,
+    comment: Option<String>,
+    loop_count: Option<u32>,
+    transparency_threshold: u8,
+    transparent_index: u8,
+    dispose: u8,
+    quantize: QuantizeOptions,
+}
+
+/// One frame of an optimized animation, drawn over the previous frames.
+struct OptimizedFrame {
+    canvas: usize,
+    rect: FrameRect,
+    delay_ms: u64,
+    dispose: u8,
 }
 
 fn as_u64(value: Option<&DataMap>, key: &str) -> Result<u64, Error> {
@@ -369,41 +389,24 @@ fn gif_min_code_size(table_len: usize) -> usize {
     table_len.trailing_zeros() as usize
 }
 
-fn nearest_palette_index(color: [u8; 3], palette: &[[u8; 3]], start_index: usize) -> usize {
-    let mut best_index = start_index;
-    let mut best_distance = u32::MAX;
-    for (index, entry) in palette.iter().enumerate().skip(start_index) {
-        let dr = color[0] as i32 - entry[0] as i32;
-        let dg = color[1] as i32 - entry[1] as i32;
-        let db = color[2] as i32 - entry[2] as i32;
-        let distance = (dr * dr + dg * dg + db * db) as u32;
-        if distance < best_distance {
-            best_distance = distance;
-            best_index = index;
-        }
-    }
-    best_index
-}
-
 fn exact_palette_quantization(
     rgba: &[u8],
     width: usize,
     height: usize,
     background: &RGBA,
+    options: &QuantizeOptions,
 ) -> Option<QuantizedFrame> {
     let has_transparency = rgba.chunks_exact(4).any(|pixel| pixel[3] == 0);
-    let palette_limit = if has_transparency { 255 } else { 256 };
-    let mut palette = Vec::with_capacity(palette_limit + has_transparency as usize);
+    let max_colors = options.max_colors.clamp(2, 256);
+    let palette_limit = max_colors - has_transparency as usize;
+    let mut palette = Vec::with_capacity(max_colors);
     let mut lookup = HashMap::new();
     let mut indices = Vec::with_capacity(width * height);
 
-    if has_transparency {
-        palette.push([0, 0, 0]);
-    }
-
     for pixel in rgba.chunks_exact(4) {
         if pixel[3] == 0 {
-            indices.push(TRANSPARENT_INDEX);
+            // Colors use at most 255 indices here, so u8::MAX is free.
+            indices.push(u8::MAX);
             continue;
         }
 
@@ -412,7 +415,7 @@ fn exact_palette_quantization(
         let index = if let Some(index) = lookup.get(&key) {
             *index
         } else {
-            if palette.len() >= palette_limit + has_transparency as usize {
+            if palette.len() >= palette_limit {
                 return None;
             }
             let index = palette.len() as u8;
@@ -423,6 +426,24 @@ fn exact_palette_quantization(
         indices.push(index);
     }
 
+    // The transparent slot was kept free above; colors after it shift up.
+    let transparent_index = has_transparency.then(|| {
+        let index = (options.transparent_index as usize).min(max_colors - 1);
+        if palette.len() < index {
+            palette.resize(index, [0, 0, 0]);
+        }
+        palette.insert(index, [0, 0, 0]);
+        index as u8
+    });
+    if let Some(transparent) = transparent_index {
+        for value in &mut indices {
+            if *value == u8::MAX {
+                *value = transparent;
+            } else if *value >= transparent {
+                *value += 1;
+            }
+        }
+    }
     if palette.is_empty() {
         palette.push([0, 0, 0]);
     }
@@ -430,110 +451,48 @@ fn exact_palette_quantization(
     Some(QuantizedFrame {
         palette,
         indices,
-        transparent_index: has_transparency.then_some(TRANSPARENT_INDEX),
+        transparent_index,
         table_len,
         min_code_size: gif_min_code_size(table_len).max(2),
     })
 }
 
-fn histogram_palette(rgba: &[u8], background: &RGBA, has_transparency: bool) -> Vec<[u8; 3]> {
-    let palette_limit = if has_transparency { 255 } else { 256 };
-    let mut bins = vec![HistogramBin::default(); 32 * 32 * 32];
+/// Flattens partial alpha over the background so only fully transparent
+/// pixels stay transparent.
+fn opaque_rgba(rgba: &[u8], background: &RGBA) -> Vec<u8> {
+    let mut opaque = Vec::with_capacity(rgba.len());
     for pixel in rgba.chunks_exact(4) {
         if pixel[3] == 0 {
-            continue;
+            opaque.extend_from_slice(&[0, 0, 0, 0]);
+        } else {
+            opaque.extend_from_slice(&composite_rgb(pixel, background));
+            opaque.push(0xff);
         }
-        let rgb = composite_rgb(pixel, background);
-        let index =
-            ((rgb[0] as usize >> 3) << 10) | ((rgb[1] as usize >> 3) << 5) | (rgb[2] as usize >> 3);
-        let bin = &mut bins[index];
-        bin.count += 1;
-        bin.sum_r += rgb[0] as u64;
-        bin.sum_g += rgb[1] as u64;
-        bin.sum_b += rgb[2] as u64;
     }
-
-    let mut entries: Vec<_> = bins.into_iter().filter(|bin| bin.count > 0).collect();
-    entries.sort_by(|left, right| right.count.cmp(&left.count));
-
-    let mut palette = Vec::with_capacity(palette_limit + has_transparency as usize);
-    if has_transparency {
-        palette.push([0, 0, 0]);
-    }
-
-    for bin in entries.into_iter().take(palette_limit) {
-        let count = bin.count as u64;
-        palette.push([
-            (bin.sum_r / count) as u8,
-            (bin.sum_g / count) as u8,
-            (bin.sum_b / count) as u8,
-        ]);
-    }
-
-    if palette.is_empty() {
-        palette.push([0, 0, 0]);
-    }
-    palette
+    opaque
 }
 
-fn dithered_palette_quantization(
+fn quantized_palette_quantization(
     rgba: &[u8],
     width: usize,
     height: usize,
     background: &RGBA,
-) -> QuantizedFrame {
-    let has_transparency = rgba.chunks_exact(4).any(|pixel| pixel[3] == 0);
-    let palette = histogram_palette(rgba, background, has_transparency);
-    let start_index = has_transparency as usize;
-    let table_len = gif_table_len(palette.len());
-    let mut indices = vec![0_u8; width * height];
-    let mut current_errors = vec![[0_i32; 3]; width + 2];
-    let mut next_errors = vec![[0_i32; 3]; width + 2];
-
-    for y in 0..height {
-        for x in 0..width {
-            let offset = (y * width + x) * 4;
-            let pixel = &rgba[offset..offset + 4];
-            if pixel[3] == 0 {
-                indices[y * width + x] = TRANSPARENT_INDEX;
-                continue;
-            }
-
-            let mut rgb = composite_rgb(pixel, background);
-            for channel in 0..3 {
-                rgb[channel] = (rgb[channel] as i32 + (current_errors[x + 1][channel] + 8) / 16)
-                    .clamp(0, 255) as u8;
-            }
-
-            let palette_index = nearest_palette_index(rgb, &palette, start_index);
-            let palette_color = palette[palette_index];
-            indices[y * width + x] = palette_index as u8;
-
-            let error = [
-                rgb[0] as i32 - palette_color[0] as i32,
-                rgb[1] as i32 - palette_color[1] as i32,
-                rgb[2] as i32 - palette_color[2] as i32,
-            ];
-
-            for channel in 0..3 {
-                current_errors[x + 2][channel] += error[channel] * 7;
-                next_errors[x][channel] += error[channel] * 3;
-                next_errors[x + 1][channel] += error[channel] * 5;
-                next_errors[x + 2][channel] += error[channel];
-            }
-        }
-
-        current_errors.fill([0, 0, 0]);
-        std::mem::swap(&mut current_errors, &mut next_errors);
-    }
-
-    QuantizedFrame {
-        palette,
-        indices,
-        transparent_index: has_transparency.then_some(TRANSPARENT_INDEX),
+    options: &QuantizeOptions,
+) -> Result<QuantizedFrame, Error> {
+    let quantized = quantize::quantize(&opaque_rgba(rgba, background), width, height, options)?;
+    let table_len = gif_table_len(quantized.palette.len());
+
+    Ok(QuantizedFrame {
+        palette: quantized
+            .palette
+            .iter()
+            .map(|color| [color[0], color[1], color[2]])
+            .collect(),
+        indices: quantized.indices,
+        transparent_index: quantized.transparent_index,
         table_len,
         min_code_size: gif_min_code_size(table_len).max(2),
-    }
+    })
 }
 
 fn quantize_frame(
@@ -541,6 +500,7 @@ fn quantize_frame(
     width: usize,
     height: usize,
     background: &RGBA,
+    options: &QuantizeOptions,
 ) -> Result<QuantizedFrame, Error> {
     let expected_len = width
         .checked_mul(height)
@@ -558,12 +518,10 @@ fn quantize_frame(
         )));
     }
 
-    if let Some(frame) = exact_palette_quantization(rgba, width, height, background) {
+    if let Some(frame) = exact_palette_quantization(rgba, width, height, background, options) {
         return Ok(frame);
     }
-    Ok(dithered_palette_quantization(
-        rgba, width, height, background,
-    ))
+    quantized_palette_quantization(rgba, width, height, background, options)
 }
 
 fn write_sub_blocks(buf: &mut Vec<u8>, data: &[u8]) {
@@ -574,10 +532,17 @@ fn write_sub_blocks(buf: &mut Vec<u8>, data: &[u8]) {
     buf.push(0);
 }
 
+fn write_comment_extension(buf: &mut Vec<u8>, comment: &str) {
+    buf.push(0x21);
+    buf.push(0xfe);
+    write_sub_blocks(buf, comment.as_bytes());
+}
+
 fn write_graphic_control_extension(
     buf: &mut Vec<u8>,
     delay_ms: u64,
     transparent_index: Option<u8>,
+    dispose: u8,
 ) -> Result<(), Error> {
     let delay_cs = if delay_ms == 0 {
         0
@@ -593,7 +558,7 @@ fn write_graphic_control_extension(
     } else {
         0x00
     };
-    buf.push(packed);
+    buf.push(packed | ((dispose & 0x07) << 2));
     write_u16_le(delay_cs as u16, buf);
     buf.push(transparent_index.unwrap_or(0));
     buf.push(0);
@@ -619,32 +584,38 @@ fn write_netscape_loop_extension(buf: &mut Vec<u8>, loop_count: u32) -> Result<(
     Ok(())
 }
 
-fn write_image_descriptor(
-    buf: &mut Vec<u8>,
-    width: usize,
-    height: usize,
-    table_len: usize,
-) -> Result<(), Error> {
-    let width = u16::try_from(width).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "GIF width exceeds u16".to_string(),
-        )) as Error
-    })?;
-    let height = u16::try_from(height).map_err(|_| {
+fn gif_dimension(value: usize, name: &str) -> Result<u16, Error> {
+    u16::try_from(value).map_err(|_| {
         Box::new(ImgError::new_const(
             ImgErrorKind::InvalidParameter,
-            "GIF height exceeds u16".to_string(),
+            format!("GIF {name} exceeds u16"),
         )) as Error
-    })?;
-    let size_code = gif_min_code_size(table_len).saturating_sub(1);
+    })
+}
+
+fn write_image_descriptor(
+    buf: &mut Vec<u8>,
+    rect: FrameRect,
+    local_table_len: Option<usize>,
+    interlace: bool,
+) -> Result<(), Error> {
+    let (x, y, width, height) = rect;
+    let width = gif_dimension(width, "width")?;
+    let height = gif_dimension(height, "height")?;
 
     buf.push(0x2c);
-    write_u16_le(0, buf);
-    write_u16_le(0, buf);
+    write_u16_le(x as u16, buf);
+    write_u16_le(y as u16, buf);
     write_u16_le(width, buf);
     write_u16_le(height, buf);
-    buf.push(0x80 | (size_code as u8 & 0x07));
+    let interlace = if interlace { 0x40 } else { 0x00 };
+    match local_table_len {
+        Some(table_len) => {
+            let size_code = gif_min_code_size(table_len).saturating_sub(1);
+            buf.push(0x80 | interlace | (size_code as u8 & 0x07));
+        }
+        None => buf.push(interlace),
+    }
     Ok(())
 }
 
@@ -659,21 +630,72 @@ fn write_local_color_table(buf: &mut Vec<u8>, quantized: &QuantizedFrame) {
     }
 }
 
+/// Reorders rows into the four GIF interlace passes.
+fn interlace_rows(indices: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut interlaced = Vec::with_capacity(indices.len());
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        for y in (start..height).step_by(step) {
+            interlaced.extend_from_slice(&indices[y * width..(y + 1) * width]);
+        }
+    }
+    interlaced
+}
+
+/// Writes the LZW image data of a frame, interlaced when requested.
+fn write_image_data(
+    buf: &mut Vec<u8>,
+    indices: &[u8],
+    rect: FrameRect,
+    min_code_size: usize,
+    interlace: bool,
+) -> Result<(), Error> {
+    buf.push(min_code_size as u8);
+    let lzw = if interlace {
+        encode_gif(&interlace_rows(indices, rect.2, rect.3), min_code_size)?
+    } else {
+        encode_gif(indices, min_code_size)?
+    };
+    write_sub_blocks(buf, &lzw);
+    Ok(())
+}
+
+/// Turns pixels with alpha below `threshold` fully transparent and flattens
+/// the rest over the background.
+fn apply_transparency_threshold(rgba: &mut [u8], background: &RGBA, threshold: u8) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        if pixel[3] < threshold {
+            pixel.copy_from_slice(&[0, 0, 0, 0]);
+        } else if pixel[3] != 255 {
+            let rgb = composite_rgb(pixel, background);
+            pixel.copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+        }
+    }
+}
+
 fn encode_frame(
     buf: &mut Vec<u8>,
-    rgba: &[u8],
-    width: usize,
-    height: usize,
-    background: &RGBA,
+    quantized: &QuantizedFrame,
+    rect: FrameRect,
     delay_ms: u64,
+    dispose: u8,
+    interlace: bool,
 ) -> Result<(), Error> {
-    let quantized = quantize_frame(rgba, width, height, background)?;
-    write_graphic_control_extension(buf, delay_ms, quantized.transparent_index)?;
-    write_image_descriptor(buf, width, height, quantized.table_len)?;
-    write_local_color_table(buf, &quantized);
-    buf.push(quantized.min_code_size as u8);
-    let lzw = encode_gif(&quantized.indices, quantized.min_code_size)?;
-    write_sub_blocks(buf, &lzw);
+    write_graphic_control_extension(buf, delay_ms, quantized.transparent_index, dispose)?;
+    write_image_descriptor(buf, rect, Some(quantized.table_len), interlace)?;
+    write_local_color_table(buf, quantized);
+    write_image_data(
+        buf,
+        &quantized.indices,
+        rect,
+        quantized.min_code_size,
+        interlace,
+    )
+}
+
+fn write_header(buf: &mut Vec<u8>, width: usize, height: usize) -> Result<(), Error> {
+    write_bytes(b"GIF89a", buf);
+    write_u16_le(gif_dimension(width, "width")?, buf);
+    write_u16_le(gif_dimension(height, "height")?, buf);
     Ok(())
 }
 
@@ -682,77 +704,399 @@ fn encode_still(
     height: usize,
     rgba: &[u8],
     background: &RGBA,
+    options: &GifOptions,
 ) -> Result<Vec<u8>, Error> {
-    let width = u16::try_from(width).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "GIF width exceeds u16".to_string(),
-        )) as Error
-    })?;
-    let height = u16::try_from(height).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "GIF height exceeds u16".to_string(),
-        )) as Error
-    })?;
     let mut data = Vec::new();
-    write_bytes(b"GIF89a", &mut data);
-    write_u16_le(width, &mut data);
-    write_u16_le(height, &mut data);
+    write_header(&mut data, width, height)?;
     data.extend_from_slice(&[0x00, 0x00, 0x00]);
+    if let Some(comment) = &options.comment {
+        write_comment_extension(&mut data, comment);
+    }
+
+    let mut rgba = rgba.to_vec();
+    apply_transparency_threshold(&mut rgba, background, options.transparency_threshold);
+    let quantize = QuantizeOptions {
+        transparent_index: options.transparent_index,
+        ..options.quantize.clone()
+    };
+    let quantized = quantize_frame(&rgba, width, height, background, &quantize)?;
     encode_frame(
         &mut data,
-        rgba,
-        width as usize,
-        height as usize,
-        background,
+        &quantized,
+        (0, 0, width, height),
         0,
+        options.dispose,
+        options.interlace,
     )?;
     data.push(0x3b);
     Ok(data)
 }
 
-fn encode_animation(profile: &ImageProfiles, animation: AnimationInfo) -> Result<Vec<u8>, Error> {
+/// Composes animation frames to full canvases with the transparency
+/// threshold applied.
+fn threshold_animation_frames(
+    profile: &ImageProfiles,
+    animation: &AnimationInfo,
+    options: &GifOptions,
+) -> Result<Vec<(Vec<u8>, u64)>, Error> {
+    let mut frames = compose_animation_frames(profile, animation)?;
+    for (canvas, _) in &mut frames {
+        apply_transparency_threshold(
+            canvas,
+            &animation.background,
+            options.transparency_threshold,
+        );
+    }
+    Ok(frames)
+}
+
+fn encode_animation(
+    profile: &ImageProfiles,
+    animation: AnimationInfo,
+    options: &GifOptions,
+) -> Result<Vec<u8>, Error> {
     let mut data = Vec::new();
-    write_bytes(b"GIF89a", &mut data);
-    let width = u16::try_from(profile.width).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "GIF width exceeds u16".to_string(),
-        )) as Error
-    })?;
-    let height = u16::try_from(profile.height).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "GIF height exceeds u16".to_string(),
-        )) as Error
-    })?;
-    write_u16_le(width, &mut data);
-    write_u16_le(height, &mut data);
+    write_header(&mut data, profile.width, profile.height)?;
     data.extend_from_slice(&[0x00, 0x00, 0x00]);
 
     write_netscape_loop_extension(&mut data, animation.loop_count)?;
-    for (canvas, delay_ms) in compose_animation_frames(profile, &animation)? {
-        encode_frame(
-            &mut data,
+    if let Some(comment) = &options.comment {
+        write_comment_extension(&mut data, comment);
+    }
+    let rect = (0, 0, profile.width, profile.height);
+    for (canvas, delay_ms) in threshold_animation_frames(profile, &animation, options)? {
+        let quantized = quantize_frame(
             &canvas,
             profile.width,
             profile.height,
             &animation.background,
-            delay_ms,
+            &options.quantize,
         )?;
+        encode_frame(&mut data, &quantized, rect, delay_ms, 0, options.interlace)?;
     }
     data.push(0x3b);
     Ok(data)
 }
 
+fn gif_pixels(rgba: &[u8], background: &RGBA) -> Vec<u32> {
+    rgba.chunks_exact(4)
+        .map(|pixel| {
+            if pixel[3] == 0 {
+                TRANSPARENT_PIXEL
+            } else {
+                let rgb = composite_rgb(pixel, background);
+                ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32
+            }
+        })
+        .collect()
+}
+
+fn gif_pixel_rgba(pixel: u32) -> [u8; 4] {
+    if pixel == TRANSPARENT_PIXEL {
+        [0, 0, 0, 0]
+    } else {
+        [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 0xff]
+    }
+}
+
+fn union_rect(left: FrameRect, right: FrameRect) -> FrameRect {
+    let x = left.0.min(right.0);
+    let y = left.1.min(right.1);
+    let end_x = (left.0 + left.2).max(right.0 + right.2);
+    let end_y = (left.1 + left.3).max(right.1 + right.3);
+    (x, y, end_x - x, end_y - y)
+}
+
+/// Bounding box of the pixels for which `select(index)` holds.
+fn bounding_rect(width: usize, height: usize, select: impl Fn(usize) -> bool) -> Option<FrameRect> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            if select(y * width + x) {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+    }
+    (min_x != usize::MAX).then(|| (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+fn clear_pixels(canvas: &mut [u32], width: usize, rect: FrameRect) {
+    for y in rect.1..rect.1 + rect.3 {
+        canvas[y * width + rect.0..y * width + rect.0 + rect.2].fill(TRANSPARENT_PIXEL);
+    }
+}
+
+/// Chooses the sub-rectangle and disposal of each frame. Identical frames are
+/// merged into the previous frame's delay. Pixels that turn transparent are
+/// cleared by disposing the previous frame to the background.
+fn plan_optimized_frames(
+    width: usize,
+    height: usize,
+    canvases: &[Vec<u32>],
+    delays: &[u64],
+) -> Vec<OptimizedFrame> {
+    let mut frames = vec![OptimizedFrame {
+        canvas: 0,
+        rect: (0, 0, width, height),
+        delay_ms: delays[0],
+        dispose: DISPOSE_NONE,
+    }];
+    let mut shown = &canvases[0];
+
+    for (index, target) in canvases.iter().enumerate().skip(1) {
+        let mut base = shown.clone();
+        let cleared = bounding_rect(width, height, |pixel| {
+            target[pixel] == TRANSPARENT_PIXEL && shown[pixel] != TRANSPARENT_PIXEL
+        });
+        if let (Some(cleared), Some(previous)) = (cleared, frames.last_mut()) {
+            previous.rect = union_rect(previous.rect, cleared);
+            previous.dispose = DISPOSE_BACKGROUND;
+            clear_pixels(&mut base, width, previous.rect);
+        }
+
+        let rect = match bounding_rect(width, height, |pixel| target[pixel] != base[pixel]) {
+            Some(rect) => rect,
+            None if cleared.is_some() => (0, 0, 1, 1),
+            None => {
+                if let Some(previous) = frames.last_mut() {
+                    previous.delay_ms += delays[index];
+                }
+                continue;
+            }
+        };
+        frames.push(OptimizedFrame {
+            canvas: index,
+            rect,
+            delay_ms: delays[index],
+            dispose: DISPOSE_NONE,
+        });
+        shown = target;
+    }
+    frames
+}
+
+/// Builds one palette shared by every frame. Index 0 is reserved for
+/// transparency so unchanged pixels can be skipped.
+fn global_palette(
+    canvases: &[Vec<u32>],
+    options: &QuantizeOptions,
+) -> (Vec<[u8; 4]>, Option<HashMap<u32, u8>>) {
+    let mut palette = vec![[0, 0, 0, 0]];
+    let mut lookup = HashMap::new();
+    for &pixel in canvases.iter().flatten() {
+        if pixel == TRANSPARENT_PIXEL || lookup.contains_key(&pixel) {
+            continue;
+        }
+        if palette.len() == options.max_colors.clamp(2, 256) {
+            let mut histogram = ColorHistogram::new(false);
+            for &pixel in canvases.iter().flatten() {
+                histogram.add_pixel(gif_pixel_rgba(pixel));
+            }
+            let options = QuantizeOptions {
+                reserve_transparent: true,
+                ..options.clone()
+            };
+            return (histogram.palette(&options), None);
+        }
+        lookup.insert(pixel, palette.len() as u8);
+        palette.push(gif_pixel_rgba(pixel));
+    }
+    (palette, Some(lookup))
+}
+
+fn encode_optimized_animation(
+    profile: &ImageProfiles,
+    animation: &AnimationInfo,
+    options: &GifOptions,
+) -> Result<Vec<u8>, Error> {
+    let (width, height) = (profile.width, profile.height);
+    let composed = threshold_animation_frames(profile, animation, options)?;
+    let delays: Vec<u64> = composed.iter().map(|(_, delay_ms)| *delay_ms).collect();
+    let canvases: Vec<Vec<u32>> = composed
+        .iter()
+        .map(|(canvas, _)| gif_pixels(canvas, &animation.background))
+        .collect();
+    drop(composed);
+
+    let (palette, lookup) = global_palette(&canvases, &options.quantize);
+    let table_len = gif_table_len(palette.len());
+    let min_code_size = gif_min_code_size(table_len).max(2);
+
+    let mut data = Vec::new();
+    write_header(&mut data, width, height)?;
+    let size_code = gif_min_code_size(table_len).saturating_sub(1) as u8;
+    data.extend_from_slice(&[0xf0 | size_code, TRANSPARENT_INDEX, 0x00]);
+    for color in &palette {
+        data.extend_from_slice(&color[..3]);
+    }
+    for _ in palette.len()..table_len {
+        data.extend_from_slice(&[0, 0, 0]);
+    }
+    write_netscape_loop_extension(&mut data, animation.loop_count)?;
+    if let Some(comment) = &options.comment {
+        write_comment_extension(&mut data, comment);
+    }
+
+    let mut shown = vec![TRANSPARENT_PIXEL; width * height];
+    for frame in plan_optimized_frames(width, height, &canvases, &delays) {
+        let target = &canvases[frame.canvas];
+        let (x, y, rect_width, rect_height) = frame.rect;
+        let mut rgba = Vec::with_capacity(rect_width * rect_height * 4);
+        for row in y..y + rect_height {
+            for offset in row * width + x..row * width + x + rect_width {
+                let pixel = target[offset];
+                if pixel == shown[offset] || pixel == TRANSPARENT_PIXEL {
+                    rgba.extend_from_slice(&[0, 0, 0, 0]);
+                } else {
+                    rgba.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]);
+                    rgba.push(0xff);
+                }
+                shown[offset] = pixel;
+            }
+        }
+
+        let indices = match lookup.as_ref() {
+            Some(lookup) => rgba
+                .chunks_exact(4)
+                .map(|pixel| {
+                    if pixel[3] == 0 {
+                        TRANSPARENT_INDEX
+                    } else {
+                        let key =
+                            ((pixel[0] as u32) << 16) | ((pixel[1] as u32) << 8) | pixel[2] as u32;
+                        lookup[&key]
+                    }
+                })
+                .collect(),
+            None => quantize::remap(&rgba, rect_width, rect_height, &palette, &options.quantize)?,
+        };
+
+        write_graphic_control_extension(
+            &mut data,
+            frame.delay_ms,
+            Some(TRANSPARENT_INDEX),
+            frame.dispose,
+        )?;
+        write_image_descriptor(&mut data, frame.rect, None, options.interlace)?;
+        write_image_data(
+            &mut data,
+            &indices,
+            frame.rect,
+            min_code_size,
+            options.interlace,
+        )?;
+
+        if frame.dispose == DISPOSE_BACKGROUND {
+            clear_pixels(&mut shown, width, frame.rect);
+        }
+    }
+    data.push(0x3b);
+    Ok(data)
+}
+
+fn gif_option_uint(
+    options: &HashMap<String, DataMap>,
+    key: &str,
+    max: u64,
+) -> Result<Option<u64>, Error> {
+    let value = match options.get(key) {
+        None => return Ok(None),
+        Some(DataMap::UInt(value)) => Some(*value),
+        Some(DataMap::SInt(value)) => u64::try_from(*value).ok(),
+        Some(_) => None,
+    };
+    match value {
+        Some(value) if value <= max => Ok(Some(value)),
+        _ => Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            format!("{key} must be an integer in 0..={max}"),
+        ))),
+    }
+}
+
+fn gif_options(image: &DrawEncodeOptions<'_>) -> Result<GifOptions, Error> {
+    let mut gif = GifOptions {
+        optimize: false,
+        interlace: false,
+        comment: None,
+        loop_count: None,
+        transparency_threshold: 1,
+        transparent_index: TRANSPARENT_INDEX,
+        dispose: 0,
+        quantize: QuantizeOptions::from_options(image.options.as_ref())?,
+    };
+    let Some(options) = image.options.as_ref() else {
+        return Ok(gif);
+    };
+
+    if let Some(value) = gif_option_uint(options, "optimize", u64::MAX)? {
+        gif.optimize = value > 0;
+    }
+    if let Some(value) = gif_option_uint(options, "interlace", u64::MAX)? {
+        gif.interlace = value > 0;
+    }
+    if let Some(value) = gif_option_uint(options, "loop_count", u16::MAX as u64)? {
+        gif.loop_count = Some(value as u32);
+    }
+    if let Some(value) = gif_option_uint(options, "transparency_threshold", 255)? {
+        gif.transparency_threshold = value as u8;
+    }
+    if let Some(value) = gif_option_uint(options, "transparent_index", 255)? {
+        if value as usize >= gif.quantize.max_colors {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "transparent_index must be below max_colors".to_string(),
+            )));
+        }
+        gif.transparent_index = value as u8;
+    }
+    if let Some(value) = gif_option_uint(options, "dispose", 3)? {
+        gif.dispose = value as u8;
+    }
+    match options.get("comment") {
+        None => {}
+        Some(DataMap::Ascii(comment)) => gif.comment = Some(comment.clone()),
+        Some(_) => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "comment must be a string".to_string(),
+            )));
+        }
+    }
+    Ok(gif)
+}
+
 /// Encodes an image source to GIF89a.
 ///
-/// Palette generation uses a two-pass histogram palette and error diffusion
-/// when the frame exceeds 256 colors. Frames with 256 colors or fewer are kept
-/// exact without dithering. Animated input is flattened to full-canvas GIF
-/// frames with per-frame local palettes.
+/// Frames with more than `max_colors` colors are reduced with
+/// [`crate::quantize`] (median-cut with k-means refinement and
+/// Floyd-Steinberg by default). Smaller frames are kept exact without
+/// dithering. Animated input is flattened to full-canvas GIF frames with
+/// per-frame local palettes.
+///
+/// Supported `EncodeOptions.options` keys:
+/// - `optimize`: a non-zero value encodes animations with one global palette,
+///   stores only the changed rectangle of each frame, and marks unchanged
+///   pixels transparent
+/// - `interlace`: a non-zero value writes interlaced image data
+/// - `comment`: text stored in a comment extension
+/// - `loop_count`: overrides the animation loop count, `0` loops forever
+/// - `transparency_threshold`: pixels with alpha below this value become
+///   transparent, others are flattened over the background (default `1`)
+/// - `transparent_index`: palette index of the transparent color in a still
+///   image, below `max_colors` (default `0`)
+/// - `dispose`: disposal method `0..=3` written for a still image
+/// - `max_colors`, `quantize`, `kmeans`, `color_distance`: palette generation
+///   settings
+/// - `dither`, `serpentine`, `dither_strength`: dithering for frames that do
+///   not fit an exact palette
+///
+/// See [`QuantizeOptions::from_options`] for the palette and dithering values.
 pub fn encode(image: &mut DrawEncodeOptions<'_>) -> Result<Vec<u8>, Error> {
+    let options = gif_options(image)?;
     let profile = image.drawer.encode_start(None)?;
     let profile = profile.ok_or_else(|| {
         Box::new(ImgError::new_const(
@@ -767,8 +1111,15 @@ pub fn encode(image: &mut DrawEncodeOptions<'_>) -> Result<Vec<u8>, Error> {
         alpha: 0,
     });
 
-    let data = if let Some(animation) = parse_animation_info(&profile)? {
-        encode_animation(&profile, animation)?
+    let data = if let Some(mut animation) = parse_animation_info(&profile)? {
+        if let Some(loop_count) = options.loop_count {
+            animation.loop_count = loop_count;
+        }
+        if options.optimize {
+            encode_optimized_animation(&profile, &animation, &options)?
+        } else {
+            encode_animation(&profile, animation, &options)?
+        }
     } else {
         let rgba = image
             .drawer
@@ -779,7 +1130,7 @@ pub fn encode(image: &mut DrawEncodeOptions<'_>) -> Result<Vec<u8>, Error> {
                     "Image buffer nothing".to_string(),
                 )) as Error
             })?;
-        encode_still(profile.width, profile.height, &rgba, &background)?
+        encode_still(profile.width, profile.height, &rgba, &background, &options)?
     };
 
     image.drawer.encode_end(None)?;
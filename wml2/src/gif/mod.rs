@@ -2,4 +2,6 @@
 
 pub mod decoder;
 pub mod encoder;
+mod font;
 pub mod header;
+pub mod warning;
@@ -0,0 +1,65 @@
+//! GIF-specific warning types.
+
+use crate::warning::{ImgWarning, WarningKind};
+use std::fmt::*;
+
+#[derive(Debug)]
+pub enum GifWarningKind {
+    TruncatedData,
+    MissingRows,
+    InvalidCode,
+    CodeTableOverflow,
+    InvalidColorIndex,
+    MalformedBlock,
+}
+
+pub struct GifWarning {
+    kind: GifWarningKind,
+    message: Option<String>,
+}
+
+impl ImgWarning for GifWarning {}
+
+impl Debug for GifWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for GifWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.kind.as_str(), message),
+            None => write!(f, "{}", self.kind.as_str()),
+        }
+    }
+}
+
+impl GifWarning {
+    pub fn new(kind: GifWarningKind) -> Self {
+        Self {
+            kind,
+            message: None,
+        }
+    }
+
+    pub fn new_const(kind: GifWarningKind, message: String) -> Self {
+        Self {
+            kind,
+            message: Some(message),
+        }
+    }
+}
+
+impl WarningKind for GifWarningKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GifWarningKind::TruncatedData => "Truncated data",
+            GifWarningKind::MissingRows => "Missing rows",
+            GifWarningKind::InvalidCode => "Invalid LZW code",
+            GifWarningKind::CodeTableOverflow => "LZW code table overflow",
+            GifWarningKind::InvalidColorIndex => "Invalid color index",
+            GifWarningKind::MalformedBlock => "Malformed block",
+        }
+    }
+}
@@ -3,7 +3,9 @@
 type Error = Box<dyn std::error::Error>;
 
 use super::header::{IcoEntry, IcoHeader};
-use crate::draw::{DecodeOptions, ImageBuffer, InitOptions};
+use crate::draw::{
+    DecodeOptions, ImageBuffer, InitOptions, options_without_page, page_option, page_out_of_range,
+};
 use crate::error::{ImgError, ImgErrorKind};
 use crate::metadata::DataMap;
 use crate::warning::ImgWarnings;
@@ -305,6 +307,7 @@ fn decode_png_payload(
         let mut part_option = DecodeOptions {
             debug_flag: option.debug_flag,
             drawer: &mut image,
+            options: options_without_page(option.options.as_ref()),
         };
         let mut reader = BytesReader::from(data);
         let warnings = crate::png::decoder::decode(&mut reader, &mut part_option)?;
@@ -337,6 +340,7 @@ fn decode_dib_payload(
         let mut part_option = DecodeOptions {
             debug_flag: option.debug_flag,
             drawer: &mut image,
+            options: options_without_page(option.options.as_ref()),
         };
         let mut reader = BytesReader::from(bmp);
         let warnings = crate::bmp::decoder::decode(&mut reader, &mut part_option)?;
@@ -358,6 +362,12 @@ fn decode_dib_payload(
     }
 }
 
+/// Returns the number of directory entries.
+pub fn page_count<B: BinaryReader>(reader: &mut B) -> Result<usize, Error> {
+    Ok(IcoHeader::new(reader)?.entries.len())
+}
+
+/// Decodes the largest entry, or the one picked by the `page` option.
 pub fn decode<B: BinaryReader>(
     reader: &mut B,
     option: &mut DecodeOptions,
@@ -371,7 +381,11 @@ pub fn decode<B: BinaryReader>(
         option.drawer.verbose(&format!("{header:?}"), None)?;
     }
 
-    let selected_index = header.best_entry_index();
+    let selected_index = match page_option(option.options.as_ref())? {
+        Some(page) if page < header.entries.len() => page,
+        Some(page) => return Err(page_out_of_range(page, header.entries.len())),
+        None => header.best_entry_index(),
+    };
     let entry = &header.entries[selected_index];
     let image_start = start
         .checked_add(entry.image_offset as u64)
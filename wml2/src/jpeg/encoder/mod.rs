@@ -9,15 +9,18 @@ mod quantize_table;
 use crate::draw::EncodeOptions as DrawEncodeOptions;
 use crate::error::{ImgError, ImgErrorKind};
 use crate::metadata::{DataMap, get_exif_option};
+use std::collections::HashMap;
 
 type Error = Box<dyn std::error::Error>;
 
 pub use self::encoder::create_qt;
 
 pub(crate) fn quality_from_draw_options(option: &DrawEncodeOptions<'_>) -> usize {
-    option
-        .options
-        .as_ref()
+    quality_from_options(option.options.as_ref())
+}
+
+pub(crate) fn quality_from_options(options: Option<&HashMap<String, DataMap>>) -> usize {
+    options
         .and_then(|map| map.get("quality"))
         .and_then(|value| match value {
             DataMap::UInt(v) => Some(*v as usize),
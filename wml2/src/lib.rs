@@ -190,5 +190,6 @@ pub mod avif;
 pub mod color;
 pub mod decoder;
 pub mod metadata;
+pub mod quantize;
 #[cfg(feature = "webp")]
 pub mod webp;
@@ -226,6 +226,8 @@ fn inferred_length(data: &DataPack) -> usize {
         DataPack::Double(data) => data.len(),
         DataPack::SShort(data) => data.len(),
         DataPack::SLong(data) => data.len(),
+        DataPack::Long8(data) => data.len(),
+        DataPack::SLong8(data) => data.len(),
         DataPack::Unkown(data) => data.len(),
         DataPack::Undef(data) => data.len(),
     }
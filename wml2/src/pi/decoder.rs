@@ -5,6 +5,7 @@ use bin_rs::reader::BinaryReader;
 use crate::draw::DecodeOptions;
 use crate::error::ImgErrorKind;
 use crate::metadata::DataMap;
+use crate::quantize::PC98_16_PALETTE;
 use crate::retro::{BitReaderWordMsb, ByteCursor, draw_indexed, err, read_all};
 use crate::warning::ImgWarnings;
 
@@ -23,20 +24,16 @@ struct PiHeader {
 }
 
 fn create_default_palette(plane: usize) -> Vec<(u8, u8, u8)> {
-    let color_count = 1usize << plane;
-    let mut palette = Vec::with_capacity(color_count);
     if plane == 4 {
-        for i in 0..16 {
-            let level = if (i & 0x08) != 0 { 0xff } else { 0x77 };
-            palette.push((
-                if (i & 0x02) != 0 { level } else { 0 },
-                if (i & 0x04) != 0 { level } else { 0 },
-                if (i & 0x01) != 0 { level } else { 0 },
-            ));
-        }
-        return palette;
+        return PC98_16_PALETTE
+            .iter()
+            .map(|color| (color[0], color[1], color[2]))
+            .collect();
     }
 
+    let color_count = 1usize << plane;
+    let mut palette = Vec::with_capacity(color_count);
+
     for i in 0..color_count {
         palette.push((
             (((i & 0x1c) >> 2) * 255 / 7) as u8,
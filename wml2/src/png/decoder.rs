@@ -4,16 +4,23 @@
 use crate::color::RGBA;
 use crate::draw::*;
 use crate::error::*;
+use crate::metadata::DataMap;
 use crate::png::header::*;
+use crate::png::utils::CRC32;
+use crate::png::utils::ColorCorrection;
 use crate::png::utils::make_metadata;
 use crate::png::utils::paeth_dec;
+use crate::png::utils::update_adler32;
 use crate::png::warning::PngWarning;
 use crate::warning::*;
+use bin_rs::io::{read_u16_be, read_u32_be};
 use bin_rs::reader::BinaryReader;
+use miniz_oxide::inflate::stream::{InflateState, inflate};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
 type Error = Box<dyn std::error::Error>;
 
-const START_X: [usize; 7] = [0, 0, 4, 0, 2, 0, 1];
-const START_Y: [usize; 7] = [0, 4, 0, 2, 0, 1, 0];
+const START_X: [usize; 7] = [0, 4, 0, 2, 0, 1, 0];
+const START_Y: [usize; 7] = [0, 0, 4, 0, 2, 0, 1];
 const STEP_Y: [usize; 7] = [8, 8, 8, 4, 4, 2, 2];
 const STEP_X: [usize; 7] = [8, 8, 4, 4, 2, 2, 1];
 
@@ -40,797 +47,384 @@ fn draw_rect(header: &PngHeader) -> (u32, u32) {
     }
 }
 
-fn load_grayscale(
-    header: &PngHeader,
-    buffer: &[u8],
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
-    let is_alpha = if header.color_type == 4 { 1 } else { 0 };
-    let (width, height) = draw_rect(header);
-    let raw_length = (width * (header.bitpersample as u32 / 8 + is_alpha) + 1) as usize;
-    let mut prev_buf: Vec<u8> = Vec::new();
+fn check_color(pallet: &[RGBA], color: usize) -> Result<(), Error> {
+    if color >= pallet.len() {
+        return Err(png_error(
+            ImgErrorKind::IllegalData,
+            format!("palette index {} is out of range {}", color, pallet.len()),
+        ));
+    }
+    Ok(())
+}
 
-    for y in 0..height as usize {
-        let mut ptr = raw_length * y;
-        let flag = buffer[ptr];
-        let mut outbuf: Vec<u8> = (0..width * 4).map(|_| 0).collect();
-        ptr += 1;
-        let mut outptr = 0;
-        for _ in 0..width as usize {
-            let (mut gray, mut alpha) = (0, 0xff);
-            match header.bitpersample {
-                16 => {
-                    gray = buffer[ptr];
-                    ptr += 2;
-                    if is_alpha == 1 {
-                        alpha = buffer[ptr];
-                        ptr += 2;
-                    } else {
-                        alpha = 0xff;
-                    }
-                }
-                8 => {
-                    gray = buffer[ptr];
-                    ptr += 1;
-                    if is_alpha == 1 {
-                        alpha = buffer[ptr];
-                        ptr += 1;
-                    } else {
-                        alpha = 0xff;
-                    }
-                }
-                _ => {}
+fn unfilter(flag: u8, row: &mut [u8], prev: &[u8], bpp: usize) {
+    match flag {
+        1 => {
+            // Sub
+            for i in bpp..row.len() {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
             }
-            match flag {
-                1 => {
-                    // Sub
-                    if outptr > 0 {
-                        gray = gray.wrapping_add(outbuf[outptr - 4]);
-                        alpha = alpha.wrapping_add(outbuf[outptr - 1]);
-                    }
-                }
-                2 => {
-                    // Up
-                    if !prev_buf.is_empty() {
-                        gray = gray.wrapping_add(prev_buf[outptr]);
-                        alpha = alpha.wrapping_add(prev_buf[outptr + 3]);
-                    }
-                }
-                3 => {
-                    // Avalage
-                    let (mut gray_, mut alpha_);
-                    if outptr > 0 {
-                        gray_ = outbuf[outptr - 4] as u32;
-                        alpha_ = outbuf[outptr - 1] as u32;
-                    } else {
-                        gray_ = 0;
-                        alpha_ = 0;
-                    }
-                    if !prev_buf.is_empty() {
-                        gray_ += prev_buf[outptr] as u32;
-                        alpha_ += prev_buf[outptr + 3] as u32;
-                    } else {
-                        gray_ += 0;
-                        alpha_ += 0;
-                    }
-                    gray_ /= 2;
-                    alpha_ /= 2;
-
-                    gray = gray.wrapping_add(gray_ as u8);
-                    alpha = alpha.wrapping_add(alpha_ as u8);
-                }
-                4 => {
-                    // Pease
-                    let (gray_a, alpha_a);
-                    if outptr > 0 {
-                        gray_a = outbuf[outptr - 4] as i32;
-                        alpha_a = outbuf[outptr - 1] as i32;
-                    } else {
-                        gray_a = 0;
-                        alpha_a = 0;
-                    }
-                    let (gray_b, alpha_b);
-                    if !prev_buf.is_empty() {
-                        gray_b = prev_buf[outptr] as i32;
-                        alpha_b = prev_buf[outptr + 3] as i32;
-                    } else {
-                        gray_b = 0;
-                        alpha_b = 0;
-                    }
-                    let (gray_c, alpha_c);
-                    if !prev_buf.is_empty() && outptr > 0 {
-                        gray_c = prev_buf[outptr - 4] as i32;
-                        alpha_c = prev_buf[outptr - 1] as i32;
-                    } else {
-                        gray_c = 0;
-                        alpha_c = 0;
-                    }
-
-                    gray = paeth_dec(gray, gray_a, gray_b, gray_c);
-                    alpha = paeth_dec(alpha, alpha_a, alpha_b, alpha_c);
-                }
-                _ => {} // None
+        }
+        2 => {
+            // Up
+            for i in 0..row.len() {
+                row[i] = row[i].wrapping_add(prev[i]);
             }
-            outbuf[outptr] = gray;
-            outbuf[outptr + 1] = gray;
-            outbuf[outptr + 2] = gray;
-            if is_alpha == 0 {
-                alpha = 0xff;
+        }
+        3 => {
+            // Average
+            for i in 0..row.len() {
+                let left = if i >= bpp { row[i - bpp] as u32 } else { 0 };
+                row[i] = row[i].wrapping_add(((left + prev[i] as u32) / 2) as u8);
             }
-            outbuf[outptr + 3] = alpha;
-            outptr += 4;
         }
-        option.drawer.draw(0, y, width as usize, 1, &outbuf, None)?;
-        prev_buf = outbuf;
+        4 => {
+            // Paeth
+            for i in 0..row.len() {
+                let (left, upper_left) = if i >= bpp {
+                    (row[i - bpp] as i32, prev[i - bpp] as i32)
+                } else {
+                    (0, 0)
+                };
+                row[i] = paeth_dec(row[i], left, prev[i] as i32, upper_left);
+            }
+        }
+        _ => {} // None
     }
-    Ok(None)
 }
 
-fn load_grayscale_progressive(
-    header: &PngHeader,
-    buffer: &[u8],
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
-    let is_alpha = if header.color_type == 6 { 1 } else { 0 };
-    let mut prev_buf: Vec<u8> = Vec::new();
-
-    let (width, height) = draw_rect(header);
-    let mut ptr = 0;
+/// Inflates IDAT/fdAT data incrementally and draws each scanline as soon as
+/// it has been unfiltered, so the compressed stream is never held in memory.
+struct ScanlineDecoder {
+    inflater: Box<InflateState>,
+    inflated: Vec<u8>,
+    color_type: u8,
+    bitpersample: usize,
+    channels: usize,
+    /// Palette for indexed images and gray levels for low bit depth grayscale.
+    pallet: Vec<RGBA>,
+    width: usize,
+    height: usize,
+    interlaced: bool,
+    pass: usize,
+    pass_width: usize,
+    pass_height: usize,
+    y: usize,
+    bpp: usize,
+    /// Filter type byte of the scanline being read, kept apart from `row`.
+    filter: u8,
+    row: Vec<u8>,
+    /// Bytes of the current scanline read so far, counting the filter byte.
+    filled: usize,
+    prev: Vec<u8>,
+    rgba: Vec<u8>,
+    /// Decoded frame kept for redrawing the default image as APNG frame 0.
+    replay: Option<Vec<u8>>,
+    done: bool,
+    stream_end: bool,
+    checksum: ChecksumMode,
+    adler: u32,
+}
 
-    for i in 0..7 {
-        let sx = START_Y[i];
-        let sy = START_X[i];
-        let step_x = STEP_X[i];
-        let step_y = STEP_Y[i];
-        let mut y = sy;
-        while y < height as usize {
-            let mut outbuf: Vec<u8> = (0..width * 4).map(|_| 0).collect();
-            let flag = buffer[ptr];
-            ptr += 1;
-            let mut outptr = 0;
-            let mut x = sx;
-            while x < width as usize {
-                let (mut gray, mut alpha) = (0, 0xff);
-                match header.bitpersample {
-                    16 => {
-                        gray = buffer[ptr];
-                        ptr += 2;
-                        if is_alpha == 1 {
-                            alpha = buffer[ptr];
-                            ptr += 2;
-                        } else {
-                            alpha = 0xff;
-                        }
-                    }
-                    8 => {
-                        gray = buffer[ptr];
-                        ptr += 1;
-                        if is_alpha == 1 {
-                            alpha = buffer[ptr];
-                            ptr += 1;
-                        } else {
-                            alpha = 0xff;
-                        }
-                    }
-                    _ => {}
-                }
-                match flag {
-                    1 => {
-                        // Sub
-                        if outptr > 0 {
-                            gray = gray.wrapping_add(outbuf[outptr - 4]);
-                            alpha = alpha.wrapping_add(outbuf[outptr - 1]);
-                        }
-                    }
-                    2 => {
-                        // Up
-                        if !prev_buf.is_empty() {
-                            gray = gray.wrapping_add(prev_buf[outptr]);
-                            alpha = alpha.wrapping_add(prev_buf[outptr + 3]);
-                        }
-                    }
-                    3 => {
-                        // Avalage
-                        let (mut gray_, mut alpha_);
-                        if outptr > 0 {
-                            gray_ = outbuf[outptr - 4] as u32;
-                            alpha_ = outbuf[outptr - 1] as u32;
-                        } else {
-                            gray_ = 0;
-                            alpha_ = 0;
-                        }
-                        if !prev_buf.is_empty() {
-                            gray_ += prev_buf[outptr] as u32;
-                            alpha_ += prev_buf[outptr + 3] as u32;
-                        } else {
-                            gray_ += 0;
-                            alpha_ += 0;
+impl ScanlineDecoder {
+    fn new(header: &PngHeader, keep_frame: bool, checksum: ChecksumMode) -> Result<Self, Error> {
+        let bitpersample = header.bitpersample as usize;
+        let channels = match header.color_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => {
+                return Err(png_error(
+                    ImgErrorKind::IllegalData,
+                    format!("Color type {} is unknown", header.color_type),
+                ));
+            }
+        };
+        if !matches!(bitpersample, 1 | 2 | 4 | 8 | 16) {
+            return Err(png_error(
+                ImgErrorKind::IllegalData,
+                format!("Bit depth {} is unsupported", bitpersample),
+            ));
+        }
+        let pallet = match header.color_type {
+            3 => palette_entries(header)?.to_vec(),
+            0 if bitpersample < 8 => {
+                let color_max = (1 << bitpersample) - 1;
+                (0..=color_max)
+                    .map(|i| {
+                        let gray = (i * 255 / color_max) as u8;
+                        RGBA {
+                            red: gray,
+                            green: gray,
+                            blue: gray,
+                            alpha: 0xff,
                         }
-                        gray_ /= 2;
-                        alpha_ /= 2;
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let (width, height) = draw_rect(header);
+        let (width, height) = (width as usize, height as usize);
+        let replay = if keep_frame {
+            Some(vec![0; width * height * 4])
+        } else {
+            None
+        };
 
-                        gray = gray.wrapping_add(gray_ as u8);
-                        alpha = alpha.wrapping_add(alpha_ as u8);
-                    }
-                    4 => {
-                        // Pease
-                        let (gray_a, alpha_a);
-                        if outptr > 0 {
-                            gray_a = outbuf[outptr - 4] as i32;
-                            alpha_a = outbuf[outptr - 1] as i32;
-                        } else {
-                            gray_a = 0;
-                            alpha_a = 0;
-                        }
-                        let (gray_b, alpha_b);
-                        if !prev_buf.is_empty() {
-                            gray_b = prev_buf[outptr] as i32;
-                            alpha_b = prev_buf[outptr + 3] as i32;
-                        } else {
-                            gray_b = 0;
-                            alpha_b = 0;
-                        }
-                        let (gray_c, alpha_c);
-                        if !prev_buf.is_empty() && outptr > 0 {
-                            gray_c = prev_buf[outptr - 4] as i32;
-                            alpha_c = prev_buf[outptr - 1] as i32;
-                        } else {
-                            gray_c = 0;
-                            alpha_c = 0;
-                        }
+        let mut decoder = Self {
+            inflater: InflateState::new_boxed(DataFormat::ZLibIgnoreChecksum),
+            inflated: vec![0; 32 * 1024],
+            color_type: header.color_type,
+            bitpersample,
+            channels,
+            pallet,
+            width,
+            height,
+            interlaced: header.interace_method != 0,
+            pass: 0,
+            pass_width: 0,
+            pass_height: 0,
+            y: 0,
+            bpp: (channels * bitpersample).div_ceil(8),
+            filter: 0,
+            row: Vec::new(),
+            filled: 0,
+            prev: Vec::new(),
+            rgba: Vec::new(),
+            replay,
+            done: false,
+            stream_end: false,
+            checksum,
+            adler: 1,
+        };
+        decoder.start_pass();
+        Ok(decoder)
+    }
 
-                        gray = paeth_dec(gray, gray_a, gray_b, gray_c);
-                        alpha = paeth_dec(alpha, alpha_a, alpha_b, alpha_c);
-                    }
-                    _ => {} // None
-                }
-                outbuf[outptr] = gray;
-                outbuf[outptr + 1] = gray;
-                outbuf[outptr + 2] = gray;
-                outbuf[outptr + 3] = alpha;
-                outptr += 4;
-                option.drawer.draw(x, y, 1, 1, &outbuf, None)?;
-                x += step_x;
+    /// Moves to the next non-empty Adam7 pass, or the whole image when the
+    /// image is not interlaced.
+    fn start_pass(&mut self) {
+        let passes = if self.interlaced { 7 } else { 1 };
+        while self.pass < passes {
+            let (pass_width, pass_height) = if self.interlaced {
+                (
+                    (self.width + STEP_X[self.pass] - 1).saturating_sub(START_X[self.pass])
+                        / STEP_X[self.pass],
+                    (self.height + STEP_Y[self.pass] - 1).saturating_sub(START_Y[self.pass])
+                        / STEP_Y[self.pass],
+                )
+            } else {
+                (self.width, self.height)
+            };
+            if pass_width > 0 && pass_height > 0 {
+                let row_length = (pass_width * self.channels * self.bitpersample).div_ceil(8);
+                self.pass_width = pass_width;
+                self.pass_height = pass_height;
+                self.y = 0;
+                self.row.clear();
+                self.row.resize(row_length, 0);
+                self.filled = 0;
+                self.prev.clear();
+                self.prev.resize(row_length, 0);
+                self.rgba = vec![0; pass_width * 4];
+                return;
             }
-            y += step_y;
-            prev_buf = outbuf;
+            self.pass += 1;
         }
+        self.done = true;
     }
-    Ok(None)
-}
-
-fn load_truecolor(
-    header: &PngHeader,
-    buffer: &[u8],
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
-    let is_alpha = if header.color_type == 6 { 1 } else { 0 };
-    let (width, height) = draw_rect(header);
-    let raw_length = (width * (header.bitpersample as u32 / 8 * (3 + is_alpha)) + 1) as usize;
-    let mut prev_buf: Vec<u8> = Vec::new();
-
-    for y in 0..height as usize {
-        let mut ptr = raw_length * y;
-        let flag = buffer[ptr];
-        if option.debug_flag & 0x4 == 0x4 {
-            let string = format!("Y:{} filter is {} ", y, flag);
-            option.drawer.verbose(&string, None)?;
-        }
 
-        let mut outbuf: Vec<u8> = (0..width * 4).map(|_| 0).collect();
-        ptr += 1;
-        let mut outptr = 0;
-        for _ in 0..width as usize {
-            let (mut red, mut green, mut blue, mut alpha);
-            if header.bitpersample == 16 {
-                red = buffer[ptr];
-                ptr += 2;
-                green = buffer[ptr];
-                ptr += 2;
-                blue = buffer[ptr];
-                ptr += 2;
-                if is_alpha == 1 {
-                    alpha = buffer[ptr];
-                    ptr += 2;
-                } else {
-                    alpha = 0xff;
-                }
-            } else {
-                red = buffer[ptr];
-                ptr += 1;
-                green = buffer[ptr];
-                ptr += 1;
-                blue = buffer[ptr];
-                ptr += 1;
-                if is_alpha == 1 {
-                    alpha = buffer[ptr];
-                    ptr += 1;
-                } else {
-                    alpha = 0xff;
-                }
+    /// Feeds compressed bytes from one IDAT or fdAT chunk.
+    fn push(
+        &mut self,
+        data: &[u8],
+        option: &mut DecodeOptions,
+        warnings: &mut Option<ImgWarnings>,
+    ) -> Result<(), Error> {
+        let mut input = data;
+        while !self.stream_end {
+            let result = inflate(&mut self.inflater, input, &mut self.inflated, MZFlush::None);
+            input = &input[result.bytes_consumed..];
+            let inflated = std::mem::take(&mut self.inflated);
+            let produced = &inflated[..result.bytes_written];
+            if self.checksum != ChecksumMode::Ignore {
+                self.adler = update_adler32(self.adler, produced);
             }
-            match flag {
-                1 => {
-                    // Sub
-                    if outptr > 0 {
-                        red = red.wrapping_add(outbuf[outptr - 4]);
-                        green = green.wrapping_add(outbuf[outptr - 3]);
-                        blue = blue.wrapping_add(outbuf[outptr - 2]);
-                        alpha = alpha.wrapping_add(outbuf[outptr - 1]);
-                    }
+            let consumed = self.consume(produced, option);
+            self.inflated = inflated;
+            consumed?;
+
+            match result.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.stream_end = true;
+                    self.verify_adler32(warnings)?;
                 }
-                2 => {
-                    // Up
-                    if !prev_buf.is_empty() {
-                        red = red.wrapping_add(prev_buf[outptr]);
-                        green = green.wrapping_add(prev_buf[outptr + 1]);
-                        blue = blue.wrapping_add(prev_buf[outptr + 2]);
-                        alpha = alpha.wrapping_add(prev_buf[outptr + 3]);
-                    }
-                }
-                3 => {
-                    // Avalage
-                    let (mut red_, mut green_, mut blue_, mut alpha_);
-                    if outptr > 0 {
-                        red_ = outbuf[outptr - 4] as u32;
-                        green_ = outbuf[outptr - 3] as u32;
-                        blue_ = outbuf[outptr - 2] as u32;
-                        alpha_ = outbuf[outptr - 1] as u32;
-                    } else {
-                        red_ = 0;
-                        green_ = 0;
-                        blue_ = 0;
-                        alpha_ = 0;
-                    }
-                    if !prev_buf.is_empty() {
-                        red_ += prev_buf[outptr] as u32;
-                        green_ += prev_buf[outptr + 1] as u32;
-                        blue_ += prev_buf[outptr + 2] as u32;
-                        alpha_ += prev_buf[outptr + 3] as u32;
-                    } else {
-                        red_ += 0;
-                        green_ += 0;
-                        blue_ += 0;
-                        alpha_ += 0;
-                    }
-                    red_ /= 2;
-                    green_ /= 2;
-                    blue_ /= 2;
-                    alpha_ /= 2;
-
-                    red = red.wrapping_add(red_ as u8);
-                    green = green.wrapping_add(green_ as u8);
-                    blue = blue.wrapping_add(blue_ as u8);
-                    alpha = alpha.wrapping_add(alpha_ as u8);
+                Ok(_) => {}
+                Err(MZError::Buf) => break,
+                Err(err) => {
+                    // Keep the rows drawn so far and stop reading this stream.
+                    self.stream_end = true;
+                    self.checksum
+                        .damaged(format!("Uncompressed Error {:?}", err), warnings)?;
                 }
-                4 => {
-                    // Pease
-                    let (red_a, green_a, blue_a, alpha_a);
-                    if outptr > 0 {
-                        red_a = outbuf[outptr - 4] as i32;
-                        green_a = outbuf[outptr - 3] as i32;
-                        blue_a = outbuf[outptr - 2] as i32;
-                        alpha_a = outbuf[outptr - 1] as i32;
-                    } else {
-                        red_a = 0;
-                        green_a = 0;
-                        blue_a = 0;
-                        alpha_a = 0;
-                    }
-                    let (red_b, green_b, blue_b, alpha_b);
-                    if !prev_buf.is_empty() {
-                        red_b = prev_buf[outptr] as i32;
-                        green_b = prev_buf[outptr + 1] as i32;
-                        blue_b = prev_buf[outptr + 2] as i32;
-                        alpha_b = prev_buf[outptr + 3] as i32;
-                    } else {
-                        red_b = 0;
-                        green_b = 0;
-                        blue_b = 0;
-                        alpha_b = 0;
-                    }
-                    let (red_c, green_c, blue_c, alpha_c);
-                    if !prev_buf.is_empty() && outptr > 0 {
-                        red_c = prev_buf[outptr - 4] as i32;
-                        green_c = prev_buf[outptr - 3] as i32;
-                        blue_c = prev_buf[outptr - 2] as i32;
-                        alpha_c = prev_buf[outptr - 1] as i32;
-                    } else {
-                        red_c = 0;
-                        green_c = 0;
-                        blue_c = 0;
-                        alpha_c = 0;
-                    }
-
-                    red = paeth_dec(red, red_a, red_b, red_c);
-                    green = paeth_dec(green, green_a, green_b, green_c);
-                    blue = paeth_dec(blue, blue_a, blue_b, blue_c);
-                    alpha = paeth_dec(alpha, alpha_a, alpha_b, alpha_c);
-                }
-                _ => {} // None
             }
-            outbuf[outptr] = red;
-            outbuf[outptr + 1] = green;
-            outbuf[outptr + 2] = blue;
-            if is_alpha == 0 {
-                alpha = 0xff;
+            if input.is_empty() && result.bytes_written < self.inflated.len() {
+                break;
             }
-            outbuf[outptr + 3] = alpha;
-            outptr += 4;
         }
-        option.drawer.draw(0, y, width as usize, 1, &outbuf, None)?;
-        prev_buf = outbuf;
+        Ok(())
     }
-    Ok(None)
-}
-
-fn load_truecolor_progressive(
-    header: &PngHeader,
-    buffer: &[u8],
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
-    let is_alpha = if header.color_type == 6 { 1 } else { 0 };
-    let mut prev_buf: Vec<u8> = Vec::new();
-    let (width, height) = draw_rect(header);
-    let mut ptr = 0;
 
-    for i in 0..7 {
-        let sx = START_Y[i];
-        let sy = START_X[i];
-        let step_x = STEP_X[i];
-        let step_y = STEP_Y[i];
-        let mut y = sy;
-        while y < height as usize {
-            let mut outbuf: Vec<u8> = (0..width * 4).map(|_| 0).collect();
-            let flag = buffer[ptr];
-            ptr += 1;
-            let mut outptr = 0;
-            let mut x = sx;
-            while x < width as usize {
-                let (mut red, mut green, mut blue, mut alpha);
-                if header.bitpersample == 16 {
-                    red = buffer[ptr];
-                    ptr += 2;
-                    green = buffer[ptr];
-                    ptr += 2;
-                    blue = buffer[ptr];
-                    ptr += 2;
-                    if is_alpha == 1 {
-                        alpha = buffer[ptr];
-                        ptr += 2;
-                    } else {
-                        alpha = 0xff;
-                    }
-                } else {
-                    red = buffer[ptr];
-                    ptr += 1;
-                    green = buffer[ptr];
-                    ptr += 1;
-                    blue = buffer[ptr];
-                    ptr += 1;
-                    if is_alpha == 1 {
-                        alpha = buffer[ptr];
-                        ptr += 1;
-                    } else {
-                        alpha = 0xff;
-                    }
-                }
-                match flag {
-                    1 => {
-                        // Sub
-                        if outptr > 0 {
-                            red = red.wrapping_add(outbuf[outptr - 4]);
-                            green = green.wrapping_add(outbuf[outptr - 3]);
-                            blue = blue.wrapping_add(outbuf[outptr - 2]);
-                            alpha = alpha.wrapping_add(outbuf[outptr - 1]);
-                        }
-                    }
-                    2 => {
-                        // Up
-                        if !prev_buf.is_empty() {
-                            red = red.wrapping_add(prev_buf[outptr]);
-                            green = green.wrapping_add(prev_buf[outptr + 1]);
-                            blue = blue.wrapping_add(prev_buf[outptr + 2]);
-                            alpha = alpha.wrapping_add(prev_buf[outptr + 3]);
-                        }
-                    }
-                    3 => {
-                        // Avalage
-                        let (mut red_, mut green_, mut blue_, mut alpha_);
-                        if outptr > 0 {
-                            red_ = outbuf[outptr - 4] as u32;
-                            green_ = outbuf[outptr - 3] as u32;
-                            blue_ = outbuf[outptr - 2] as u32;
-                            alpha_ = outbuf[outptr - 1] as u32;
-                        } else {
-                            red_ = 0;
-                            green_ = 0;
-                            blue_ = 0;
-                            alpha_ = 0;
-                        }
-                        if !prev_buf.is_empty() {
-                            red_ += prev_buf[outptr] as u32;
-                            green_ += prev_buf[outptr + 1] as u32;
-                            blue_ += prev_buf[outptr + 2] as u32;
-                            alpha_ += prev_buf[outptr + 3] as u32;
-                        } else {
-                            red_ += 0;
-                            green_ += 0;
-                            blue_ += 0;
-                            alpha_ += 0;
-                        }
-                        red_ /= 2;
-                        green_ /= 2;
-                        blue_ /= 2;
-                        alpha_ /= 2;
-
-                        red = red.wrapping_add(red_ as u8);
-                        green = green.wrapping_add(green_ as u8);
-                        blue = blue.wrapping_add(blue_ as u8);
-                        alpha = alpha.wrapping_add(alpha_ as u8);
-                    }
-                    4 => {
-                        // Pease
-                        let (red_a, green_a, blue_a, alpha_a);
-                        if outptr > 0 {
-                            red_a = outbuf[outptr - 4] as i32;
-                            green_a = outbuf[outptr - 3] as i32;
-                            blue_a = outbuf[outptr - 2] as i32;
-                            alpha_a = outbuf[outptr - 1] as i32;
-                        } else {
-                            red_a = 0;
-                            green_a = 0;
-                            blue_a = 0;
-                            alpha_a = 0;
-                        }
-                        let (red_b, green_b, blue_b, alpha_b);
-                        if !prev_buf.is_empty() {
-                            red_b = prev_buf[outptr] as i32;
-                            green_b = prev_buf[outptr + 1] as i32;
-                            blue_b = prev_buf[outptr + 2] as i32;
-                            alpha_b = prev_buf[outptr + 3] as i32;
-                        } else {
-                            red_b = 0;
-                            green_b = 0;
-                            blue_b = 0;
-                            alpha_b = 0;
-                        }
-                        let (red_c, green_c, blue_c, alpha_c);
-                        if !prev_buf.is_empty() && outptr > 0 {
-                            red_c = prev_buf[outptr - 4] as i32;
-                            green_c = prev_buf[outptr - 3] as i32;
-                            blue_c = prev_buf[outptr - 2] as i32;
-                            alpha_c = prev_buf[outptr - 1] as i32;
-                        } else {
-                            red_c = 0;
-                            green_c = 0;
-                            blue_c = 0;
-                            alpha_c = 0;
-                        }
+    fn verify_adler32(&mut self, warnings: &mut Option<ImgWarnings>) -> Result<(), Error> {
+        if self.checksum == ChecksumMode::Ignore {
+            return Ok(());
+        }
+        let Some(stored) = self.inflater.decompressor().adler32_header() else {
+            return Ok(());
+        };
+        if stored != self.adler {
+            let message = format!(
+                "zlib Adler-32 mismatch: stored {:08x}, computed {:08x}",
+                stored, self.adler
+            );
+            self.checksum.mismatch(message, warnings)?;
+        }
+        Ok(())
+    }
 
-                        red = paeth_dec(red, red_a, red_b, red_c);
-                        green = paeth_dec(green, green_a, green_b, green_c);
-                        blue = paeth_dec(blue, blue_a, blue_b, blue_c);
-                        alpha = paeth_dec(alpha, alpha_a, alpha_b, alpha_c);
-                    }
-                    _ => {} // None
-                }
-                outbuf[outptr] = red;
-                outbuf[outptr + 1] = green;
-                outbuf[outptr + 2] = blue;
-                if is_alpha == 0 {
-                    alpha = 0xff;
-                }
-                outbuf[outptr + 3] = alpha;
-                outptr += 4;
-                option.drawer.draw(x, y, 1, 1, &outbuf, None)?;
-                x += step_x;
+    fn consume(&mut self, mut data: &[u8], option: &mut DecodeOptions) -> Result<(), Error> {
+        while !data.is_empty() && !self.done {
+            if self.filled == 0 {
+                self.filter = data[0];
+                self.filled = 1;
+                data = &data[1..];
+                continue;
+            }
+            let offset = self.filled - 1;
+            let length = (self.row.len() - offset).min(data.len());
+            self.row[offset..offset + length].copy_from_slice(&data[..length]);
+            self.filled += length;
+            data = &data[length..];
+            if self.filled == self.row.len() + 1 {
+                self.emit_row(option)?;
             }
-            y += step_y;
-            prev_buf = outbuf;
         }
+        Ok(())
     }
-    Ok(None)
-}
 
-fn check_color(pallet: &[RGBA], color: usize) -> Result<(), Error> {
-    let color = color as usize;
-    if color >= pallet.len() {
-        return Err(png_error(
-            ImgErrorKind::IllegalData,
-            format!("palette index {} is out of range {}", color, pallet.len()),
-        ));
+    fn sample(&self, raw: &[u8], index: usize) -> u8 {
+        match self.bitpersample {
+            16 => raw[index * 2],
+            8 => raw[index],
+            depth => {
+                let bit = index * depth;
+                let shift = 8 - depth - bit % 8;
+                (raw[bit / 8] >> shift) & ((1 << depth) - 1)
+            }
+        }
     }
-    Ok(())
-}
-
-fn load_index_color(
-    header: &PngHeader,
-    buffer: &[u8],
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
-    let (width, height) = draw_rect(header);
-    let pallet = palette_entries(header)?;
-
-    let raw_length = ((width * header.bitpersample as u32 + 7) / 8 + 1) as usize;
-
-    let mut outbuf: Vec<u8> = (0..width * 4).map(|_| 0).collect();
 
-    for y in 0..height as usize {
-        let mut ptr = raw_length * y;
-        ptr += 1;
-        let mut outptr = 0;
-        for x in 0..width as usize {
-            let mut color = 0;
-            match header.bitpersample {
-                8 => {
-                    color = buffer[ptr];
-                    ptr += 1;
+    fn emit_row(&mut self, option: &mut DecodeOptions) -> Result<(), Error> {
+        let flag = self.filter;
+        if option.debug_flag & 0x4 == 0x4 {
+            let string = format!("Y:{} filter is {} ", self.y, flag);
+            option.drawer.verbose(&string, None)?;
+        }
+        unfilter(flag, &mut self.row, &self.prev, self.bpp);
+
+        let raw = &self.row;
+        for x in 0..self.pass_width {
+            let pixel = match self.color_type {
+                0 if self.bitpersample < 8 => {
+                    let gray = self.sample(raw, x) as usize;
+                    let color = &self.pallet[gray];
+                    [color.red, color.green, color.blue, color.alpha]
                 }
-                4 => {
-                    if x % 2 == 0 {
-                        color = buffer[ptr] >> 4;
-                    } else {
-                        color = buffer[ptr] & 0xf;
-                        ptr += 1;
-                    }
+                0 => {
+                    let gray = self.sample(raw, x);
+                    [gray, gray, gray, 0xff]
                 }
-                2 => {
-                    let shift = 6 - (x % 4) * 2;
-                    color = (buffer[ptr] >> shift) & 0x3;
-                    if shift == 0 {
-                        ptr += 1;
-                    }
+                4 => {
+                    let gray = self.sample(raw, x * 2);
+                    [gray, gray, gray, self.sample(raw, x * 2 + 1)]
                 }
-                1 => {
-                    let shift = 7 - (x % 8);
-                    color = (buffer[ptr] >> shift) & 0x1;
-                    if shift == 0 {
-                        ptr += 1;
-                    }
+                2 => [
+                    self.sample(raw, x * 3),
+                    self.sample(raw, x * 3 + 1),
+                    self.sample(raw, x * 3 + 2),
+                    0xff,
+                ],
+                6 => [
+                    self.sample(raw, x * 4),
+                    self.sample(raw, x * 4 + 1),
+                    self.sample(raw, x * 4 + 2),
+                    self.sample(raw, x * 4 + 3),
+                ],
+                _ => {
+                    let color = self.sample(raw, x) as usize;
+                    check_color(&self.pallet, color)?;
+                    let color = &self.pallet[color];
+                    [color.red, color.green, color.blue, color.alpha]
                 }
-                _ => {}
-            }
-            // index color also no use filter
-            let color = color as usize;
-            check_color(pallet, color)?;
-            outbuf[outptr] = pallet[color].red;
-            outbuf[outptr + 1] = pallet[color].green;
-            outbuf[outptr + 2] = pallet[color].blue;
-            outbuf[outptr + 3] = pallet[color].alpha;
-            outptr += 4;
+            };
+            self.rgba[x * 4..x * 4 + 4].copy_from_slice(&pixel);
         }
-        option.drawer.draw(0, y, width as usize, 1, &outbuf, None)?;
-    }
-    Ok(None)
-}
-
-fn load_index_color_progressive(
-    header: &PngHeader,
-    buffer: &[u8],
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
-    let pallet = palette_entries(header)?;
-    let (width, height) = draw_rect(header);
 
-    let mut outbuf: Vec<u8> = (0..width * 4).map(|_| 0).collect();
-    let mut ptr = 0;
-
-    for i in 0..7 {
-        let sx = START_Y[i];
-        let sy = START_X[i];
-        let step_x = STEP_X[i];
-        let step_y = STEP_Y[i];
-        let mut y = sy;
-        while y < height as usize {
-            ptr += 1;
-            let mut outptr = 0;
-            let mut x = sx;
-            let mut x_ = 0;
-            while x < width as usize {
-                let mut color = 0;
-                match header.bitpersample {
-                    8 => {
-                        color = buffer[ptr];
-                        ptr += 1;
-                    }
-                    4 => {
-                        if x_ % 2 == 0 {
-                            color = buffer[ptr] >> 4;
-                        } else {
-                            color = buffer[ptr] & 0xf;
-                            ptr += 1;
-                        }
-                    }
-                    2 => {
-                        let shift = 6 - (x % 4) * 2;
-                        color = (buffer[ptr] >> shift) & 0x3;
-                        if shift == 0 {
-                            ptr += 1;
-                        }
-                    }
-                    1 => {
-                        let shift = 7 - (x % 8);
-                        color = (buffer[ptr] >> shift) & 0x1;
-                        if shift == 0 {
-                            ptr += 1;
-                        }
-                    }
-                    _ => {}
+        if self.interlaced {
+            let y = START_Y[self.pass] + self.y * STEP_Y[self.pass];
+            for x in 0..self.pass_width {
+                let px = START_X[self.pass] + x * STEP_X[self.pass];
+                let pixel = &self.rgba[x * 4..x * 4 + 4];
+                option.drawer.draw(px, y, 1, 1, pixel, None)?;
+                if let Some(frame) = self.replay.as_mut() {
+                    let offset = (y * self.width + px) * 4;
+                    frame[offset..offset + 4].copy_from_slice(pixel);
                 }
-
-                // index color also no use filter
-                let color = color as usize;
-                check_color(pallet, color)?;
-                outbuf[outptr] = pallet[color].red;
-                outbuf[outptr + 1] = pallet[color].green;
-                outbuf[outptr + 2] = pallet[color].blue;
-                outbuf[outptr + 3] = pallet[color].alpha;
-                outptr += 4;
-                option.drawer.draw(x, y, 1, 1, &outbuf, None)?;
-                x_ += 1;
-                x += step_x;
             }
-            y += step_y;
-        }
-    }
-    Ok(None)
-}
-
-fn load(
-    header: &mut PngHeader,
-    buffer: &[u8],
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
-    match header.color_type {
-        0 | 4 => {
-            if header.bitpersample >= 8 {
-                if header.interace_method == 0 {
-                    return load_grayscale(header, buffer, option);
-                } else {
-                    return load_grayscale_progressive(header, buffer, option);
-                }
-            } else {
-                let color_max = 1 << header.bitpersample;
-                let mut pallet: Vec<RGBA> = Vec::new();
-                for i in 0..color_max {
-                    let gray = (i * 255 / (color_max - 1)) as u8;
-                    pallet.push(RGBA {
-                        red: gray,
-                        green: gray,
-                        blue: gray,
-                        alpha: 0xff,
-                    });
-                }
-                header.pallete = Some(pallet);
-                if header.interace_method == 0 {
-                    return load_index_color(header, buffer, option);
-                } else {
-                    return load_index_color_progressive(header, buffer, option);
-                }
+        } else {
+            option
+                .drawer
+                .draw(0, self.y, self.width, 1, &self.rgba, None)?;
+            if let Some(frame) = self.replay.as_mut() {
+                let offset = self.y * self.width * 4;
+                frame[offset..offset + self.rgba.len()].copy_from_slice(&self.rgba);
             }
         }
-        2 | 6 => {
-            if header.interace_method == 0 {
-                return load_truecolor(header, buffer, option);
-            } else {
-                return load_truecolor_progressive(header, buffer, option);
-            }
+
+        std::mem::swap(&mut self.prev, &mut self.row);
+        self.filled = 0;
+        self.y += 1;
+        if self.y == self.pass_height {
+            self.pass += 1;
+            self.start_pass();
         }
-        3 => {
-            if header.interace_method == 0 {
-                return load_index_color(header, buffer, option);
-            } else {
-                return load_index_color_progressive(header, buffer, option);
-            }
+        Ok(())
+    }
+
+    /// Checks that every scanline of the frame has been decoded. Outside
+    /// strict mode a truncated frame keeps the rows decoded so far.
+    fn finish(&self, warnings: &mut Option<ImgWarnings>) -> Result<(), Error> {
+        if !self.done {
+            let message = format!(
+                "Uncompressed Error: image data is truncated at row {} of pass {}",
+                self.y, self.pass
+            );
+            self.checksum.damaged(message, warnings)?;
         }
-        _ => {
-            let string = format!("Color type {} is unknown", header.color_type);
-            Err(Box::new(ImgError::new_const(
-                ImgErrorKind::IllegalData,
-                string,
-            )))
+        Ok(())
+    }
+
+    /// Draws the decoded frame again, used when the default image is also the
+    /// first animation frame.
+    fn replay(&self, option: &mut DecodeOptions) -> Result<(), Error> {
+        if let Some(frame) = self.replay.as_ref() {
+            option
+                .drawer
+                .draw(0, 0, self.width, self.height, frame, None)?;
         }
+        Ok(())
     }
 }
 
@@ -867,12 +461,205 @@ fn next_options(frame_control: &FrameControl) -> NextOptions {
     }
 }
 
+fn color_correction_option(option: &DecodeOptions) -> Result<bool, Error> {
+    let Some(value) = option
+        .options
+        .as_ref()
+        .and_then(|map| map.get("color_correction"))
+    else {
+        return Ok(false);
+    };
+
+    match value {
+        DataMap::Ascii(value) => match value.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(true),
+            "none" => Ok(false),
+            _ => Err(png_error(
+                ImgErrorKind::InvalidParameter,
+                format!("unsupported PNG color_correction: {value}"),
+            )),
+        },
+        DataMap::UInt(0) | DataMap::SInt(0) => Ok(false),
+        DataMap::UInt(1) | DataMap::SInt(1) => Ok(true),
+        _ => Err(png_error(
+            ImgErrorKind::InvalidParameter,
+            "PNG color_correction must be `srgb`, `none`, 0, or 1",
+        )),
+    }
+}
+
+fn checksum_option(option: &DecodeOptions) -> Result<ChecksumMode, Error> {
+    let Some(value) = option.options.as_ref().and_then(|map| map.get("checksum")) else {
+        return Ok(ChecksumMode::Warn);
+    };
+
+    match value {
+        DataMap::Ascii(value) => match value.to_ascii_lowercase().as_str() {
+            "strict" => Ok(ChecksumMode::Strict),
+            "warn" => Ok(ChecksumMode::Warn),
+            "ignore" => Ok(ChecksumMode::Ignore),
+            _ => Err(png_error(
+                ImgErrorKind::InvalidParameter,
+                format!("unsupported PNG checksum: {value}"),
+            )),
+        },
+        _ => Err(png_error(
+            ImgErrorKind::InvalidParameter,
+            "PNG checksum must be `strict`, `warn`, or `ignore`",
+        )),
+    }
+}
+
+/// Forwards decoded pixels to the caller's drawer after sRGB correction.
+struct ColorCorrectedDrawer<'a> {
+    drawer: &'a mut dyn DrawCallback,
+    correction: ColorCorrection,
+}
+
+impl DrawCallback for ColorCorrectedDrawer<'_> {
+    fn init(
+        &mut self,
+        width: usize,
+        height: usize,
+        option: Option<InitOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        let option = option.map(|mut option| {
+            if let Some(background) = option.background.as_mut() {
+                let mut pixel = [
+                    background.red,
+                    background.green,
+                    background.blue,
+                    background.alpha,
+                ];
+                self.correction.apply(&mut pixel);
+                background.red = pixel[0];
+                background.green = pixel[1];
+                background.blue = pixel[2];
+            }
+            option
+        });
+        self.drawer.init(width, height, option)
+    }
+
+    fn draw(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        data: &[u8],
+        option: Option<DrawOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        let mut data = data.to_vec();
+        self.correction.apply(&mut data);
+        self.drawer
+            .draw(start_x, start_y, width, height, &data, option)
+    }
+
+    fn terminate(
+        &mut self,
+        term: Option<TerminateOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.terminate(term)
+    }
+
+    fn next(&mut self, next: Option<NextOptions>) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.next(next)
+    }
+
+    fn verbose(
+        &mut self,
+        verbose: &str,
+        option: Option<VerboseOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.verbose(verbose, option)
+    }
+
+    fn set_metadata(
+        &mut self,
+        key: &str,
+        value: DataMap,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.set_metadata(key, value)
+    }
+
+    fn draw_samples(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        data: &[f64],
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer
+            .draw_samples(start_x, start_y, width, height, channels, data)
+    }
+}
+
+/// Decodes a PNG or APNG stream.
+///
+/// Supported `DecodeOptions.options` keys:
+/// - `color_correction`: `Ascii("srgb")` or `UInt(1)` converts `gAMA`/`cHRM`
+///   tagged images to sRGB; `Ascii("none")` or `UInt(0)` keeps the stored
+///   samples (default). Images tagged with `sRGB` or `iCCP` are left as-is.
+///   The `"color corrected"` metadata entry reports whether pixels changed.
+/// - `checksum`: `Ascii("strict")` fails on chunk CRC or zlib Adler-32
+///   mismatches and truncated data; `Ascii("warn")` (default) reports them
+///   as warnings and returns the rows decoded so far; `Ascii("ignore")`
+///   skips checksum verification but still recovers truncated data.
 pub fn decode<'decode, B: BinaryReader>(
     reader: &mut B,
     option: &mut DecodeOptions,
 ) -> Result<Option<ImgWarnings>, Error> {
-    let mut header = PngHeader::new(reader, option.debug_flag)?;
+    let checksum = checksum_option(option)?;
+    let crc32 = CRC32::new();
+    let (header, warnings) =
+        PngHeader::new_with_checksum(reader, option.debug_flag, checksum, &crc32)?;
+    if !color_correction_option(option)? {
+        return decode_image(reader, header, option, None, checksum, &crc32, warnings);
+    }
 
+    let Some(correction) = ColorCorrection::new(&header) else {
+        return decode_image(
+            reader,
+            header,
+            option,
+            Some(false),
+            checksum,
+            &crc32,
+            warnings,
+        );
+    };
+    let mut drawer = ColorCorrectedDrawer {
+        drawer: &mut *option.drawer,
+        correction,
+    };
+    let mut corrected = DecodeOptions {
+        debug_flag: option.debug_flag,
+        drawer: &mut drawer,
+        options: option.options.clone(),
+    };
+    decode_image(
+        reader,
+        header,
+        &mut corrected,
+        Some(true),
+        checksum,
+        &crc32,
+        warnings,
+    )
+}
+
+fn decode_image<B: BinaryReader>(
+    reader: &mut B,
+    mut header: PngHeader,
+    option: &mut DecodeOptions,
+    color_corrected: Option<bool>,
+    checksum: ChecksumMode,
+    crc32: &CRC32,
+    mut warnings: Option<ImgWarnings>,
+) -> Result<Option<ImgWarnings>, Error> {
     let backgroud = if let Some(ref background) = header.background_color {
         let background = match background {
             BacgroundColor::Grayscale(gray) => RGBA {
@@ -970,185 +757,151 @@ pub fn decode<'decode, B: BinaryReader>(
         }
     }
 
-    let mut buffer: Vec<u8> = Vec::new();
+    let mut stream: Option<ScanlineDecoder> = None;
     let mut idat = true;
     let mut allow_multi_image = false;
 
     loop {
-        let length = reader.read_u32_be()?;
-        let ret_chunck = reader.read_bytes_as_vec(4);
-        match ret_chunck {
-            Ok(chunck) => {
-                if chunck == IMAGE_DATA {
-                    if option.debug_flag > 1 {
-                        let string = format!("read compressed image data {} bytes", length);
-                        option.drawer.verbose(&string, None)?;
-                    }
-                    let mut buf = reader.read_bytes_as_vec(length as usize)?;
-                    buffer.append(&mut buf);
-                    let _crc = reader.read_u32_be()?;
-                } else {
-                    if idat {
-                        let decomressed = miniz_oxide::inflate::decompress_to_vec_zlib(&buffer);
-                        match decomressed {
-                            Ok(debuffer) => {
-                                load(&mut header.clone(), &debuffer, option)?;
-                                if !header.frame_controls.is_empty() {
-                                    let frame_control = &header.frame_controls[0];
-                                    let next = next_options(frame_control);
-                                    let result = option.drawer.next(Some(next))?;
-                                    if let Some(response) = result {
-                                        if response.response == ResponseCommand::Continue {
-                                            allow_multi_image = true;
-                                            load(&mut header.clone(), &debuffer, option)?;
-                                            // Image = Animation Frame 0
-                                        }
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                let message = format!("Uncompressed Error {:?}", err);
-                                return Err(Box::new(ImgError::new_const(
-                                    ImgErrorKind::DecodeError,
-                                    message,
-                                )));
-                            }
-                        }
-
-                        idat = false;
-                        buffer = vec![];
+        let length = match reader.read_u32_be() {
+            Ok(length) => length,
+            Err(err) => {
+                checksum.damaged(
+                    format!("Unexpected end of PNG data: {}", err),
+                    &mut warnings,
+                )?;
+                break;
+            }
+        };
+        let Ok(chunck) = reader.read_bytes_as_vec(4) else {
+            warnings = ImgWarnings::add(
+                warnings,
+                Box::new(PngWarning::new(
+                    "Data crruption after image datas".to_string(),
+                )),
+            );
+            break;
+        };
+        if idat && chunck != IMAGE_DATA {
+            if let Some(decoder) = stream.take() {
+                decoder.finish(&mut warnings)?;
+                if !header.frame_controls.is_empty() {
+                    let frame_control = &header.frame_controls[0];
+                    let next = next_options(frame_control);
+                    let result = option.drawer.next(Some(next))?;
+                    if let Some(response) = result
+                        && response.response == ResponseCommand::Continue
+                    {
+                        allow_multi_image = true;
+                        // Image = Animation Frame 0
+                        decoder.replay(option)?;
                     }
-                    if chunck == IMAGE_END {
-                        if !buffer.is_empty() {
-                            let decomressed = miniz_oxide::inflate::decompress_to_vec_zlib(&buffer);
-                            match decomressed {
-                                Ok(debuffer) => {
-                                    load(&mut header, &debuffer, option)?;
-                                }
-                                Err(err) => {
-                                    let message = format!("Uncompressed Error {:?}", err);
-                                    return Err(Box::new(ImgError::new_const(
-                                        ImgErrorKind::DecodeError,
-                                        message,
-                                    )));
-                                }
-                            }
-                        }
-                        break;
-                    } else if chunck == TEXTDATA || chunck == I18N_TEXT {
-                        let text = reader.read_bytes_as_vec(length as usize)?;
-                        let (keyword, string) = to_string(&text, false);
-                        header.text.push((keyword, string));
-                        let _crc = reader.read_u32_be()?;
-                    } else if chunck == COMPRESSED_TEXTUAL_DATA {
-                        let text = reader.read_bytes_as_vec(length as usize)?;
-                        let (keyword, string) = to_string(&text, true);
-                        header.text.push((keyword, string));
-                        let _crc = reader.read_u32_be()?;
-                    } else if chunck == C2PA_CHUNK {
-                        #[cfg(feature = "c2pa")]
-                        {
-                            let mut c2pa = reader.read_bytes_as_vec(length as usize)?;
-                            header.c2pa.get_or_insert_with(Vec::new).append(&mut c2pa);
-                        }
-                        #[cfg(not(feature = "c2pa"))]
-                        {
-                            reader.skip_ptr(length as usize)?;
-                        }
-                        let _crc = reader.read_u32_be()?;
-                    } else if chunck == ANIMATION_CONTROLE {
-                        // noimpl error!
-                        reader.skip_ptr(length as usize)?;
-                        let _crc = reader.read_u32_be()?;
-                    } else if chunck == FRAME_CONTROLE {
-                        let frame_control = FrameControl {
-                            sequence_number: reader.read_u32_be()?,
-                            width: reader.read_u32_be()?,
-                            height: reader.read_u32_be()?,
-                            x_offset: reader.read_u32_be()?,
-                            y_offset: reader.read_u32_be()?,
-                            delay_num: reader.read_u16_be()?,
-                            delay_den: reader.read_u16_be()?,
-                            dispose_op: reader.read_byte()?,
-                            blend_op: reader.read_byte()?,
-                        };
-                        if !buffer.is_empty() && allow_multi_image {
-                            let decomressed = miniz_oxide::inflate::decompress_to_vec_zlib(&buffer);
-                            match decomressed {
-                                Ok(debuffer) => {
-                                    load(&mut header, &debuffer, option)?;
-                                }
-                                Err(err) => {
-                                    let message = format!("Uncompressed Error {:?}", err);
-                                    return Err(Box::new(ImgError::new_const(
-                                        ImgErrorKind::DecodeError,
-                                        message,
-                                    )));
-                                }
-                            }
-                        }
-                        buffer = vec![];
-
-                        let next = next_options(&frame_control);
-                        let result = option.drawer.next(Some(next))?;
-                        if let Some(response) = result {
-                            if response.response == ResponseCommand::Continue {
-                                allow_multi_image = true;
-                            }
-                            if option.debug_flag > 0 {
-                                let str = format!("{:?}", frame_control);
-                                option.drawer.verbose(&str, None)?;
-                            }
-                        }
-
-                        header.frame_controls.push(frame_control);
+                }
+            }
+            idat = false;
+        }
+        if chunck == IMAGE_END {
+            break;
+        }
+        let (data, complete) = read_chunk(reader, &chunck, length, checksum, crc32, &mut warnings)?;
 
-                        let _crc = reader.read_u32_be()?;
-                    } else if chunck == FRAME_DATA {
-                        let sequence_number = reader.read_u32_be()?;
-                        if option.debug_flag > 0 {
-                            let string = format!(
-                                "read compressed animation image data:{} {} bytes",
-                                sequence_number,
-                                length - 4
-                            );
-                            option.drawer.verbose(&string, None)?;
-                        }
+        if chunck == IMAGE_DATA {
+            if option.debug_flag > 1 {
+                let string = format!("read compressed image data {} bytes", length);
+                option.drawer.verbose(&string, None)?;
+            }
+            if idat {
+                let decoder = match stream.as_mut() {
+                    Some(decoder) => decoder,
+                    None => stream.insert(ScanlineDecoder::new(
+                        &header,
+                        !header.frame_controls.is_empty(),
+                        checksum,
+                    )?),
+                };
+                decoder.push(&data, option, &mut warnings)?;
+            }
+        } else if chunck == TEXTDATA || chunck == I18N_TEXT {
+            header.text.push(to_string(&data, false));
+        } else if chunck == COMPRESSED_TEXTUAL_DATA {
+            header.text.push(to_string(&data, true));
+        } else if chunck == C2PA_CHUNK {
+            #[cfg(feature = "c2pa")]
+            header
+                .c2pa
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(&data);
+        } else if chunck == FRAME_CONTROLE && complete {
+            if data.len() != 26 {
+                return Err(chunk_size_error("fcTL", length, "26"));
+            }
+            let frame_control = FrameControl {
+                sequence_number: read_u32_be(&data, 0),
+                width: read_u32_be(&data, 4),
+                height: read_u32_be(&data, 8),
+                x_offset: read_u32_be(&data, 12),
+                y_offset: read_u32_be(&data, 16),
+                delay_num: read_u16_be(&data, 20),
+                delay_den: read_u16_be(&data, 22),
+                dispose_op: data[24],
+                blend_op: data[25],
+            };
+            if let Some(decoder) = stream.take() {
+                decoder.finish(&mut warnings)?;
+            }
 
-                        let mut buf = reader.read_bytes_as_vec(length as usize - 4)?;
-                        buffer.append(&mut buf);
-                        let _crc = reader.read_u32_be()?;
-                    } else {
-                        reader.skip_ptr(length as usize)?;
-                        let _crc = reader.read_u32_be()?;
-                    }
+            let next = next_options(&frame_control);
+            let result = option.drawer.next(Some(next))?;
+            if let Some(response) = result {
+                if response.response == ResponseCommand::Continue {
+                    allow_multi_image = true;
+                }
+                if option.debug_flag > 0 {
+                    let str = format!("{:?}", frame_control);
+                    option.drawer.verbose(&str, None)?;
                 }
             }
-            Err(_) => {
-                let warnings = ImgWarnings::add(
-                    None,
-                    Box::new(PngWarning::new(
-                        "Data crruption after image datas".to_string(),
-                    )),
+
+            header.frame_controls.push(frame_control);
+            if allow_multi_image {
+                stream = Some(ScanlineDecoder::new(&header, false, checksum)?);
+            }
+        } else if chunck == FRAME_DATA && data.len() >= 4 {
+            let sequence_number = read_u32_be(&data, 0);
+            if option.debug_flag > 0 {
+                let string = format!(
+                    "read compressed animation image data:{} {} bytes",
+                    sequence_number,
+                    data.len() - 4
                 );
-                if option.debug_flag > 1 {
-                    let string = format!("{:?}", &header);
-                    option.drawer.verbose(&string, None)?;
-                }
-                option.drawer.terminate(None)?;
-                return Ok(warnings);
+                option.drawer.verbose(&string, None)?;
             }
+            if let Some(decoder) = stream.as_mut() {
+                decoder.push(&data[4..], option, &mut warnings)?;
+            }
+        }
+        // acTL is read by the header, other chunks: no impl
+        if !complete {
+            break;
         }
     }
+    if let Some(decoder) = stream.take() {
+        decoder.finish(&mut warnings)?;
+    }
     if option.debug_flag > 1 {
         let string = format!("{:?}", &header);
         option.drawer.verbose(&string, None)?;
     }
-    let map = make_metadata(&header);
+    let mut map = make_metadata(&header);
+    if let Some(color_corrected) = color_corrected {
+        map.insert(
+            "color corrected".to_string(),
+            DataMap::Ascii(color_corrected.to_string()),
+        );
+    }
     for (key, value) in &map {
         option.drawer.set_metadata(key, value.clone())?;
     }
 
     option.drawer.terminate(None)?;
-    Ok(None)
+    Ok(warnings)
 }
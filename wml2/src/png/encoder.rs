@@ -161,6 +161,184 @@ fn normalize_apng_frames(
     })
 }
 
+/// Pixel rectangle on the APNG canvas as `(x, y, width, height)`.
+type FrameRect = (u32, u32, u32, u32);
+/// Encoded size, dispose op of the previous frame, blend op, rect, frame data, base canvas.
+type FrameCandidate = (usize, u8, u8, FrameRect, Vec<u8>, Vec<u8>);
+
+fn changed_rect(base: &[u8], target: &[u8], width: u32, height: u32) -> Option<FrameRect> {
+    let width = width as usize;
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+    for y in 0..height as usize {
+        let row = y * width * 4;
+        for x in 0..width {
+            let offset = row + x * 4;
+            if base[offset..offset + 4] != target[offset..offset + 4] {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+    }
+    if min_x == usize::MAX {
+        return None;
+    }
+    Some((
+        min_x as u32,
+        min_y as u32,
+        (max_x - min_x + 1) as u32,
+        (max_y - min_y + 1) as u32,
+    ))
+}
+
+fn crop_rect(canvas: &[u8], canvas_width: u32, rect: FrameRect) -> Vec<u8> {
+    let (x, y, width, height) = rect;
+    let mut buffer = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in y..y + height {
+        let start = (row as usize * canvas_width as usize + x as usize) * 4;
+        buffer.extend_from_slice(&canvas[start..start + width as usize * 4]);
+    }
+    buffer
+}
+
+/// Builds an `APNG_BLEND_OP_OVER` frame whose unchanged pixels are transparent.
+///
+/// Returns `None` when a changed pixel would be blended with a visible base
+/// pixel, which `OVER` cannot reproduce exactly.
+fn over_rect(base: &[u8], target: &[u8], canvas_width: u32, rect: FrameRect) -> Option<Vec<u8>> {
+    let (x, y, width, height) = rect;
+    let mut buffer = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in y..y + height {
+        for column in x..x + width {
+            let offset = (row as usize * canvas_width as usize + column as usize) * 4;
+            let base = &base[offset..offset + 4];
+            let target = &target[offset..offset + 4];
+            if base == target {
+                buffer.extend_from_slice(&[0, 0, 0, 0]);
+            } else if target[3] == 0xff || base[3] == 0 {
+                buffer.extend_from_slice(target);
+            } else {
+                return None;
+            }
+        }
+    }
+    Some(buffer)
+}
+
+fn clear_rect(canvas: &mut [u8], canvas_width: u32, rect: FrameRect) {
+    let (x, y, width, height) = rect;
+    for row in y..y + height {
+        let start = (row as usize * canvas_width as usize + x as usize) * 4;
+        canvas[start..start + width as usize * 4].fill(0);
+    }
+}
+
+/// Rewrites composed APNG frames as minimal difference frames.
+///
+/// Every frame after the first covers only the bounding box of the pixels that
+/// differ from the canvas left behind by the previous frame. For each frame the
+/// previous frame's dispose op (`NONE`, `BACKGROUND` or `PREVIOUS`) and the
+/// frame's blend op (`SOURCE` or `OVER` with transparent unchanged pixels) are
+/// chosen by comparing the compressed sizes of the candidates.
+fn optimize_apng_frames(
+    width: u32,
+    height: u32,
+    background: Option<crate::color::RGBA>,
+    apng: ApngInfo,
+) -> Result<ApngInfo, Error> {
+    let composed = normalize_apng_frames(width, height, background, apng)?;
+    let mut frames: Vec<ApngFrame> = Vec::with_capacity(composed.frames.len());
+    let mut canvases = composed.frames.into_iter();
+    let Some(first) = canvases.next() else {
+        return Ok(ApngInfo {
+            loop_count: composed.loop_count,
+            frames,
+        });
+    };
+
+    let full = (0, 0, width, height);
+    // Canvas before the current frame was drawn, used by APNG_DISPOSE_OP_PREVIOUS.
+    let mut previous_base = vec![0u8; width as usize * height as usize * 4];
+    let mut previous_rect = full;
+    let mut current = first.buffer.clone();
+    frames.push(first);
+
+    for target in canvases {
+        let mut disposes = vec![APNG_DISPOSE_OP_NONE, APNG_DISPOSE_OP_BACKGROUND];
+        if frames.len() > 1 {
+            disposes.push(APNG_DISPOSE_OP_PREVIOUS);
+        }
+
+        let mut best: Option<FrameCandidate> = None;
+        for dispose_op in disposes {
+            let base = match dispose_op {
+                APNG_DISPOSE_OP_BACKGROUND => {
+                    let mut base = current.clone();
+                    clear_rect(&mut base, width, previous_rect);
+                    base
+                }
+                APNG_DISPOSE_OP_PREVIOUS => previous_base.clone(),
+                _ => current.clone(),
+            };
+            let rect = changed_rect(&base, &target.buffer, width, height).unwrap_or((0, 0, 1, 1));
+
+            let mut candidates =
+                vec![(APNG_BLEND_OP_SOURCE, crop_rect(&target.buffer, width, rect))];
+            if let Some(buffer) = over_rect(&base, &target.buffer, width, rect) {
+                candidates.push((APNG_BLEND_OP_OVER, buffer));
+            }
+            for (blend_op, buffer) in candidates {
+                let size = encode_frame_data(rect.2, rect.3, &buffer)?.len();
+                if best.as_ref().is_none_or(|best| size < best.0) {
+                    best = Some((size, dispose_op, blend_op, rect, buffer, base.clone()));
+                }
+            }
+        }
+
+        let Some((_, dispose_op, blend_op, rect, buffer, base)) = best else {
+            continue;
+        };
+        if let Some(last) = frames.last_mut() {
+            last.dispose_op = dispose_op;
+        }
+        frames.push(ApngFrame {
+            width: rect.2,
+            height: rect.3,
+            x_offset: rect.0,
+            y_offset: rect.1,
+            delay_num: target.delay_num,
+            delay_den: target.delay_den,
+            dispose_op: APNG_DISPOSE_OP_NONE,
+            blend_op,
+            buffer,
+        });
+        previous_base = base;
+        previous_rect = rect;
+        current = target.buffer;
+    }
+
+    Ok(ApngInfo {
+        loop_count: composed.loop_count,
+        frames,
+    })
+}
+
+fn apng_optimize(image: &EncodeOptions<'_>) -> Result<bool, Error> {
+    let Some(value) = image.options.as_ref().and_then(|map| map.get("optimize")) else {
+        return Ok(false);
+    };
+
+    match value {
+        DataMap::UInt(value) => Ok(*value > 0),
+        DataMap::SInt(value) if *value >= 0 => Ok(*value > 0),
+        _ => Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "optimize must be a non-negative integer".to_string(),
+        ))),
+    }
+}
+
 fn write_chunk(write_buffer: &mut Vec<u8>, crc32: &CRC32, chunk_type: &[u8; 4], data: &[u8]) {
     write_u32_be(data.len() as u32, write_buffer);
     let mut temp_buffer = Vec::with_capacity(4 + data.len());
@@ -505,10 +683,21 @@ fn write_fctl(
 ///
 /// Supported `EncodeOptions.options` keys:
 /// - `exif`: `Raw(bytes)`, `Exif(headers)`, or `Ascii("copy")`
+/// - `optimize`: a non-zero value stores APNG frames as minimal difference
+///   rectangles with per-frame dispose/blend selection
 pub fn encode(image: &mut EncodeOptions<'_>) -> Result<Vec<u8>, Error> {
+    let optimize = apng_optimize(image)?;
     let profile = image.drawer.encode_start(None)?;
     let (width, height, background, apng_info, exif) = if let Some(profile) = profile {
-        let apng_info = parse_apng_info(&profile)?;
+        let mut apng_info = parse_apng_info(&profile)?;
+        if optimize && let Some(apng) = apng_info {
+            apng_info = Some(optimize_apng_frames(
+                profile.width as u32,
+                profile.height as u32,
+                profile.background.clone(),
+                apng,
+            )?);
+        }
         let exif = get_exif_option(image.options.as_ref(), profile.metadata.as_ref())?;
         (
             profile.width as u32,
@@ -2,8 +2,11 @@
 
 use crate::color::RGBA;
 use crate::error::*;
+use crate::png::utils::CRC32;
+use crate::png::warning::PngWarning;
+use crate::warning::ImgWarnings;
 use bin_rs::io::*;
-use bin_rs::reader::BinaryReader;
+use bin_rs::reader::{BinaryReader, BytesReader};
 type Error = Box<dyn std::error::Error>;
 
 pub(crate) const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
@@ -14,9 +17,7 @@ pub(crate) const IMAGE_END: [u8; 4] = [b'I', b'E', b'N', b'D'];
 const TRANNCEPEARENCY: [u8; 4] = [b't', b'R', b'N', b'S'];
 
 const GAMMA: [u8; 4] = [b'g', b'A', b'M', b'A'];
-/*
-const COLOR_HMR:[u8;4] = [b'c',b'H',b'R',b'M'];
-*/
+const COLOR_HMR: [u8; 4] = [b'c', b'H', b'R', b'M'];
 const SRGB: [u8; 4] = [b's', b'R', b'G', b'B'];
 const ICC_PROFILE: [u8; 4] = [b'i', b'C', b'C', b'P'];
 pub(crate) const EXIF_PROFILE: [u8; 4] = [b'e', b'X', b'I', b'f'];
@@ -51,13 +52,125 @@ pub(crate) const ANIMATION_CONTROLE: [u8; 4] = [b'a', b'c', b'T', b'L'];
 pub(crate) const FRAME_CONTROLE: [u8; 4] = [b'f', b'c', b'T', b'L'];
 pub(crate) const FRAME_DATA: [u8; 4] = [b'f', b'd', b'A', b'T'];
 
-fn chunk_size_error(name: &str, length: u32, expected: &str) -> Error {
+pub(crate) const APNG_DISPOSE_OP_NONE: u8 = 0;
+pub(crate) const APNG_DISPOSE_OP_BACKGROUND: u8 = 1;
+pub(crate) const APNG_DISPOSE_OP_PREVIOUS: u8 = 2;
+pub(crate) const APNG_BLEND_OP_SOURCE: u8 = 0;
+pub(crate) const APNG_BLEND_OP_OVER: u8 = 1;
+
+pub(crate) fn chunk_size_error(name: &str, length: u32, expected: &str) -> Error {
     Box::new(ImgError::new_const(
         ImgErrorKind::IllegalData,
         format!("Illegal size {}: {} (expected {})", name, length, expected),
     ))
 }
 
+/// How chunk CRC and zlib Adler-32 mismatches are handled while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// Checksum mismatches and truncated data are errors.
+    Strict,
+    /// Checksum mismatches are reported as warnings and damaged image data
+    /// is decoded as far as possible (default).
+    Warn,
+    /// Checksums are not verified; damaged image data is decoded as far as
+    /// possible.
+    Ignore,
+}
+
+impl ChecksumMode {
+    /// Reports a damaged stream as an error in strict mode, otherwise as a
+    /// warning.
+    pub(crate) fn damaged(
+        self,
+        message: String,
+        warnings: &mut Option<ImgWarnings>,
+    ) -> Result<(), Error> {
+        if self == ChecksumMode::Strict {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::DecodeError,
+                message,
+            )));
+        }
+        *warnings = ImgWarnings::add(warnings.take(), Box::new(PngWarning::new(message)));
+        Ok(())
+    }
+
+    /// Reports a checksum mismatch according to the mode.
+    pub(crate) fn mismatch(
+        self,
+        message: String,
+        warnings: &mut Option<ImgWarnings>,
+    ) -> Result<(), Error> {
+        match self {
+            ChecksumMode::Ignore => Ok(()),
+            _ => self.damaged(message, warnings),
+        }
+    }
+}
+
+pub(crate) fn verify_chunk_crc(
+    chunk_type: &[u8],
+    data: &[u8],
+    crc: u32,
+    checksum: ChecksumMode,
+    crc32: &CRC32,
+    warnings: &mut Option<ImgWarnings>,
+) -> Result<(), Error> {
+    if checksum == ChecksumMode::Ignore {
+        return Ok(());
+    }
+    let computed = crc32.chunk_crc(chunk_type, data);
+    if computed != crc {
+        let message = format!(
+            "{} chunk CRC mismatch: stored {:08x}, computed {:08x}",
+            String::from_utf8_lossy(chunk_type),
+            crc,
+            computed
+        );
+        return checksum.mismatch(message, warnings);
+    }
+    Ok(())
+}
+
+/// Reads the data and CRC of the current chunk. Outside strict mode a chunk
+/// cut short by the end of the stream is returned as far as it could be
+/// read, with `false` as the second value.
+pub(crate) fn read_chunk<B: BinaryReader>(
+    reader: &mut B,
+    chunk_type: &[u8],
+    length: u32,
+    checksum: ChecksumMode,
+    crc32: &CRC32,
+    warnings: &mut Option<ImgWarnings>,
+) -> Result<(Vec<u8>, bool), Error> {
+    let name = String::from_utf8_lossy(chunk_type).to_string();
+    let data = match reader.read_bytes_as_vec(length as usize) {
+        Ok(data) => data,
+        Err(err) => {
+            checksum.damaged(format!("{} chunk is truncated: {}", name, err), warnings)?;
+            let mut data = Vec::new();
+            while data.len() < length as usize {
+                match reader.read_byte() {
+                    Ok(byte) => data.push(byte),
+                    Err(_) => break,
+                }
+            }
+            return Ok((data, false));
+        }
+    };
+    match reader.read_u32_be() {
+        Ok(crc) => {
+            verify_chunk_crc(chunk_type, &data, crc, checksum, crc32, warnings)?;
+            Ok((data, true))
+        }
+        Err(err) => {
+            checksum.damaged(format!("{} chunk CRC is missing: {}", name, err), warnings)?;
+            Ok((data, false))
+        }
+    }
+}
+
 pub(crate) fn to_string<'a>(text: &[u8], compressed: bool) -> (String, String) {
     let mut split = 0;
     let keyword = read_ascii_string(text, 0, text.len());
@@ -114,6 +227,8 @@ pub struct PngHeader {
     pub image_lenghth: u32,
     pub pallete: Option<Vec<RGBA>>,
     pub gamma: Option<u32>,
+    /// cHRM white point and red/green/blue primaries as x/y pairs scaled by 100000.
+    pub chromaticity: Option<[u32; 8]>,
     pub transparency: Option<Vec<u8>>,
     pub srgb: Option<u8>,
     pub iccprofile: Option<Vec<u8>>,
@@ -131,7 +246,20 @@ pub struct PngHeader {
 }
 
 impl PngHeader {
-    pub fn new<B: BinaryReader>(reader: &mut B, _opt: usize) -> Result<Self, Error> {
+    pub fn new<B: BinaryReader>(reader: &mut B, opt: usize) -> Result<Self, Error> {
+        let (header, _) =
+            Self::new_with_checksum(reader, opt, ChecksumMode::Ignore, &CRC32::new())?;
+        Ok(header)
+    }
+
+    /// Parses the chunks before the first IDAT, verifying chunk CRCs with
+    /// `crc32` as requested by `checksum`.
+    pub fn new_with_checksum<B: BinaryReader>(
+        reader: &mut B,
+        _opt: usize,
+        checksum: ChecksumMode,
+        crc32: &CRC32,
+    ) -> Result<(Self, Option<ImgWarnings>), Error> {
         let signature = reader.read_bytes_as_vec(8)?;
         if signature != SIGNATURE {
             return Err(Box::new(ImgError::new_const(
@@ -152,6 +280,7 @@ impl PngHeader {
             image_lenghth: 0,
             pallete: None,
             gamma: None,
+            chromaticity: None,
             transparency: None,
             srgb: None,
             iccprofile: None,
@@ -169,26 +298,46 @@ impl PngHeader {
         };
 
         let mut pallete_size = 0;
+        let mut warnings = None;
 
         loop {
-            let buf = reader.read_bytes_no_move(8)?;
+            let buf = match reader.read_bytes_no_move(8) {
+                Ok(buf) => buf,
+                Err(err) => {
+                    checksum.damaged(
+                        format!("Unexpected end of PNG data: {}", err),
+                        &mut warnings,
+                    )?;
+                    break;
+                }
+            };
             let length = read_u32_be(&buf, 0);
             let chunck = &buf[4..];
             if chunck == IMAGE_DATA {
                 header.image_lenghth = length;
                 break;
-            } else if chunck == IMAGE_HEADER {
+            }
+            reader.skip_ptr(8)?;
+            // A chunk cut short ends the header; the image decoder then
+            // finds no image data, as with a stream truncated inside IDAT.
+            let (data, complete) =
+                read_chunk(reader, chunck, length, checksum, crc32, &mut warnings)?;
+            if !complete {
+                break;
+            }
+            let mut chunk = BytesReader::new(&data);
+
+            if chunck == IMAGE_HEADER {
                 if length != 13 {
                     return Err(Box::new(ImgError::new_const(
                         ImgErrorKind::IllegalData,
                         "Illegal size IHDR".to_string(),
                     )));
                 }
-                reader.skip_ptr(8)?;
-                header.width = reader.read_u32_be()?;
-                header.height = reader.read_u32_be()?;
-                header.bitpersample = reader.read_byte()?;
-                header.color_type = reader.read_byte()?;
+                header.width = chunk.read_u32_be()?;
+                header.height = chunk.read_u32_be()?;
+                header.bitpersample = chunk.read_byte()?;
+                header.color_type = chunk.read_byte()?;
 
                 match header.color_type {
                     0 => {
@@ -269,12 +418,10 @@ impl PngHeader {
                     }
                 }
 
-                header.compression = reader.read_byte()?;
-                header.filter_method = reader.read_byte()?;
-                header.interace_method = reader.read_byte()?;
-                let _crc = reader.read_u32_be()?;
+                header.compression = chunk.read_byte()?;
+                header.filter_method = chunk.read_byte()?;
+                header.interace_method = chunk.read_byte()?;
             } else if chunck == PALLET {
-                reader.skip_ptr(8)?;
                 if length % 3 != 0 {
                     return Err(Box::new(ImgError::new_const(
                         ImgErrorKind::IllegalData,
@@ -285,18 +432,16 @@ impl PngHeader {
                 let mut pallet: Vec<RGBA> = Vec::with_capacity(pallete_size);
                 for _ in 0..pallete_size {
                     let color = RGBA {
-                        red: reader.read_byte()?,
-                        green: reader.read_byte()?,
-                        blue: reader.read_byte()?,
+                        red: chunk.read_byte()?,
+                        green: chunk.read_byte()?,
+                        blue: chunk.read_byte()?,
                         alpha: 0xff,
                     };
                     pallet.push(color);
                 }
                 header.pallete = Some(pallet);
-                let _crc = reader.read_u32_be()?;
             } else if chunck == TRANNCEPEARENCY {
-                reader.skip_ptr(8)?;
-                let transparency = reader.read_bytes_as_vec(length as usize)?;
+                let transparency = chunk.read_bytes_as_vec(length as usize)?;
                 match header.color_type {
                     3 => {
                         if header.pallete.is_none() {
@@ -334,28 +479,29 @@ impl PngHeader {
                     }
                 }
                 header.transparency = Some(transparency);
-                let _crc = reader.read_u32_be()?;
             } else if chunck == GAMMA {
-                reader.skip_ptr(8)?;
                 if length != 4 {
                     return Err(chunk_size_error("gAMA", length, "4"));
                 }
-                let gamma = reader.read_u32_be()?;
+                let gamma = chunk.read_u32_be()?;
                 header.gamma = Some(gamma);
-                let _crc = reader.read_u32_be()?;
+            } else if chunck == COLOR_HMR {
+                if length != 32 {
+                    return Err(chunk_size_error("cHRM", length, "32"));
+                }
+                let mut chromaticity = [0_u32; 8];
+                for value in chromaticity.iter_mut() {
+                    *value = chunk.read_u32_be()?;
+                }
+                header.chromaticity = Some(chromaticity);
             } else if chunck == TEXTDATA || chunck == I18N_TEXT {
-                reader.skip_ptr(8)?;
-                let text = reader.read_bytes_as_vec(length as usize)?;
+                let text = chunk.read_bytes_as_vec(length as usize)?;
                 header.text.push(to_string(&text, false));
-                let _crc = reader.read_u32_be()?;
             } else if chunck == COMPRESSED_TEXTUAL_DATA {
-                reader.skip_ptr(8)?;
-                let text = reader.read_bytes_as_vec(length as usize)?;
+                let text = chunk.read_bytes_as_vec(length as usize)?;
                 header.text.push(to_string(&text, true));
-                let _crc = reader.read_u32_be()?;
             } else if chunck == BACKGROUND_COLOR {
-                reader.skip_ptr(8)?;
-                let buffer = reader.read_bytes_as_vec(length as usize)?;
+                let buffer = chunk.read_bytes_as_vec(length as usize)?;
                 match header.color_type {
                     3 => {
                         if length != 1 {
@@ -382,89 +528,72 @@ impl PngHeader {
                     }
                     _ => {}
                 }
-                let _crc = reader.read_u32_be()?;
             } else if chunck == MODIFIED_TIME {
                 // no impl...
-                reader.skip_ptr(8)?;
                 if length != 7 {
                     return Err(chunk_size_error("tIME", length, "7"));
                 }
-                let year = reader.read_u16_be()?;
-                let month = reader.read_byte()?;
-                let day = reader.read_byte()?;
-                let hour = reader.read_byte()?;
-                let miniute = reader.read_byte()?;
-                let second = reader.read_byte()?;
+                let year = chunk.read_u16_be()?;
+                let month = chunk.read_byte()?;
+                let day = chunk.read_byte()?;
+                let hour = chunk.read_byte()?;
+                let miniute = chunk.read_byte()?;
+                let second = chunk.read_byte()?;
                 let date = format!("{}-{}-{} {}:{}:{}", year, month, day, hour, miniute, second);
                 header.modified_time = Some(date);
-                let _crc = reader.read_u32_be()?;
             } else if chunck == ANIMATION_CONTROLE {
-                reader.skip_ptr(8)?;
                 if length != 8 {
                     return Err(chunk_size_error("acTL", length, "8"));
                 }
                 header.is_apng = true;
-                header.num_frames = reader.read_u32_be()?;
-                header.num_plays = reader.read_u32_be()?;
-                let _crc = reader.read_u32_be()?;
+                header.num_frames = chunk.read_u32_be()?;
+                header.num_plays = chunk.read_u32_be()?;
             } else if chunck == FRAME_CONTROLE {
                 // noimpl
-                reader.skip_ptr(8)?;
                 if length != 26 {
                     return Err(chunk_size_error("fcTL", length, "26"));
                 }
                 let frame_control = FrameControl {
-                    sequence_number: reader.read_u32_be()?,
-                    width: reader.read_u32_be()?,
-                    height: reader.read_u32_be()?,
-                    x_offset: reader.read_u32_be()?,
-                    y_offset: reader.read_u32_be()?,
-                    delay_num: reader.read_u16_be()?,
-                    delay_den: reader.read_u16_be()?,
-                    dispose_op: reader.read_byte()?,
-                    blend_op: reader.read_byte()?,
+                    sequence_number: chunk.read_u32_be()?,
+                    width: chunk.read_u32_be()?,
+                    height: chunk.read_u32_be()?,
+                    x_offset: chunk.read_u32_be()?,
+                    y_offset: chunk.read_u32_be()?,
+                    delay_num: chunk.read_u16_be()?,
+                    delay_den: chunk.read_u16_be()?,
+                    dispose_op: chunk.read_byte()?,
+                    blend_op: chunk.read_byte()?,
                 };
                 header.frame_controls.push(frame_control);
-                let _crc = reader.read_u32_be()?;
             } else if chunck == SRGB {
                 // noimpl
-                reader.skip_ptr(8)?;
                 if length != 1 {
                     return Err(chunk_size_error("sRGB", length, "1"));
                 }
-                let srgb = reader.read_byte()?;
+                let srgb = chunk.read_byte()?;
                 header.srgb = Some(srgb);
-                let _crc = reader.read_u32_be()?;
             } else if chunck == ICC_PROFILE {
                 // noimpl
-                reader.skip_ptr(8)?;
-                let icc_profile = reader.read_bytes_as_vec(length as usize)?;
+                let icc_profile = chunk.read_bytes_as_vec(length as usize)?;
                 header.iccprofile = Some(icc_profile);
-                let _crc = reader.read_u32_be()?;
             } else if chunck == EXIF_PROFILE {
-                reader.skip_ptr(8)?;
-                let exif = reader.read_bytes_as_vec(length as usize)?;
+                let exif = chunk.read_bytes_as_vec(length as usize)?;
                 header.exif = Some(exif);
-                let _crc = reader.read_u32_be()?;
             } else if chunck == C2PA_CHUNK {
-                reader.skip_ptr(8)?;
                 #[cfg(feature = "c2pa")]
-                {
-                    let mut c2pa = reader.read_bytes_as_vec(length as usize)?;
-                    header.c2pa.get_or_insert_with(Vec::new).append(&mut c2pa);
-                }
-                #[cfg(not(feature = "c2pa"))]
-                {
-                    reader.skip_ptr(length as usize)?;
-                }
-                let _crc = reader.read_u32_be()?;
-            } else {
-                // no impl...
-                reader.skip_ptr(8)?;
-                reader.skip_ptr(length as usize)?;
-                let _crc = reader.read_u32_be()?;
+                header
+                    .c2pa
+                    .get_or_insert_with(Vec::new)
+                    .extend_from_slice(&data);
             }
+            // other chunks: no impl...
         }
-        Ok(header)
+        if header.width == 0 || header.height == 0 {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::IllegalData,
+                "IHDR is missing or truncated".to_string(),
+            )));
+        }
+        Ok((header, warnings))
     }
 }
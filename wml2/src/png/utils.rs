@@ -1,5 +1,7 @@
 //! PNG utility helpers.
 
+use crate::color::{D65_WHITE_POINT, SRGB_PRIMARIES, transform_matrix3};
+use crate::color::{Matrix3, linear_to_srgb, rgb_to_srgb_matrix, srgb_to_linear};
 use crate::metadata::DataMap;
 use crate::tiff::header::read_tags;
 use bin_rs::reader::BytesReader;
@@ -67,6 +69,25 @@ impl CRC32 {
     pub fn crc32(&self, buf: &[u8]) -> u32 {
         self.update_crc32(0xffff_ffff, buf) ^ 0xffff_ffff
     }
+
+    /// CRC of a chunk, computed over the chunk type and data.
+    pub fn chunk_crc(&self, chunk_type: &[u8], data: &[u8]) -> u32 {
+        let crc = self.update_crc32(0xffff_ffff, chunk_type);
+        self.update_crc32(crc, data) ^ 0xffff_ffff
+    }
+}
+
+pub(crate) fn update_adler32(adler: u32, buf: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    for chunk in buf.chunks(5552) {
+        for data in chunk {
+            a += *data as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
 }
 
 /*
@@ -88,14 +109,35 @@ pub(crate) fn make_metadata(header: &super::header::PngHeader) -> HashMap<String
         let gamma = gamma as f64 / 100000.0;
         map.insert("gamma".to_string(), DataMap::Float(gamma));
     }
+    if let Some(chromaticity) = &header.chromaticity {
+        let chromaticity = chromaticity
+            .iter()
+            .map(|value| *value as f64 / 100000.0)
+            .collect();
+        map.insert(
+            "chromaticity".to_string(),
+            DataMap::FloatAllay(chromaticity),
+        );
+    }
     if let Some(modified_time) = &header.modified_time {
         map.insert(
-            "gamma".to_string(),
+            "modified time".to_string(),
             DataMap::Ascii(modified_time.to_string()),
         );
     }
     if let Some(srgb) = &header.srgb {
         map.insert("sRGB".to_string(), DataMap::UInt(*srgb as u64));
+        let intent = match srgb {
+            0 => "Perceptual",
+            1 => "Relative colorimetric",
+            2 => "Saturation",
+            3 => "Absolute colorimetric",
+            _ => "Unknown",
+        };
+        map.insert(
+            "sRGB rendering intent".to_string(),
+            DataMap::Ascii(intent.to_string()),
+        );
     }
     for (key, val) in &header.text {
         map.insert(key.to_string(), DataMap::Ascii(val.to_string()));
@@ -128,6 +170,117 @@ pub(crate) fn make_metadata(header: &super::header::PngHeader) -> HashMap<String
     map
 }
 
+// libpng treats gAMA values within 5% of 1/2.2 as sRGB.
+const SRGB_GAMMA: f64 = 0.45455;
+const SRGB_GAMMA_THRESHOLD: f64 = 0.05;
+const LINEAR_STEPS: usize = 4096;
+
+/// Converts decoded `gAMA`/`cHRM` samples to sRGB.
+///
+/// Samples are linearized with the file gamma (or the sRGB curve when only
+/// `cHRM` is present), mapped through the primaries matrix, and re-encoded with
+/// the sRGB transfer curve. Alpha is left untouched.
+pub(crate) struct ColorCorrection {
+    to_linear: [f64; 256],
+    matrix: Option<Matrix3>,
+    to_srgb: Vec<u8>,
+}
+
+impl ColorCorrection {
+    /// Builds a correction for a PNG header, or `None` when the image is
+    /// already sRGB or carries no usable colorimetry.
+    ///
+    /// `sRGB` takes precedence over `gAMA`/`cHRM` for every rendering intent,
+    /// and `iCCP` does as well because the embedded profile describes the
+    /// image more precisely than the fallback chunks.
+    pub(crate) fn new(header: &super::header::PngHeader) -> Option<Self> {
+        if header.srgb.is_some() || header.iccprofile.is_some() {
+            return None;
+        }
+        let gamma = match header.gamma {
+            Some(0) => return None,
+            Some(gamma) => Some(gamma as f64 / 100000.0),
+            None => None,
+        };
+        let matrix = match &header.chromaticity {
+            Some(chromaticity) => {
+                let xy = |i: usize| {
+                    (
+                        chromaticity[i * 2] as f64 / 100000.0,
+                        chromaticity[i * 2 + 1] as f64 / 100000.0,
+                    )
+                };
+                let white = xy(0);
+                let primaries = [xy(1), xy(2), xy(3)];
+                if is_srgb_colorimetry(white, primaries) {
+                    None
+                } else {
+                    Some(rgb_to_srgb_matrix(white, primaries)?)
+                }
+            }
+            None => None,
+        };
+        let gamma =
+            gamma.filter(|gamma| ((gamma - SRGB_GAMMA) / SRGB_GAMMA).abs() > SRGB_GAMMA_THRESHOLD);
+        if gamma.is_none() && matrix.is_none() {
+            return None;
+        }
+
+        let mut to_linear = [0.0; 256];
+        for (i, value) in to_linear.iter_mut().enumerate() {
+            let sample = i as f64 / 255.0;
+            *value = match gamma {
+                Some(gamma) => sample.powf(1.0 / gamma),
+                None => srgb_to_linear(sample),
+            };
+        }
+        let to_srgb = (0..LINEAR_STEPS)
+            .map(|i| {
+                let value = linear_to_srgb(i as f64 / (LINEAR_STEPS - 1) as f64);
+                (value * 255.0 + 0.5) as u8
+            })
+            .collect();
+        Some(Self {
+            to_linear,
+            matrix,
+            to_srgb,
+        })
+    }
+
+    fn encode(&self, value: f64) -> u8 {
+        let index = (value.clamp(0.0, 1.0) * (LINEAR_STEPS - 1) as f64 + 0.5) as usize;
+        self.to_srgb[index]
+    }
+
+    /// Corrects RGBA pixels in place.
+    pub(crate) fn apply(&self, data: &mut [u8]) {
+        for pixel in data.chunks_exact_mut(4) {
+            let rgb = [
+                self.to_linear[pixel[0] as usize],
+                self.to_linear[pixel[1] as usize],
+                self.to_linear[pixel[2] as usize],
+            ];
+            let rgb = match &self.matrix {
+                Some(matrix) => transform_matrix3(matrix, rgb),
+                None => rgb,
+            };
+            pixel[0] = self.encode(rgb[0]);
+            pixel[1] = self.encode(rgb[1]);
+            pixel[2] = self.encode(rgb[2]);
+        }
+    }
+}
+
+fn is_srgb_colorimetry(white: (f64, f64), primaries: [(f64, f64); 3]) -> bool {
+    let near =
+        |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 0.001 && (a.1 - b.1).abs() < 0.001;
+    near(white, D65_WHITE_POINT)
+        && primaries
+            .iter()
+            .zip(SRGB_PRIMARIES.iter())
+            .all(|(a, b)| near(*a, *b))
+}
+
 fn decode_icc_profile(profile: &[u8]) -> Option<(String, Vec<u8>)> {
     // iCCP payload layout: profile_name\0 compression_method compressed_profile
     let name_end = profile.iter().position(|b| *b == 0)?;
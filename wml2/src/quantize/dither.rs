@@ -0,0 +1,375 @@
+//! Error diffusion and ordered dithering used when mapping pixels to a
+//! palette.
+
+use super::NearestColor;
+use std::sync::OnceLock;
+
+const BLUE_NOISE_SIZE: usize = 64;
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// Dithering applied when pixels are mapped to a palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Maps every pixel to its nearest palette entry.
+    None,
+    /// Floyd-Steinberg error diffusion over two rows.
+    FloydSteinberg,
+    /// Atkinson error diffusion, which drops a quarter of the error and keeps
+    /// flat areas clean.
+    Atkinson,
+    /// Three-row Sierra error diffusion.
+    Sierra,
+    /// Jarvis-Judice-Ninke error diffusion over three rows.
+    Jarvis,
+    /// Ordered dithering with a Bayer matrix of the given size (2, 4, 8 or 16).
+    Bayer(usize),
+    /// Ordered dithering with a 64x64 void-and-cluster blue-noise mask.
+    BlueNoise,
+}
+
+impl Dither {
+    /// Parses an option value such as `floyd_steinberg`, `atkinson`,
+    /// `bayer8` or `blue_noise`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase().replace(['-', ' '], "_");
+        let dither = match name.as_str() {
+            "none" | "off" => Self::None,
+            "floyd_steinberg" | "floydsteinberg" | "fs" => Self::FloydSteinberg,
+            "atkinson" => Self::Atkinson,
+            "sierra" => Self::Sierra,
+            "jarvis" | "jjn" => Self::Jarvis,
+            "bayer" | "ordered" => Self::Bayer(4),
+            "blue_noise" | "bluenoise" => Self::BlueNoise,
+            _ => {
+                let size = name.strip_prefix("bayer")?.trim_start_matches('_');
+                match size.parse() {
+                    Ok(size @ (2 | 4 | 8 | 16)) => Self::Bayer(size),
+                    _ => return None,
+                }
+            }
+        };
+        Some(dither)
+    }
+
+    pub(super) fn kernel(&self) -> Option<&'static Kernel> {
+        match self {
+            Self::FloydSteinberg => Some(&FLOYD_STEINBERG),
+            Self::Atkinson => Some(&ATKINSON),
+            Self::Sierra => Some(&SIERRA),
+            Self::Jarvis => Some(&JARVIS),
+            _ => None,
+        }
+    }
+
+    pub(super) fn threshold_map(&self) -> Option<(usize, &'static [f32])> {
+        match self {
+            Self::Bayer(size) => {
+                static MAPS: [OnceLock<Vec<f32>>; 4] = [const { OnceLock::new() }; 4];
+                let size = size.clamp(&2, &16).next_power_of_two();
+                let map = MAPS[size.trailing_zeros() as usize - 1].get_or_init(|| bayer(size));
+                Some((size, map))
+            }
+            Self::BlueNoise => {
+                static MAP: OnceLock<Vec<f32>> = OnceLock::new();
+                let map = MAP.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE));
+                Some((BLUE_NOISE_SIZE, map))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Error diffusion weights as `(dx, dy, weight)` relative to the current
+/// pixel, in scan direction.
+pub(super) struct Kernel {
+    divisor: f32,
+    taps: &'static [(isize, usize, f32)],
+}
+
+static FLOYD_STEINBERG: Kernel = Kernel {
+    divisor: 16.0,
+    taps: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+};
+
+static ATKINSON: Kernel = Kernel {
+    divisor: 8.0,
+    taps: &[
+        (1, 0, 1.0),
+        (2, 0, 1.0),
+        (-1, 1, 1.0),
+        (0, 1, 1.0),
+        (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+};
+
+static SIERRA: Kernel = Kernel {
+    divisor: 32.0,
+    taps: &[
+        (1, 0, 5.0),
+        (2, 0, 3.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 5.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-1, 2, 2.0),
+        (0, 2, 3.0),
+        (1, 2, 2.0),
+    ],
+};
+
+static JARVIS: Kernel = Kernel {
+    divisor: 48.0,
+    taps: &[
+        (1, 0, 7.0),
+        (2, 0, 5.0),
+        (-2, 1, 3.0),
+        (-1, 1, 5.0),
+        (0, 1, 7.0),
+        (1, 1, 5.0),
+        (2, 1, 3.0),
+        (-2, 2, 1.0),
+        (-1, 2, 3.0),
+        (0, 2, 5.0),
+        (1, 2, 3.0),
+        (2, 2, 1.0),
+    ],
+};
+
+/// Source pixel and palette access shared by the dithering passes.
+pub(super) struct DitherTarget<'a> {
+    pub rgba: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    pub palette: &'a [[u8; 4]],
+    pub channels: usize,
+    pub strength: f32,
+}
+
+impl DitherTarget<'_> {
+    fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        let pixel = &self.rgba[offset..offset + 4];
+        [pixel[0], pixel[1], pixel[2], pixel[3]]
+    }
+
+    fn adjust(&self, pixel: [u8; 4], offset: [f32; 4]) -> [u8; 4] {
+        let mut color = pixel;
+        for channel in 0..self.channels {
+            let minimum = if channel == 3 { 1.0 } else { 0.0 };
+            color[channel] = (pixel[channel] as f32 + offset[channel])
+                .round()
+                .clamp(minimum, 255.0) as u8;
+        }
+        color
+    }
+}
+
+/// Maps pixels with error diffusion. Odd rows run right to left when
+/// `serpentine` is set.
+pub(super) fn diffuse(
+    target: &DitherTarget<'_>,
+    nearest: &mut NearestColor,
+    kernel: &Kernel,
+    serpentine: bool,
+) -> Vec<u8> {
+    let (width, height) = (target.width, target.height);
+    let rows = kernel.taps.iter().map(|tap| tap.1).max().unwrap_or(0) + 1;
+    let mut errors = vec![vec![[0.0_f32; 4]; width + 4]; rows];
+    let mut indices = vec![0_u8; width * height];
+
+    for y in 0..height {
+        let reverse = serpentine && y % 2 == 1;
+        let row = y % rows;
+        for step in 0..width {
+            let x = if reverse { width - 1 - step } else { step };
+            let pixel = target.pixel(x, y);
+            if pixel[3] == 0 {
+                indices[y * width + x] = nearest.nearest([0, 0, 0, 0]) as u8;
+                continue;
+            }
+
+            let color = target.adjust(pixel, errors[row][x + 2]);
+            let index = nearest.nearest(color);
+            indices[y * width + x] = index as u8;
+
+            let entry = target.palette[index];
+            let mut error = [0.0_f32; 4];
+            for channel in 0..target.channels {
+                error[channel] = (color[channel] as f32 - entry[channel] as f32) * target.strength;
+            }
+            for &(dx, dy, weight) in kernel.taps {
+                let dx = if reverse { -dx } else { dx };
+                let column = x as isize + 2 + dx;
+                if dy > 0 && y + dy >= height {
+                    continue;
+                }
+                let Some(cell) = errors[(y + dy) % rows].get_mut(column as usize) else {
+                    continue;
+                };
+                for channel in 0..target.channels {
+                    cell[channel] += error[channel] * weight / kernel.divisor;
+                }
+            }
+        }
+        errors[row].fill([0.0; 4]);
+    }
+    indices
+}
+
+/// Maps pixels with an ordered threshold map scaled by `spread`.
+pub(super) fn ordered(
+    target: &DitherTarget<'_>,
+    nearest: &mut NearestColor,
+    map: (usize, &[f32]),
+    spread: f32,
+) -> Vec<u8> {
+    let (size, thresholds) = map;
+    let mut indices = Vec::with_capacity(target.width * target.height);
+    for y in 0..target.height {
+        for x in 0..target.width {
+            let pixel = target.pixel(x, y);
+            if pixel[3] == 0 {
+                indices.push(nearest.nearest([0, 0, 0, 0]) as u8);
+                continue;
+            }
+            let offset = thresholds[(y % size) * size + x % size] * spread * target.strength;
+            let color = target.adjust(pixel, [offset; 4]);
+            indices.push(nearest.nearest(color) as u8);
+        }
+    }
+    indices
+}
+
+fn thresholds_from_ranks(ranks: &[u32]) -> Vec<f32> {
+    let count = ranks.len() as f32;
+    ranks
+        .iter()
+        .map(|&rank| (rank as f32 + 0.5) / count - 0.5)
+        .collect()
+}
+
+fn bayer(size: usize) -> Vec<f32> {
+    let mut matrix = vec![0_u32];
+    let mut current = 1;
+    while current < size {
+        let next_size = current * 2;
+        let mut next = vec![0_u32; next_size * next_size];
+        for y in 0..current {
+            for x in 0..current {
+                let value = matrix[y * current + x] * 4;
+                next[y * next_size + x] = value;
+                next[y * next_size + x + current] = value + 2;
+                next[(y + current) * next_size + x] = value + 3;
+                next[(y + current) * next_size + x + current] = value + 1;
+            }
+        }
+        matrix = next;
+        current = next_size;
+    }
+    thresholds_from_ranks(&matrix)
+}
+
+/// Toroidal Gaussian energy of a binary pattern for void-and-cluster.
+struct NoiseField {
+    size: usize,
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+    ones: Vec<bool>,
+}
+
+impl NoiseField {
+    fn new(size: usize) -> Self {
+        let count = size * size;
+        let denominator = 2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA;
+        let kernel = (0..count)
+            .map(|index| {
+                let (dx, dy) = (index % size, index / size);
+                let (dx, dy) = (dx.min(size - dx) as f32, dy.min(size - dy) as f32);
+                (-(dx * dx + dy * dy) / denominator).exp()
+            })
+            .collect();
+        Self {
+            size,
+            kernel,
+            energy: vec![0.0; count],
+            ones: vec![false; count],
+        }
+    }
+
+    fn set(&mut self, point: usize, value: bool) {
+        self.ones[point] = value;
+        let sign = if value { 1.0 } else { -1.0 };
+        let size = self.size;
+        let (px, py) = (point % size, point / size);
+        for y in 0..size {
+            let dy = (y + size - py) % size;
+            for x in 0..size {
+                let dx = (x + size - px) % size;
+                self.energy[y * size + x] += sign * self.kernel[dy * size + dx];
+            }
+        }
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        (0..self.ones.len())
+            .filter(|&point| self.ones[point])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap_or(0)
+    }
+
+    fn largest_void(&self) -> usize {
+        (0..self.ones.len())
+            .filter(|&point| !self.ones[point])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap_or(0)
+    }
+}
+
+/// Builds a blue-noise threshold map with Ulichney's void-and-cluster method.
+fn void_and_cluster(size: usize) -> Vec<f32> {
+    let count = size * size;
+    let mut field = NoiseField::new(size);
+
+    let mut seed = 0x2545_f491_u32;
+    let mut initial = 0;
+    while initial < count / 10 {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let point = seed as usize % count;
+        if !field.ones[point] {
+            field.set(point, true);
+            initial += 1;
+        }
+    }
+    for _ in 0..count {
+        let cluster = field.tightest_cluster();
+        field.set(cluster, false);
+        let void = field.largest_void();
+        field.set(void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0_u32; count];
+    let mut removal = NoiseField {
+        size,
+        kernel: field.kernel.clone(),
+        energy: field.energy.clone(),
+        ones: field.ones.clone(),
+    };
+    for rank in (0..initial).rev() {
+        let cluster = removal.tightest_cluster();
+        removal.set(cluster, false);
+        ranks[cluster] = rank as u32;
+    }
+    for rank in initial..count {
+        let void = field.largest_void();
+        field.set(void, true);
+        ranks[void] = rank as u32;
+    }
+    thresholds_from_ranks(&ranks)
+}
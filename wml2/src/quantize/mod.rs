@@ -0,0 +1,846 @@
+//! Color quantization shared by indexed encoders.
+//!
+//! Palettes are built from a color histogram with median-cut, octree or
+//! histogram-bin selection, optionally refined with k-means, and pixels are
+//! mapped to the nearest entry in RGB or CIELAB space with optional
+//! dithering. Colors are handled as `[red, green, blue, alpha]` arrays.
+
+mod dither;
+
+pub use dither::Dither;
+
+use crate::color::{
+    D65_WHITE_POINT, Matrix3, SRGB_PRIMARIES, rgb_to_xyz_matrix, srgb_to_linear, transform_matrix3,
+    xy_to_xyz,
+};
+use crate::error::{ImgError, ImgErrorKind};
+use crate::metadata::DataMap;
+use dither::DitherTarget;
+use std::collections::HashMap;
+
+type Error = Box<dyn std::error::Error>;
+
+/// Histograms with more distinct colors than this are merged into 6-bit bins
+/// before palette generation.
+const MAX_HISTOGRAM_ENTRIES: usize = 1 << 16;
+const OCTREE_DEPTH: usize = 6;
+const NEAREST_CACHE_LIMIT: usize = 1 << 20;
+
+/// The PC-98 default 16-color palette (GRB digital colors at half and full
+/// intensity). The Pi decoder uses it for 16-color images without a palette.
+pub const PC98_16_PALETTE: [[u8; 4]; 16] = [
+    [0x00, 0x00, 0x00, 0xff],
+    [0x00, 0x00, 0x77, 0xff],
+    [0x77, 0x00, 0x00, 0xff],
+    [0x77, 0x00, 0x77, 0xff],
+    [0x00, 0x77, 0x00, 0xff],
+    [0x00, 0x77, 0x77, 0xff],
+    [0x77, 0x77, 0x00, 0xff],
+    [0x77, 0x77, 0x77, 0xff],
+    [0x00, 0x00, 0x00, 0xff],
+    [0x00, 0x00, 0xff, 0xff],
+    [0xff, 0x00, 0x00, 0xff],
+    [0xff, 0x00, 0xff, 0xff],
+    [0x00, 0xff, 0x00, 0xff],
+    [0x00, 0xff, 0xff, 0xff],
+    [0xff, 0xff, 0x00, 0xff],
+    [0xff, 0xff, 0xff, 0xff],
+];
+
+/// Palette generation algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuantizeMethod {
+    /// Recursively splits the color box with the widest weighted range.
+    MedianCut,
+    /// Builds an octree and folds the least used branches.
+    Octree,
+    /// Keeps the most populated 5-bit histogram bins.
+    Histogram,
+}
+
+/// Metric used for nearest-color search and k-means assignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDistance {
+    /// Squared Euclidean distance of the 8-bit channels.
+    Rgb,
+    /// CIE76 distance in CIELAB (D65), closer to perceived difference.
+    Lab,
+}
+
+/// Palette generation and mapping settings.
+#[derive(Debug, Clone)]
+pub struct QuantizeOptions {
+    /// Palette generation algorithm.
+    pub method: QuantizeMethod,
+    /// Palette size limit in `2..=256`, including the transparent entry.
+    pub max_colors: usize,
+    /// k-means refinement passes over the generated palette. `0` disables it.
+    pub kmeans_iterations: usize,
+    /// Metric used when mapping colors to palette entries.
+    pub distance: ColorDistance,
+    /// Quantizes alpha as a fourth channel. When disabled, only fully
+    /// transparent pixels stay transparent and all other pixels are opaque.
+    pub alpha: bool,
+    /// Reserves a transparent entry even if no pixel is transparent.
+    pub reserve_transparent: bool,
+    /// Palette index of the transparent entry. The slot is reserved while
+    /// the palette is built, so it counts against `max_colors`; indices past
+    /// the last color are filled with opaque black.
+    pub transparent_index: u8,
+    /// Dithering used when the palette is not exact.
+    pub dither: Dither,
+    /// Alternates the scan direction of error diffusion on every row.
+    pub serpentine: bool,
+    /// Scales diffused error or ordered threshold offsets, `0.0..=1.0`.
+    pub dither_strength: f32,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            method: QuantizeMethod::MedianCut,
+            max_colors: 256,
+            kmeans_iterations: 3,
+            distance: ColorDistance::Rgb,
+            alpha: false,
+            reserve_transparent: false,
+            transparent_index: 0,
+            dither: Dither::FloydSteinberg,
+            serpentine: false,
+            dither_strength: 1.0,
+        }
+    }
+}
+
+impl QuantizeOptions {
+    /// Reads the shared quantizer keys from encoder options.
+    ///
+    /// Supported keys:
+    /// - `max_colors`: palette size limit, `2..=256`
+    /// - `quantize`: `median_cut`, `octree`, or `histogram`
+    /// - `kmeans`: number of k-means refinement passes
+    /// - `color_distance`: `rgb` or `lab`
+    /// - `dither`: `none`, `floyd_steinberg`, `atkinson`, `sierra`, `jarvis`,
+    ///   `bayer2`, `bayer4`, `bayer8`, `bayer16`, or `blue_noise`
+    /// - `serpentine`: non-zero alternates the error diffusion direction
+    /// - `dither_strength`: `0.0..=1.0`
+    pub fn from_options(options: Option<&HashMap<String, DataMap>>) -> Result<Self, Error> {
+        let mut quantize = Self::default();
+        let Some(options) = options else {
+            return Ok(quantize);
+        };
+
+        if let Some(value) = options.get("max_colors") {
+            quantize.max_colors = match value {
+                DataMap::UInt(value @ 2..=256) => *value as usize,
+                DataMap::SInt(value @ 2..=256) => *value as usize,
+                _ => {
+                    return Err(quantize_error(
+                        "max_colors must be an integer in 2..=256".to_string(),
+                    ));
+                }
+            };
+        }
+        if let Some(value) = options.get("quantize") {
+            quantize.method = match ascii_option(value, "quantize")?.as_str() {
+                "median_cut" | "mediancut" => QuantizeMethod::MedianCut,
+                "octree" => QuantizeMethod::Octree,
+                "histogram" => QuantizeMethod::Histogram,
+                other => {
+                    return Err(quantize_error(format!(
+                        "unsupported quantize method: {other}"
+                    )));
+                }
+            };
+        }
+        if let Some(value) = options.get("kmeans") {
+            quantize.kmeans_iterations = match value {
+                DataMap::UInt(value) => *value as usize,
+                DataMap::SInt(value) if *value >= 0 => *value as usize,
+                _ => {
+                    return Err(quantize_error(
+                        "kmeans must be a non-negative integer".to_string(),
+                    ));
+                }
+            };
+        }
+        if let Some(value) = options.get("color_distance") {
+            quantize.distance = match ascii_option(value, "color_distance")?.as_str() {
+                "rgb" => ColorDistance::Rgb,
+                "lab" => ColorDistance::Lab,
+                other => {
+                    return Err(quantize_error(format!(
+                        "unsupported color distance: {other}"
+                    )));
+                }
+            };
+        }
+        if let Some(value) = options.get("dither") {
+            let name = ascii_option(value, "dither")?;
+            quantize.dither = Dither::from_name(&name)
+                .ok_or_else(|| quantize_error(format!("unsupported dither: {name}")))?;
+        }
+        if let Some(value) = options.get("serpentine") {
+            quantize.serpentine = match value {
+                DataMap::UInt(value) => *value > 0,
+                DataMap::SInt(value) if *value >= 0 => *value > 0,
+                _ => {
+                    return Err(quantize_error(
+                        "serpentine must be a non-negative integer".to_string(),
+                    ));
+                }
+            };
+        }
+        if let Some(value) = options.get("dither_strength") {
+            let strength = match value {
+                DataMap::Float(value) => *value,
+                DataMap::UInt(value) => *value as f64,
+                DataMap::SInt(value) => *value as f64,
+                _ => f64::NAN,
+            };
+            if !(0.0..=1.0).contains(&strength) {
+                return Err(quantize_error(
+                    "dither_strength must be between 0.0 and 1.0".to_string(),
+                ));
+            }
+            quantize.dither_strength = strength as f32;
+        }
+        Ok(quantize)
+    }
+
+    fn palette_limit(&self) -> usize {
+        self.max_colors.clamp(2, 256)
+    }
+}
+
+/// Palette and per-pixel indices produced by [`quantize`].
+#[derive(Debug, Clone)]
+pub struct QuantizedImage {
+    /// Palette entries as `[red, green, blue, alpha]`.
+    pub palette: Vec<[u8; 4]>,
+    /// One palette index per pixel in row-major order.
+    pub indices: Vec<u8>,
+    /// Index of the fully transparent entry, if the palette has one.
+    pub transparent_index: Option<u8>,
+    /// `true` when every source color is in the palette unchanged.
+    pub exact: bool,
+}
+
+fn quantize_error(message: String) -> Error {
+    Box::new(ImgError::new_const(ImgErrorKind::InvalidParameter, message))
+}
+
+fn ascii_option(value: &DataMap, key: &str) -> Result<String, Error> {
+    match value {
+        DataMap::Ascii(value) => Ok(value.to_ascii_lowercase()),
+        _ => Err(quantize_error(format!("{key} must be a string"))),
+    }
+}
+
+fn check_buffer(rgba: &[u8], width: usize, height: usize) -> Result<(), Error> {
+    let expected_len = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or_else(|| quantize_error("image dimensions overflow".to_string()))?;
+    if rgba.len() != expected_len {
+        return Err(quantize_error("RGBA buffer size mismatch".to_string()));
+    }
+    Ok(())
+}
+
+fn pack(color: [u8; 4]) -> u32 {
+    u32::from_be_bytes(color)
+}
+
+fn unpack(key: u32) -> [u8; 4] {
+    key.to_be_bytes()
+}
+
+/// A histogram entry: a color and the number of pixels it stands for.
+#[derive(Debug, Clone, Copy)]
+struct ColorCount {
+    color: [u8; 4],
+    count: u64,
+}
+
+/// Running channel sums used to average clusters.
+#[derive(Debug, Clone, Copy, Default)]
+struct ColorSum {
+    sum: [u64; 4],
+    count: u64,
+}
+
+impl ColorSum {
+    fn add(&mut self, color: [u8; 4], count: u64) {
+        for (sum, value) in self.sum.iter_mut().zip(color) {
+            *sum += value as u64 * count;
+        }
+        self.count += count;
+    }
+
+    fn mean(&self) -> [u8; 4] {
+        let count = self.count.max(1);
+        self.sum.map(|sum| ((sum + count / 2) / count) as u8)
+    }
+}
+
+/// Counts distinct colors across one or more images.
+///
+/// Several frames can be added to build one shared palette.
+#[derive(Debug, Clone)]
+pub struct ColorHistogram {
+    alpha: bool,
+    counts: HashMap<u32, u64>,
+    transparent: u64,
+}
+
+impl ColorHistogram {
+    /// Creates an empty histogram. `alpha` keeps partial alpha distinct as in
+    /// [`QuantizeOptions::alpha`].
+    pub fn new(alpha: bool) -> Self {
+        Self {
+            alpha,
+            counts: HashMap::new(),
+            transparent: 0,
+        }
+    }
+
+    fn key_color(&self, pixel: [u8; 4]) -> Option<[u8; 4]> {
+        if pixel[3] == 0 {
+            None
+        } else if self.alpha {
+            Some(pixel)
+        } else {
+            Some([pixel[0], pixel[1], pixel[2], 0xff])
+        }
+    }
+
+    /// Adds one pixel.
+    pub fn add_pixel(&mut self, pixel: [u8; 4]) {
+        match self.key_color(pixel) {
+            Some(color) => *self.counts.entry(pack(color)).or_default() += 1,
+            None => self.transparent += 1,
+        }
+    }
+
+    /// Adds every pixel of an RGBA buffer.
+    pub fn add_rgba(&mut self, rgba: &[u8]) {
+        for pixel in rgba.chunks_exact(4) {
+            self.add_pixel([pixel[0], pixel[1], pixel[2], pixel[3]]);
+        }
+    }
+
+    /// Number of distinct colors, not counting fully transparent pixels.
+    pub fn color_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Returns `true` when a fully transparent pixel was added.
+    pub fn has_transparency(&self) -> bool {
+        self.transparent > 0
+    }
+
+    /// Returns `true` when the histogram fits in a palette without loss.
+    pub fn is_exact(&self, options: &QuantizeOptions) -> bool {
+        let reserved = (options.reserve_transparent || self.has_transparency()) as usize;
+        self.color_count() + reserved <= options.palette_limit()
+    }
+
+    fn entries(&self) -> Vec<ColorCount> {
+        let mut entries: Vec<ColorCount> = if self.counts.len() > MAX_HISTOGRAM_ENTRIES {
+            let mut bins: HashMap<u32, ColorSum> = HashMap::new();
+            for (&key, &count) in &self.counts {
+                let color = unpack(key);
+                bins.entry(pack(color.map(|value| value & 0xfc)))
+                    .or_default()
+                    .add(color, count);
+            }
+            bins.values()
+                .map(|bin| ColorCount {
+                    color: bin.mean(),
+                    count: bin.count,
+                })
+                .collect()
+        } else {
+            self.counts
+                .iter()
+                .map(|(&key, &count)| ColorCount {
+                    color: unpack(key),
+                    count,
+                })
+                .collect()
+        };
+        entries.sort_unstable_by_key(|entry| pack(entry.color));
+        entries
+    }
+
+    /// Builds a palette of at most `options.max_colors` entries.
+    ///
+    /// When the image has transparent pixels or
+    /// [`QuantizeOptions::reserve_transparent`] is set, the entry at
+    /// [`QuantizeOptions::transparent_index`] (limited to the last palette
+    /// slot) is `[0, 0, 0, 0]`. Histograms that already fit are returned
+    /// unchanged.
+    pub fn palette(&self, options: &QuantizeOptions) -> Vec<[u8; 4]> {
+        let reserve = options.reserve_transparent || self.has_transparency();
+        let budget = options.palette_limit() - reserve as usize;
+        let channels = if self.alpha { 4 } else { 3 };
+        let entries = self.entries();
+
+        let mut palette: Vec<[u8; 4]> = if self.counts.len() <= budget {
+            entries.iter().map(|entry| entry.color).collect()
+        } else {
+            let mut colors = match options.method {
+                QuantizeMethod::MedianCut => median_cut(&entries, budget, channels),
+                QuantizeMethod::Octree => octree(&entries, budget, channels),
+                QuantizeMethod::Histogram => histogram_bins(&entries, budget),
+            };
+            kmeans(
+                &entries,
+                &mut colors,
+                options.kmeans_iterations,
+                options.distance,
+                self.alpha,
+            );
+            colors
+        };
+
+        if reserve {
+            let index = (options.transparent_index as usize).min(budget);
+            if palette.len() < index {
+                palette.resize(index, [0, 0, 0, 0xff]);
+            }
+            palette.insert(index, [0, 0, 0, 0]);
+        }
+        if palette.len() == reserve as usize {
+            palette.push([0, 0, 0, 0xff]);
+        }
+        palette
+    }
+}
+
+/// A box of histogram entries for median-cut.
+#[derive(Debug, Clone, Copy)]
+struct ColorBox {
+    start: usize,
+    end: usize,
+    channel: usize,
+    score: u64,
+}
+
+impl ColorBox {
+    fn new(entries: &[ColorCount], start: usize, end: usize, channels: usize) -> Self {
+        let mut min = [u8::MAX; 4];
+        let mut max = [0_u8; 4];
+        let mut count = 0;
+        for entry in &entries[start..end] {
+            for channel in 0..channels {
+                min[channel] = min[channel].min(entry.color[channel]);
+                max[channel] = max[channel].max(entry.color[channel]);
+            }
+            count += entry.count;
+        }
+        let (channel, range) = (0..channels)
+            .map(|channel| (channel, (max[channel] - min[channel]) as u64))
+            .max_by_key(|(_, range)| *range)
+            .unwrap_or((0, 0));
+        let score = if end - start > 1 { range * count } else { 0 };
+        Self {
+            start,
+            end,
+            channel,
+            score,
+        }
+    }
+}
+
+fn median_cut(entries: &[ColorCount], budget: usize, channels: usize) -> Vec<[u8; 4]> {
+    let mut entries = entries.to_vec();
+    let mut boxes = vec![ColorBox::new(&entries, 0, entries.len(), channels)];
+
+    while boxes.len() < budget {
+        let Some((pick, color_box)) = boxes
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.score > 0)
+            .max_by_key(|(_, color_box)| color_box.score)
+        else {
+            break;
+        };
+
+        let slice = &mut entries[color_box.start..color_box.end];
+        let channel = color_box.channel;
+        slice.sort_unstable_by_key(|entry| (entry.color[channel], pack(entry.color)));
+        let total: u64 = slice.iter().map(|entry| entry.count).sum();
+        let mut cumulative = 0;
+        let mut middle = slice.len() - 1;
+        for (index, entry) in slice.iter().enumerate() {
+            cumulative += entry.count;
+            if cumulative * 2 >= total {
+                middle = index + 1;
+                break;
+            }
+        }
+        let middle = color_box.start + middle.clamp(1, slice.len() - 1);
+
+        boxes[pick] = ColorBox::new(&entries, color_box.start, middle, channels);
+        boxes.push(ColorBox::new(&entries, middle, color_box.end, channels));
+    }
+
+    boxes
+        .iter()
+        .map(|color_box| {
+            let mut sum = ColorSum::default();
+            for entry in &entries[color_box.start..color_box.end] {
+                sum.add(entry.color, entry.count);
+            }
+            sum.mean()
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct OctreeNode {
+    sum: ColorSum,
+    children: [u32; 16],
+    leaf: bool,
+}
+
+fn octree_child(color: [u8; 4], level: usize, channels: usize) -> usize {
+    let shift = 7 - level;
+    (0..channels).fold(0, |index, channel| {
+        (index << 1) | ((color[channel] >> shift) & 1) as usize
+    })
+}
+
+fn octree(entries: &[ColorCount], budget: usize, channels: usize) -> Vec<[u8; 4]> {
+    let mut nodes = vec![OctreeNode::default()];
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); OCTREE_DEPTH];
+    levels[0].push(0);
+    let mut leaf_count = 0;
+
+    for entry in entries {
+        let mut node = 0;
+        nodes[node].sum.add(entry.color, entry.count);
+        for level in 0..OCTREE_DEPTH {
+            let child = octree_child(entry.color, level, channels);
+            let next = nodes[node].children[child] as usize;
+            let next = if next == 0 {
+                let index = nodes.len();
+                nodes.push(OctreeNode::default());
+                nodes[node].children[child] = index as u32;
+                if level + 1 == OCTREE_DEPTH {
+                    nodes[index].leaf = true;
+                    leaf_count += 1;
+                } else {
+                    levels[level + 1].push(index);
+                }
+                index
+            } else {
+                next
+            };
+            nodes[next].sum.add(entry.color, entry.count);
+            node = next;
+        }
+    }
+
+    'reduce: for level in (0..OCTREE_DEPTH).rev() {
+        let mut candidates = std::mem::take(&mut levels[level]);
+        candidates.sort_by_key(|&node| nodes[node].sum.count);
+        for node in candidates {
+            if leaf_count <= budget {
+                break 'reduce;
+            }
+            let children = nodes[node]
+                .children
+                .iter()
+                .filter(|&&child| child != 0)
+                .count();
+            nodes[node].leaf = true;
+            leaf_count = leaf_count + 1 - children;
+        }
+    }
+
+    let mut colors = Vec::with_capacity(leaf_count);
+    let mut stack = vec![0_usize];
+    while let Some(node) = stack.pop() {
+        if nodes[node].leaf {
+            colors.push(nodes[node].sum.mean());
+        } else {
+            stack.extend(
+                nodes[node]
+                    .children
+                    .iter()
+                    .rev()
+                    .filter(|&&child| child != 0)
+                    .map(|&child| child as usize),
+            );
+        }
+    }
+    colors
+}
+
+fn histogram_bins(entries: &[ColorCount], budget: usize) -> Vec<[u8; 4]> {
+    let mut bins: HashMap<u32, ColorSum> = HashMap::new();
+    for entry in entries {
+        bins.entry(pack(entry.color.map(|value| value & 0xf8)))
+            .or_default()
+            .add(entry.color, entry.count);
+    }
+    let mut bins: Vec<(u32, ColorSum)> = bins.into_iter().collect();
+    bins.sort_unstable_by(|left, right| {
+        right.1.count.cmp(&left.1.count).then(left.0.cmp(&right.0))
+    });
+    bins.iter()
+        .take(budget)
+        .map(|(_, bin)| bin.mean())
+        .collect()
+}
+
+fn kmeans(
+    entries: &[ColorCount],
+    colors: &mut [[u8; 4]],
+    iterations: usize,
+    distance: ColorDistance,
+    alpha: bool,
+) {
+    for _ in 0..iterations {
+        let mut nearest = NearestColor::new(colors, distance, alpha);
+        let mut sums = vec![ColorSum::default(); colors.len()];
+        for entry in entries {
+            sums[nearest.nearest(entry.color)].add(entry.color, entry.count);
+        }
+
+        let mut changed = false;
+        for (color, sum) in colors.iter_mut().zip(&sums) {
+            if sum.count > 0 && *color != sum.mean() {
+                *color = sum.mean();
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Nearest palette entry lookup with a per-color cache.
+///
+/// Fully transparent colors map to the first fully transparent entry. Unless
+/// alpha is quantized, opaque colors never map to that entry.
+#[derive(Debug, Clone)]
+pub struct NearestColor {
+    points: Vec<[f32; 4]>,
+    candidates: Vec<usize>,
+    transparent: Option<usize>,
+    alpha: bool,
+    lab: Option<Box<LabConverter>>,
+    cache: HashMap<u32, u8>,
+}
+
+impl NearestColor {
+    /// Prepares a lookup for `palette`. `alpha` compares alpha as a fourth
+    /// channel as in [`QuantizeOptions::alpha`].
+    pub fn new(palette: &[[u8; 4]], distance: ColorDistance, alpha: bool) -> Self {
+        let lab = (distance == ColorDistance::Lab).then(|| Box::new(LabConverter::new()));
+        let transparent = palette.iter().position(|color| color[3] == 0);
+        let mut candidates: Vec<usize> = (0..palette.len())
+            .filter(|&index| alpha || palette[index][3] != 0)
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..palette.len()).collect();
+        }
+
+        let mut nearest = Self {
+            points: Vec::with_capacity(palette.len()),
+            candidates,
+            transparent,
+            alpha,
+            lab,
+            cache: HashMap::new(),
+        };
+        nearest.points = palette.iter().map(|&color| nearest.point(color)).collect();
+        nearest
+    }
+
+    fn point(&self, color: [u8; 4]) -> [f32; 4] {
+        let alpha = if self.alpha { color[3] as f32 } else { 0.0 };
+        match &self.lab {
+            Some(lab) => {
+                let [l, a, b] = lab.convert(color);
+                [l, a, b, alpha * (100.0 / 255.0)]
+            }
+            None => [color[0] as f32, color[1] as f32, color[2] as f32, alpha],
+        }
+    }
+
+    /// Returns the palette index closest to `color`.
+    pub fn nearest(&mut self, color: [u8; 4]) -> usize {
+        if color[3] == 0
+            && let Some(transparent) = self.transparent
+        {
+            return transparent;
+        }
+        let color = if self.alpha {
+            color
+        } else {
+            [color[0], color[1], color[2], 0xff]
+        };
+        let key = pack(color);
+        if let Some(&index) = self.cache.get(&key) {
+            return index as usize;
+        }
+
+        let point = self.point(color);
+        let mut best = self.candidates[0];
+        let mut best_distance = f32::MAX;
+        for &index in &self.candidates {
+            let entry = self.points[index];
+            let distance = (0..4)
+                .map(|channel| (point[channel] - entry[channel]).powi(2))
+                .sum::<f32>();
+            if distance < best_distance {
+                best_distance = distance;
+                best = index;
+            }
+        }
+
+        if self.cache.len() >= NEAREST_CACHE_LIMIT {
+            self.cache.clear();
+        }
+        self.cache.insert(key, best as u8);
+        best
+    }
+}
+
+/// sRGB to CIELAB (D65) conversion with a linearization table.
+#[derive(Debug, Clone)]
+struct LabConverter {
+    linear: [f64; 256],
+    matrix: Matrix3,
+    white: [f64; 3],
+}
+
+impl LabConverter {
+    fn new() -> Self {
+        let mut linear = [0.0; 256];
+        for (value, linear) in linear.iter_mut().enumerate() {
+            *linear = srgb_to_linear(value as f64 / 255.0);
+        }
+        Self {
+            linear,
+            matrix: rgb_to_xyz_matrix(D65_WHITE_POINT, SRGB_PRIMARIES).unwrap_or_default(),
+            white: xy_to_xyz(D65_WHITE_POINT).unwrap_or([1.0; 3]),
+        }
+    }
+
+    fn convert(&self, color: [u8; 4]) -> [f32; 3] {
+        let rgb = [
+            self.linear[color[0] as usize],
+            self.linear[color[1] as usize],
+            self.linear[color[2] as usize],
+        ];
+        let xyz = transform_matrix3(&self.matrix, rgb);
+        let [x, y, z] = [0, 1, 2].map(|channel| {
+            let t = xyz[channel] / self.white[channel];
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        });
+        [
+            (116.0 * y - 16.0) as f32,
+            (500.0 * (x - y)) as f32,
+            (200.0 * (y - z)) as f32,
+        ]
+    }
+}
+
+/// Maps an RGBA buffer to `palette`, one index per pixel.
+///
+/// `options.dither` selects error diffusion or ordered dithering, scaled by
+/// `options.dither_strength`. The palette can be fixed, such as
+/// [`PC98_16_PALETTE`]. Fully transparent pixels map to the palette's
+/// transparent entry when it has one and are never dithered.
+pub fn remap(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    palette: &[[u8; 4]],
+    options: &QuantizeOptions,
+) -> Result<Vec<u8>, Error> {
+    check_buffer(rgba, width, height)?;
+    if palette.is_empty() || palette.len() > 256 {
+        return Err(quantize_error(
+            "palette must have 1 to 256 entries".to_string(),
+        ));
+    }
+
+    let mut nearest = NearestColor::new(palette, options.distance, options.alpha);
+    let strength = options.dither_strength.clamp(0.0, 1.0);
+    if options.dither == Dither::None || strength == 0.0 {
+        return Ok(rgba
+            .chunks_exact(4)
+            .map(|pixel| nearest.nearest([pixel[0], pixel[1], pixel[2], pixel[3]]) as u8)
+            .collect());
+    }
+
+    let target = DitherTarget {
+        rgba,
+        width,
+        height,
+        palette,
+        channels: if options.alpha { 4 } else { 3 },
+        strength,
+    };
+    if let Some(kernel) = options.dither.kernel() {
+        return Ok(dither::diffuse(
+            &target,
+            &mut nearest,
+            kernel,
+            options.serpentine,
+        ));
+    }
+    let map = options.dither.threshold_map().unwrap_or((1, &[0.0]));
+    // One palette step per channel, assuming entries spread evenly over the
+    // color cube.
+    let colors = palette.iter().filter(|color| color[3] != 0).count().max(2);
+    let spread = 255.0 / ((colors as f32).cbrt() - 1.0).max(1.0);
+    Ok(dither::ordered(&target, &mut nearest, map, spread))
+}
+
+/// Quantizes an RGBA image to at most `options.max_colors` palette entries.
+///
+/// Images that already fit in the palette keep their exact colors and are
+/// never dithered.
+pub fn quantize(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    options: &QuantizeOptions,
+) -> Result<QuantizedImage, Error> {
+    check_buffer(rgba, width, height)?;
+    let mut histogram = ColorHistogram::new(options.alpha);
+    histogram.add_rgba(rgba);
+    let exact = histogram.is_exact(options);
+    let palette = histogram.palette(options);
+
+    let mut mapping = options.clone();
+    if exact {
+        mapping.dither = Dither::None;
+    }
+    let indices = remap(rgba, width, height, &palette, &mapping)?;
+    let transparent_index = palette
+        .iter()
+        .position(|color| color[3] == 0)
+        .map(|index| index as u8);
+
+    Ok(QuantizedImage {
+        palette,
+        indices,
+        transparent_index,
+        exact,
+    })
+}
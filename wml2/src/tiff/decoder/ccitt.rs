@@ -7,9 +7,14 @@ use crate::error::*;
 use crate::tiff::decoder::Tiff;
 use crate::tiff::header::Compression;
 
-pub fn decode(buf: &[u8], header: &Tiff) -> Result<(Vec<u8>, bool), Error> {
+/// Decodes a `width` x `height` block (a strip, tile or whole image).
+pub fn decode(
+    buf: &[u8],
+    header: &Tiff,
+    width: usize,
+    height: usize,
+) -> Result<(Vec<u8>, bool), Error> {
     let t4_options = header.t4_options;
-    let t6_options = header.t6_options;
 
     let encoding = match header.compression {
         Compression::CCITTHuffmanRLE => {
@@ -32,19 +37,15 @@ pub fn decode(buf: &[u8], header: &Tiff) -> Result<(Vec<u8>, bool), Error> {
         }
     };
 
-    if t4_options & 0x2 > 0 || t6_options & 0x2 > 0 {
-        //        encoding = 0;   // UNCOMPRESSED
-        return Err(Box::new(ImgError::new_const(
-            ImgErrorKind::DecodeError,
-            "Uncompress mode is not support".to_string(),
-        )));
-    }
-
+    // T4Options bit 2 (fill bits) needs nothing extra: EOLs are searched
+    // bit by bit. Uncompressed mode (T4Options/T6Options bit 1) is decoded
+    // whenever its extension code shows up.
     //    let photometric_interpretation = header.photometric_interpretation.clone();
 
-    let width = header.width as usize;
-    let height = header.height as usize;
     let is_lsb = header.fill_order == 2;
+    // Rows past the image are never drawn, so a tile or strip taller than
+    // the image is decoded only as far as the image height.
+    let height = height.min(header.height as usize);
 
     decoder(buf, width, height, encoding, is_lsb)
 }
@@ -9,29 +9,117 @@ use crate::tiff::header::*;
 use crate::warning::ImgWarnings;
 use bin_rs::reader::BinaryReader;
 
-fn draw_jpeg(
+fn decode_jpeg_part(
     data: Vec<u8>,
-    x: usize,
-    y: usize,
-    option: &mut DecodeOptions,
-) -> Result<Option<ImgWarnings>, Error> {
+    option: &DecodeOptions,
+) -> Result<(ImageBuffer, Option<ImgWarnings>), Error> {
     let mut image = ImageBuffer::new();
     let mut part_option = DecodeOptions {
         debug_flag: option.debug_flag,
         drawer: &mut image,
+        options: option.options.clone(),
     };
     let mut reader = bin_rs::reader::BytesReader::from(data);
     let ws = crate::jpeg::decoder::decode(&mut reader, &mut part_option)?;
-    let width = image.width;
-    let height = image.height;
+    Ok((image, ws))
+}
 
-    if let Some(buffer) = image.buffer.as_ref() {
-        option.drawer.draw(x, y, width, height, buffer, None)?;
+/// Draws a decoded RGBA block at (`x`, `y`), dropping the parts of partial
+/// edge tiles that lie outside the image.
+fn draw_clipped(
+    buffer: &[u8],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+) -> Result<(), Error> {
+    let draw_width = width.min((header.width as usize).saturating_sub(x));
+    let draw_height = height.min((header.height as usize).saturating_sub(y));
+    if draw_width == width {
+        let end = (width * draw_height * 4).min(buffer.len());
+        option
+            .drawer
+            .draw(x, y, width, draw_height, &buffer[..end], None)?;
+        return Ok(());
     }
+    for row in 0..draw_height {
+        let offset = row * width * 4;
+        let Some(line) = buffer.get(offset..offset + draw_width * 4) else {
+            break;
+        };
+        option.drawer.draw(x, y + row, draw_width, 1, line, None)?;
+    }
+    Ok(())
+}
 
+fn draw_jpeg(
+    data: Vec<u8>,
+    x: usize,
+    y: usize,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+) -> Result<Option<ImgWarnings>, Error> {
+    let (image, ws) = decode_jpeg_part(data, option)?;
+    if let Some(buffer) = image.buffer.as_ref() {
+        draw_clipped(buffer, image.width, image.height, x, y, option, header)?;
+    }
     Ok(ws)
 }
 
+/// Draws a tile stored as one single-component JPEG per sample plane
+/// (PlanarConfiguration 2).
+fn draw_jpeg_planes(
+    planes: Vec<Vec<u8>>,
+    x: usize,
+    y: usize,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+) -> Result<Option<ImgWarnings>, Error> {
+    let mut warnings = None;
+    let mut samples = vec![];
+    let (mut width, mut height) = (0, 0);
+    for data in planes {
+        let (image, ws) = decode_jpeg_part(data, option)?;
+        warnings = ImgWarnings::append(warnings, ws);
+        width = image.width;
+        height = image.height;
+        let buffer = image.buffer.unwrap_or_default();
+        samples.push(
+            buffer
+                .chunks_exact(4)
+                .map(|pixel| pixel[0])
+                .collect::<Vec<u8>>(),
+        );
+    }
+
+    let mut buffer = Vec::with_capacity(width * height * 4);
+    for i in 0..width * height {
+        let sample = |plane: usize| samples.get(plane).and_then(|s| s.get(i)).copied();
+        let gray = sample(0).unwrap_or(0);
+        buffer.push(gray);
+        buffer.push(sample(1).unwrap_or(gray));
+        buffer.push(sample(2).unwrap_or(gray));
+        buffer.push(sample(3).unwrap_or(0xff));
+    }
+    draw_clipped(&buffer, width, height, x, y, option, header)?;
+    Ok(warnings)
+}
+
+/// Builds a complete JPEG stream from the shared tables and one segment.
+fn jpeg_stream(metadata: &[u8], buf: &[u8], kind: &str) -> Result<Vec<u8>, Error> {
+    if buf.len() < 2 {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::DecodeError,
+            format!("JPEG {} payload is truncated", kind),
+        )));
+    }
+    let mut data = metadata.to_vec();
+    data.extend_from_slice(&buf[2..]); // remove SOI
+    Ok(data)
+}
+
 // Tiff in JPEG is a multi parts image.
 pub fn decode_jpeg_compresson<'decode, B: BinaryReader>(
     reader: &mut B,
@@ -54,7 +142,6 @@ pub fn decode_jpeg_compresson<'decode, B: BinaryReader>(
         metadata = jpeg_tables[..len].to_vec(); // remove EOI
     }
     let mut warnings: Option<ImgWarnings> = None;
-    let mut x = 0;
     let mut y = 0;
     if initialize {
         let init = if animation {
@@ -71,50 +158,49 @@ pub fn decode_jpeg_compresson<'decode, B: BinaryReader>(
             .init(header.width as usize, header.height as usize, init)?;
     }
 
-    if header.tile_width != 0
-        && header.tile_length != 0
-        && !header.tile_byte_counts.is_empty()
-        && !header.tile_offsets.is_empty()
-    {
-        for (i, offset) in header.tile_offsets.iter().enumerate() {
-            reader.seek(std::io::SeekFrom::Start(*offset as u64))?;
-            let mut data = vec![];
-            data.append(&mut metadata.to_vec());
-            let buf = reader.read_bytes_as_vec(header.tile_byte_counts[i] as usize)?;
-            if buf.len() < 2 {
-                return Err(Box::new(ImgError::new_const(
-                    ImgErrorKind::DecodeError,
-                    "JPEG tile payload is truncated".to_string(),
-                )));
-            }
-            data.append(&mut buf[2..].to_vec()); // remove SOI
+    if header.is_tiled() {
+        let tile_width = header.tile_width as usize;
+        let tile_length = header.tile_length as usize;
+        let across = (header.width as usize).div_ceil(tile_width);
+        let tiles = across * (header.height as usize).div_ceil(tile_length);
+        let planes = if header.planar_config == 2 {
+            header.samples_per_pixel.max(1) as usize
+        } else {
+            1
+        };
+        if header.tile_offsets.len() < tiles * planes
+            || header.tile_byte_counts.len() < tiles * planes
+        {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::DecodeError,
+                "Mismach length, tile offsets and tile byte counts.".to_string(),
+            )));
+        }
 
-            let ws = draw_jpeg(data, x, y, option)?;
-            warnings = ImgWarnings::append(warnings, ws);
-            x += header.tile_width as usize;
-            if x >= header.width as usize {
-                x = 0;
-                y += header.tile_length as usize;
-            }
-            if header.tile_length >= header.height {
-                break;
+        for tile in 0..tiles {
+            let mut parts = vec![];
+            for plane in 0..planes {
+                let index = plane * tiles + tile;
+                reader.seek(std::io::SeekFrom::Start(header.tile_offsets[index]))?;
+                let buf = reader.read_bytes_as_vec(header.tile_byte_counts[index] as usize)?;
+                parts.push(jpeg_stream(&metadata, &buf, "tile")?);
             }
+            let x = tile % across * tile_width;
+            let y = tile / across * tile_length;
+            let ws = if planes > 1 {
+                draw_jpeg_planes(parts, x, y, option, header)?
+            } else {
+                draw_jpeg(parts.remove(0), x, y, option, header)?
+            };
+            warnings = ImgWarnings::append(warnings, ws);
         }
     } else {
         for (i, offset) in header.strip_offsets.iter().enumerate() {
-            reader.seek(std::io::SeekFrom::Start(*offset as u64))?;
-            let mut data = vec![];
-            data.append(&mut metadata.to_vec());
+            reader.seek(std::io::SeekFrom::Start(*offset))?;
             let buf = reader.read_bytes_as_vec(header.strip_byte_counts[i] as usize)?;
-            if buf.len() < 2 {
-                return Err(Box::new(ImgError::new_const(
-                    ImgErrorKind::DecodeError,
-                    "JPEG strip payload is truncated".to_string(),
-                )));
-            }
-            data.append(&mut buf[2..].to_vec()); // remove SOI
+            let data = jpeg_stream(&metadata, &buf, "strip")?;
 
-            let ws = draw_jpeg(data, 0, y, option)?;
+            let ws = draw_jpeg(data, 0, y, option, header)?;
             y += header.rows_per_strip as usize;
             warnings = ImgWarnings::append(warnings, ws);
         }
@@ -122,3 +208,340 @@ pub fn decode_jpeg_compresson<'decode, B: BinaryReader>(
 
     Ok(warnings)
 }
+
+fn ojpeg_error(message: impl Into<String>) -> Error {
+    Box::new(ImgError::new_const(
+        ImgErrorKind::DecodeError,
+        message.into(),
+    ))
+}
+
+fn push_segment(stream: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    stream.extend_from_slice(&[0xff, marker]);
+    stream.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    stream.extend_from_slice(payload);
+}
+
+/// Table segments (DQT, DHT and DRI) of an old-style JPEG image with the
+/// number of quantization, DC and AC tables they define.
+struct OJpegTables {
+    segments: Vec<u8>,
+    counts: [usize; 3],
+}
+
+impl OJpegTables {
+    /// Rebuilds the tables from the JPEGQTables, JPEGDCTables and JPEGACTables
+    /// tags. Quantization tables are 64 bytes in zigzag order, Huffman tables
+    /// are 16 code counts followed by the symbols, as in DQT and DHT.
+    fn from_tags<B: BinaryReader>(reader: &mut B, header: &Tiff) -> Result<Self, Error> {
+        if header.jpeg_q_tables.is_empty()
+            || header.jpeg_dc_tables.is_empty()
+            || header.jpeg_ac_tables.is_empty()
+        {
+            return Err(ojpeg_error(
+                "Old-style JPEG needs JPEGInterchangeFormat or JPEGQTables/JPEGDCTables/JPEGACTables.",
+            ));
+        }
+        let mut segments = vec![];
+        for (id, offset) in header.jpeg_q_tables.iter().take(4).enumerate() {
+            reader.seek(std::io::SeekFrom::Start(*offset))?;
+            let mut payload = vec![id as u8];
+            payload.append(&mut reader.read_bytes_as_vec(64)?);
+            push_segment(&mut segments, 0xdb, &payload);
+        }
+        for (class, offsets) in [
+            (0x00, &header.jpeg_dc_tables),
+            (0x10, &header.jpeg_ac_tables),
+        ] {
+            for (id, offset) in offsets.iter().take(4).enumerate() {
+                reader.seek(std::io::SeekFrom::Start(*offset))?;
+                let mut payload = vec![class | id as u8];
+                let mut counts = reader.read_bytes_as_vec(16)?;
+                let symbols: usize = counts.iter().map(|&count| count as usize).sum();
+                payload.append(&mut counts);
+                payload.append(&mut reader.read_bytes_as_vec(symbols)?);
+                push_segment(&mut segments, 0xc4, &payload);
+            }
+        }
+        if header.jpeg_restart_interval != 0 {
+            push_segment(
+                &mut segments,
+                0xdd,
+                &header.jpeg_restart_interval.to_be_bytes(),
+            );
+        }
+        Ok(Self {
+            segments,
+            counts: [
+                header.jpeg_q_tables.len().min(4),
+                header.jpeg_dc_tables.len().min(4),
+                header.jpeg_ac_tables.len().min(4),
+            ],
+        })
+    }
+
+    /// Collects the DQT, DHT and DRI segments of a JPEGInterchangeFormat
+    /// stream that has no scan of its own.
+    fn from_stream(segments: &[(u8, &[u8])]) -> Self {
+        let mut tables = Self {
+            segments: vec![],
+            counts: [0; 3],
+        };
+        for &(marker, segment) in segments {
+            let payload = &segment[4..];
+            match marker {
+                0xdb => {
+                    let mut i = 0;
+                    while i < payload.len() {
+                        let id = (payload[i] & 0x0f) as usize;
+                        tables.counts[0] = tables.counts[0].max(id + 1);
+                        i += 1 + 64 * (1 + (payload[i] >> 4) as usize);
+                    }
+                }
+                0xc4 => {
+                    let mut i = 0;
+                    while i + 17 <= payload.len() {
+                        let class = 1 + (payload[i] >> 4).min(1) as usize;
+                        let id = (payload[i] & 0x0f) as usize;
+                        tables.counts[class] = tables.counts[class].max(id + 1);
+                        let symbols: usize =
+                            payload[i + 1..i + 17].iter().map(|&c| c as usize).sum();
+                        i += 17 + symbols;
+                    }
+                }
+                0xdd => {}
+                _ => continue,
+            }
+            tables.segments.extend_from_slice(segment);
+        }
+        tables
+    }
+
+    /// Builds a JPEG stream for one strip or tile of `width` x `height`
+    /// pixels. Component `n` uses table `components[n]`, or the last table
+    /// when there are fewer tables than components.
+    fn stream(
+        &self,
+        header: &Tiff,
+        width: usize,
+        height: usize,
+        components: &[usize],
+        scan: &[u8],
+    ) -> Vec<u8> {
+        let table = |kind: usize, component: usize| {
+            component.min(self.counts[kind].saturating_sub(1)) as u8
+        };
+        let sampling = |index: usize| {
+            if index == 0 && components.len() >= 3 && header.photometric_interpretation == 6 {
+                let [h, v] = header.ycbcr_subsampling;
+                (h as u8) << 4 | v as u8
+            } else {
+                0x11
+            }
+        };
+
+        let mut stream = vec![0xff, 0xd8];
+        stream.extend_from_slice(&self.segments);
+        let mut frame = vec![8];
+        frame.extend_from_slice(&(height as u16).to_be_bytes());
+        frame.extend_from_slice(&(width as u16).to_be_bytes());
+        frame.push(components.len() as u8);
+        for (index, &component) in components.iter().enumerate() {
+            frame.extend_from_slice(&[index as u8 + 1, sampling(index), table(0, component)]);
+        }
+        push_segment(&mut stream, 0xc0, &frame);
+        let mut scan_header = vec![components.len() as u8];
+        for (index, &component) in components.iter().enumerate() {
+            scan_header.push(index as u8 + 1);
+            scan_header.push(table(1, component) << 4 | table(2, component));
+        }
+        scan_header.extend_from_slice(&[0, 63, 0]);
+        push_segment(&mut stream, 0xda, &scan_header);
+        stream.extend_from_slice(scan);
+        stream.extend_from_slice(&[0xff, 0xd9]);
+        stream
+    }
+}
+
+/// Splits a JPEG stream into its marker segments. Stops after the first SOS
+/// and returns the offset of the entropy-coded data that follows it.
+fn jpeg_segments(stream: &[u8]) -> (Vec<(u8, &[u8])>, Option<usize>) {
+    let mut segments = vec![];
+    let mut i = 0;
+    while i + 1 < stream.len() {
+        if stream[i] != 0xff {
+            break;
+        }
+        let marker = stream[i + 1];
+        match marker {
+            0xff => {
+                i += 1;
+                continue;
+            }
+            0x01 | 0xd0..=0xd8 => {
+                i += 2;
+                continue;
+            }
+            0xd9 => break,
+            _ => {}
+        }
+        if i + 4 > stream.len() {
+            break;
+        }
+        let length = u16::from_be_bytes([stream[i + 2], stream[i + 3]]) as usize;
+        let end = (i + 2 + length).min(stream.len());
+        if length < 2 {
+            break;
+        }
+        segments.push((marker, &stream[i..end]));
+        i = end;
+        if marker == 0xda {
+            return (segments, Some(i));
+        }
+    }
+    (segments, None)
+}
+
+/// Reads the stream at JPEGInterchangeFormat. Without a length it runs to
+/// the end of the last strip or tile.
+fn read_interchange<B: BinaryReader>(reader: &mut B, header: &Tiff) -> Result<Vec<u8>, Error> {
+    let offset = header.jpeg_interchange_format;
+    let length = match header.jpeg_interchange_format_length {
+        0 => {
+            let (offsets, counts) = if header.is_tiled() {
+                (&header.tile_offsets, &header.tile_byte_counts)
+            } else {
+                (&header.strip_offsets, &header.strip_byte_counts)
+            };
+            let end = offsets
+                .iter()
+                .zip(counts)
+                .map(|(offset, count)| offset + count)
+                .max()
+                .unwrap_or(0);
+            end.checked_sub(offset)
+                .filter(|&length| length > 0)
+                .ok_or_else(|| ojpeg_error("JPEGInterchangeFormatLength is missing."))?
+        }
+        length => length,
+    };
+    reader.seek(std::io::SeekFrom::Start(offset))?;
+    Ok(reader.read_bytes_as_vec(length as usize)?)
+}
+
+/// Decodes old-style JPEG (Compression 6, TIFF 6.0 Section 22).
+///
+/// A JPEGInterchangeFormat stream with its own scan is decoded as the whole
+/// image; when its scan data is missing, the strips are appended to it. A
+/// stream without a scan, or the JPEGQTables/JPEGDCTables/JPEGACTables tags,
+/// supply the tables for one JPEG stream per strip or tile.
+pub fn decode_old_jpeg_compresson<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+    initialize: bool,
+    animation: bool,
+) -> Result<Option<ImgWarnings>, Error> {
+    if header.jpeg_proc != 1 {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::NoSupportFormat,
+            format!(
+                "Old-style JPEG process {} is not support.",
+                header.jpeg_proc
+            ),
+        )));
+    }
+    if initialize {
+        super::init_canvas(option, header, animation)?;
+    }
+
+    let tiled = header.is_tiled();
+    let (offsets, counts) = if tiled {
+        (&header.tile_offsets, &header.tile_byte_counts)
+    } else {
+        (&header.strip_offsets, &header.strip_byte_counts)
+    };
+
+    let tables = if header.jpeg_interchange_format != 0 {
+        let stream = read_interchange(reader, header)?;
+        let (segments, scan) = jpeg_segments(&stream);
+        if let Some(scan) = scan {
+            let mut data = stream.clone();
+            if stream[scan..].len() <= 2 {
+                // Only the headers are in the interchange stream.
+                data.truncate(scan);
+                for (offset, count) in offsets.iter().zip(counts) {
+                    reader.seek(std::io::SeekFrom::Start(*offset))?;
+                    data.append(&mut reader.read_bytes_as_vec(*count as usize)?);
+                }
+                data.extend_from_slice(&[0xff, 0xd9]);
+            }
+            return draw_jpeg(data, 0, 0, option, header);
+        }
+        OJpegTables::from_stream(&segments)
+    } else {
+        OJpegTables::from_tags(reader, header)?
+    };
+
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let (block_width, block_length) = if tiled {
+        (header.tile_width as usize, header.tile_length as usize)
+    } else {
+        match header.rows_per_strip as usize {
+            0 => (width, height),
+            rows => (width, rows.min(height)),
+        }
+    };
+    if block_width == 0 || block_length == 0 {
+        return Err(ojpeg_error("Image has no strips or tiles."));
+    }
+    let across = width.div_ceil(block_width);
+    let blocks = across * height.div_ceil(block_length);
+    let samples = header.samples_per_pixel.max(1) as usize;
+    let planes = if header.planar_config == 2 {
+        samples
+    } else {
+        1
+    };
+    if offsets.len() < blocks * planes || counts.len() < blocks * planes {
+        return Err(ojpeg_error(
+            "Mismach length, strip or tile offsets and byte counts.",
+        ));
+    }
+
+    let mut warnings = None;
+    for block in 0..blocks {
+        let x = block % across * block_width;
+        let y = block / across * block_length;
+        let rows = if tiled {
+            block_length
+        } else {
+            block_length.min(height - y)
+        };
+        let mut parts = vec![];
+        for plane in 0..planes {
+            let index = plane * blocks + block;
+            reader.seek(std::io::SeekFrom::Start(offsets[index]))?;
+            let scan = reader.read_bytes_as_vec(counts[index] as usize)?;
+            if scan.starts_with(&[0xff, 0xd8]) {
+                // Some writers store a complete JPEG stream per strip.
+                parts.push(scan);
+                continue;
+            }
+            let components: Vec<usize> = if planes > 1 {
+                vec![plane]
+            } else {
+                (0..samples).collect()
+            };
+            parts.push(tables.stream(header, block_width, rows, &components, &scan));
+        }
+        let ws = if planes > 1 {
+            draw_jpeg_planes(parts, x, y, option, header)?
+        } else {
+            draw_jpeg(parts.remove(0), x, y, option, header)?
+        };
+        warnings = ImgWarnings::append(warnings, ws);
+    }
+    Ok(warnings)
+}
@@ -0,0 +1,130 @@
+//! Transparency masks (NewSubfileType bit 2, PhotometricInterpretation 4).
+//!
+//! The 1-bit mask is decoded as a BlackIsZero image, scaled to the size of
+//! the image it belongs to, and multiplied into the alpha of every pixel the
+//! image decoder draws.
+
+type Error = Box<dyn std::error::Error>;
+use super::compression_decode;
+use crate::draw::*;
+use crate::metadata::DataMap;
+use crate::tiff::header::Tiff;
+use bin_rs::reader::BinaryReader;
+
+/// Decodes `mask` into one alpha value per pixel of a `width` x `height`
+/// image. Set bits are the interior of the region and become opaque.
+pub(super) fn mask_alpha<B: BinaryReader>(
+    reader: &mut B,
+    mask: &Tiff,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut mask = mask.clone();
+    mask.photometric_interpretation = 1;
+    mask.color_table = None;
+    let mut image = ImageBuffer::new();
+    let mut option = DecodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: None,
+    };
+    compression_decode(reader, &mut option, &mask, true, false)?;
+
+    let buffer = image.buffer.unwrap_or_default();
+    let (mask_width, mask_height) = (image.width, image.height);
+    let mut alpha = vec![255; width * height];
+    if mask_width == 0 || mask_height == 0 || buffer.len() < mask_width * mask_height * 4 {
+        return Ok(alpha);
+    }
+    for y in 0..height {
+        let my = y * mask_height / height;
+        for x in 0..width {
+            let mx = x * mask_width / width;
+            alpha[y * width + x] = buffer[(my * mask_width + mx) * 4];
+        }
+    }
+    Ok(alpha)
+}
+
+/// Passes everything through to `drawer`, multiplying drawn alpha by the mask.
+pub(super) struct MaskedDrawer<'a> {
+    pub(super) drawer: &'a mut dyn DrawCallback,
+    pub(super) alpha: Vec<u8>,
+    pub(super) width: usize,
+    pub(super) height: usize,
+}
+
+impl DrawCallback for MaskedDrawer<'_> {
+    fn init(
+        &mut self,
+        width: usize,
+        height: usize,
+        option: Option<InitOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.init(width, height, option)
+    }
+
+    fn draw(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        data: &[u8],
+        option: Option<DrawOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        let mut masked = data.to_vec();
+        for (index, pixel) in masked.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (
+                start_x + index % width.max(1),
+                start_y + index / width.max(1),
+            );
+            if x < self.width && y < self.height {
+                let alpha = self.alpha[y * self.width + x] as u32;
+                pixel[3] = (pixel[3] as u32 * alpha / 255) as u8;
+            }
+        }
+        self.drawer
+            .draw(start_x, start_y, width, height, &masked, option)
+    }
+
+    fn terminate(
+        &mut self,
+        term: Option<TerminateOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.terminate(term)
+    }
+
+    fn next(&mut self, next: Option<NextOptions>) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.next(next)
+    }
+
+    fn verbose(
+        &mut self,
+        verbose: &str,
+        option: Option<VerboseOptions>,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.verbose(verbose, option)
+    }
+
+    fn set_metadata(
+        &mut self,
+        key: &str,
+        value: DataMap,
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer.set_metadata(key, value)
+    }
+
+    fn draw_samples(
+        &mut self,
+        start_x: usize,
+        start_y: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        data: &[f64],
+    ) -> Result<Option<CallbackResponse>, Error> {
+        self.drawer
+            .draw_samples(start_x, start_y, width, height, channels, data)
+    }
+}
@@ -2,13 +2,14 @@
 
 type Error = Box<dyn std::error::Error>;
 #[cfg(feature = "tiff-jpeg")]
-use self::jpeg::decode_jpeg_compresson;
+use self::jpeg::{decode_jpeg_compresson, decode_old_jpeg_compresson};
 use crate::color::RGBA;
 use crate::decoder::lzw::Lzwdecode;
 use crate::draw::*;
 use crate::error::ImgError;
 use crate::error::ImgErrorKind;
 use crate::metadata::DataMap;
+use crate::tiff::geotiff::{GEOTIFF_METADATA_KEY, GeoTiff};
 use crate::tiff::header::*;
 use crate::tiff::warning::TiffWarning;
 use crate::warning::ImgWarnings;
@@ -18,7 +19,10 @@ use bin_rs::reader::BinaryReader;
 mod ccitt;
 #[cfg(feature = "tiff-jpeg")]
 mod jpeg;
+mod mask;
 mod packbits;
+mod photometric;
+mod sample;
 
 fn create_pallet(bits: usize, is_black_zero: bool) -> Vec<RGBA> {
     let color_max = 1 << bits;
@@ -48,26 +52,30 @@ fn create_pallet(bits: usize, is_black_zero: bool) -> Vec<RGBA> {
     pallet
 }
 
-fn planar_to_chuncky(data: &[u8], header: &Tiff) -> Result<Vec<u8>, Error> {
-    let mut buf = vec![];
-    let mut total_length = 0;
-    for bits in &header.bitspersamples {
-        total_length += header.height as usize * header.width as usize * ((*bits as usize + 7) / 8);
-    }
+/// Interleaves separate sample planes (PlanarConfiguration 2) of a
+/// `width` x `height` block into chunky pixels.
+fn planar_to_chuncky(
+    data: &[u8],
+    header: &Tiff,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u8>, Error> {
+    let bytes = (header.bitspersamples[0] as usize).div_ceil(8);
+    let plane_length = width * height * bytes;
+    let planes = header.samples_per_pixel as usize;
 
-    if data.len() < total_length {
+    if data.len() < plane_length * planes {
         return Err(Box::new(ImgError::new_const(
             ImgErrorKind::DecodeError,
             "Data shotage.".to_string(),
         )));
     }
 
-    let length = header.height as usize
-        * header.width as usize
-        * ((header.bitspersamples[0] as usize + 7) / 8);
-    for i in 0..length {
-        for j in 0..header.samples_per_pixel as usize {
-            buf.push(data[i + j * length]);
+    let mut buf = Vec::with_capacity(plane_length * planes);
+    for i in (0..plane_length).step_by(bytes) {
+        for j in 0..planes {
+            let offset = i + j * plane_length;
+            buf.extend_from_slice(&data[offset..offset + bytes]);
         }
     }
 
@@ -91,6 +99,8 @@ pub fn draw_strip(
     draw_tile(data, y, strip, 0, header.width as usize, option, header)
 }
 
+/// Draws `rows` rows of `width` pixels at (`x`, `y`). Pixels beyond the
+/// image edge, as in partial edge tiles, are decoded but not drawn.
 pub fn draw_tile(
     data: &[u8],
     y: usize,
@@ -111,7 +121,7 @@ pub fn draw_tile(
 
     // no debug
     if header.planar_config == 2 && header.samples_per_pixel > 1 {
-        data = planar_to_chuncky(&data, header)?;
+        data = planar_to_chuncky(&data, header, width, strip)?;
     }
 
     let color_table: Option<Vec<RGBA>> = if let Some(color_table) = header.color_table.as_ref() {
@@ -145,26 +155,6 @@ pub fn draw_tile(
                 // RGB Palette
                 Some(create_pallet(bitspersample as usize, true))
             }
-            5 => {
-                if header.samples_per_pixel < 4 {
-                    return Err(Box::new(ImgError::new_const(
-                        ImgErrorKind::DecodeError,
-                        "YMCK image needs Sample per pixel >=4.".to_string(),
-                    )));
-                } else {
-                    None
-                }
-            }
-            6 => {
-                if header.samples_per_pixel < 3 {
-                    return Err(Box::new(ImgError::new_const(
-                        ImgErrorKind::DecodeError,
-                        "YCbCr image needs Sample per pixel >=3.".to_string(),
-                    )));
-                } else {
-                    None
-                }
-            }
             _ => {
                 return Err(Box::new(ImgError::new_const(
                     ImgErrorKind::DecodeError,
@@ -187,7 +177,12 @@ pub fn draw_tile(
                     "This is an index color image,but A color table is empty.".to_string(),
                 )) as Error
             })?;
-            let required_len = 1usize << header.bitspersample as usize;
+            let bits = header
+                .bitspersamples
+                .first()
+                .copied()
+                .unwrap_or(header.bitspersample);
+            let required_len = 1usize << bits.min(8) as usize;
             if palette.len() < required_len {
                 return Err(Box::new(ImgError::new_const(
                     ImgErrorKind::DecodeError,
@@ -203,7 +198,7 @@ pub fn draw_tile(
         _ => &[],
     };
 
-    let mut row_len = ((header.width as usize * header.bitspersample as usize) + 7) / 8;
+    let mut row_len = (width * header.bitspersample as usize).div_ceil(8);
     if header.bitspersample == 4 {
         row_len *= 2;
     } else if header.bitspersample == 2 {
@@ -212,12 +207,16 @@ pub fn draw_tile(
         row_len *= 8;
     }
 
+    let draw_width = width.min((header.width as usize).saturating_sub(x));
     for (l, y) in (y..(y + strip)).enumerate() {
+        if y >= header.height as usize {
+            break;
+        }
         let mut buf = vec![];
         let mut prevs = vec![0_u8; header.samples_per_pixel as usize];
         let mut i = l * row_len;
 
-        for _ in 0..header.width as usize {
+        for _ in 0..width {
             match header.photometric_interpretation {
                 0 | 1 | 3 => {
                     match header.bitspersamples[0] {
@@ -249,7 +248,7 @@ pub fn draw_tile(
                                 }
                                 let mut color = data[i];
                                 if header.predictor == 2 {
-                                    color += prevs[0];
+                                    color = color.wrapping_add(prevs[0]);
                                     prevs[0] = color;
                                 }
                                 buf.push(color);
@@ -266,16 +265,27 @@ pub fn draw_tile(
                             }
                             let mut color = data[i];
                             if header.predictor == 2 {
-                                color += prevs[0];
+                                color = color.wrapping_add(prevs[0]);
                                 prevs[0] = color;
                             }
 
                             let rgba = &palette[color as usize];
+                            let mut alpha = rgba.alpha;
+                            if header.samples_per_pixel >= 2
+                                && matches!(header.extra_samples.first(), Some(1 | 2))
+                                && i + 1 < data.len()
+                            {
+                                alpha = data[i + 1];
+                                if header.predictor == 2 {
+                                    alpha = alpha.wrapping_add(prevs[1]);
+                                    prevs[1] = alpha;
+                                }
+                            }
 
                             buf.push(rgba.red);
                             buf.push(rgba.green);
                             buf.push(rgba.blue);
-                            buf.push(rgba.alpha);
+                            buf.push(alpha);
                             i += header.samples_per_pixel as usize;
                         }
                         4 => {
@@ -426,14 +436,14 @@ pub fn draw_tile(
                     }
 
                     if header.predictor == 2 {
-                        r += prevs[0];
+                        r = r.wrapping_add(prevs[0]);
                         prevs[0] = r;
-                        g += prevs[1];
+                        g = g.wrapping_add(prevs[1]);
                         prevs[1] = g;
-                        b += prevs[2];
+                        b = b.wrapping_add(prevs[2]);
                         prevs[2] = b;
                         if !header.extra_samples.is_empty() && header.extra_samples[0] == 2 {
-                            a += prevs[3];
+                            a = a.wrapping_add(prevs[3]);
                             prevs[3] = a;
                         }
                     }
@@ -443,170 +453,15 @@ pub fn draw_tile(
                     buf.push(a);
                 }
                 // 4 : Transparentary musk is not support
-                5 => {
-                    //CMYK
-                    let (mut c, mut m, mut y, mut k, mut a);
-                    match header.bitspersamples[0] {
-                        //bit per samples same (8,8,8), but also (8,16,8) pattern
-                        8 => {
-                            if !has_bytes(&data, i, header.samples_per_pixel as usize) {
-                                return Ok(None);
-                            }
-                            c = data[i];
-                            m = data[i + 1];
-                            y = data[i + 2];
-                            k = data[i + 3];
-                            a = if !header.extra_samples.is_empty()
-                                && header.extra_samples[0] == 2
-                                && header.samples_per_pixel > 4
-                            {
-                                data[i + 4]
-                            } else {
-                                0xff
-                            };
-                            i += header.samples_per_pixel as usize;
-                        }
-                        16 => {
-                            let bytes = header.samples_per_pixel as usize * 2;
-                            if !has_bytes(&data, i, bytes) {
-                                return Ok(None);
-                            }
-                            c = (read_u16(&data, i, header.tiff_headers.endian) >> 8) as u8;
-                            m = (read_u16(&data, i + 2, header.tiff_headers.endian) >> 8) as u8;
-                            y = (read_u16(&data, i + 4, header.tiff_headers.endian) >> 8) as u8;
-                            k = (read_u16(&data, i + 6, header.tiff_headers.endian) >> 8) as u8;
-                            a = if !header.extra_samples.is_empty()
-                                && header.extra_samples[0] == 2
-                                && header.samples_per_pixel > 4
-                            {
-                                (read_u16(&data, i + 8, header.tiff_headers.endian) >> 8) as u8
-                            } else {
-                                0xff
-                            };
-                            i += header.samples_per_pixel as usize * 2;
-                        }
-                        32 => {
-                            let bytes = header.samples_per_pixel as usize * 4;
-                            if !has_bytes(&data, i, bytes) {
-                                return Ok(None);
-                            }
-                            c = (read_u32(&data, i, header.tiff_headers.endian) >> 24) as u8;
-                            m = (read_u32(&data, i + 4, header.tiff_headers.endian) >> 24) as u8;
-                            y = (read_u32(&data, i + 8, header.tiff_headers.endian) >> 24) as u8;
-                            k = (read_u32(&data, i + 12, header.tiff_headers.endian) >> 24) as u8;
-                            a = if !header.extra_samples.is_empty()
-                                && header.extra_samples[0] == 2
-                                && header.samples_per_pixel > 4
-                            {
-                                (read_u32(&data, i + 16, header.tiff_headers.endian) >> 24) as u8
-                            } else {
-                                0xff
-                            };
-                            i += header.samples_per_pixel as usize * 4;
-                        }
-                        _ => {
-                            return Err(Box::new(ImgError::new_const(
-                                ImgErrorKind::DecodeError,
-                                "This bit per sample is not support.".to_string(),
-                            )));
-                        }
-                    }
-
-                    if header.predictor == 2 {
-                        y += prevs[0];
-                        prevs[0] = y;
-                        m += prevs[1];
-                        prevs[1] = m;
-                        c += prevs[2];
-                        prevs[2] = c;
-                        k += prevs[3];
-                        prevs[3] = k;
-                        if !header.extra_samples.is_empty() && header.extra_samples[0] == 2 {
-                            a += prevs[4];
-                            prevs[4] = a;
-                        }
-                    }
-                    let r = 255 - c;
-                    let g = 255 - m;
-                    let b = 255 - y;
-
-                    buf.push(r);
-                    buf.push(g);
-                    buf.push(b);
-                    buf.push(a);
-                }
-                // not support
-                /*
-                6 => {  // YCbCr ... this function is not suport YCbCrCoficients,positioning...,yet.  ....4:1:1 sampling
-                    let (mut y, mut cb, mut cr, mut a);
-                    match header.bitspersamples[0] {  //bit per samples same (8,8,8), but also (8,16,8) pattern
-                        8 => {
-                            y = data[i];
-                            cb = data[i+1];
-                            cr = data[i+2];
-                            a = if header.extra_samples.len() > 0 && header.extra_samples[0] == 2
-                                        && header.samples_per_pixel > 3 {
-                                data[i+3] } else { 0xff };
-                            i += header.samples_per_pixel as usize;
-                        },
-                        16 => {
-                            y = (read_u16(&data,i,header.tiff_headers.endian) >> 8) as u8;
-                            cb = (read_u16(&data,i+2,header.tiff_headers.endian) >> 8) as u8;
-                            cr = (read_u16(&data,i+4,header.tiff_headers.endian) >> 8) as u8;
-                            a = if header.extra_samples.len() > 0 && header.extra_samples[0] == 2
-                                        && header.samples_per_pixel > 3 {
-                                    (read_u16(&data,i+6,header.tiff_headers.endian) >> 8) as u8 } else { 0xff };
-                            i += header.samples_per_pixel as usize * 2;
-                        },
-                        32 => {
-                            y = (read_u32(&data,i,header.tiff_headers.endian) >> 24) as u8;
-                            cb = (read_u32(&data,i+4,header.tiff_headers.endian) >> 24) as u8;
-                            cr = (read_u32(&data,i+8,header.tiff_headers.endian) >> 24) as u8;
-                            a = if header.extra_samples.len() > 0 && header.extra_samples[0] == 2
-                                        && header.samples_per_pixel > 3 {
-                                    (read_u32(&data,i+12,header.tiff_headers.endian) >> 24) as u8 } else { 0xff };
-                            i += header.samples_per_pixel as usize * 4;
-                        },
-                        _ => {
-                            return Err(Box::new(ImgError::new_const(ImgErrorKind::DecodeError,"This bit per sample is not support.".to_string())));
-                        }
-                    }
-
-                    if header.predictor == 2 {
-                        y += prevs[0];
-                        prevs[0] = y;
-                        cb += prevs[1];
-                        prevs[1] = cb;
-                        cr += prevs[2];
-                        prevs[2] = cr;
-                        if header.extra_samples.len() > 0 && header.extra_samples[0]  == 2 {
-                            a += prevs[3];
-                            prevs[3] = a;
-                        }
-                    }
-                    // Bt601.1
-                    let lr = 0.299;
-                    let lg = 0.587;
-                    let lb = 0.114;
-
-                    let r = ((y as f32 + (2.0 - 2.0 * lr ) * cr as f32) as i16).clamp(0,255) as u8;
-                    let b = ((y as f32 + (2.0 - 2.0 * lb ) * cr as f32) as i16).clamp(0,255) as u8;
-                    let g = (((y as f32 - lb * b as f32 - lr * r as f32) / lg) as i16).clamp(0,255) as u8;
-
-                    buf.push(r);
-                    buf.push(g);
-                    buf.push(b);
-                    buf.push(a);
-
-                }
-                */
+                // 5, 6, 8, 9 and 10 are drawn by the photometric module
                 _ => {
                     //                    return Err(Box::new(ImgError::new_const(ImgErrorKind::DecodeError,"Not support color space.".to_string())));
                 }
             }
         }
 
-        option.drawer.draw(x, y, width, 1, &buf, None)?;
+        buf.truncate(draw_width * 4);
+        option.drawer.draw(x, y, draw_width, 1, &buf, None)?;
     }
     Ok(None)
 }
@@ -648,7 +503,7 @@ fn read_strips<'decode, B: BinaryReader>(reader: &mut B, header: &Tiff) -> Resul
             && header.strip_byte_counts.is_empty()
             && header.compression == Compression::NoneCompression
         {
-            let offset = header.strip_offsets[0] as u64;
+            let offset = header.strip_offsets[0];
             reader.seek(std::io::SeekFrom::Start(offset))?;
             let mut byte = 0;
             for sample in &header.bitspersamples {
@@ -665,7 +520,7 @@ fn read_strips<'decode, B: BinaryReader>(reader: &mut B, header: &Tiff) -> Resul
         )));
     }
     for (i, offset) in header.strip_offsets.iter().enumerate() {
-        reader.seek(std::io::SeekFrom::Start(*offset as u64))?;
+        reader.seek(std::io::SeekFrom::Start(*offset))?;
         let mut buf = reader.read_bytes_as_vec(header.strip_byte_counts[i] as usize)?;
         data.append(&mut buf);
     }
@@ -686,7 +541,7 @@ pub fn decode_lzw_compresson<'decode, B: BinaryReader>(
     let mut y = 0;
     let mut strip = header.rows_per_strip as usize;
     for (i, offset) in header.strip_offsets.iter().enumerate() {
-        reader.seek(std::io::SeekFrom::Start(*offset as u64))?;
+        reader.seek(std::io::SeekFrom::Start(*offset))?;
         let buf = reader.read_bytes_as_vec(header.strip_byte_counts[i] as usize)?;
         let mut decoder = Lzwdecode::tiff(is_lsb);
         let data = decoder.decode(&buf)?;
@@ -771,7 +626,7 @@ pub fn decode_ccitt_compresson<'decode, B: BinaryReader>(
     if initialize {
         init_canvas(option, header, animation)?;
     }
-    let warnings = None;
+    let mut damaged = false;
 
     let header = &mut header.clone();
 
@@ -780,28 +635,152 @@ pub fn decode_ccitt_compresson<'decode, B: BinaryReader>(
 
     if header.compression == Compression::CCITTGroup3Fax {
         let buf = read_strips(reader, header)?;
-        let (data, _warning) = ccitt::decode(&buf, header)?;
+        let (width, height) = (header.width as usize, header.height as usize);
+        let (data, warning) = ccitt::decode(&buf, header, width, height)?;
+        damaged |= warning;
         draw(&data, option, header)?;
     } else {
         // CCITGroup4FAX
+        let height = header.height as usize;
+        // RowsPerStrip of 0 or larger than the image means a single strip.
+        let rows_per_strip = match header.rows_per_strip as usize {
+            0 => height,
+            rows => rows.min(height),
+        };
         let mut y = 0;
-        let mut strip = header.rows_per_strip as usize;
         for (i, offset) in header.strip_offsets.iter().enumerate() {
-            reader.seek(std::io::SeekFrom::Start(*offset as u64))?;
+            if y >= height {
+                break;
+            }
+            let strip = rows_per_strip.min(height - y);
+            reader.seek(std::io::SeekFrom::Start(*offset))?;
             let buf = reader.read_bytes_as_vec(header.strip_byte_counts[i] as usize)?;
-            let (data, _warning) = ccitt::decode(&buf, header)?;
+            let (data, warning) = ccitt::decode(&buf, header, header.width as usize, strip)?;
+            damaged |= warning;
             draw_strip(&data, y, strip, option, header)?;
             y += strip;
-            if y >= header.height as usize {
-                break;
-            }
-            if y + strip >= header.height as usize {
-                strip = header.height as usize - y;
+        }
+    }
+    Ok(damaged_warnings(damaged))
+}
+
+/// Returns the warning for CCITT data in which broken rows were skipped.
+fn damaged_warnings(damaged: bool) -> Option<ImgWarnings> {
+    if !damaged {
+        return None;
+    }
+    let warning = TiffWarning::new(
+        "CCITT data is damaged; broken rows were skipped up to the next EOL".to_string(),
+    );
+    ImgWarnings::add(None, Box::new(warning))
+}
+
+/// Decompresses one tile or strip of `width` x `height` pixels. The flag is
+/// set when the CCITT decoder had to skip broken rows.
+fn decompress_block(
+    buf: &[u8],
+    header: &Tiff,
+    width: usize,
+    height: usize,
+) -> Result<(Vec<u8>, bool), Error> {
+    let data = match header.compression {
+        Compression::NoneCompression => buf.to_vec(),
+        Compression::LZW => {
+            let mut decoder = Lzwdecode::tiff(header.fill_order == 2);
+            decoder.decode(buf)?
+        }
+        Compression::Packbits => packbits::decode(buf)?,
+        Compression::AdobeDeflate | Compression::DEFLATE => {
+            miniz_oxide::inflate::decompress_to_vec_zlib(buf).map_err(|err| {
+                Box::new(ImgError::new_const(
+                    ImgErrorKind::DecodeError,
+                    format!("{:?}", err),
+                )) as Error
+            })?
+        }
+        Compression::CCITTHuffmanRLE
+        | Compression::CCITTGroup3Fax
+        | Compression::CCITTGroup4Fax => return ccitt::decode(buf, header, width, height),
+        _ => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::DecodeError,
+                "Not suport compression".to_string(),
+            )));
+        }
+    };
+    Ok((data, false))
+}
+
+/// Decodes a tiled image (TIFF 6.0 section 15). With PlanarConfiguration 2
+/// the tiles of each sample plane follow each other, so tile `n` of plane
+/// `p` is entry `p * tiles + n`.
+pub fn decode_tiles<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+    initialize: bool,
+    animation: bool,
+) -> Result<Option<ImgWarnings>, Error> {
+    if initialize {
+        init_canvas(option, header, animation)?;
+    }
+    let tile_width = header.tile_width as usize;
+    let tile_length = header.tile_length as usize;
+    let across = (header.width as usize).div_ceil(tile_width);
+    let down = (header.height as usize).div_ceil(tile_length);
+    let tiles = across * down;
+    let planes = if header.planar_config == 2 {
+        header.samples_per_pixel.max(1) as usize
+    } else {
+        1
+    };
+    if header.tile_offsets.len() < tiles * planes || header.tile_byte_counts.len() < tiles * planes
+    {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::DecodeError,
+            format!(
+                "Mismach length, {} tiles need {} tile offsets and byte counts.",
+                tiles,
+                tiles * planes
+            ),
+        )));
+    }
+
+    let mut draw_header = header.clone();
+    if matches!(
+        header.compression,
+        Compression::CCITTHuffmanRLE | Compression::CCITTGroup3Fax | Compression::CCITTGroup4Fax
+    ) {
+        // The CCITT decoder outputs one byte per pixel.
+        draw_header.bitspersample = 8;
+        draw_header.bitspersamples = [8].to_vec();
+    }
+    let plane_length = if planes > 1 {
+        tile_width * tile_length * (header.bitspersamples[0] as usize).div_ceil(8)
+    } else {
+        0
+    };
+
+    let mut damaged = false;
+    for tile in 0..tiles {
+        let mut data = vec![];
+        for plane in 0..planes {
+            let index = plane * tiles + tile;
+            reader.seek(std::io::SeekFrom::Start(header.tile_offsets[index]))?;
+            let buf = reader.read_bytes_as_vec(header.tile_byte_counts[index] as usize)?;
+            let (mut block, warning) = decompress_block(&buf, header, tile_width, tile_length)?;
+            damaged |= warning;
+            if planes > 1 {
+                block.resize(plane_length, 0);
             }
+            data.append(&mut block);
         }
+        let x = tile % across * tile_width;
+        let y = tile / across * tile_length;
+        draw_tile(&data, y, tile_length, x, tile_width, option, &draw_header)?;
     }
 
-    Ok(warnings)
+    Ok(damaged_warnings(damaged))
 }
 
 fn compression_decode<'decode, B: BinaryReader>(
@@ -811,6 +790,16 @@ fn compression_decode<'decode, B: BinaryReader>(
     initialize: bool,
     animation: bool,
 ) -> Result<Option<ImgWarnings>, Error> {
+    if photometric::is_photometric_image(header) {
+        return photometric::decode_photometric(reader, option, header, initialize, animation);
+    }
+    if sample::is_sample_image(header, option)? {
+        return sample::decode_samples(reader, option, header, initialize, animation);
+    }
+    if header.is_tiled() && !matches!(header.compression, Compression::Jpeg | Compression::OldJpeg)
+    {
+        return decode_tiles(reader, option, header, initialize, animation);
+    }
     match header.compression {
         Compression::NoneCompression => {
             return decode_none_compresson(reader, option, header, initialize, animation);
@@ -830,10 +819,22 @@ fn compression_decode<'decode, B: BinaryReader>(
                 )));
             }
         }
+        Compression::OldJpeg => {
+            #[cfg(feature = "tiff-jpeg")]
+            return decode_old_jpeg_compresson(reader, option, header, initialize, animation);
+            #[cfg(not(feature = "tiff-jpeg"))]
+            {
+                let _ = (reader, option, header, initialize, animation);
+                return Err(Box::new(ImgError::new_const(
+                    ImgErrorKind::NoSupportFormat,
+                    "TIFF JPEG compression support is disabled by feature flags".to_string(),
+                )));
+            }
+        }
         Compression::Packbits => {
             return decode_packbits_compresson(reader, option, header, initialize, animation);
         }
-        Compression::AdobeDeflate => {
+        Compression::AdobeDeflate | Compression::DEFLATE => {
             return decode_deflate_compresson(reader, option, header, initialize, animation);
         }
         Compression::CCITTHuffmanRLE
@@ -848,87 +849,238 @@ fn compression_decode<'decode, B: BinaryReader>(
     }
 }
 
+/// The images of one page: the page itself followed by its reduced-resolution
+/// copies (from SubIFDs or from the IFDs after it), and its masks.
+struct PageImages {
+    images: Vec<Tiff>,
+    masks: Vec<Tiff>,
+}
+
+impl PageImages {
+    fn new(page: Tiff) -> Self {
+        let mut this = Self {
+            images: vec![],
+            masks: vec![],
+        };
+        let subs = page.sub_ifds.clone();
+        this.images.push(page);
+        for sub in subs {
+            this.add(sub);
+        }
+        this
+    }
+
+    fn add(&mut self, ifd: Tiff) {
+        let subs = ifd.sub_ifds.clone();
+        if ifd.is_mask() {
+            self.masks.push(ifd);
+        } else {
+            self.images.push(ifd);
+        }
+        for sub in subs {
+            self.add(sub);
+        }
+    }
+
+    /// A mask of the same size, else one of the same resolution class, which
+    /// is scaled to the image.
+    fn mask_of(&self, image: &Tiff) -> Option<&Tiff> {
+        self.masks
+            .iter()
+            .find(|mask| mask.width == image.width && mask.height == image.height)
+            .or_else(|| {
+                self.masks
+                    .iter()
+                    .find(|mask| mask.is_reduced() == image.is_reduced())
+            })
+    }
+}
+
+/// Groups the first IFD and every later full-resolution page with the
+/// reduced-resolution copies and masks that follow it.
+fn page_images(header: &Tiff) -> Vec<PageImages> {
+    let mut first = header.clone();
+    first.multi_page = Box::default();
+    let mut pages = vec![PageImages::new(first)];
+    for ifd in header.multi_page.iter() {
+        if ifd.is_page() {
+            pages.push(PageImages::new(ifd.clone()));
+        } else if let Some(page) = pages.last_mut() {
+            page.add(ifd.clone());
+        }
+    }
+    pages
+}
+
+/// Counts the main image and every later full-resolution page.
+fn pages_of(header: &Tiff) -> usize {
+    1 + header
+        .multi_page
+        .iter()
+        .filter(|page| page.is_page())
+        .count()
+}
+
+/// Returns the number of pages without decoding any image data.
+pub fn page_count<B: BinaryReader>(reader: &mut B) -> Result<usize, Error> {
+    Ok(pages_of(&Tiff::new(reader)?))
+}
+
+/// Returns the number of images selectable with the `level` option for
+/// `page`: the page itself and its reduced-resolution copies.
+pub fn level_count<B: BinaryReader>(reader: &mut B, page: usize) -> Result<usize, Error> {
+    let pages = page_images(&Tiff::new(reader)?);
+    let count = pages.len();
+    pages
+        .get(page)
+        .map(|page| page.images.len())
+        .ok_or_else(|| page_out_of_range(page, count))
+}
+
+/// Decodes `image`, applying `mask` as alpha when there is one.
+fn decode_image<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    image: &Tiff,
+    mask: Option<&Tiff>,
+    initialize: bool,
+    animation: bool,
+) -> Result<Option<ImgWarnings>, Error> {
+    let Some(mask) = mask else {
+        return compression_decode(reader, option, image, initialize, animation);
+    };
+    let (width, height) = (image.width as usize, image.height as usize);
+    let alpha = match mask::mask_alpha(reader, mask, width, height) {
+        Ok(alpha) => alpha,
+        Err(error) => {
+            let warning = TiffWarning::new(format!("transparency mask is skipped: {}", error));
+            let warnings = ImgWarnings::add(None, Box::new(warning));
+            let warn = compression_decode(reader, option, image, initialize, animation)?;
+            return Ok(ImgWarnings::append(warnings, warn));
+        }
+    };
+    let mut drawer = mask::MaskedDrawer {
+        drawer: &mut *option.drawer,
+        alpha,
+        width,
+        height,
+    };
+    let mut masked = DecodeOptions {
+        debug_flag: option.debug_flag,
+        drawer: &mut drawer,
+        options: option.options.clone(),
+    };
+    compression_decode(reader, &mut masked, image, initialize, animation)
+}
+
+/// Decodes the first page as the canvas and later pages as animation
+/// frames. The `page` option decodes only the selected page instead, and
+/// `level` picks one of its reduced-resolution copies (0 is the page
+/// itself). Transparency masks are applied as alpha.
 pub fn decode<'decode, B: BinaryReader>(
     reader: &mut B,
     option: &mut DecodeOptions,
 ) -> Result<Option<ImgWarnings>, Error> {
-    let mut header = Tiff::new(reader)?;
+    let header = Tiff::new(reader)?;
+    let pages = page_images(&header);
 
-    let mut count = 1;
-    if header.multi_page.len() > 0 {
-        for append in header.multi_page.iter() {
-            if append.newsubfiletype == 0 && append.subfiletype == 0 {
-                count += 1;
-            }
-        }
+    let count = pages.len() as u64;
+    let selected = page_option(option.options.as_ref())?;
+    let level = index_option(option.options.as_ref(), &["level"])?;
+    let page_index = selected.unwrap_or(0);
+    if page_index >= pages.len() {
+        return Err(page_out_of_range(page_index, pages.len()));
+    }
+    let page = &pages[page_index];
+    let levels = page.images.len();
+    if let Some(level) = level
+        && level >= levels
+    {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::OutboundIndex,
+            format!("level {level} is out of range ({levels} levels)"),
+        )));
     }
+    let image = page.images[level.unwrap_or(0)].clone();
+    let animation = selected.is_none() && level.is_none() && count > 1;
     option
         .drawer
         .set_metadata("image pages", DataMap::UInt(count))?;
+    option
+        .drawer
+        .set_metadata("image levels", DataMap::UInt(levels as u64))?;
 
     option
         .drawer
         .set_metadata("Format", DataMap::Ascii("Tiff".to_owned()))?;
     option
         .drawer
-        .set_metadata("width", DataMap::UInt(header.width as u64))?;
+        .set_metadata("width", DataMap::UInt(image.width as u64))?;
+    option
+        .drawer
+        .set_metadata("height", DataMap::UInt(image.height as u64))?;
     option
         .drawer
-        .set_metadata("height", DataMap::UInt(header.height as u64))?;
+        .set_metadata("bits per pixel", DataMap::UInt(image.bitspersample as u64))?;
+    let tiff_headers = &page.images[0].tiff_headers;
     option
         .drawer
-        .set_metadata("bits per pixel", DataMap::UInt(header.bitspersample as u64))?;
+        .set_metadata("Tiff headers", DataMap::Exif(tiff_headers.clone()))?;
     option
         .drawer
-        .set_metadata("Tiff headers", DataMap::Exif(header.tiff_headers.clone()))?;
-    option.drawer.set_metadata(
-        "compression",
-        DataMap::Ascii(header.compression.to_string()),
-    )?;
-    if let Some(ref icc_profile) = header.icc_profile {
+        .set_metadata("compression", DataMap::Ascii(image.compression.to_string()))?;
+    if let Some(geo) = GeoTiff::from_headers(first_ifd_tags(&tiff_headers.headers)) {
+        option
+            .drawer
+            .set_metadata(GEOTIFF_METADATA_KEY, DataMap::JSON(geo.to_json()))?;
+    }
+    if let Some(icc_profile) = image
+        .icc_profile
+        .as_ref()
+        .or(page.images[0].icc_profile.as_ref())
+    {
         option
             .drawer
             .set_metadata("ICC Profile", DataMap::ICCProfile(icc_profile.to_vec()))?;
     }
     let mut warnings = None;
 
-    let warn = compression_decode(reader, option, &mut header, true, count > 1)?;
+    let mask = page.mask_of(&image).cloned();
+    let warn = decode_image(reader, option, &image, mask.as_ref(), true, animation)?;
 
     warnings = ImgWarnings::append(warnings, warn);
 
-    if count > 1 {
-        for append in header.multi_page.iter() {
-            if append.newsubfiletype == 0 && append.subfiletype == 0 {
-                let rect = ImageRect {
-                    width: append.width as usize,
-                    height: append.height as usize,
-                    start_x: append.startx as i32,
-                    start_y: append.starty as i32,
-                };
-                let opt = NextOptions {
-                    flag: NextOption::Next,
-                    await_time: 0,
-                    image_rect: Some(rect),
-                    dispose_option: None,
-                    blend: None,
-                };
-
-                let result = option.drawer.next(Some(opt))?;
-                if let Some(response) = result {
-                    if response.response == ResponseCommand::Abort {
-                        return Ok(warnings);
-                    }
+    if animation {
+        for page in pages.iter().skip(1) {
+            let append = &page.images[0];
+            let rect = ImageRect {
+                width: append.width as usize,
+                height: append.height as usize,
+                start_x: append.startx as i32,
+                start_y: append.starty as i32,
+            };
+            let opt = NextOptions {
+                flag: NextOption::Next,
+                await_time: 0,
+                image_rect: Some(rect),
+                dispose_option: None,
+                blend: None,
+            };
+
+            let result = option.drawer.next(Some(opt))?;
+            if let Some(response) = result {
+                if response.response == ResponseCommand::Abort {
+                    return Ok(warnings);
                 }
-                let header = &mut append.clone();
-                let result = compression_decode(reader, option, header, false, false);
-                match result {
-                    Ok(warn) => {
-                        warnings = ImgWarnings::append(warnings, warn);
-                    }
-                    Err(error) => {
-                        let warning = TiffWarning::new(error.to_string());
-                        warnings = ImgWarnings::add(warnings, Box::new(warning));
-                    }
+            }
+            let result = decode_image(reader, option, append, page.mask_of(append), false, false);
+            match result {
+                Ok(warn) => {
+                    warnings = ImgWarnings::append(warnings, warn);
+                }
+                Err(error) => {
+                    let warning = TiffWarning::new(error.to_string());
+                    warnings = ImgWarnings::add(warnings, Box::new(warning));
                 }
             }
         }
@@ -0,0 +1,461 @@
+//! Separated (CMYK), YCbCr and CIELab/ICCLab/ITULab photometric
+//! interpretations for images that are not JPEG compressed.
+//!
+//! CMYK and Lab samples go through the sample reader and are converted and
+//! drawn one strip or tile at a time. YCbCr has its own reader because subsampled data is stored in data units of `h * v` luma
+//! samples followed by one Cb and one Cr sample (TIFF 6.0 section 21).
+
+type Error = Box<dyn std::error::Error>;
+use super::sample::{SampleFormat, SampleLayout, read_blocks, sample_error, undo_horizontal};
+use super::{damaged_warnings, decompress_block, init_canvas};
+use crate::color::{
+    D50_WHITE_POINT, linear_to_srgb, transform_matrix3, xy_to_xyz, xyz_to_srgb_matrix,
+};
+use crate::draw::DecodeOptions;
+use crate::tiff::header::{Compression, Tiff};
+use crate::warning::ImgWarnings;
+use bin_rs::reader::BinaryReader;
+
+/// Default ITULab ranges of L*, a* and b* (RFC 2301).
+const ITULAB_DECODE: [f64; 6] = [0.0, 100.0, -85.0, 85.0, -75.0, 125.0];
+
+/// Returns whether the image is drawn by this module.
+pub(super) fn is_photometric_image(header: &Tiff) -> bool {
+    !matches!(header.compression, Compression::Jpeg | Compression::OldJpeg)
+        && matches!(header.photometric_interpretation, 5 | 6 | 8 | 9 | 10)
+}
+
+pub(super) fn decode_photometric<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+    initialize: bool,
+    animation: bool,
+) -> Result<Option<ImgWarnings>, Error> {
+    match header.photometric_interpretation {
+        5 => return decode_cmyk(reader, option, header, initialize, animation),
+        6 => {}
+        _ => return decode_lab(reader, option, header, initialize, animation),
+    }
+    let (rgba, damaged) = decode_ycbcr(reader, header)?;
+    if initialize {
+        init_canvas(option, header, animation)?;
+    }
+    let width = header.width as usize;
+    if width > 0 {
+        for (y, row) in rgba.chunks_exact(width * 4).enumerate() {
+            option.drawer.draw(0, y, width, 1, row, None)?;
+        }
+    }
+    Ok(damaged_warnings(damaged))
+}
+
+/// Draws each strip or tile as soon as it is read, converting the color
+/// samples of every pixel with `convert`.
+fn draw_blocks<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+    layout: &SampleLayout,
+    initialize: bool,
+    animation: bool,
+    convert: impl Fn(&[f64]) -> [u8; 3],
+) -> Result<Option<ImgWarnings>, Error> {
+    if initialize {
+        init_canvas(option, header, animation)?;
+    }
+    let damaged = read_blocks(reader, header, layout, |x, y, width, height, samples| {
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for pixel in samples.chunks_exact(layout.samples) {
+            rgba.extend_from_slice(&convert(pixel));
+            rgba.push(alpha(pixel, layout));
+        }
+        option.drawer.draw(x, y, width, height, &rgba, None)?;
+        Ok(())
+    })?;
+    Ok(damaged_warnings(damaged))
+}
+
+fn to_u8(value: f64) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Maps a sample to `0.0..=1.0`. Floating-point samples are already in range.
+fn unit(value: f64, layout: &SampleLayout) -> f64 {
+    match layout.format {
+        SampleFormat::Float => value,
+        _ => {
+            let (min, max) = layout.type_range();
+            (value - min) / (max - min)
+        }
+    }
+}
+
+fn alpha(pixel: &[f64], layout: &SampleLayout) -> u8 {
+    layout
+        .alpha
+        .map(|alpha| to_u8(unit(pixel[alpha], layout)))
+        .unwrap_or(0xff)
+}
+
+fn decode_cmyk<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+    initialize: bool,
+    animation: bool,
+) -> Result<Option<ImgWarnings>, Error> {
+    if header.ink_set != 1 {
+        return Err(sample_error(format!(
+            "InkSet {} is not support. only CMYK inks can be decoded.",
+            header.ink_set
+        )));
+    }
+    let layout = SampleLayout::with_color(header, 4, false)?;
+    let convert = |pixel: &[f64]| {
+        let black = 1.0 - unit(pixel[3], &layout);
+        let mut rgb = [0; 3];
+        for (channel, &ink) in rgb.iter_mut().zip(&pixel[..3]) {
+            *channel = to_u8((1.0 - unit(ink, &layout)) * black);
+        }
+        rgb
+    };
+    draw_blocks(
+        reader, option, header, &layout, initialize, animation, convert,
+    )
+}
+
+/// Converts the stored samples of a Lab pixel to L*, a* and b*.
+fn lab_values(pixel: &[f64], layout: &SampleLayout, photometric: u16, decode: &[f64]) -> [f64; 3] {
+    if layout.format == SampleFormat::Float {
+        return [pixel[0], pixel[1], pixel[2]];
+    }
+    let full = (1_u64 << layout.bits) as f64;
+    let max = full - 1.0;
+    // Signed sample formats are read back as two's complement codes.
+    let code = |value: f64| if value < 0.0 { value + full } else { value };
+    let lightness = code(pixel[0]) * 100.0 / max;
+    match photometric {
+        8 => {
+            // CIELab: a* and b* are signed, 16-bit values in 1/256 units.
+            let scale = full / 256.0;
+            let signed = |value: f64| {
+                let value = code(value);
+                if value >= full / 2.0 {
+                    value - full
+                } else {
+                    value
+                }
+            };
+            [
+                lightness,
+                signed(pixel[1]) / scale,
+                signed(pixel[2]) / scale,
+            ]
+        }
+        9 => {
+            // ICCLab: a* and b* have an offset of 128.
+            let chroma = |value: f64| code(value) * 255.0 / max - 128.0;
+            [lightness, chroma(pixel[1]), chroma(pixel[2])]
+        }
+        _ => {
+            let decode = if decode.len() >= 6 {
+                decode
+            } else {
+                &ITULAB_DECODE
+            };
+            let mut lab = [0.0; 3];
+            for (i, value) in lab.iter_mut().enumerate() {
+                let (min, max_value) = (decode[i * 2], decode[i * 2 + 1]);
+                *value = min + code(pixel[i]) * (max_value - min) / max;
+            }
+            lab
+        }
+    }
+}
+
+fn decode_lab<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+    initialize: bool,
+    animation: bool,
+) -> Result<Option<ImgWarnings>, Error> {
+    let layout = SampleLayout::with_color(header, 3, false)?;
+    let white_point = header.white_point.unwrap_or(D50_WHITE_POINT);
+    let (white, matrix) = xy_to_xyz(white_point)
+        .zip(xyz_to_srgb_matrix(white_point))
+        .ok_or_else(|| sample_error("WhitePoint is invalid."))?;
+
+    let convert = |pixel: &[f64]| {
+        let lab = lab_values(
+            pixel,
+            &layout,
+            header.photometric_interpretation,
+            &header.decode,
+        );
+        let xyz = crate::color::lab_to_xyz(lab, white);
+        transform_matrix3(&matrix, xyz).map(|value| to_u8(linear_to_srgb(value)))
+    };
+    draw_blocks(
+        reader, option, header, &layout, initialize, animation, convert,
+    )
+}
+
+/// Full resolution luma and subsampled chroma planes of a YCbCr image.
+struct YCbCrPlanes {
+    luma: Vec<u8>,
+    alpha: Option<Vec<u8>>,
+    cb: Vec<u8>,
+    cr: Vec<u8>,
+    chroma_width: usize,
+    chroma_height: usize,
+    /// CCITT data had broken rows.
+    damaged: bool,
+}
+
+fn subsampling(header: &Tiff) -> Result<(usize, usize), Error> {
+    let [horizontal, vertical] = header.ycbcr_subsampling;
+    if !matches!(horizontal, 1 | 2 | 4) || !matches!(vertical, 1 | 2 | 4) {
+        return Err(sample_error(format!(
+            "YCbCrSubSampling {horizontal}x{vertical} is not support."
+        )));
+    }
+    Ok((horizontal as usize, vertical as usize))
+}
+
+fn read_ycbcr<B: BinaryReader>(reader: &mut B, header: &Tiff) -> Result<YCbCrPlanes, Error> {
+    let (h, v) = subsampling(header)?;
+    let samples = header.samples_per_pixel as usize;
+    if samples < 3 {
+        return Err(sample_error("YCbCr image needs Sample per pixel >=3."));
+    }
+    if header.bitspersamples.iter().any(|&bits| bits != 8) {
+        return Err(sample_error("YCbCr image needs 8 bits per sample."));
+    }
+    let subsampled = h * v > 1;
+    if subsampled && header.predictor != 1 {
+        return Err(sample_error(
+            "Predictor is not support for subsampled YCbCr images.",
+        ));
+    }
+
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let (offsets, counts, block_width, block_length) = if header.is_tiled() {
+        (
+            &header.tile_offsets,
+            &header.tile_byte_counts,
+            header.tile_width as usize,
+            header.tile_length as usize,
+        )
+    } else {
+        let rows = match header.rows_per_strip as usize {
+            0 => height,
+            rows => rows.min(height),
+        };
+        (
+            &header.strip_offsets,
+            &header.strip_byte_counts,
+            width,
+            rows,
+        )
+    };
+    if block_width == 0 || block_length == 0 {
+        return Err(sample_error("Image has no strips or tiles."));
+    }
+    let across = width.div_ceil(block_width);
+    let blocks = across * height.div_ceil(block_length);
+    let planar = header.planar_config == 2;
+    let planes = if planar { samples } else { 1 };
+    if offsets.len() < blocks * planes {
+        return Err(sample_error(format!(
+            "Mismach length, {} blocks need {} offsets.",
+            blocks,
+            blocks * planes
+        )));
+    }
+
+    let chroma_width = width.div_ceil(h);
+    let chroma_height = height.div_ceil(v);
+    let has_alpha =
+        !subsampled && samples > 3 && matches!(header.extra_samples.first(), Some(1 | 2));
+    let mut planes_out = YCbCrPlanes {
+        luma: vec![0; width * height],
+        alpha: has_alpha.then(|| vec![0xff; width * height]),
+        cb: vec![128; chroma_width * chroma_height],
+        cr: vec![128; chroma_width * chroma_height],
+        chroma_width,
+        chroma_height,
+        damaged: false,
+    };
+    let units_across = block_width.div_ceil(h);
+    let units_down = block_length.div_ceil(v);
+
+    let mut damaged = false;
+    let mut read_block = |index: usize, length: usize| -> Result<Vec<u8>, Error> {
+        let count = match counts.get(index) {
+            Some(count) => *count as usize,
+            None if header.compression == Compression::NoneCompression => length,
+            None => return Err(sample_error("Strip or tile byte count is missing.")),
+        };
+        reader.seek(std::io::SeekFrom::Start(offsets[index]))?;
+        let buf = reader.read_bytes_as_vec(count)?;
+        let (mut data, warning) = decompress_block(&buf, header, block_width, block_length)?;
+        damaged |= warning;
+        data.resize(length, 0);
+        Ok(data)
+    };
+
+    for block in 0..blocks {
+        let x0 = block % across * block_width;
+        let y0 = block / across * block_length;
+        let (cx0, cy0) = (x0 / h, y0 / v);
+        if planar {
+            let luma = read_block(block, block_width * block_length)?;
+            for (row, line) in luma.chunks_exact(block_width).enumerate() {
+                let y = y0 + row;
+                if y >= height {
+                    break;
+                }
+                for (col, &value) in line.iter().enumerate().take(width.saturating_sub(x0)) {
+                    planes_out.luma[y * width + x0 + col] = value;
+                }
+            }
+            for plane in 1..3 {
+                let chroma = read_block(plane * blocks + block, units_across * units_down)?;
+                let target = if plane == 1 {
+                    &mut planes_out.cb
+                } else {
+                    &mut planes_out.cr
+                };
+                for (row, line) in chroma.chunks_exact(units_across).enumerate() {
+                    let y = cy0 + row;
+                    if y >= chroma_height {
+                        break;
+                    }
+                    for (col, &value) in line.iter().enumerate() {
+                        if cx0 + col < chroma_width {
+                            target[y * chroma_width + cx0 + col] = value;
+                        }
+                    }
+                }
+            }
+            continue;
+        }
+
+        let unit_len = if subsampled { h * v + 2 } else { samples };
+        let mut data = read_block(block, units_across * units_down * unit_len)?;
+        if header.predictor == 2 {
+            for line in data.chunks_exact_mut(units_across * unit_len) {
+                undo_horizontal(line, 1, unit_len, header.tiff_headers.endian);
+            }
+        }
+        for (index, unit) in data.chunks_exact(unit_len).enumerate() {
+            let (ux, uy) = (index % units_across, index / units_across);
+            for (i, &value) in unit[..h * v].iter().enumerate() {
+                let x = x0 + ux * h + i % h;
+                let y = y0 + uy * v + i / h;
+                if x < width && y < height {
+                    planes_out.luma[y * width + x] = value;
+                }
+            }
+            let (cx, cy) = (cx0 + ux, cy0 + uy);
+            if cx < chroma_width && cy < chroma_height {
+                planes_out.cb[cy * chroma_width + cx] = unit[h * v];
+                planes_out.cr[cy * chroma_width + cx] = unit[h * v + 1];
+            }
+            if let Some(alpha) = planes_out.alpha.as_mut() {
+                let (x, y) = (x0 + ux, y0 + uy);
+                if x < width && y < height {
+                    alpha[y * width + x] = unit[3];
+                }
+            }
+        }
+    }
+    planes_out.damaged = damaged;
+    Ok(planes_out)
+}
+
+/// Position of a pixel in chroma sample units. Centered chroma samples sit
+/// in the middle of their `factor` luma samples, co-sited ones on the first.
+fn chroma_position(
+    position: usize,
+    factor: usize,
+    centered: bool,
+    len: usize,
+) -> (usize, usize, f64) {
+    let offset = if centered {
+        (factor as f64 - 1.0) / 2.0
+    } else {
+        0.0
+    };
+    let position = ((position as f64 - offset) / factor as f64).clamp(0.0, (len - 1) as f64);
+    let first = position.floor() as usize;
+    (first, (first + 1).min(len - 1), position - first as f64)
+}
+
+fn decode_ycbcr<B: BinaryReader>(reader: &mut B, header: &Tiff) -> Result<(Vec<u8>, bool), Error> {
+    let (h, v) = subsampling(header)?;
+    let planes = read_ycbcr(reader, header)?;
+    let width = header.width as usize;
+    let height = header.height as usize;
+
+    let [luma_red, luma_green, luma_blue] = header.ycbcr_coefficients;
+    if luma_green == 0.0 {
+        return Err(sample_error("YCbCrCoefficients are invalid."));
+    }
+    let reference = match header.reference_black_white.as_slice() {
+        reference @ [_, _, _, _, _, _, ..] => [
+            reference[0],
+            reference[1],
+            reference[2],
+            reference[3],
+            reference[4],
+            reference[5],
+        ],
+        _ => [0.0, 255.0, 128.0, 255.0, 128.0, 255.0],
+    };
+    let scale = |value: f64, component: usize, range: f64| {
+        let (black, white) = (reference[component * 2], reference[component * 2 + 1]);
+        if white == black {
+            0.0
+        } else {
+            (value - black) * range / (white - black)
+        }
+    };
+
+    let centered = header.ycbcr_positioning != 2;
+    let (chroma_width, chroma_height) = (planes.chroma_width, planes.chroma_height);
+    let sample = |plane: &[u8], x: (usize, usize, f64), y: (usize, usize, f64)| {
+        let at = |cx: usize, cy: usize| plane[cy * chroma_width + cx] as f64;
+        let top = at(x.0, y.0) * (1.0 - x.2) + at(x.1, y.0) * x.2;
+        let bottom = at(x.0, y.1) * (1.0 - x.2) + at(x.1, y.1) * x.2;
+        top * (1.0 - y.2) + bottom * y.2
+    };
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let cy = chroma_position(y, v, centered, chroma_height);
+        for x in 0..width {
+            let cx = chroma_position(x, h, centered, chroma_width);
+            let luma = scale(planes.luma[y * width + x] as f64, 0, 255.0);
+            let cb = scale(sample(&planes.cb, cx, cy), 1, 127.0);
+            let cr = scale(sample(&planes.cr, cx, cy), 2, 127.0);
+            let red = cr * (2.0 - 2.0 * luma_red) + luma;
+            let blue = cb * (2.0 - 2.0 * luma_blue) + luma;
+            let green = (luma - luma_blue * blue - luma_red * red) / luma_green;
+            for value in [red, green, blue] {
+                rgba.push(value.round().clamp(0.0, 255.0) as u8);
+            }
+            rgba.push(
+                planes
+                    .alpha
+                    .as_ref()
+                    .map(|alpha| alpha[y * width + x])
+                    .unwrap_or(0xff),
+            );
+        }
+    }
+    Ok((rgba, planes.damaged))
+}
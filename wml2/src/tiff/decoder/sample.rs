@@ -0,0 +1,576 @@
+//! Signed integer and floating-point samples (SampleFormat 2 and 3),
+//! high-bit-depth unsigned samples and the floating-point predictor
+//! (Adobe Photoshop TIFF Technical Note 3).
+//!
+//! Samples are converted to RGBA one strip or tile at a time. Min/max
+//! normalization reads the blocks twice, first to find the range.
+
+type Error = Box<dyn std::error::Error>;
+use super::{damaged_warnings, decompress_block, init_canvas};
+use crate::draw::DecodeOptions;
+use crate::error::{ImgError, ImgErrorKind};
+use crate::metadata::DataMap;
+use crate::tiff::header::{Compression, Tiff};
+use crate::warning::ImgWarnings;
+use bin_rs::Endian;
+use bin_rs::reader::BinaryReader;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum SampleFormat {
+    Unsigned,
+    Signed,
+    Float,
+}
+
+/// How samples are mapped to 8-bit RGBA.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Normalize {
+    /// Min/max of the image for floating-point samples, the full range of the
+    /// type for integer samples.
+    Auto,
+    /// Min/max of the color samples in the image.
+    MinMax,
+    /// Fixed `(min, max)` range.
+    Range(f64, f64),
+}
+
+pub(super) struct SampleLayout {
+    pub(super) format: SampleFormat,
+    pub(super) bits: usize,
+    bytes: usize,
+    pub(super) samples: usize,
+    color: usize,
+    pub(super) alpha: Option<usize>,
+    white_is_zero: bool,
+}
+
+pub(super) fn sample_error(message: impl Into<String>) -> Error {
+    Box::new(ImgError::new_const(
+        ImgErrorKind::DecodeError,
+        message.into(),
+    ))
+}
+
+fn option_error(message: impl Into<String>) -> Error {
+    Box::new(ImgError::new_const(
+        ImgErrorKind::InvalidParameter,
+        message.into(),
+    ))
+}
+
+impl SampleLayout {
+    fn new(header: &Tiff) -> Result<Self, Error> {
+        let (color, white_is_zero) = match header.photometric_interpretation {
+            0 => (1, true),
+            1 => (1, false),
+            2 => (3, false),
+            photometric => {
+                return Err(sample_error(format!(
+                    "Photometric interpretation {photometric} is not support for this sample format."
+                )));
+            }
+        };
+        Self::with_color(header, color, white_is_zero)
+    }
+
+    /// Builds the layout of an image with `color` color channels followed by
+    /// extra samples.
+    pub(super) fn with_color(
+        header: &Tiff,
+        color: usize,
+        white_is_zero: bool,
+    ) -> Result<Self, Error> {
+        let format = match header.sample_format {
+            1 => SampleFormat::Unsigned,
+            2 => SampleFormat::Signed,
+            3 => SampleFormat::Float,
+            format => {
+                return Err(sample_error(format!(
+                    "SampleFormat {format} is not support."
+                )));
+            }
+        };
+        let bits = header.bitspersamples.first().copied().unwrap_or(0) as usize;
+        if header.bitspersamples.iter().any(|&b| b as usize != bits) {
+            return Err(sample_error(
+                "Mixed bits per sample are not support for this sample format.",
+            ));
+        }
+        let supported = match format {
+            SampleFormat::Float => matches!(bits, 16 | 24 | 32 | 64),
+            _ => matches!(bits, 8 | 16 | 32 | 64),
+        };
+        if !supported {
+            return Err(sample_error(format!(
+                "{bits} bit {} samples are not support.",
+                format_name(format)
+            )));
+        }
+
+        let samples = header.samples_per_pixel.max(1) as usize;
+        if samples < color {
+            return Err(sample_error(
+                "Samples per pixel is less than the color channels.",
+            ));
+        }
+        let alpha = (samples > color && matches!(header.extra_samples.first(), Some(1) | Some(2)))
+            .then_some(color);
+
+        Ok(Self {
+            format,
+            bits,
+            bytes: bits / 8,
+            samples,
+            color,
+            alpha,
+            white_is_zero,
+        })
+    }
+
+    /// Value range of the sample type. Floating-point samples use 0.0 - 1.0.
+    pub(super) fn type_range(&self) -> (f64, f64) {
+        match self.format {
+            SampleFormat::Unsigned => (0.0, (u64::MAX >> (64 - self.bits)) as f64),
+            SampleFormat::Signed => {
+                let max = (i64::MAX >> (64 - self.bits)) as f64;
+                (-max - 1.0, max)
+            }
+            SampleFormat::Float => (0.0, 1.0),
+        }
+    }
+
+    fn read(&self, data: &[u8], endian: Endian) -> f64 {
+        let value = read_uint(data, endian);
+        match self.format {
+            SampleFormat::Unsigned => value as f64,
+            SampleFormat::Signed => {
+                let shift = 64 - self.bits;
+                (((value << shift) as i64) >> shift) as f64
+            }
+            SampleFormat::Float => match self.bits {
+                16 => small_float(value, 5, 10),
+                24 => small_float(value, 7, 16),
+                32 => f32::from_bits(value as u32) as f64,
+                _ => f64::from_bits(value),
+            },
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{}{}", format_name(self.format), self.bits)
+    }
+}
+
+fn format_name(format: SampleFormat) -> &'static str {
+    match format {
+        SampleFormat::Unsigned => "uint",
+        SampleFormat::Signed => "int",
+        SampleFormat::Float => "float",
+    }
+}
+
+fn read_uint(data: &[u8], endian: Endian) -> u64 {
+    let mut value = 0_u64;
+    match endian {
+        Endian::BigEndian => {
+            for &byte in data {
+                value = value << 8 | byte as u64;
+            }
+        }
+        Endian::LittleEndian => {
+            for &byte in data.iter().rev() {
+                value = value << 8 | byte as u64;
+            }
+        }
+    }
+    value
+}
+
+fn write_uint(data: &mut [u8], value: u64, endian: Endian) {
+    let len = data.len();
+    for (i, byte) in data.iter_mut().enumerate() {
+        let shift = match endian {
+            Endian::BigEndian => (len - 1 - i) * 8,
+            Endian::LittleEndian => i * 8,
+        };
+        *byte = (value >> shift) as u8;
+    }
+}
+
+/// Decodes an IEEE 754 style float with the given field widths, used for
+/// 16-bit half floats (5/10) and Adobe 24-bit floats (7/16).
+fn small_float(value: u64, exponent_bits: u32, mantissa_bits: u32) -> f64 {
+    let sign = if (value >> (exponent_bits + mantissa_bits)) & 1 == 1 {
+        -1.0
+    } else {
+        1.0
+    };
+    let max_exponent = (1_u64 << exponent_bits) - 1;
+    let bias = (max_exponent >> 1) as i32;
+    let exponent = (value >> mantissa_bits) & max_exponent;
+    let mantissa = (value & ((1 << mantissa_bits) - 1)) as f64 / (1_u64 << mantissa_bits) as f64;
+    if exponent == 0 {
+        sign * mantissa * 2_f64.powi(1 - bias)
+    } else if exponent == max_exponent {
+        if mantissa == 0.0 {
+            sign * f64::INFINITY
+        } else {
+            f64::NAN
+        }
+    } else {
+        sign * (1.0 + mantissa) * 2_f64.powi(exponent as i32 - bias)
+    }
+}
+
+/// Undoes horizontal differencing (Predictor 2) on `bytes` sized samples.
+pub(super) fn undo_horizontal(row: &mut [u8], bytes: usize, stride: usize, endian: Endian) {
+    let mask = u64::MAX >> (64 - bytes * 8);
+    for i in stride..row.len() / bytes {
+        let prev = read_uint(&row[(i - stride) * bytes..(i - stride + 1) * bytes], endian);
+        let current = &mut row[i * bytes..(i + 1) * bytes];
+        let value = read_uint(current, endian).wrapping_add(prev) & mask;
+        write_uint(current, value, endian);
+    }
+}
+
+/// Undoes the floating-point predictor (Predictor 3). The row is stored as
+/// byte planes, most significant byte first, differenced byte by byte. The
+/// result is big-endian.
+fn undo_floating_point(row: &mut [u8], bytes: usize, stride: usize) {
+    for i in stride..row.len() {
+        row[i] = row[i].wrapping_add(row[i - stride]);
+    }
+    let count = row.len() / bytes;
+    let planes = row.to_vec();
+    for i in 0..count {
+        for byte in 0..bytes {
+            row[i * bytes + byte] = planes[byte * count + i];
+        }
+    }
+}
+
+fn normalize_option(option: &DecodeOptions) -> Result<Option<Normalize>, Error> {
+    let Some(options) = option.options.as_ref() else {
+        return Ok(None);
+    };
+    let Some(value) = options.get("normalize") else {
+        return Ok(None);
+    };
+    let bound = |key: &str| -> Result<f64, Error> {
+        match options.get(key) {
+            Some(DataMap::Float(value)) => Ok(*value),
+            Some(DataMap::UInt(value)) => Ok(*value as f64),
+            Some(DataMap::SInt(value)) => Ok(*value as f64),
+            Some(_) => Err(option_error(format!("TIFF {key} must be a number"))),
+            None => Err(option_error(format!("TIFF normalize=range needs {key}"))),
+        }
+    };
+
+    match value {
+        DataMap::Ascii(value) => match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Some(Normalize::Auto)),
+            "minmax" | "min_max" => Ok(Some(Normalize::MinMax)),
+            "range" => {
+                let (min, max) = (bound("normalize_min")?, bound("normalize_max")?);
+                if min.is_finite() && max.is_finite() && min < max {
+                    Ok(Some(Normalize::Range(min, max)))
+                } else {
+                    Err(option_error(
+                        "TIFF normalize_min must be less than normalize_max",
+                    ))
+                }
+            }
+            _ => Err(option_error(format!("unsupported TIFF normalize: {value}"))),
+        },
+        _ => Err(option_error(
+            "TIFF normalize must be `auto`, `minmax`, or `range`",
+        )),
+    }
+}
+
+fn high_bit_depth_option(option: &DecodeOptions) -> Result<bool, Error> {
+    let Some(value) = option
+        .options
+        .as_ref()
+        .and_then(|map| map.get("high_bit_depth"))
+    else {
+        return Ok(false);
+    };
+
+    match value {
+        DataMap::UInt(value) => Ok(*value != 0),
+        DataMap::SInt(value) => Ok(*value != 0),
+        DataMap::Ascii(value) => match value.to_ascii_lowercase().as_str() {
+            "true" | "on" => Ok(true),
+            "false" | "off" => Ok(false),
+            _ => Err(option_error(format!(
+                "unsupported TIFF high_bit_depth: {value}"
+            ))),
+        },
+        _ => Err(option_error(
+            "TIFF high_bit_depth must be `true`, `false`, 0, or 1",
+        )),
+    }
+}
+
+/// Returns whether the image goes through the sample path: signed or
+/// floating-point samples, the floating-point predictor, or unsigned samples
+/// wider than 8 bits when normalization or high-bit-depth output is asked for.
+pub(super) fn is_sample_image(header: &Tiff, option: &DecodeOptions) -> Result<bool, Error> {
+    if header.compression == Compression::Jpeg || header.compression == Compression::OldJpeg {
+        return Ok(false);
+    }
+    if header.sample_format != 1 || header.predictor == 3 {
+        return Ok(true);
+    }
+    let wide = header.bitspersamples.first().copied().unwrap_or(0) >= 16
+        && matches!(header.photometric_interpretation, 0..=2);
+    Ok(wide && (normalize_option(option)?.is_some() || high_bit_depth_option(option)?))
+}
+
+/// Reads the strips or tiles one at a time and passes each to `f` as
+/// `(x, y, width, height, samples)`, clipped to the image, with `samples`
+/// values per pixel. Returns whether CCITT data had broken rows.
+pub(super) fn read_blocks<B: BinaryReader>(
+    reader: &mut B,
+    header: &Tiff,
+    layout: &SampleLayout,
+    mut f: impl FnMut(usize, usize, usize, usize, &[f64]) -> Result<(), Error>,
+) -> Result<bool, Error> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let (offsets, counts, block_width, block_length) = if header.is_tiled() {
+        (
+            &header.tile_offsets,
+            &header.tile_byte_counts,
+            header.tile_width as usize,
+            header.tile_length as usize,
+        )
+    } else {
+        let rows = match header.rows_per_strip as usize {
+            0 => height,
+            rows => rows.min(height),
+        };
+        (
+            &header.strip_offsets,
+            &header.strip_byte_counts,
+            width,
+            rows,
+        )
+    };
+    if block_width == 0 || block_length == 0 {
+        return Err(sample_error("Image has no strips or tiles."));
+    }
+    let across = width.div_ceil(block_width);
+    let blocks = across * height.div_ceil(block_length);
+    let planes = if header.planar_config == 2 {
+        layout.samples
+    } else {
+        1
+    };
+    let plane_samples = layout.samples / planes;
+    if offsets.len() < blocks * planes {
+        return Err(sample_error(format!(
+            "Mismach length, {} blocks need {} offsets.",
+            blocks,
+            blocks * planes
+        )));
+    }
+
+    let endian = match header.predictor {
+        3 => Endian::BigEndian,
+        _ => header.tiff_headers.endian,
+    };
+    let row_len = block_width * plane_samples * layout.bytes;
+    let mut damaged = false;
+    for block in 0..blocks {
+        let x0 = block % across * block_width;
+        let y0 = block / across * block_length;
+        let visible_width = block_width.min(width - x0);
+        let visible_length = block_length.min(height - y0);
+        let mut samples = vec![0.0; visible_width * visible_length * layout.samples];
+        for plane in 0..planes {
+            let index = plane * blocks + block;
+            let count = match counts.get(index) {
+                Some(count) => *count as usize,
+                None if header.compression == Compression::NoneCompression => {
+                    row_len * block_length
+                }
+                None => return Err(sample_error("Strip or tile byte count is missing.")),
+            };
+            reader.seek(std::io::SeekFrom::Start(offsets[index]))?;
+            let buf = reader.read_bytes_as_vec(count)?;
+            let (mut data, warning) = decompress_block(&buf, header, block_width, block_length)?;
+            damaged |= warning;
+            data.resize(row_len * block_length, 0);
+
+            for (row, line) in data
+                .chunks_exact_mut(row_len)
+                .take(visible_length)
+                .enumerate()
+            {
+                match header.predictor {
+                    2 => undo_horizontal(line, layout.bytes, plane_samples, endian),
+                    3 => undo_floating_point(line, layout.bytes, plane_samples),
+                    _ => {}
+                }
+                for (col, pixel) in line
+                    .chunks_exact(plane_samples * layout.bytes)
+                    .take(visible_width)
+                    .enumerate()
+                {
+                    for (sample, value) in pixel.chunks_exact(layout.bytes).enumerate() {
+                        let channel = if planes > 1 { plane } else { sample };
+                        samples[(row * visible_width + col) * layout.samples + channel] =
+                            layout.read(value, endian);
+                    }
+                }
+            }
+        }
+        f(x0, y0, visible_width, visible_length, &samples)?;
+    }
+    Ok(damaged)
+}
+
+/// Returns the range that maps to 0 - 255. Min/max normalization reads the
+/// color samples of every block before anything is drawn.
+fn color_range<B: BinaryReader>(
+    reader: &mut B,
+    header: &Tiff,
+    layout: &SampleLayout,
+    normalize: Normalize,
+) -> Result<(f64, f64), Error> {
+    let normalize = match normalize {
+        Normalize::Auto if layout.format == SampleFormat::Float => Normalize::MinMax,
+        Normalize::Auto => Normalize::Range(layout.type_range().0, layout.type_range().1),
+        normalize => normalize,
+    };
+    if let Normalize::Range(min, max) = normalize {
+        return Ok((min, max));
+    }
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    read_blocks(reader, header, layout, |_, _, _, _, samples| {
+        for pixel in samples.chunks_exact(layout.samples) {
+            for &value in pixel[..layout.color].iter().filter(|v| v.is_finite()) {
+                min = min.min(value);
+                max = max.max(value);
+            }
+        }
+        Ok(())
+    })?;
+    Ok(if min > max { (0.0, 1.0) } else { (min, max) })
+}
+
+fn to_u8(value: f64, (min, max): (f64, f64)) -> u8 {
+    if max <= min {
+        return 0;
+    }
+    ((value - min) / (max - min))
+        .clamp(0.0, 1.0)
+        .mul_add(255.0, 0.5) as u8
+}
+
+/// Decodes a signed, floating-point or high-bit-depth image and draws it as
+/// RGBA. Color samples that are NaN are drawn transparent. With
+/// `high_bit_depth` the unnormalized samples also go to
+/// [`DrawCallback::draw_samples`](crate::draw::DrawCallback::draw_samples).
+pub(super) fn decode_samples<B: BinaryReader>(
+    reader: &mut B,
+    option: &mut DecodeOptions,
+    header: &Tiff,
+    initialize: bool,
+    animation: bool,
+) -> Result<Option<ImgWarnings>, Error> {
+    if header.width == 0 || header.height == 0 {
+        return Err(sample_error("Image width or height is zero."));
+    }
+    let layout = SampleLayout::new(header)?;
+    let normalize = normalize_option(option)?.unwrap_or(Normalize::Auto);
+    let high_bit_depth = high_bit_depth_option(option)?;
+    if initialize {
+        init_canvas(option, header, animation)?;
+    }
+
+    let range = color_range(reader, header, &layout, normalize)?;
+    let alpha_range = layout.type_range();
+
+    let damaged = read_blocks(reader, header, &layout, |x, y, width, height, samples| {
+        let mut buf = Vec::with_capacity(width * height * 4);
+        for pixel in samples.chunks_exact(layout.samples) {
+            let color = &pixel[..layout.color];
+            if color.iter().any(|value| value.is_nan()) {
+                buf.extend_from_slice(&[0, 0, 0, 0]);
+                continue;
+            }
+            let mut rgb = [0_u8; 3];
+            for (i, channel) in rgb.iter_mut().enumerate() {
+                let value = to_u8(color[i.min(layout.color - 1)], range);
+                *channel = if layout.white_is_zero {
+                    255 - value
+                } else {
+                    value
+                };
+            }
+            let alpha = layout
+                .alpha
+                .map(|alpha| to_u8(pixel[alpha], alpha_range))
+                .unwrap_or(0xff);
+            buf.extend_from_slice(&[rgb[0], rgb[1], rgb[2], alpha]);
+        }
+        option.drawer.draw(x, y, width, height, &buf, None)?;
+        if high_bit_depth {
+            option
+                .drawer
+                .draw_samples(x, y, width, height, layout.samples, samples)?;
+        }
+        Ok(())
+    })?;
+
+    option
+        .drawer
+        .set_metadata("sample format", DataMap::Ascii(layout.name()))?;
+    option
+        .drawer
+        .set_metadata("sample range", DataMap::FloatAllay(vec![range.0, range.1]))?;
+    Ok(damaged_warnings(damaged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_floats_decode() {
+        assert_eq!(small_float(0x3c00, 5, 10), 1.0);
+        assert_eq!(small_float(0xc000, 5, 10), -2.0);
+        assert_eq!(small_float(0x0001, 5, 10), 2_f64.powi(-24));
+        assert!(small_float(0x7e00, 5, 10).is_nan());
+        // Adobe 24-bit float: bias 63
+        assert_eq!(small_float(0x3f_0000, 7, 16), 1.0);
+        assert_eq!(small_float(0x40_8000, 7, 16), 3.0);
+    }
+
+    #[test]
+    fn floating_point_predictor_restores_big_endian_bytes() {
+        let values = [1.5_f32, -2.25, 1024.0];
+        // Encode: byte planes, MSB first, then byte-wise differencing.
+        let bytes: Vec<[u8; 4]> = values.iter().map(|v| v.to_be_bytes()).collect();
+        let mut planes = vec![];
+        for byte in 0..4 {
+            for value in &bytes {
+                planes.push(value[byte]);
+            }
+        }
+        let mut encoded = planes.clone();
+        for i in (1..encoded.len()).rev() {
+            encoded[i] = encoded[i].wrapping_sub(encoded[i - 1]);
+        }
+
+        undo_floating_point(&mut encoded, 4, 1);
+        let decoded: Vec<f32> = encoded
+            .chunks_exact(4)
+            .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(decoded, values);
+    }
+}
@@ -5,16 +5,22 @@ use crate::draw::{
     ENCODE_ANIMATION_FRAMES_KEY, EncodeOptions as DrawEncodeOptions, ImageProfiles,
     encode_animation_frame_key,
 };
+use crate::encoder::ccitt::{self, CcittOptions, Encoding as CcittEncoding};
 use crate::encoder::lzw::encode_tiff;
+use crate::encoder::{bool_option, opaque_rgba, packbits};
 use crate::error::{ImgError, ImgErrorKind};
 #[cfg(feature = "tiff-jpeg")]
-use crate::jpeg::encoder::{encode_rgba as encode_jpeg_rgba, quality_from_draw_options};
+use crate::jpeg::encoder::{encode_rgba as encode_jpeg_rgba, quality_from_options};
 use crate::metadata::{DataMap, get_exif_option};
+use crate::quantize::{self, Dither, QuantizeOptions};
+use crate::tiff::geotiff::is_geotiff_tag;
 use crate::tiff::header::{
-    DataPack, Rational, TiffHeader, TiffHeaders, read_tags, tiff_pages_to_bytes,
+    BIGTIFF_VERSION, DataPack, Rational, TiffHeader, TiffHeaders, first_ifd_tags, read_tags,
+    tiff_pages_to_bytes,
 };
 use bin_rs::Endian;
 use bin_rs::reader::BytesReader;
+use std::collections::HashMap;
 
 type Error = Box<dyn std::error::Error>;
 
@@ -49,6 +55,11 @@ struct PagePlan {
 enum TiffCompressionMode {
     None,
     Lzw { is_lsb: bool },
+    Deflate,
+    PackBits,
+    CcittRle,
+    CcittG3,
+    CcittG4,
     Jpeg { quality: usize },
 }
 
@@ -57,10 +68,19 @@ impl TiffCompressionMode {
         match self {
             Self::None => 1,
             Self::Lzw { .. } => 5,
+            Self::Deflate => 8,
+            Self::PackBits => 32773,
+            Self::CcittRle => 2,
+            Self::CcittG3 => 3,
+            Self::CcittG4 => 4,
             Self::Jpeg { .. } => 7,
         }
     }
 
+    fn is_ccitt(self) -> bool {
+        matches!(self, Self::CcittRle | Self::CcittG3 | Self::CcittG4)
+    }
+
     fn fill_order(self) -> u16 {
         match self {
             Self::Lzw { is_lsb: true } => 2,
@@ -69,8 +89,47 @@ impl TiffCompressionMode {
     }
 
     fn supports_alpha(self) -> bool {
-        !matches!(self, Self::Jpeg { .. })
+        !matches!(self, Self::Jpeg { .. }) && !self.is_ccitt()
     }
+
+    fn supports_predictor(self) -> bool {
+        matches!(self, Self::Lzw { .. } | Self::Deflate)
+    }
+}
+
+/// Photometric interpretation written by the encoder.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TiffColorMode {
+    Rgb,
+    Gray,
+    Palette,
+    Bilevel,
+}
+
+struct TiffSettings {
+    compression: TiffCompressionMode,
+    color: TiffColorMode,
+    predictor: bool,
+    quantize: QuantizeOptions,
+    ccitt: CcittOptions,
+    resolution: Option<PageResolution>,
+    description: Option<String>,
+}
+
+/// XResolution, YResolution and ResolutionUnit of one page.
+struct PageResolution {
+    x: Rational,
+    y: Rational,
+    unit: u16,
+}
+
+/// Sample layout of one encoded page.
+struct PageLayout {
+    photometric: u16,
+    bits_per_sample: u16,
+    samples_per_pixel: usize,
+    with_alpha: bool,
+    color_map: Option<Vec<u16>>,
 }
 
 fn as_u64(value: Option<&DataMap>, key: &str) -> Result<u64, Error> {
@@ -122,12 +181,10 @@ fn as_raw(value: Option<&DataMap>, key: &str) -> Result<Vec<u8>, Error> {
     }
 }
 
-fn tiff_compression(option: &DrawEncodeOptions<'_>) -> Result<TiffCompressionMode, Error> {
-    let Some(value) = option
-        .options
-        .as_ref()
-        .and_then(|map| map.get("compression"))
-    else {
+fn tiff_compression(
+    options: Option<&HashMap<String, DataMap>>,
+) -> Result<TiffCompressionMode, Error> {
+    let Some(value) = options.and_then(|map| map.get("compression")) else {
         return Ok(TiffCompressionMode::None);
     };
 
@@ -136,9 +193,14 @@ fn tiff_compression(option: &DrawEncodeOptions<'_>) -> Result<TiffCompressionMod
             "none" | "uncompressed" => Ok(TiffCompressionMode::None),
             "lzw" | "lzw_msb" => Ok(TiffCompressionMode::Lzw { is_lsb: false }),
             "lzw_lsb" => Ok(TiffCompressionMode::Lzw { is_lsb: true }),
+            "deflate" | "adobe_deflate" | "zip" => Ok(TiffCompressionMode::Deflate),
+            "packbits" => Ok(TiffCompressionMode::PackBits),
+            "ccitt_rle" | "ccitt_huffman" => Ok(TiffCompressionMode::CcittRle),
+            "ccitt_g3" | "g3" | "fax3" => Ok(TiffCompressionMode::CcittG3),
+            "ccitt_g4" | "g4" | "fax4" => Ok(TiffCompressionMode::CcittG4),
             #[cfg(feature = "tiff-jpeg")]
             "jpeg" | "jpg" => Ok(TiffCompressionMode::Jpeg {
-                quality: quality_from_draw_options(option),
+                quality: quality_from_options(options),
             }),
             #[cfg(not(feature = "tiff-jpeg"))]
             "jpeg" | "jpg" => Err(Box::new(ImgError::new_const(
@@ -151,10 +213,15 @@ fn tiff_compression(option: &DrawEncodeOptions<'_>) -> Result<TiffCompressionMod
             ))),
         },
         DataMap::UInt(1) | DataMap::SInt(1) => Ok(TiffCompressionMode::None),
+        DataMap::UInt(2) | DataMap::SInt(2) => Ok(TiffCompressionMode::CcittRle),
+        DataMap::UInt(3) | DataMap::SInt(3) => Ok(TiffCompressionMode::CcittG3),
+        DataMap::UInt(4) | DataMap::SInt(4) => Ok(TiffCompressionMode::CcittG4),
         DataMap::UInt(5) | DataMap::SInt(5) => Ok(TiffCompressionMode::Lzw { is_lsb: false }),
+        DataMap::UInt(8 | 32946) | DataMap::SInt(8 | 32946) => Ok(TiffCompressionMode::Deflate),
+        DataMap::UInt(32773) | DataMap::SInt(32773) => Ok(TiffCompressionMode::PackBits),
         #[cfg(feature = "tiff-jpeg")]
         DataMap::UInt(7) | DataMap::SInt(7) => Ok(TiffCompressionMode::Jpeg {
-            quality: quality_from_draw_options(option),
+            quality: quality_from_options(options),
         }),
         #[cfg(not(feature = "tiff-jpeg"))]
         DataMap::UInt(7) | DataMap::SInt(7) => Err(Box::new(ImgError::new_const(
@@ -163,12 +230,314 @@ fn tiff_compression(option: &DrawEncodeOptions<'_>) -> Result<TiffCompressionMod
         ))),
         _ => Err(Box::new(ImgError::new_const(
             ImgErrorKind::InvalidParameter,
-            "TIFF compression must be `none`, `lzw`, `lzw_msb`, `lzw_lsb`, `jpeg`, 1, 5, or 7"
+            "TIFF compression must be `none`, `lzw`, `lzw_msb`, `lzw_lsb`, `deflate`, \
+             `packbits`, `ccitt_rle`, `ccitt_g3`, `ccitt_g4`, `jpeg`, 1, 2, 3, 4, 5, 7, 8, \
+             32773, or 32946"
                 .to_string(),
         ))),
     }
 }
 
+fn tiff_color_mode(
+    options: Option<&HashMap<String, DataMap>>,
+    compression: TiffCompressionMode,
+) -> Result<TiffColorMode, Error> {
+    let value = options.and_then(|map| map.get("photometric"));
+    let color = match value {
+        None if compression.is_ccitt() => TiffColorMode::Bilevel,
+        None => TiffColorMode::Rgb,
+        Some(DataMap::Ascii(value)) => match value.to_ascii_lowercase().as_str() {
+            "rgb" => TiffColorMode::Rgb,
+            "gray" | "grayscale" => TiffColorMode::Gray,
+            "palette" => TiffColorMode::Palette,
+            "bilevel" => TiffColorMode::Bilevel,
+            _ => {
+                return Err(Box::new(ImgError::new_const(
+                    ImgErrorKind::InvalidParameter,
+                    format!("unsupported TIFF photometric: {value}"),
+                )));
+            }
+        },
+        Some(_) => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "TIFF photometric must be `rgb`, `gray`, `palette`, or `bilevel`".to_string(),
+            )));
+        }
+    };
+
+    if compression.is_ccitt() && color != TiffColorMode::Bilevel {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF CCITT compression requires photometric=bilevel".to_string(),
+        )));
+    }
+    if matches!(compression, TiffCompressionMode::Jpeg { .. }) && color != TiffColorMode::Rgb {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF JPEG compression requires photometric=rgb".to_string(),
+        )));
+    }
+    Ok(color)
+}
+
+fn tiff_predictor(
+    options: Option<&HashMap<String, DataMap>>,
+    compression: TiffCompressionMode,
+    color: TiffColorMode,
+) -> Result<bool, Error> {
+    let Some(value) = options.and_then(|map| map.get("predictor")) else {
+        return Ok(false);
+    };
+
+    let predictor = match value {
+        DataMap::UInt(1) | DataMap::SInt(1) => false,
+        DataMap::UInt(2) | DataMap::SInt(2) => true,
+        DataMap::Ascii(value) => match value.to_ascii_lowercase().as_str() {
+            "none" => false,
+            "horizontal" => true,
+            _ => {
+                return Err(Box::new(ImgError::new_const(
+                    ImgErrorKind::InvalidParameter,
+                    format!("unsupported TIFF predictor: {value}"),
+                )));
+            }
+        },
+        _ => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "TIFF predictor must be `none`, `horizontal`, 1, or 2".to_string(),
+            )));
+        }
+    };
+    if predictor && !compression.supports_predictor() {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF predictor requires lzw or deflate compression".to_string(),
+        )));
+    }
+    if predictor && color == TiffColorMode::Bilevel {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF predictor is not supported for bilevel output".to_string(),
+        )));
+    }
+    Ok(predictor)
+}
+
+/// Reads the Group 3 keys `ccitt_2d`, `ccitt_k` and `ccitt_fill_bits`.
+fn tiff_ccitt_options(
+    options: Option<&HashMap<String, DataMap>>,
+    compression: TiffCompressionMode,
+) -> Result<CcittOptions, Error> {
+    let map = options;
+    let two_d = map.and_then(|map| map.get("ccitt_2d"));
+    let k = map.and_then(|map| map.get("ccitt_k"));
+    let fill_bits = map.and_then(|map| map.get("ccitt_fill_bits"));
+    let is_g3 = matches!(compression, TiffCompressionMode::CcittG3);
+    if !is_g3 && (two_d.is_some() || k.is_some() || fill_bits.is_some()) {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF ccitt_2d, ccitt_k and ccitt_fill_bits require ccitt_g3 compression".to_string(),
+        )));
+    }
+
+    let k = match k {
+        None => 2,
+        Some(DataMap::UInt(value)) if *value >= 1 => *value as usize,
+        Some(DataMap::SInt(value)) if *value >= 1 => *value as usize,
+        Some(_) => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "TIFF ccitt_k must be a positive integer".to_string(),
+            )));
+        }
+    };
+    let encoding = match compression {
+        TiffCompressionMode::CcittRle => CcittEncoding::HuffmanRle,
+        TiffCompressionMode::CcittG3 => match two_d {
+            Some(value) if bool_option(value, "TIFF ccitt_2d")? => CcittEncoding::G3TwoD { k },
+            _ => CcittEncoding::G3OneD,
+        },
+        _ => CcittEncoding::G4,
+    };
+    Ok(CcittOptions {
+        encoding,
+        byte_align: match fill_bits {
+            Some(value) => bool_option(value, "TIFF ccitt_fill_bits")?,
+            None => false,
+        },
+        // TIFF strips carry their own length, so Group 3 data ends without RTC.
+        end_marker: matches!(compression, TiffCompressionMode::CcittG4),
+        ..CcittOptions::default()
+    })
+}
+
+/// Converts a positive resolution to a RATIONAL with three decimals.
+fn resolution_value(value: f64) -> Option<Rational> {
+    if !(value > 0.0 && value < (u32::MAX / 1000) as f64) {
+        return None;
+    }
+    let (mut n, mut d) = ((value * 1000.0).round() as u32, 1000);
+    let (mut a, mut b) = (n, d);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    if a > 1 {
+        n /= a;
+        d /= a;
+    }
+    Some(Rational { n, d })
+}
+
+fn parse_resolution(value: &str) -> Option<Rational> {
+    resolution_value(value.trim().parse().ok()?)
+}
+
+/// Reads `resolution` (`300`, `204x196`) and `resolution_unit` (`inch`,
+/// `cm` or `none`).
+fn tiff_resolution(
+    options: Option<&HashMap<String, DataMap>>,
+) -> Result<Option<PageResolution>, Error> {
+    let resolution = options.and_then(|map| map.get("resolution"));
+    let unit = options.and_then(|map| map.get("resolution_unit"));
+    let invalid = || {
+        Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF resolution must be a positive number or `XxY`".to_string(),
+        )) as Error
+    };
+    let (x, y) = match resolution {
+        None if unit.is_none() => return Ok(None),
+        None => (
+            Rational {
+                n: DEFAULT_RESOLUTION,
+                d: 1,
+            },
+            Rational {
+                n: DEFAULT_RESOLUTION,
+                d: 1,
+            },
+        ),
+        Some(DataMap::UInt(value)) => {
+            let value = resolution_value(*value as f64).ok_or_else(invalid)?;
+            (value.clone(), value)
+        }
+        Some(DataMap::SInt(value)) => {
+            let value = resolution_value(*value as f64).ok_or_else(invalid)?;
+            (value.clone(), value)
+        }
+        Some(DataMap::Float(value)) => {
+            let value = resolution_value(*value).ok_or_else(invalid)?;
+            (value.clone(), value)
+        }
+        Some(DataMap::Ascii(value)) => {
+            let lower = value.to_ascii_lowercase();
+            match lower.split_once('x') {
+                Some((x, y)) => (
+                    parse_resolution(x).ok_or_else(invalid)?,
+                    parse_resolution(y).ok_or_else(invalid)?,
+                ),
+                None => {
+                    let value = parse_resolution(&lower).ok_or_else(invalid)?;
+                    (value.clone(), value)
+                }
+            }
+        }
+        Some(_) => return Err(invalid()),
+    };
+    let unit = match unit {
+        None => 2,
+        Some(DataMap::UInt(value @ 1..=3)) => *value as u16,
+        Some(DataMap::SInt(value @ 1..=3)) => *value as u16,
+        Some(DataMap::Ascii(value)) => match value.to_ascii_lowercase().as_str() {
+            "none" => 1,
+            "inch" | "dpi" => 2,
+            "cm" | "centimeter" | "dpcm" => 3,
+            _ => {
+                return Err(Box::new(ImgError::new_const(
+                    ImgErrorKind::InvalidParameter,
+                    format!("unsupported TIFF resolution_unit: {value}"),
+                )));
+            }
+        },
+        Some(_) => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "TIFF resolution_unit must be `none`, `inch`, `cm`, 1, 2, or 3".to_string(),
+            )));
+        }
+    };
+    Ok(Some(PageResolution { x, y, unit }))
+}
+
+fn tiff_description(options: Option<&HashMap<String, DataMap>>) -> Result<Option<String>, Error> {
+    match options.and_then(|map| map.get("description")) {
+        None => Ok(None),
+        Some(DataMap::Ascii(value)) if value.is_ascii() && !value.contains('\0') => {
+            Ok(Some(value.clone()))
+        }
+        Some(_) => Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF description must be an ASCII string".to_string(),
+        ))),
+    }
+}
+
+fn tiff_settings(options: Option<&HashMap<String, DataMap>>) -> Result<TiffSettings, Error> {
+    let compression = tiff_compression(options)?;
+    let color = tiff_color_mode(options, compression)?;
+    let predictor = tiff_predictor(options, compression, color)?;
+    let quantize = match color {
+        TiffColorMode::Palette | TiffColorMode::Bilevel => {
+            let mut quantize = QuantizeOptions::from_options(options)?;
+            // Fax output is thresholded unless dithering is asked for.
+            let has_dither = options.is_some_and(|map| map.contains_key("dither"));
+            if color == TiffColorMode::Bilevel && !has_dither {
+                quantize.dither = Dither::None;
+            }
+            quantize
+        }
+        _ => QuantizeOptions::default(),
+    };
+    let ccitt = tiff_ccitt_options(options, compression)?;
+    Ok(TiffSettings {
+        compression,
+        color,
+        predictor,
+        quantize,
+        ccitt,
+        resolution: tiff_resolution(options)?,
+        description: tiff_description(options)?,
+    })
+}
+
+/// Reads the `bigtiff` option. `None` means the encoder picks BigTIFF only
+/// when the output does not fit in 32-bit offsets.
+fn tiff_bigtiff(option: &DrawEncodeOptions<'_>) -> Result<Option<bool>, Error> {
+    let Some(value) = option.options.as_ref().and_then(|map| map.get("bigtiff")) else {
+        return Ok(None);
+    };
+
+    match value {
+        DataMap::UInt(value) => Ok(Some(*value != 0)),
+        DataMap::SInt(value) => Ok(Some(*value != 0)),
+        DataMap::Ascii(value) => match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(None),
+            "true" | "yes" | "on" => Ok(Some(true)),
+            "false" | "no" | "off" => Ok(Some(false)),
+            _ => Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                format!("unsupported TIFF bigtiff option: {value}"),
+            ))),
+        },
+        _ => Err(Box::new(ImgError::new_const(
+            ImgErrorKind::InvalidParameter,
+            "TIFF bigtiff must be `auto`, `true`, `false`, 0, or 1".to_string(),
+        ))),
+    }
+}
+
 fn parse_animation_info(profile: &ImageProfiles) -> Result<Option<AnimationInfo>, Error> {
     let Some(metadata) = &profile.metadata else {
         return Ok(None);
@@ -414,15 +783,114 @@ fn rgba_to_tiff_samples(rgba: &[u8], with_alpha: bool) -> Vec<u8> {
     pixel_data
 }
 
-fn first_ifd_tags(tags: &[TiffHeader]) -> &[TiffHeader] {
-    let mut split_index = tags.len();
-    for index in 1..tags.len() {
-        if tags[index].tagid < tags[index - 1].tagid {
-            split_index = index;
-            break;
+fn rgba_to_gray_samples(rgba: &[u8], with_alpha: bool) -> Vec<u8> {
+    let channels = if with_alpha { 2 } else { 1 };
+    let mut pixel_data = Vec::with_capacity(rgba.len() / 4 * channels);
+    for pixel in rgba.chunks_exact(4) {
+        let luma =
+            (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114 + 500) / 1000;
+        pixel_data.push(luma as u8);
+        if with_alpha {
+            pixel_data.push(pixel[3]);
+        }
+    }
+    pixel_data
+}
+
+/// ColorMap values: all reds, then all greens, then all blues, scaled to
+/// 16 bits.
+fn color_map(palette: &[[u8; 4]]) -> Vec<u16> {
+    let mut map = vec![0; 3 * 256];
+    for (index, color) in palette.iter().enumerate() {
+        for (channel, value) in color.iter().take(3).enumerate() {
+            map[channel * 256 + index] = *value as u16 * 257;
+        }
+    }
+    map
+}
+
+/// Packs palette indices into 1-bit WhiteIsZero rows. Index 0 is black.
+fn pack_bilevel(indices: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let row_len = width.div_ceil(8);
+    let mut data = vec![0; row_len * height];
+    for (y, row) in indices.chunks_exact(width).enumerate() {
+        for (x, index) in row.iter().enumerate() {
+            if *index == 0 {
+                data[y * row_len + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    data
+}
+
+/// Applies horizontal differencing (Predictor 2) to 8-bit samples.
+fn apply_horizontal_predictor(data: &mut [u8], row_len: usize, samples_per_pixel: usize) {
+    for row in data.chunks_mut(row_len) {
+        for index in (samples_per_pixel..row.len()).rev() {
+            row[index] = row[index].wrapping_sub(row[index - samples_per_pixel]);
+        }
+    }
+}
+
+/// Converts RGBA to the uncompressed samples of the requested photometric.
+fn page_samples(
+    width: usize,
+    height: usize,
+    rgba: &[u8],
+    settings: &TiffSettings,
+) -> Result<(PageLayout, Vec<u8>), Error> {
+    let with_alpha = settings.compression.supports_alpha() && rgba_has_alpha(rgba);
+    let extra = usize::from(with_alpha);
+    match settings.color {
+        TiffColorMode::Rgb => {
+            let is_jpeg = matches!(settings.compression, TiffCompressionMode::Jpeg { .. });
+            let layout = PageLayout {
+                photometric: if is_jpeg { 6 } else { 2 },
+                bits_per_sample: 8,
+                samples_per_pixel: 3 + extra,
+                with_alpha,
+                color_map: None,
+            };
+            Ok((layout, rgba_to_tiff_samples(rgba, with_alpha)))
+        }
+        TiffColorMode::Gray => {
+            let layout = PageLayout {
+                photometric: 1,
+                bits_per_sample: 8,
+                samples_per_pixel: 1 + extra,
+                with_alpha,
+                color_map: None,
+            };
+            Ok((layout, rgba_to_gray_samples(rgba, with_alpha)))
+        }
+        TiffColorMode::Palette => {
+            let mut options = settings.quantize.clone();
+            options.alpha = false;
+            let quantized = quantize::quantize(&opaque_rgba(rgba), width, height, &options)?;
+            let layout = PageLayout {
+                photometric: 3,
+                bits_per_sample: 8,
+                samples_per_pixel: 1,
+                with_alpha: false,
+                color_map: Some(color_map(&quantized.palette)),
+            };
+            Ok((layout, quantized.indices))
+        }
+        TiffColorMode::Bilevel => {
+            let mut options = settings.quantize.clone();
+            options.alpha = false;
+            let palette = [[0, 0, 0, 0xff], [0xff, 0xff, 0xff, 0xff]];
+            let indices = quantize::remap(&opaque_rgba(rgba), width, height, &palette, &options)?;
+            let layout = PageLayout {
+                photometric: 0,
+                bits_per_sample: 1,
+                samples_per_pixel: 1,
+                with_alpha: false,
+                color_map: None,
+            };
+            Ok((layout, pack_bilevel(&indices, width, height)))
         }
     }
-    &tags[..split_index]
 }
 
 fn source_headers(profile: &ImageProfiles) -> Option<TiffHeaders> {
@@ -436,6 +904,21 @@ fn source_headers(profile: &ImageProfiles) -> Option<TiffHeaders> {
     }
 }
 
+/// Carries georeferencing of the decoded image over into an `exif`
+/// replacement, unless the replacement has its own.
+fn keep_geotiff_tags(headers: &mut TiffHeaders, decoded: &TiffHeaders) {
+    let split = first_ifd_tags(&headers.headers).len();
+    let mut first = headers.headers[..split].to_vec();
+    for tag in first_ifd_tags(&decoded.headers) {
+        if is_geotiff_tag(tag.tagid) {
+            ensure_tag(&mut first, tag.clone());
+        }
+    }
+    first.sort_by_key(|tag| tag.tagid);
+    first.extend_from_slice(&headers.headers[split..]);
+    headers.headers = first;
+}
+
 fn exif_headers_from_bytes(bytes: &[u8]) -> Result<TiffHeaders, Error> {
     let mut reader = BytesReader::new(bytes);
     read_tags(&mut reader)
@@ -479,13 +962,17 @@ fn should_copy_source_tag(tagid: usize) -> bool {
             | 0x011c
             | 0x011e
             | 0x011f
+            | 0x0124
+            | 0x0125
             | 0x013d
             | 0x0140
             | 0x0142
             | 0x0143
             | 0x0144
             | 0x0145
+            | 0x014a
             | 0x0152
+            | 0x0153
             | 0x01b5
             | 0x0200
             | 0x0201
@@ -566,6 +1053,14 @@ fn undef_tag(tagid: usize, data: Vec<u8>) -> TiffHeader {
     }
 }
 
+fn ascii_tag(tagid: usize, value: &str) -> TiffHeader {
+    TiffHeader {
+        tagid,
+        data: DataPack::Ascii(value.to_string()),
+        length: value.len() + 1,
+    }
+}
+
 fn rational_tag(tagid: usize, numerator: u32, denominator: u32) -> TiffHeader {
     TiffHeader {
         tagid,
@@ -580,9 +1075,8 @@ fn rational_tag(tagid: usize, numerator: u32, denominator: u32) -> TiffHeader {
 fn build_page_headers(
     width: usize,
     height: usize,
-    pixel_data_len: usize,
-    with_alpha: bool,
-    compression: TiffCompressionMode,
+    layout: &PageLayout,
+    settings: &TiffSettings,
     source: Option<&TiffHeaders>,
     icc_profile: Option<&[u8]>,
 ) -> Result<TiffHeaders, Error> {
@@ -598,13 +1092,6 @@ fn build_page_headers(
             "TIFF height exceeds u32".to_string(),
         )) as Error
     })?;
-    let strip_byte_count = u32::try_from(pixel_data_len).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "TIFF strip byte count exceeds u32".to_string(),
-        )) as Error
-    })?;
-
     let mut headers = TiffHeaders::empty(Endian::LittleEndian);
     if let Some(source) = source {
         for tag in first_ifd_tags(&source.headers) {
@@ -616,19 +1103,17 @@ fn build_page_headers(
         headers.gps = source.gps.clone();
     }
 
-    let samples_per_pixel = if with_alpha { 4 } else { 3 };
+    let compression = settings.compression;
+    let samples_per_pixel = layout.samples_per_pixel;
     let is_jpeg = matches!(compression, TiffCompressionMode::Jpeg { .. });
     upsert_tag(&mut headers.headers, long_tag(0x0100, width));
     upsert_tag(&mut headers.headers, long_tag(0x0101, height));
     upsert_tag(
         &mut headers.headers,
-        short_array_tag(0x0102, vec![8; samples_per_pixel]),
+        short_array_tag(0x0102, vec![layout.bits_per_sample; samples_per_pixel]),
     );
     upsert_tag(&mut headers.headers, short_tag(0x0103, compression.code()));
-    upsert_tag(
-        &mut headers.headers,
-        short_tag(0x0106, if is_jpeg { 6 } else { 2 }),
-    );
+    upsert_tag(&mut headers.headers, short_tag(0x0106, layout.photometric));
     upsert_tag(
         &mut headers.headers,
         short_tag(0x010a, compression.fill_order()),
@@ -640,9 +1125,23 @@ fn build_page_headers(
         short_tag(0x0115, samples_per_pixel as u16),
     );
     upsert_tag(&mut headers.headers, long_tag(0x0116, height));
-    upsert_tag(&mut headers.headers, long_tag(0x0117, strip_byte_count));
+    upsert_tag(&mut headers.headers, long_tag(0x0117, 0));
     upsert_tag(&mut headers.headers, short_tag(0x011c, 1));
 
+    if let Some(resolution) = &settings.resolution {
+        upsert_tag(
+            &mut headers.headers,
+            rational_array_tag(0x011a, vec![resolution.x.clone()]),
+        );
+        upsert_tag(
+            &mut headers.headers,
+            rational_array_tag(0x011b, vec![resolution.y.clone()]),
+        );
+        upsert_tag(&mut headers.headers, short_tag(0x0128, resolution.unit));
+    }
+    if let Some(description) = &settings.description {
+        upsert_tag(&mut headers.headers, ascii_tag(0x010e, description));
+    }
     ensure_tag(
         &mut headers.headers,
         rational_tag(0x011a, DEFAULT_RESOLUTION, 1),
@@ -653,11 +1152,29 @@ fn build_page_headers(
     );
     ensure_tag(&mut headers.headers, short_tag(0x0128, 2));
 
-    if with_alpha {
+    if layout.with_alpha {
         upsert_tag(&mut headers.headers, short_array_tag(0x0152, vec![2]));
     } else {
         remove_tag(&mut headers.headers, 0x0152);
     }
+    if settings.predictor {
+        upsert_tag(&mut headers.headers, short_tag(0x013d, 2));
+    }
+    if let Some(color_map) = &layout.color_map {
+        upsert_tag(
+            &mut headers.headers,
+            short_array_tag(0x0140, color_map.clone()),
+        );
+    }
+    match compression {
+        TiffCompressionMode::CcittG3 => {
+            let two_d = matches!(settings.ccitt.encoding, CcittEncoding::G3TwoD { .. });
+            let t4_options = u32::from(two_d) | if settings.ccitt.byte_align { 4 } else { 0 };
+            upsert_tag(&mut headers.headers, long_tag(0x0124, t4_options));
+        }
+        TiffCompressionMode::CcittG4 => upsert_tag(&mut headers.headers, long_tag(0x0125, 0)),
+        _ => {}
+    }
     if let Some(profile) = icc_profile {
         upsert_tag(&mut headers.headers, undef_tag(0x8773, profile.to_vec()));
     }
@@ -705,15 +1222,33 @@ fn build_page_headers(
     Ok(headers)
 }
 
-fn set_strip_offset(headers: &mut TiffHeaders, offset: u32) -> Result<(), Error> {
-    let Some(tag) = headers.headers.iter_mut().find(|tag| tag.tagid == 0x0111) else {
-        return Err(Box::new(ImgError::new_const(
-            ImgErrorKind::EncodeError,
-            "TIFF strip offsets tag missing".to_string(),
-        )));
-    };
-    tag.length = 1;
-    tag.data = DataPack::Long(vec![offset]);
+/// Sets StripOffsets and StripByteCounts as LONG for TIFF or LONG8 for
+/// BigTIFF.
+fn set_strip_layout(
+    headers: &mut TiffHeaders,
+    offset: u64,
+    byte_count: u64,
+    is_bigtiff: bool,
+) -> Result<(), Error> {
+    for (tagid, value) in [(0x0111, offset), (0x0117, byte_count)] {
+        let Some(tag) = headers.headers.iter_mut().find(|tag| tag.tagid == tagid) else {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::EncodeError,
+                "TIFF strip tags missing".to_string(),
+            )));
+        };
+        tag.length = 1;
+        tag.data = if is_bigtiff {
+            DataPack::Long8(vec![value])
+        } else {
+            DataPack::Long(vec![u32::try_from(value).map_err(|_| {
+                Box::new(ImgError::new_const(
+                    ImgErrorKind::InvalidParameter,
+                    "TIFF data exceeds 4 GiB; BigTIFF output is required".to_string(),
+                )) as Error
+            })?])
+        };
+    }
     Ok(())
 }
 
@@ -721,7 +1256,7 @@ fn build_page_plan(
     width: usize,
     height: usize,
     rgba: &[u8],
-    compression: TiffCompressionMode,
+    settings: &TiffSettings,
     source: Option<&TiffHeaders>,
     icc_profile: Option<&[u8]>,
 ) -> Result<PagePlan, Error> {
@@ -741,11 +1276,23 @@ fn build_page_plan(
         )));
     }
 
-    let with_alpha = compression.supports_alpha() && rgba_has_alpha(rgba);
-    let raw_pixel_data = rgba_to_tiff_samples(rgba, with_alpha);
-    let pixel_data = match compression {
+    let (layout, mut raw_pixel_data) = page_samples(width, height, rgba, settings)?;
+    let row_len = (width * layout.samples_per_pixel * layout.bits_per_sample as usize).div_ceil(8);
+    if settings.predictor {
+        apply_horizontal_predictor(&mut raw_pixel_data, row_len, layout.samples_per_pixel);
+    }
+    let pixel_data = match settings.compression {
         TiffCompressionMode::None => raw_pixel_data,
         TiffCompressionMode::Lzw { is_lsb } => encode_tiff(&raw_pixel_data, is_lsb)?,
+        TiffCompressionMode::Deflate => {
+            miniz_oxide::deflate::compress_to_vec_zlib(&raw_pixel_data, 8)
+        }
+        TiffCompressionMode::PackBits => packbits::encode(&raw_pixel_data, row_len),
+        TiffCompressionMode::CcittRle
+        | TiffCompressionMode::CcittG3
+        | TiffCompressionMode::CcittG4 => {
+            ccitt::encode(&raw_pixel_data, width, height, &settings.ccitt)?
+        }
         #[cfg(feature = "tiff-jpeg")]
         TiffCompressionMode::Jpeg { quality } => encode_jpeg_rgba(width, height, rgba, quality)?,
         #[cfg(not(feature = "tiff-jpeg"))]
@@ -756,24 +1303,28 @@ fn build_page_plan(
             )));
         }
     };
-    let headers = build_page_headers(
-        width,
-        height,
-        pixel_data.len(),
-        with_alpha,
-        compression,
-        source,
-        icc_profile,
-    )?;
+    let headers = build_page_headers(width, height, &layout, settings, source, icc_profile)?;
     Ok(PagePlan {
         headers,
         pixel_data,
     })
 }
 
+/// Returns the encoder options with the page options laid over them.
+fn page_options(
+    options: Option<&HashMap<String, DataMap>>,
+    page: Option<&HashMap<String, DataMap>>,
+) -> HashMap<String, DataMap> {
+    let mut merged = options.cloned().unwrap_or_default();
+    if let Some(page) = page {
+        merged.extend(page.iter().map(|(key, value)| (key.clone(), value.clone())));
+    }
+    merged
+}
+
 fn build_animation_pages(
     profile: &ImageProfiles,
-    compression: TiffCompressionMode,
+    settings: &TiffSettings,
     source: Option<&TiffHeaders>,
     icc_profile: Option<&[u8]>,
     animation: AnimationInfo,
@@ -785,7 +1336,7 @@ fn build_animation_pages(
             profile.width,
             profile.height,
             canvas,
-            compression,
+            settings,
             if index == 0 { source } else { None },
             if index == 0 { icc_profile } else { None },
         )?);
@@ -795,15 +1346,42 @@ fn build_animation_pages(
 
 /// Encodes an image source to TIFF.
 ///
-/// Still images are written as a single TIFF page using uncompressed, LZW, or
-/// JPEG compression depending on `compression`. Animated input is flattened
-/// into full-canvas multi-page TIFF output so it can be round-tripped through
-/// [`crate::draw::convert`]. JPEG-compressed TIFF pages reuse the JPEG encoder
-/// and therefore store RGB only.
+/// Still images are written as a single TIFF page using the compression named
+/// by `compression`. Animated input is flattened into full-canvas multi-page
+/// TIFF output so it can be round-tripped through [`crate::draw::convert`].
+/// JPEG-compressed TIFF pages reuse the JPEG encoder and therefore store RGB
+/// only. CCITT compression writes 1-bit WhiteIsZero pages.
+///
+/// Sources that hand out pages through
+/// [`crate::draw::PickCallback::encode_next_page`], such as
+/// [`crate::draw::PageSource`], are written one page per IFD. Each page keeps
+/// its own size, and its [`crate::draw::EncodePage::options`] override the
+/// keys below except `bigtiff` and `exif`. Multi-page output is tagged with
+/// NewSubfileType 2 and, up to 65535 pages, PageNumber.
+///
+/// Tags of the decoded `"Tiff headers"` metadata are copied to the first page,
+/// so GeoTIFF georeferencing (GeoKey directory, tie points, pixel scale,
+/// transformation, GDAL nodata and metadata) survives a conversion. When the
+/// `exif` option replaces those tags, the decoded GeoTIFF tags are still kept
+/// unless the replacement carries its own.
 ///
 /// Supported `EncodeOptions.options` keys:
-/// - `compression`: `none`, `lzw`, `lzw_msb`, `lzw_lsb`, or `jpeg`
+/// - `compression`: `none`, `lzw`, `lzw_msb`, `lzw_lsb`, `deflate`,
+///   `packbits`, `ccitt_rle`, `ccitt_g3`, `ccitt_g4`, or `jpeg`
+/// - `photometric`: `rgb` (default), `gray`, `palette`, or `bilevel`.
+///   Palette and bilevel output use the quantizer keys of
+///   [`QuantizeOptions::from_options`]; bilevel output is not dithered unless
+///   `dither` is given
+/// - `predictor`: `none` (default) or `horizontal`, for LZW and Deflate
+/// - `ccitt_2d`, `ccitt_k`, `ccitt_fill_bits`: T.4 two-dimensional coding
+///   with a 1D row every `ccitt_k` rows (default 2) and byte-aligned EOLs,
+///   for `compression=ccitt_g3`
 /// - `quality`: JPEG quality when `compression=jpeg`
+/// - `resolution`: DPI as a number or `XxY` (for example `204x196`), and
+///   `resolution_unit`: `inch` (default), `cm`, or `none`
+/// - `description`: ImageDescription text
+/// - `bigtiff`: `auto` (default), `true`, or `false`. `auto` writes BigTIFF
+///   only when the file would exceed 4 GiB
 /// - `exif`: `Raw(bytes)`, `Exif(headers)`, or `Ascii("copy")`
 pub fn encode(image: &mut DrawEncodeOptions<'_>) -> Result<Vec<u8>, Error> {
     let profile = image.drawer.encode_start(None)?;
@@ -813,69 +1391,101 @@ pub fn encode(image: &mut DrawEncodeOptions<'_>) -> Result<Vec<u8>, Error> {
             "Image profiles nothing".to_string(),
         )) as Error
     })?;
-    let compression = tiff_compression(image)?;
 
     let source =
         if let Some(exif) = get_exif_option(image.options.as_ref(), profile.metadata.as_ref())? {
-            Some(exif_headers_from_bytes(&exif)?)
+            let mut headers = exif_headers_from_bytes(&exif)?;
+            if let Some(decoded) = source_headers(&profile) {
+                keep_geotiff_tags(&mut headers, &decoded);
+            }
+            Some(headers)
         } else {
             source_headers(&profile)
         };
     let icc_profile = source_icc_profile(&profile, source.as_ref());
 
-    let mut pages = if let Some(animation) = parse_animation_info(&profile)? {
-        build_animation_pages(
-            &profile,
-            compression,
-            source.as_ref(),
-            icc_profile.as_deref(),
-            animation,
-        )?
-    } else {
-        let rgba = image
-            .drawer
-            .encode_pick(0, 0, profile.width, profile.height, None)?
-            .ok_or_else(|| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::EncodeError,
-                    "Image buffer nothing".to_string(),
-                )) as Error
-            })?;
-        vec![build_page_plan(
-            profile.width,
-            profile.height,
-            &rgba,
-            compression,
-            source.as_ref(),
-            icc_profile.as_deref(),
-        )?]
-    };
+    let mut pages = Vec::new();
+    while let Some(page) = image.drawer.encode_next_page()? {
+        let options = page_options(image.options.as_ref(), page.options.as_ref());
+        let settings = tiff_settings(Some(&options))?;
+        let first = pages.is_empty();
+        pages.push(build_page_plan(
+            page.width,
+            page.height,
+            &page.buffer,
+            &settings,
+            if first { source.as_ref() } else { None },
+            if first { icc_profile.as_deref() } else { None },
+        )?);
+    }
+    if pages.len() > 1 {
+        // PageNumber is a SHORT pair, so it is left out past 65535 pages.
+        let count = u16::try_from(pages.len()).ok();
+        for (index, page) in pages.iter_mut().enumerate() {
+            upsert_tag(&mut page.headers.headers, long_tag(0x00fe, 2));
+            if let Some(count) = count {
+                upsert_tag(
+                    &mut page.headers.headers,
+                    short_array_tag(0x0129, vec![index as u16, count]),
+                );
+            }
+        }
+    }
+
+    if pages.is_empty() {
+        let settings = tiff_settings(image.options.as_ref())?;
+        pages = if let Some(animation) = parse_animation_info(&profile)? {
+            build_animation_pages(
+                &profile,
+                &settings,
+                source.as_ref(),
+                icc_profile.as_deref(),
+                animation,
+            )?
+        } else {
+            let rgba = image
+                .drawer
+                .encode_pick(0, 0, profile.width, profile.height, None)?
+                .ok_or_else(|| {
+                    Box::new(ImgError::new_const(
+                        ImgErrorKind::EncodeError,
+                        "Image buffer nothing".to_string(),
+                    )) as Error
+                })?;
+            vec![build_page_plan(
+                profile.width,
+                profile.height,
+                &rgba,
+                &settings,
+                source.as_ref(),
+                icc_profile.as_deref(),
+            )?]
+        };
+    }
 
     let provisional_headers: Vec<TiffHeaders> =
         pages.iter().map(|page| page.headers.clone()).collect();
-    let provisional_len = tiff_pages_to_bytes(&provisional_headers)?.len();
-    let mut strip_offset = u32::try_from(provisional_len).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "TIFF data offset exceeds u32".to_string(),
-        )) as Error
-    })?;
+    let pixel_data_len: u64 = pages.iter().map(|page| page.pixel_data.len() as u64).sum();
+    let is_bigtiff = match tiff_bigtiff(image)? {
+        Some(is_bigtiff) => is_bigtiff,
+        None => {
+            let classic_len = tiff_pages_to_bytes(&provisional_headers)?.len() as u64;
+            classic_len + pixel_data_len > u32::MAX as u64
+        }
+    };
+    if is_bigtiff {
+        for page in &mut pages {
+            page.headers.version = BIGTIFF_VERSION;
+        }
+    }
 
+    let provisional_headers: Vec<TiffHeaders> =
+        pages.iter().map(|page| page.headers.clone()).collect();
+    let mut strip_offset = tiff_pages_to_bytes(&provisional_headers)?.len() as u64;
     for page in &mut pages {
-        set_strip_offset(&mut page.headers, strip_offset)?;
-        strip_offset = strip_offset
-            .checked_add(u32::try_from(page.pixel_data.len()).map_err(|_| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::InvalidParameter,
-                    "TIFF page data exceeds u32".to_string(),
-                )) as Error
-            })?)
-            .ok_or_else(|| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::InvalidParameter,
-                    "TIFF data offset overflow".to_string(),
-                )) as Error
-            })?;
+        let byte_count = page.pixel_data.len() as u64;
+        set_strip_layout(&mut page.headers, strip_offset, byte_count, is_bigtiff)?;
+        strip_offset += byte_count;
     }
 
     let headers: Vec<TiffHeaders> = pages.iter().map(|page| page.headers.clone()).collect();
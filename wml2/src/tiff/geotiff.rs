@@ -0,0 +1,525 @@
+//! GeoTIFF georeferencing tags.
+//!
+//! Parses the GeoKey directory, model tie points, pixel scale,
+//! transformation and the GDAL nodata/metadata tags of an IFD into
+//! [`GeoTiff`]. The decoder stores [`GeoTiff::to_json`] as the `"GeoTIFF"`
+//! metadata entry; the raw tags stay in `"Tiff headers"` and are written
+//! back by the encoder.
+
+use crate::tiff::header::{DataPack, TiffHeader};
+
+/// Metadata key of the JSON view written by the TIFF decoder.
+pub const GEOTIFF_METADATA_KEY: &str = "GeoTIFF";
+
+/// 0x830e ModelPixelScaleTag
+pub const MODEL_PIXEL_SCALE: usize = 0x830e;
+/// 0x8482 ModelTiepointTag
+pub const MODEL_TIEPOINT: usize = 0x8482;
+/// 0x85d8 ModelTransformationTag
+pub const MODEL_TRANSFORMATION: usize = 0x85d8;
+/// 0x87af GeoKeyDirectoryTag
+pub const GEO_KEY_DIRECTORY: usize = 0x87af;
+/// 0x87b0 GeoDoubleParamsTag
+pub const GEO_DOUBLE_PARAMS: usize = 0x87b0;
+/// 0x87b1 GeoAsciiParamsTag
+pub const GEO_ASCII_PARAMS: usize = 0x87b1;
+/// 0xa480 GDAL_METADATA
+pub const GDAL_METADATA: usize = 0xa480;
+/// 0xa481 GDAL_NODATA
+pub const GDAL_NODATA: usize = 0xa481;
+
+/// Returns true for tags that carry georeferencing.
+pub fn is_geotiff_tag(tagid: usize) -> bool {
+    matches!(
+        tagid,
+        MODEL_PIXEL_SCALE
+            | MODEL_TIEPOINT
+            | MODEL_TRANSFORMATION
+            | GEO_KEY_DIRECTORY
+            | GEO_DOUBLE_PARAMS
+            | GEO_ASCII_PARAMS
+            | GDAL_METADATA
+            | GDAL_NODATA
+    )
+}
+
+/// A GeoKey value, resolved from the directory or the params tags.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoKeyValue {
+    Short(Vec<u16>),
+    Double(Vec<f64>),
+    Ascii(String),
+}
+
+/// One entry of the GeoKey directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoKey {
+    pub id: u16,
+    pub value: GeoKeyValue,
+}
+
+impl GeoKey {
+    pub fn name(&self) -> &'static str {
+        geo_key_name(self.id)
+    }
+
+    fn short(&self) -> Option<u16> {
+        match &self.value {
+            GeoKeyValue::Short(values) => values.first().copied(),
+            _ => None,
+        }
+    }
+}
+
+/// Maps raster space (I, J, K) onto model space (X, Y, Z).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TiePoint {
+    pub raster: [f64; 3],
+    pub model: [f64; 3],
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GeoTiff {
+    /// KeyDirectoryVersion, KeyRevision and MinorRevision
+    pub version: Option<[u16; 3]>,
+    pub keys: Vec<GeoKey>,
+    /// 0x830e ScaleX, ScaleY, ScaleZ
+    pub pixel_scale: Option<[f64; 3]>,
+    /// 0x8482
+    pub tie_points: Vec<TiePoint>,
+    /// 0x85d8 row-major 4x4 matrix
+    pub transformation: Option<[f64; 16]>,
+    /// 0xa481 as written, usually a decimal number
+    pub nodata: Option<String>,
+    /// 0xa480 GDAL XML metadata
+    pub gdal_metadata: Option<String>,
+}
+
+impl GeoTiff {
+    /// Parses the georeferencing tags of one IFD. Returns `None` when the
+    /// IFD holds none of them.
+    pub fn from_headers(headers: &[TiffHeader]) -> Option<Self> {
+        let find = |tagid: usize| {
+            headers
+                .iter()
+                .find(|tag| tag.tagid == tagid)
+                .map(|tag| &tag.data)
+        };
+        if !headers.iter().any(|tag| is_geotiff_tag(tag.tagid)) {
+            return None;
+        }
+
+        let mut geo = GeoTiff::default();
+        if let Some(directory) = find(GEO_KEY_DIRECTORY).and_then(shorts) {
+            let doubles = find(GEO_DOUBLE_PARAMS)
+                .and_then(doubles)
+                .unwrap_or_default();
+            let ascii = match find(GEO_ASCII_PARAMS) {
+                Some(DataPack::Ascii(ascii)) => ascii.as_str(),
+                _ => "",
+            };
+            geo.parse_directory(&directory, &doubles, ascii);
+        }
+        if let Some(scale) = find(MODEL_PIXEL_SCALE).and_then(doubles)
+            && scale.len() >= 3
+        {
+            geo.pixel_scale = Some([scale[0], scale[1], scale[2]]);
+        }
+        if let Some(points) = find(MODEL_TIEPOINT).and_then(doubles) {
+            geo.tie_points = points
+                .chunks_exact(6)
+                .map(|point| TiePoint {
+                    raster: [point[0], point[1], point[2]],
+                    model: [point[3], point[4], point[5]],
+                })
+                .collect();
+        }
+        if let Some(matrix) = find(MODEL_TRANSFORMATION).and_then(doubles)
+            && let Ok(matrix) = <[f64; 16]>::try_from(&matrix[..matrix.len().min(16)])
+        {
+            geo.transformation = Some(matrix);
+        }
+        if let Some(DataPack::Ascii(nodata)) = find(GDAL_NODATA) {
+            geo.nodata = Some(nodata.trim().to_string());
+        }
+        if let Some(DataPack::Ascii(metadata)) = find(GDAL_METADATA) {
+            geo.gdal_metadata = Some(metadata.clone());
+        }
+        Some(geo)
+    }
+
+    fn parse_directory(&mut self, directory: &[u16], doubles: &[f64], ascii: &str) {
+        if directory.len() < 4 {
+            return;
+        }
+        self.version = Some([directory[0], directory[1], directory[2]]);
+        let count = directory[3] as usize;
+        for entry in directory[4..].chunks_exact(4).take(count) {
+            let (id, location, count, offset) = (
+                entry[0],
+                entry[1] as usize,
+                entry[2] as usize,
+                entry[3] as usize,
+            );
+            let value = match location {
+                0 => GeoKeyValue::Short(vec![entry[3]]),
+                GEO_KEY_DIRECTORY => match directory.get(offset..offset + count) {
+                    Some(values) => GeoKeyValue::Short(values.to_vec()),
+                    None => continue,
+                },
+                GEO_DOUBLE_PARAMS => match doubles.get(offset..offset + count) {
+                    Some(values) => GeoKeyValue::Double(values.to_vec()),
+                    None => continue,
+                },
+                GEO_ASCII_PARAMS => match ascii.get(offset..offset + count) {
+                    // Each string ends with '|' in place of NUL.
+                    Some(text) => GeoKeyValue::Ascii(text.trim_end_matches('|').to_string()),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            self.keys.push(GeoKey { id, value });
+        }
+    }
+
+    pub fn key(&self, id: u16) -> Option<&GeoKey> {
+        self.keys.iter().find(|key| key.id == id)
+    }
+
+    /// 1024 GTModelTypeGeoKey: 1 projected, 2 geographic, 3 geocentric
+    pub fn model_type(&self) -> Option<u16> {
+        self.key(1024)?.short()
+    }
+
+    /// 1025 GTRasterTypeGeoKey: 1 PixelIsArea, 2 PixelIsPoint
+    pub fn raster_type(&self) -> Option<u16> {
+        self.key(1025)?.short()
+    }
+
+    /// 2048 GeographicTypeGeoKey, an EPSG code or 32767 for user-defined
+    pub fn geographic_crs(&self) -> Option<u16> {
+        self.key(2048)?.short()
+    }
+
+    /// 3072 ProjectedCSTypeGeoKey, an EPSG code or 32767 for user-defined
+    pub fn projected_crs(&self) -> Option<u16> {
+        self.key(3072)?.short()
+    }
+
+    /// 4096 VerticalCSTypeGeoKey
+    pub fn vertical_crs(&self) -> Option<u16> {
+        self.key(4096)?.short()
+    }
+
+    /// 1026 GTCitationGeoKey
+    pub fn citation(&self) -> Option<&str> {
+        match &self.key(1026)?.value {
+            GeoKeyValue::Ascii(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The EPSG code of the horizontal CRS, projected before geographic.
+    pub fn epsg(&self) -> Option<u16> {
+        [self.projected_crs(), self.geographic_crs()]
+            .into_iter()
+            .flatten()
+            .find(|code| *code != 0 && *code != USER_DEFINED)
+    }
+
+    pub fn nodata_value(&self) -> Option<f64> {
+        self.nodata.as_deref()?.parse().ok()
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        let mut fields = Vec::new();
+        if let Some([version, revision, minor]) = self.version {
+            fields.push(format!("\"version\":[{},{},{}]", version, revision, minor));
+        }
+        if let Some(model_type) = self.model_type() {
+            fields.push(format!("\"model_type\":{}", model_type));
+            fields.push(format!(
+                "\"model_type_name\":\"{}\"",
+                model_type_name(model_type)
+            ));
+        }
+        if let Some(raster_type) = self.raster_type() {
+            fields.push(format!("\"raster_type\":{}", raster_type));
+            fields.push(format!(
+                "\"raster_type_name\":\"{}\"",
+                raster_type_name(raster_type)
+            ));
+        }
+        if let Some(epsg) = self.epsg() {
+            fields.push(format!("\"epsg\":{}", epsg));
+        }
+        for (name, code) in [
+            ("projected_crs", self.projected_crs()),
+            ("geographic_crs", self.geographic_crs()),
+            ("vertical_crs", self.vertical_crs()),
+        ] {
+            if let Some(code) = code {
+                fields.push(format!("\"{}\":{}", name, code));
+            }
+        }
+        if let Some(citation) = self.citation() {
+            fields.push(format!("\"citation\":\"{}\"", json_escape(citation)));
+        }
+        if let Some(scale) = &self.pixel_scale {
+            fields.push(format!("\"pixel_scale\":{}", json_numbers(scale)));
+        }
+        if !self.tie_points.is_empty() {
+            let points: Vec<String> = self
+                .tie_points
+                .iter()
+                .map(|point| {
+                    format!(
+                        "{{\"raster\":{},\"model\":{}}}",
+                        json_numbers(&point.raster),
+                        json_numbers(&point.model)
+                    )
+                })
+                .collect();
+            fields.push(format!("\"tie_points\":[{}]", points.join(",")));
+        }
+        if let Some(matrix) = &self.transformation {
+            fields.push(format!("\"transformation\":{}", json_numbers(matrix)));
+        }
+        if let Some(nodata) = &self.nodata {
+            fields.push(format!("\"nodata\":\"{}\"", json_escape(nodata)));
+        }
+        if let Some(metadata) = &self.gdal_metadata {
+            fields.push(format!("\"gdal_metadata\":\"{}\"", json_escape(metadata)));
+        }
+        let keys: Vec<String> = self
+            .keys
+            .iter()
+            .map(|key| {
+                let value = match &key.value {
+                    GeoKeyValue::Short(values) if values.len() == 1 => values[0].to_string(),
+                    GeoKeyValue::Short(values) => {
+                        let values: Vec<String> = values.iter().map(u16::to_string).collect();
+                        format!("[{}]", values.join(","))
+                    }
+                    GeoKeyValue::Double(values) if values.len() == 1 => json_number(values[0]),
+                    GeoKeyValue::Double(values) => json_numbers(values),
+                    GeoKeyValue::Ascii(text) => format!("\"{}\"", json_escape(text)),
+                };
+                format!(
+                    "{{\"id\":{},\"name\":\"{}\",\"value\":{}}}",
+                    key.id,
+                    key.name(),
+                    value
+                )
+            })
+            .collect();
+        fields.push(format!("\"keys\":[{}]", keys.join(",")));
+        json.push_str(&fields.join(","));
+        json.push('}');
+        json
+    }
+}
+
+const USER_DEFINED: u16 = 32767;
+
+fn shorts(data: &DataPack) -> Option<Vec<u16>> {
+    match data {
+        DataPack::Short(values) => Some(values.clone()),
+        DataPack::Long(values) => Some(values.iter().map(|value| *value as u16).collect()),
+        _ => None,
+    }
+}
+
+fn doubles(data: &DataPack) -> Option<Vec<f64>> {
+    match data {
+        DataPack::Double(values) => Some(values.clone()),
+        DataPack::Float(values) => Some(values.iter().map(|value| *value as f64).collect()),
+        DataPack::Rational(values) => Some(
+            values
+                .iter()
+                .map(|value| value.n as f64 / value.d as f64)
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{}", value)
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_numbers(values: &[f64]) -> String {
+    let values: Vec<String> = values.iter().map(|value| json_number(*value)).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if ch < ' ' => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+pub fn model_type_name(model_type: u16) -> &'static str {
+    match model_type {
+        1 => "Projected",
+        2 => "Geographic",
+        3 => "Geocentric",
+        USER_DEFINED => "UserDefined",
+        _ => "Unknown",
+    }
+}
+
+pub fn raster_type_name(raster_type: u16) -> &'static str {
+    match raster_type {
+        1 => "PixelIsArea",
+        2 => "PixelIsPoint",
+        USER_DEFINED => "UserDefined",
+        _ => "Unknown",
+    }
+}
+
+pub fn geo_key_name(id: u16) -> &'static str {
+    match id {
+        1024 => "GTModelTypeGeoKey",
+        1025 => "GTRasterTypeGeoKey",
+        1026 => "GTCitationGeoKey",
+        2048 => "GeographicTypeGeoKey",
+        2049 => "GeogCitationGeoKey",
+        2050 => "GeogGeodeticDatumGeoKey",
+        2051 => "GeogPrimeMeridianGeoKey",
+        2052 => "GeogLinearUnitsGeoKey",
+        2053 => "GeogLinearUnitSizeGeoKey",
+        2054 => "GeogAngularUnitsGeoKey",
+        2055 => "GeogAngularUnitSizeGeoKey",
+        2056 => "GeogEllipsoidGeoKey",
+        2057 => "GeogSemiMajorAxisGeoKey",
+        2058 => "GeogSemiMinorAxisGeoKey",
+        2059 => "GeogInvFlatteningGeoKey",
+        2060 => "GeogAzimuthUnitsGeoKey",
+        2061 => "GeogPrimeMeridianLongGeoKey",
+        3072 => "ProjectedCSTypeGeoKey",
+        3073 => "PCSCitationGeoKey",
+        3074 => "ProjectionGeoKey",
+        3075 => "ProjCoordTransGeoKey",
+        3076 => "ProjLinearUnitsGeoKey",
+        3077 => "ProjLinearUnitSizeGeoKey",
+        3078 => "ProjStdParallel1GeoKey",
+        3079 => "ProjStdParallel2GeoKey",
+        3080 => "ProjNatOriginLongGeoKey",
+        3081 => "ProjNatOriginLatGeoKey",
+        3082 => "ProjFalseEastingGeoKey",
+        3083 => "ProjFalseNorthingGeoKey",
+        3084 => "ProjFalseOriginLongGeoKey",
+        3085 => "ProjFalseOriginLatGeoKey",
+        3086 => "ProjFalseOriginEastingGeoKey",
+        3087 => "ProjFalseOriginNorthingGeoKey",
+        3088 => "ProjCenterLongGeoKey",
+        3089 => "ProjCenterLatGeoKey",
+        3090 => "ProjCenterEastingGeoKey",
+        3091 => "ProjCenterNorthingGeoKey",
+        3092 => "ProjScaleAtNatOriginGeoKey",
+        3093 => "ProjScaleAtCenterGeoKey",
+        3094 => "ProjAzimuthAngleGeoKey",
+        3095 => "ProjStraightVertPoleLongGeoKey",
+        4096 => "VerticalCSTypeGeoKey",
+        4097 => "VerticalCitationGeoKey",
+        4098 => "VerticalDatumGeoKey",
+        4099 => "VerticalUnitsGeoKey",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(tagid: usize, data: DataPack) -> TiffHeader {
+        TiffHeader {
+            tagid,
+            data,
+            length: 0,
+        }
+    }
+
+    #[test]
+    fn parses_keys_from_every_location() {
+        let headers = vec![
+            tag(MODEL_PIXEL_SCALE, DataPack::Double(vec![0.5, 0.25, 0.0])),
+            tag(
+                MODEL_TIEPOINT,
+                DataPack::Double(vec![0.0, 0.0, 0.0, 500000.0, 4000000.0, 0.0]),
+            ),
+            tag(
+                GEO_KEY_DIRECTORY,
+                DataPack::Short(vec![
+                    1,
+                    1,
+                    0,
+                    5, //
+                    1024,
+                    0,
+                    1,
+                    1, //
+                    1025,
+                    0,
+                    1,
+                    1, //
+                    1026,
+                    GEO_ASCII_PARAMS as u16,
+                    10,
+                    0, //
+                    3072,
+                    0,
+                    1,
+                    32654, //
+                    3078,
+                    GEO_DOUBLE_PARAMS as u16,
+                    1,
+                    1, //
+                ]),
+            ),
+            tag(GEO_DOUBLE_PARAMS, DataPack::Double(vec![1.0, 35.5])),
+            tag(GEO_ASCII_PARAMS, DataPack::Ascii("UTM 54N \"|".to_string())),
+            tag(GDAL_NODATA, DataPack::Ascii("-9999 ".to_string())),
+        ];
+        let geo = GeoTiff::from_headers(&headers).unwrap();
+        assert_eq!(geo.version, Some([1, 1, 0]));
+        assert_eq!(geo.model_type(), Some(1));
+        assert_eq!(geo.projected_crs(), Some(32654));
+        assert_eq!(geo.epsg(), Some(32654));
+        assert_eq!(geo.citation(), Some("UTM 54N \""));
+        assert_eq!(
+            geo.key(3078).unwrap().value,
+            GeoKeyValue::Double(vec![35.5])
+        );
+        assert_eq!(geo.pixel_scale, Some([0.5, 0.25, 0.0]));
+        assert_eq!(geo.tie_points[0].model, [500000.0, 4000000.0, 0.0]);
+        assert_eq!(geo.nodata_value(), Some(-9999.0));
+
+        let json = geo.to_json();
+        assert!(json.contains("\"epsg\":32654"));
+        assert!(json.contains("\"citation\":\"UTM 54N \\\"\""));
+        assert!(json.contains("{\"id\":3078,\"name\":\"ProjStdParallel1GeoKey\",\"value\":35.5}"));
+    }
+
+    #[test]
+    fn ignores_ifds_without_geotiff_tags() {
+        let headers = vec![tag(0x0100, DataPack::Long(vec![1]))];
+        assert!(GeoTiff::from_headers(&headers).is_none());
+    }
+}
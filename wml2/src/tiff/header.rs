@@ -16,6 +16,7 @@ use crate::tiff::util::print_tags;
 use bin_rs::Endian;
 use bin_rs::io::*;
 use bin_rs::reader::BinaryReader;
+use std::collections::HashSet;
 use std::io::SeekFrom;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -87,6 +88,10 @@ pub enum DataPack {
     Double(Vec<f64>),
     SShort(Vec<i16>),
     SLong(Vec<i32>),
+    /// BigTIFF LONG8 and IFD8
+    Long8(Vec<u64>),
+    /// BigTIFF SLONG8
+    SLong8(Vec<i64>),
     Unkown(Vec<u8>),
     Undef(Vec<u8>),
 }
@@ -125,6 +130,12 @@ impl DataPack {
             DataPack::SLong(d) => {
                 format!("{:?}", d)
             }
+            DataPack::Long8(d) => {
+                format!("{:?}", d)
+            }
+            DataPack::SLong8(d) => {
+                format!("{:?}", d)
+            }
             DataPack::Unkown(d) => {
                 format!("{:?}", d)
             }
@@ -152,9 +163,15 @@ pub struct TiffHeader {
     pub length: usize,
 }
 
+/// Classic TIFF version number with 32-bit offsets.
+pub const TIFF_VERSION: u16 = 42;
+/// BigTIFF version number with 64-bit offsets and counts.
+pub const BIGTIFF_VERSION: u16 = 43;
+
 #[allow(unused)]
 #[derive(Debug, Clone, PartialEq)]
 pub struct TiffHeaders {
+    /// [`TIFF_VERSION`] or [`BIGTIFF_VERSION`]
     pub version: u16,
     pub headers: Vec<TiffHeader>,
     pub exif: Option<Vec<TiffHeader>>,
@@ -169,7 +186,7 @@ impl TiffHeaders {
 
     pub fn empty(endian: Endian) -> Self {
         Self {
-            version: 42,
+            version: TIFF_VERSION,
             headers: Vec::new(),
             exif: None,
             gps: None,
@@ -183,6 +200,7 @@ enum IfdMode {
     BaseTiff,
     Exif,
     Gps,
+    SubIfd,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -246,13 +264,38 @@ impl Compression {
     }
 }
 
+/// Reads StripOffsets/StripByteCounts/TileOffsets/TileByteCounts values,
+/// which may be SHORT, LONG or BigTIFF LONG8.
+fn offset_values(data: &DataPack) -> Vec<u64> {
+    match data {
+        DataPack::Short(d) => d.iter().map(|value| *value as u64).collect(),
+        DataPack::Long(d) => d.iter().map(|value| *value as u64).collect(),
+        DataPack::Long8(d) => d.clone(),
+        _ => vec![],
+    }
+}
+
+fn real_values(data: &DataPack) -> Vec<f64> {
+    match data {
+        DataPack::Short(d) => d.iter().map(|value| *value as f64).collect(),
+        DataPack::Long(d) => d.iter().map(|value| *value as f64).collect(),
+        DataPack::Rational(d) => d.iter().map(Rational::as_f64).collect(),
+        DataPack::SRational(d) => d.iter().map(SRational::as_f64).collect(),
+        DataPack::Float(d) => d.iter().map(|value| *value as f64).collect(),
+        DataPack::Double(d) => d.clone(),
+        _ => vec![],
+    }
+}
+
 /// This struct is not use embed tiff tag,also EXIF.
 /// Use only tiff encoder/decoder
 #[derive(Debug, Clone)]
 pub struct Tiff {
     /// 0x00fe  also 0
+    /// bit 0 = reduced-resolution copy, bit 1 = page of a multi-page image,
+    /// bit 2 = transparency mask
     pub newsubfiletype: u32,
-    /// 0x00ff  also 1, but 2,3 is multi part image,This decoder is not support.
+    /// 0x00ff  also 1, 2 = reduced-resolution copy, 3 = single page
     pub subfiletype: u32,
     /// These tag must need decode image.
     /// 0x0100
@@ -269,9 +312,7 @@ pub struct Tiff {
     /// 1 = black is zero black based grayscale
     /// 2 = RGB888
     /// 3 = Palette color
-    /// Transparency Mask is not support
-    ///
-    /// 4 = Transparecy Mask
+    /// 4 = Transparecy Mask, applied as alpha of the image it belongs to
     ///
     /// Extention use - This color space need color management modules.
     /// 5 = alos CMYK
@@ -292,7 +333,7 @@ pub struct Tiff {
     /// 1 = msb ,2 = lsb. usualy msb. lsb is using FAX G3/G4 compressions.
     pub fill_order: u16,
     /// Image data start offsets. strip_offsets length need equal strip_byte_counts length
-    pub strip_offsets: Vec<u32>,
+    pub strip_offsets: Vec<u64>,
     /// 0x0112 also 1  0    this parameter is not use.
     /// 1 = TOPLEFT (LEFT,TOP) Image end (RIGHT,BOTTOM)
     /// 2 = TOPRIGHT right-left reverce Image
@@ -312,7 +353,7 @@ pub struct Tiff {
     /// 0x0116 also width * Bitspersample /8
     pub rows_per_strip: u32,
     /// 0x0117 For each strip(width), the number of bytes in the strip after compression.       
-    pub strip_byte_counts: Vec<u32>,
+    pub strip_byte_counts: Vec<u64>,
     /// 0x0118 also this decoder does not use.
     pub min_sample_values: Vec<u16>,
     /// 0x0119 also this decoder does not use. default 2**(BitsPerSample) - 1
@@ -363,19 +404,44 @@ pub struct Tiff {
     pub starty: u32,             // 0x011F
     pub predictor: u16,          // 0x013D
     pub extra_samples: Vec<u16>, // 0x0152
+    /// 0x0153 SampleFormat 1 = unsigned integer, 2 = signed integer,
+    /// 3 = IEEE floating point
+    pub sample_format: u16,
+    /// 0x014C InkSet 1 = CMYK, 2 = not CMYK
+    pub ink_set: u16,
+    /// 0x013E WhitePoint chromaticity (x, y) of CIELab images
+    pub white_point: Option<(f64, f64)>,
+    /// 0x01B1 Decode, (min, max) pairs of ITULab samples
+    pub decode: Vec<f64>,
+    /// 0x0211 YCbCrCoefficients LumaRed, LumaGreen, LumaBlue
+    pub ycbcr_coefficients: [f64; 3],
+    /// 0x0212 YCbCrSubSampling horizontal, vertical
+    pub ycbcr_subsampling: [u16; 2],
+    /// 0x0213 YCbCrPositioning 1 = centered, 2 = co-sited
+    pub ycbcr_positioning: u16,
+    /// 0x0214 ReferenceBlackWhite, black and white pairs of each component
+    pub reference_black_white: Vec<f64>,
 
     /// TileWidth/TileLength/TileOffsets/TileByteCOunts are using tiled image
     /// TIFF 6.0 Section 15
     pub tile_width: u32, // 0x0142
     pub tile_length: u32,           // 0x0143
-    pub tile_offsets: Vec<u32>,     // 0x0144
-    pub tile_byte_counts: Vec<u32>, // 0x0145
+    pub tile_offsets: Vec<u64>,     // 0x0144
+    pub tile_byte_counts: Vec<u64>, // 0x0145
 
     // compression option
     // t4_options // G3
     pub t4_options: u32,
     pub t6_options: u32,      // G4
     pub jpeg_tables: Vec<u8>, // jpeg(new)
+    // jpeg(old) TIFF 6.0 Section 22
+    pub jpeg_proc: u16,                      // 0x0200 1 = baseline, 14 = lossless
+    pub jpeg_interchange_format: u64,        // 0x0201 offset of a JPEG stream (SOI)
+    pub jpeg_interchange_format_length: u64, // 0x0202
+    pub jpeg_restart_interval: u16,          // 0x0203
+    pub jpeg_q_tables: Vec<u64>,             // 0x0207 offsets of 64 byte tables
+    pub jpeg_dc_tables: Vec<u64>,            // 0x0208 offsets of DHT style tables
+    pub jpeg_ac_tables: Vec<u64>,            // 0x0209
 
     // metadata
     pub tiff_headers: TiffHeaders,
@@ -383,10 +449,33 @@ pub struct Tiff {
 
     // multi page tiff
     pub multi_page: Box<Vec<Tiff>>,
+    /// 0x014A SubIFDs, such as pyramid levels, masks and DNG previews
+    pub sub_ifds: Vec<Tiff>,
     // pub predictor // if predictor is 2,pixels are horizontal differencing. not support
 }
 
 impl Tiff {
+    /// Returns whether the image data is stored in tiles rather than strips.
+    pub fn is_tiled(&self) -> bool {
+        self.tile_width != 0 && self.tile_length != 0 && !self.tile_offsets.is_empty()
+    }
+
+    /// Returns whether the IFD is a full-resolution image or page rather
+    /// than a reduced-resolution copy or a transparency mask.
+    pub fn is_page(&self) -> bool {
+        self.newsubfiletype & 0x5 == 0 && matches!(self.subfiletype, 0 | 1 | 3)
+    }
+
+    /// Returns whether the IFD is a reduced-resolution copy of another image.
+    pub fn is_reduced(&self) -> bool {
+        self.newsubfiletype & 0x1 != 0 || self.subfiletype == 2
+    }
+
+    /// Returns whether the IFD is a transparency mask of another image.
+    pub fn is_mask(&self) -> bool {
+        self.newsubfiletype & 0x4 != 0 || self.photometric_interpretation == 4
+    }
+
     pub fn empty() -> Self {
         Self {
             newsubfiletype: 0,
@@ -413,6 +502,14 @@ impl Tiff {
             starty: 0,
             predictor: 1,
             extra_samples: vec![],
+            sample_format: 1,
+            ink_set: 1,
+            white_point: None,
+            decode: vec![],
+            ycbcr_coefficients: [0.299, 0.587, 0.114],
+            ycbcr_subsampling: [2, 2],
+            ycbcr_positioning: 1,
+            reference_black_white: vec![],
             tile_width: 0,
             tile_length: 0,
             tile_offsets: vec![],
@@ -420,9 +517,17 @@ impl Tiff {
             t4_options: 0,
             t6_options: 0,
             jpeg_tables: vec![],
+            jpeg_proc: 1,
+            jpeg_interchange_format: 0,
+            jpeg_interchange_format_length: 0,
+            jpeg_restart_interval: 0,
+            jpeg_q_tables: vec![],
+            jpeg_dc_tables: vec![],
+            jpeg_ac_tables: vec![],
             tiff_headers: TiffHeaders::empty(Endian::LittleEndian),
             icc_profile: None,
             multi_page: Box::<Vec<Tiff>>::default(),
+            sub_ifds: vec![],
         }
     }
 
@@ -434,6 +539,9 @@ impl Tiff {
         let mut this = Self::empty();
         let mut current = &mut this;
         let mut append = vec![];
+        // (IFD index, offsets); index 0 is the first IFD
+        let mut sub_ifds = vec![];
+        let mut ifd_index = 0;
 
         for header in &tiff_headers.headers {
             // skip thumbnail or multi page images
@@ -442,6 +550,7 @@ impl Tiff {
                 max_id = header.tagid;
             } else {
                 max_id = header.tagid;
+                ifd_index += 1;
                 if current.bitspersamples.is_empty() {
                     current.bitspersamples.push(current.bitspersample);
                 }
@@ -454,261 +563,377 @@ impl Tiff {
                 })?;
                 current.tiff_headers.endian = tiff_headers.endian;
             }
-            match header.tagid {
-                0xff => {
-                    if let DataPack::Long(d) = &header.data {
-                        current.subfiletype = d[0]
-                    }
+            if header.tagid == 0x014a {
+                sub_ifds.push((ifd_index, ifd_offsets(&header.data)));
+            }
+            current.set_tag(header);
+        }
+
+        if current.bitspersamples.is_empty() {
+            current.bitspersamples.push(current.bitspersample);
+        }
+        let mut visited = HashSet::new();
+        for (index, offsets) in sub_ifds {
+            let subs = read_sub_ifds(reader, &offsets, tiff_headers.version, 0, &mut visited);
+            match index {
+                0 => this.sub_ifds = subs,
+                _ => append[index - 1].sub_ifds = subs,
+            }
+        }
+        this.tiff_headers = tiff_headers;
+        this.multi_page = Box::new(append);
+        Ok(this)
+    }
+
+    fn set_tag(&mut self, header: &TiffHeader) {
+        match header.tagid {
+            0xff => {
+                if let DataPack::Long(d) = &header.data {
+                    self.subfiletype = d[0]
                 }
-                0xfe => {
-                    if let DataPack::Long(d) = &header.data {
-                        current.newsubfiletype = d[0];
-                    }
+            }
+            0xfe => {
+                if let DataPack::Long(d) = &header.data {
+                    self.newsubfiletype = d[0];
                 }
-                0x100 => {
-                    if let DataPack::Long(d) = &header.data {
-                        current.width = d[0]
-                    } else if let DataPack::Short(d) = &header.data {
-                        current.width = d[0] as u32;
-                    }
+            }
+            0x100 => {
+                if let DataPack::Long(d) = &header.data {
+                    self.width = d[0]
+                } else if let DataPack::Short(d) = &header.data {
+                    self.width = d[0] as u32;
                 }
-                0x101 => {
-                    if let DataPack::Long(d) = &header.data {
-                        current.height = d[0]
-                    } else if let DataPack::Short(d) = &header.data {
-                        current.height = d[0] as u32;
-                    }
+            }
+            0x101 => {
+                if let DataPack::Long(d) = &header.data {
+                    self.height = d[0]
+                } else if let DataPack::Short(d) = &header.data {
+                    self.height = d[0] as u32;
                 }
-                0x102 => {
-                    if header.length == 1 {
+            }
+            0x102 => {
+                if header.length == 1 {
+                    if let DataPack::Short(d) = &header.data {
+                        self.bitspersample = d[0];
+                        self.bitspersamples.push(d[0]);
+                    }
+                } else {
+                    let mut bpm = 0;
+                    for i in 0..header.length {
                         if let DataPack::Short(d) = &header.data {
-                            current.bitspersample = d[0];
-                            current.bitspersamples.push(d[0]);
+                            bpm += d[i];
+                            self.bitspersamples.push(d[i]);
                         }
-                    } else {
-                        let mut bpm = 0;
-                        for i in 0..header.length {
-                            if let DataPack::Short(d) = &header.data {
-                                bpm += d[i];
-                                current.bitspersamples.push(d[i]);
-                            }
-                        }
-                        current.bitspersample = bpm;
                     }
+                    self.bitspersample = bpm;
                 }
-                0x103 => {
-                    if let DataPack::Short(d) = &header.data {
-                        current.compression = match d[0] {
-                            1 => Compression::NoneCompression,
-                            2 => Compression::CCITTHuffmanRLE,
-                            3 => Compression::CCITTGroup3Fax,
-                            4 => Compression::CCITTGroup4Fax,
-                            5 => Compression::LZW,
-                            // obsolete
-                            6 => Compression::OldJpeg,
-                            7 => Compression::Jpeg,
-                            8 => Compression::AdobeDeflate,
-                            32766 => Compression::Next,
-                            32771 => Compression::CcittrleW,
-                            32773 => Compression::Packbits,
-                            32809 => Compression::ThunderScan,
-                            32895 => Compression::IT8CTPad,
-                            32896 => Compression::IT8LW,
-                            32897 => Compression::IT8MP,
-                            32898 => Compression::IT8BL,
-                            32908 => Compression::PIXARFILM,
-                            32909 => Compression::PIXARLOG,
-                            32946 => Compression::DEFLATE,
-                            32947 => Compression::DCS,
-                            34661 => Compression::JBIG,
-                            34676 => Compression::SGILOG,
-                            34677 => Compression::SGILOG24,
-                            34712 => Compression::Jpeg2000,
-                            _ => Compression::Unknown,
-                        };
-                    }
+            }
+            0x103 => {
+                if let DataPack::Short(d) = &header.data {
+                    self.compression = match d[0] {
+                        1 => Compression::NoneCompression,
+                        2 => Compression::CCITTHuffmanRLE,
+                        3 => Compression::CCITTGroup3Fax,
+                        4 => Compression::CCITTGroup4Fax,
+                        5 => Compression::LZW,
+                        // obsolete
+                        6 => Compression::OldJpeg,
+                        7 => Compression::Jpeg,
+                        8 => Compression::AdobeDeflate,
+                        32766 => Compression::Next,
+                        32771 => Compression::CcittrleW,
+                        32773 => Compression::Packbits,
+                        32809 => Compression::ThunderScan,
+                        32895 => Compression::IT8CTPad,
+                        32896 => Compression::IT8LW,
+                        32897 => Compression::IT8MP,
+                        32898 => Compression::IT8BL,
+                        32908 => Compression::PIXARFILM,
+                        32909 => Compression::PIXARLOG,
+                        32946 => Compression::DEFLATE,
+                        32947 => Compression::DCS,
+                        34661 => Compression::JBIG,
+                        34676 => Compression::SGILOG,
+                        34677 => Compression::SGILOG24,
+                        34712 => Compression::Jpeg2000,
+                        _ => Compression::Unknown,
+                    };
                 }
-                0x106 => {
-                    if let DataPack::Short(d) = &header.data {
-                        current.photometric_interpretation = d[0];
-                    }
+            }
+            0x106 => {
+                if let DataPack::Short(d) = &header.data {
+                    self.photometric_interpretation = d[0];
                 }
-                0x10A => {
-                    if let DataPack::Short(d) = &header.data {
-                        current.fill_order = d[0];
-                    }
+            }
+            0x10A => {
+                if let DataPack::Short(d) = &header.data {
+                    self.fill_order = d[0];
                 }
-                0x115 => {
-                    if let DataPack::Short(d) = &header.data {
-                        current.samples_per_pixel = d[0];
-                    }
+            }
+            0x115 => {
+                if let DataPack::Short(d) = &header.data {
+                    self.samples_per_pixel = d[0];
                 }
-                0x111 => {
-                    let mut strip_offsets = vec![];
-                    if let DataPack::Short(d) = &header.data {
-                        for i in 0..d.len() {
-                            strip_offsets.push(d[i] as u32);
-                        }
-                    } else if let DataPack::Long(d) = &header.data {
-                        for i in 0..d.len() {
-                            strip_offsets.push(d[i]);
-                        }
-                    }
-                    current.strip_offsets = strip_offsets;
+            }
+            0x111 => {
+                self.strip_offsets = offset_values(&header.data);
+            }
+            0x116 => {
+                if let DataPack::Short(d) = &header.data {
+                    self.rows_per_strip = d[0] as u32;
+                } else if let DataPack::Long(d) = &header.data {
+                    self.rows_per_strip = d[0];
                 }
-                0x116 => {
+            }
+            0x117 => {
+                self.strip_byte_counts = offset_values(&header.data);
+            }
+            0x118 => {
+                for i in 0..header.length {
                     if let DataPack::Short(d) = &header.data {
-                        current.rows_per_strip = d[0] as u32;
-                    } else if let DataPack::Long(d) = &header.data {
-                        current.rows_per_strip = d[0];
+                        self.min_sample_values.push(d[i]);
                     }
                 }
-                0x117 => {
-                    let mut strip_byte_counts = vec![];
+            }
+            0x119 => {
+                for i in 0..header.length {
                     if let DataPack::Short(d) = &header.data {
-                        for i in 0..d.len() {
-                            strip_byte_counts.push(d[i] as u32);
-                        }
-                    } else if let DataPack::Long(d) = &header.data {
-                        for i in 0..d.len() {
-                            strip_byte_counts.push(d[i]);
-                        }
+                        self.max_sample_values.push(d[i]);
                     }
-                    current.strip_byte_counts = strip_byte_counts;
                 }
-                0x118 => {
-                    for i in 0..header.length {
-                        if let DataPack::Short(d) = &header.data {
-                            current.min_sample_values.push(d[i]);
-                        }
-                    }
+            }
+            0x11c => {
+                if let DataPack::Short(d) = &header.data {
+                    self.planar_config = d[0];
                 }
-                0x119 => {
-                    for i in 0..header.length {
-                        if let DataPack::Short(d) = &header.data {
-                            current.max_sample_values.push(d[i]);
-                        }
-                    }
+            }
+            0x013d => {
+                // predictor
+                if let DataPack::Short(d) = &header.data {
+                    self.predictor = d[0];
                 }
-                0x11c => {
-                    if let DataPack::Short(d) = &header.data {
-                        current.planar_config = d[0];
-                    }
+            }
+            0x0124 => {
+                // T4Options
+                if let DataPack::Long(d) = &header.data {
+                    self.t4_options = d[0];
                 }
-                0x013d => {
-                    // predictor
-                    if let DataPack::Short(d) = &header.data {
-                        current.predictor = d[0];
-                    }
+            }
+            0x0125 => {
+                // T6Options
+                if let DataPack::Long(d) = &header.data {
+                    self.t6_options = d[0];
                 }
-                0x0124 => {
-                    // T4Options
-                    if let DataPack::Long(d) = &header.data {
-                        current.t4_options = d[0];
+            }
+            0x0140 => {
+                // color table
+                if let DataPack::Short(d) = &header.data {
+                    let mut table: Vec<RGBA> = Vec::new();
+                    let offset = header.length / 3;
+                    for i in 0..offset {
+                        let red = (d[i] >> 8) as u8;
+                        let green = (d[i + offset] >> 8) as u8;
+                        let blue = (d[i + offset * 2] >> 8) as u8;
+                        let alpha = 0xff;
+                        let color = RGBA {
+                            red,
+                            green,
+                            blue,
+                            alpha,
+                        };
+                        table.push(color);
                     }
+                    self.color_table = Some(table)
                 }
-                0x0125 => {
-                    // T6Options
-                    if let DataPack::Long(d) = &header.data {
-                        current.t6_options = d[0];
-                    }
+            }
+            0x142 => {
+                // TileWidth
+                if let DataPack::Short(d) = &header.data {
+                    self.tile_width = d[0] as u32;
+                } else if let DataPack::Long(d) = &header.data {
+                    self.tile_width = d[0];
                 }
-                0x0140 => {
-                    // color table
-                    if let DataPack::Short(d) = &header.data {
-                        let mut table: Vec<RGBA> = Vec::new();
-                        let offset = header.length / 3;
-                        for i in 0..offset {
-                            let red = (d[i] >> 8) as u8;
-                            let green = (d[i + offset] >> 8) as u8;
-                            let blue = (d[i + offset * 2] >> 8) as u8;
-                            let alpha = 0xff;
-                            let color = RGBA {
-                                red,
-                                green,
-                                blue,
-                                alpha,
-                            };
-                            table.push(color);
-                        }
-                        current.color_table = Some(table)
-                    }
+            }
+            0x143 => {
+                // TileWidth
+                if let DataPack::Short(d) = &header.data {
+                    self.tile_length = d[0] as u32;
+                } else if let DataPack::Long(d) = &header.data {
+                    self.tile_length = d[0];
                 }
-                0x142 => {
-                    // TileWidth
-                    if let DataPack::Short(d) = &header.data {
-                        current.tile_width = d[0] as u32;
-                    } else if let DataPack::Long(d) = &header.data {
-                        current.tile_width = d[0];
-                    }
+            }
+            0x144 => {
+                // TileOffsets
+                self.tile_offsets = offset_values(&header.data);
+            }
+            0x145 => {
+                // TileByteCounts
+                self.tile_byte_counts = offset_values(&header.data);
+            }
+            0x0152 => {
+                // ExtraSamples
+                if let DataPack::Short(d) = &header.data {
+                    self.extra_samples = d.to_vec();
                 }
-                0x143 => {
-                    // TileWidth
-                    if let DataPack::Short(d) = &header.data {
-                        current.tile_length = d[0] as u32;
-                    } else if let DataPack::Long(d) = &header.data {
-                        current.tile_length = d[0];
-                    }
+            }
+            0x0153 => {
+                // SampleFormat
+                if let DataPack::Short(d) = &header.data {
+                    self.sample_format = d[0];
                 }
-                0x144 => {
-                    // TileOffsets
-                    let mut tile_offsets = vec![];
-                    if let DataPack::Long(d) = &header.data {
-                        for i in 0..d.len() {
-                            tile_offsets.push(d[i]);
-                        }
-                    }
-                    current.tile_offsets = tile_offsets;
+            }
+            0x013e => {
+                // WhitePoint
+                if let [x, y, ..] = real_values(&header.data)[..] {
+                    self.white_point = Some((x, y));
                 }
-                0x145 => {
-                    let mut tile_byte_counts = vec![];
-                    if let DataPack::Short(d) = &header.data {
-                        for i in 0..d.len() {
-                            tile_byte_counts.push(d[i] as u32);
-                        }
-                    } else if let DataPack::Long(d) = &header.data {
-                        for i in 0..d.len() {
-                            tile_byte_counts.push(d[i]);
-                        }
-                    }
-                    current.tile_byte_counts = tile_byte_counts;
+            }
+            0x014c => {
+                // InkSet
+                if let DataPack::Short(d) = &header.data {
+                    self.ink_set = d[0];
                 }
-                0x0152 => {
-                    // ExtraSamples
-                    if let DataPack::Short(d) = &header.data {
-                        current.extra_samples = d.to_vec();
-                    }
+            }
+            0x01b1 => {
+                // Decode
+                self.decode = real_values(&header.data);
+            }
+            0x0200 => {
+                // JPEGProc
+                if let DataPack::Short(d) = &header.data {
+                    self.jpeg_proc = d[0];
                 }
-                0x015b => {
-                    //Jpeg Tables
-                    if let DataPack::Undef(d) = &header.data {
-                        current.jpeg_tables = d.to_vec();
-                    }
+            }
+            0x0201 => {
+                // JPEGInterchangeFormat
+                if let Some(offset) = offset_values(&header.data).first() {
+                    self.jpeg_interchange_format = *offset;
                 }
-                0x8773 => {
-                    //ICC Profile
-                    if let DataPack::Undef(d) = &header.data {
-                        current.icc_profile = Some(d.to_vec());
-                    }
+            }
+            0x0202 => {
+                // JPEGInterchangeFormatLength
+                if let Some(length) = offset_values(&header.data).first() {
+                    self.jpeg_interchange_format_length = *length;
                 }
-                _ => {}
             }
+            0x0203 => {
+                // JPEGRestartInterval
+                if let DataPack::Short(d) = &header.data {
+                    self.jpeg_restart_interval = d[0];
+                }
+            }
+            0x0207 => {
+                // JPEGQTables
+                self.jpeg_q_tables = offset_values(&header.data);
+            }
+            0x0208 => {
+                // JPEGDCTables
+                self.jpeg_dc_tables = offset_values(&header.data);
+            }
+            0x0209 => {
+                // JPEGACTables
+                self.jpeg_ac_tables = offset_values(&header.data);
+            }
+            0x0211 => {
+                // YCbCrCoefficients
+                if let [red, green, blue, ..] = real_values(&header.data)[..] {
+                    self.ycbcr_coefficients = [red, green, blue];
+                }
+            }
+            0x0212 => {
+                // YCbCrSubSampling
+                if let DataPack::Short(d) = &header.data
+                    && let [horizontal, vertical, ..] = d[..]
+                {
+                    self.ycbcr_subsampling = [horizontal, vertical];
+                }
+            }
+            0x0213 => {
+                // YCbCrPositioning
+                if let DataPack::Short(d) = &header.data {
+                    self.ycbcr_positioning = d[0];
+                }
+            }
+            0x0214 => {
+                // ReferenceBlackWhite
+                self.reference_black_white = real_values(&header.data);
+            }
+            0x015b => {
+                //Jpeg Tables
+                if let DataPack::Undef(d) = &header.data {
+                    self.jpeg_tables = d.to_vec();
+                }
+            }
+            0x8773 => {
+                //ICC Profile
+                if let DataPack::Undef(d) = &header.data {
+                    self.icc_profile = Some(d.to_vec());
+                }
+            }
+            _ => {}
         }
+    }
+}
 
-        if current.bitspersamples.is_empty() {
-            current.bitspersamples.push(current.bitspersample);
+/// SubIFDs nested deeper than this are ignored.
+const MAX_SUB_IFD_DEPTH: usize = 4;
+/// SubIFDs after this many in a file are ignored.
+const MAX_SUB_IFDS: usize = 256;
+
+fn ifd_offsets(data: &DataPack) -> Vec<u64> {
+    match data {
+        DataPack::Long(d) => d.iter().map(|offset| *offset as u64).collect(),
+        DataPack::Long8(d) => d.clone(),
+        _ => vec![],
+    }
+}
+
+/// Reads the IFDs listed by a SubIFDs tag. A broken SubIFD is skipped so
+/// that it cannot make the main image unreadable. `visited` holds the
+/// offsets read so far in the file; an offset is read only once.
+fn read_sub_ifds(
+    reader: &mut dyn BinaryReader,
+    offsets: &[u64],
+    version: u16,
+    depth: usize,
+    visited: &mut HashSet<u64>,
+) -> Vec<Tiff> {
+    let mut subs = vec![];
+    for offset in offsets {
+        if visited.len() >= MAX_SUB_IFDS {
+            break;
         }
-        this.tiff_headers = tiff_headers;
-        this.multi_page = Box::new(append);
-        Ok(this)
+        if !visited.insert(*offset) {
+            continue;
+        }
+        let Ok(headers) = read_tag(reader, *offset, version, IfdMode::SubIfd) else {
+            continue;
+        };
+        let mut sub = Tiff::empty();
+        let mut nested = vec![];
+        for header in &headers.headers {
+            if header.tagid == 0x014a {
+                nested = ifd_offsets(&header.data);
+            }
+            sub.set_tag(header);
+        }
+        if sub.bitspersamples.is_empty() {
+            sub.bitspersamples.push(sub.bitspersample);
+        }
+        if depth + 1 < MAX_SUB_IFD_DEPTH {
+            sub.sub_ifds = read_sub_ifds(reader, &nested, version, depth + 1, visited);
+        }
+        sub.tiff_headers = headers;
+        subs.push(sub);
     }
+    subs
 }
 
 #[derive(Debug, Clone)]
 struct EncodedTag {
     tagid: u16,
     type_id: u16,
-    count: u32,
+    count: u64,
     payload: Vec<u8>,
 }
 
@@ -722,8 +947,8 @@ fn append_even_padding(buf: &mut Vec<u8>) {
     }
 }
 
-fn validate_count(count: usize) -> Result<u32, Error> {
-    u32::try_from(count).map_err(|_| {
+fn validate_count(count: usize) -> Result<u64, Error> {
+    u64::try_from(count).map_err(|_| {
         Box::new(ImgError::new_const(
             ImgErrorKind::InvalidParameter,
             "TIFF tag count is too large".to_string(),
@@ -873,6 +1098,30 @@ fn encode_tag_data(tag: &TiffHeader, endian: Endian) -> Result<EncodedTag, Error
                 payload,
             })
         }
+        DataPack::Long8(data) => {
+            let mut payload = Vec::with_capacity(data.len() * 8);
+            for value in data {
+                write_u64(*value, &mut payload, endian);
+            }
+            Ok(EncodedTag {
+                tagid,
+                type_id: 16,
+                count: validate_count(data.len())?,
+                payload,
+            })
+        }
+        DataPack::SLong8(data) => {
+            let mut payload = Vec::with_capacity(data.len() * 8);
+            for value in data {
+                write_i64(*value, &mut payload, endian);
+            }
+            Ok(EncodedTag {
+                tagid,
+                type_id: 17,
+                count: validate_count(data.len())?,
+                payload,
+            })
+        }
     }
 }
 
@@ -882,32 +1131,66 @@ fn serialize_encoded_tag(
     tag: &EncodedTag,
     next_data_offset: &mut usize,
     endian: Endian,
+    is_bigtiff: bool,
 ) -> Result<(), Error> {
     write_u16(tag.tagid, buf, endian);
     write_u16(tag.type_id, buf, endian);
-    write_u32(tag.count, buf, endian);
+    if is_bigtiff {
+        write_u64(tag.count, buf, endian);
+    } else {
+        let count = u32::try_from(tag.count).map_err(|_| {
+            Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "TIFF tag count is too large".to_string(),
+            )) as Error
+        })?;
+        write_u32(count, buf, endian);
+    }
 
-    if tag.payload.len() <= 4 {
+    let slot = if is_bigtiff { 8 } else { 4 };
+    if tag.payload.len() <= slot {
         write_bytes(&tag.payload, buf);
-        for _ in tag.payload.len()..4 {
+        for _ in tag.payload.len()..slot {
             write_byte(0, buf);
         }
         return Ok(());
     }
 
-    let data_offset = u32::try_from(*next_data_offset).map_err(|_| {
-        Box::new(ImgError::new_const(
-            ImgErrorKind::InvalidParameter,
-            "TIFF data offset exceeds 32-bit range".to_string(),
-        )) as Error
-    })?;
-    write_u32(data_offset, buf, endian);
+    write_offset(
+        *next_data_offset,
+        buf,
+        endian,
+        is_bigtiff,
+        "TIFF data offset",
+    )?;
     append.extend_from_slice(&tag.payload);
     append_even_padding(append);
     *next_data_offset += even_padded_len(tag.payload.len());
     Ok(())
 }
 
+/// Writes a 32-bit (TIFF) or 64-bit (BigTIFF) file offset.
+fn write_offset(
+    offset: usize,
+    buf: &mut Vec<u8>,
+    endian: Endian,
+    is_bigtiff: bool,
+    name: &str,
+) -> Result<(), Error> {
+    if is_bigtiff {
+        write_u64(offset as u64, buf, endian);
+    } else {
+        let offset = u32::try_from(offset).map_err(|_| {
+            Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                format!("{name} exceeds 32-bit range"),
+            )) as Error
+        })?;
+        write_u32(offset, buf, endian);
+    }
+    Ok(())
+}
+
 fn clone_ifd_tags(tags: &[TiffHeader]) -> Vec<TiffHeader> {
     tags.to_vec()
 }
@@ -929,27 +1212,30 @@ fn serialize_ifd(
     exif: Option<&Vec<TiffHeader>>,
     gps: Option<&Vec<TiffHeader>>,
     absolute_offset: usize,
-    next_ifd_offset: u32,
+    next_ifd_offset: usize,
     endian: Endian,
+    is_bigtiff: bool,
 ) -> Result<Vec<u8>, Error> {
     let has_exif = exif.map(|tags| !tags.is_empty()).unwrap_or(false);
     let has_gps = gps.map(|tags| !tags.is_empty()).unwrap_or(false);
     let tags = filter_base_ifd_tags(tags, has_exif, has_gps);
+    // entry count, entry and next IFD offset sizes
+    let (count_size, entry_size, offset_size) = if is_bigtiff { (8, 20, 8) } else { (2, 12, 4) };
 
     let mut encoded = Vec::with_capacity(tags.len());
     let mut local_payload_len = 0usize;
     for tag in &tags {
         let encoded_tag = encode_tag_data(tag, endian)?;
-        if encoded_tag.payload.len() > 4 {
+        if encoded_tag.payload.len() > offset_size {
             local_payload_len += even_padded_len(encoded_tag.payload.len());
         }
         encoded.push(encoded_tag);
     }
 
     let entry_count = encoded.len() + has_exif as usize + has_gps as usize;
-    let ifd_size = 2usize
-        .checked_add(entry_count * 12)
-        .and_then(|size| size.checked_add(4))
+    let ifd_size = entry_count
+        .checked_mul(entry_size)
+        .and_then(|size| size.checked_add(count_size + offset_size))
         .and_then(|size| size.checked_add(local_payload_len))
         .ok_or_else(|| {
             Box::new(ImgError::new_const(
@@ -979,6 +1265,7 @@ fn serialize_ifd(
                 })?,
                 0,
                 endian,
+                is_bigtiff,
             )?)
         }
     } else {
@@ -1005,52 +1292,52 @@ fn serialize_ifd(
                 })?,
                 0,
                 endian,
+                is_bigtiff,
             )?)
         }
     } else {
         None
     };
 
-    if let Some(offset) = exif_offset {
-        encoded.push(EncodedTag {
-            tagid: 0x8769,
-            type_id: 4,
-            count: 1,
-            payload: u32::to_ne_bytes(u32::try_from(offset).map_err(|_| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::InvalidParameter,
-                    "TIFF EXIF offset exceeds 32-bit range".to_string(),
-                )) as Error
-            })?)
-            .to_vec(),
-        });
-    }
-    if let Some(offset) = gps_offset {
-        encoded.push(EncodedTag {
-            tagid: 0x8825,
-            type_id: 4,
-            count: 1,
-            payload: u32::to_ne_bytes(u32::try_from(offset).map_err(|_| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::InvalidParameter,
-                    "TIFF GPS offset exceeds 32-bit range".to_string(),
-                )) as Error
-            })?)
-            .to_vec(),
-        });
+    // EXIF and GPS pointers are IFD8 in BigTIFF and LONG in TIFF.
+    let pointer_type = if is_bigtiff { 18 } else { 4 };
+    for (tagid, offset) in [(0x8769, exif_offset), (0x8825, gps_offset)] {
+        if offset.is_some() {
+            encoded.push(EncodedTag {
+                tagid,
+                type_id: pointer_type,
+                count: 1,
+                payload: vec![],
+            });
+        }
     }
     encoded.sort_by_key(|tag| tag.tagid);
 
     let mut buf = Vec::with_capacity(ifd_size);
     let mut append = Vec::with_capacity(local_payload_len);
-    let mut next_data_offset = absolute_offset + 2 + entry_count * 12 + 4;
+    let mut next_data_offset =
+        absolute_offset + count_size + entry_count * entry_size + offset_size;
 
-    write_u16(entry_count as u16, &mut buf, endian);
+    if is_bigtiff {
+        write_u64(entry_count as u64, &mut buf, endian);
+    } else {
+        let entry_count = u16::try_from(entry_count).map_err(|_| {
+            Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                "TIFF IFD has too many entries".to_string(),
+            )) as Error
+        })?;
+        write_u16(entry_count, &mut buf, endian);
+    }
     for tag in &encoded {
         if tag.tagid == 0x8769 || tag.tagid == 0x8825 {
             write_u16(tag.tagid, &mut buf, endian);
-            write_u16(4, &mut buf, endian);
-            write_u32(1, &mut buf, endian);
+            write_u16(tag.type_id, &mut buf, endian);
+            if is_bigtiff {
+                write_u64(1, &mut buf, endian);
+            } else {
+                write_u32(1, &mut buf, endian);
+            }
             let offset = if tag.tagid == 0x8769 {
                 exif_offset.ok_or_else(|| {
                     Box::new(ImgError::new_const(
@@ -1066,13 +1353,26 @@ fn serialize_ifd(
                     )) as Error
                 })?
             };
-            write_u32(offset as u32, &mut buf, endian);
+            write_offset(offset, &mut buf, endian, is_bigtiff, "TIFF EXIF/GPS offset")?;
             continue;
         }
 
-        serialize_encoded_tag(&mut buf, &mut append, tag, &mut next_data_offset, endian)?;
+        serialize_encoded_tag(
+            &mut buf,
+            &mut append,
+            tag,
+            &mut next_data_offset,
+            endian,
+            is_bigtiff,
+        )?;
     }
-    write_u32(next_ifd_offset, &mut buf, endian);
+    write_offset(
+        next_ifd_offset,
+        &mut buf,
+        endian,
+        is_bigtiff,
+        "TIFF next IFD offset",
+    )?;
     buf.append(&mut append);
     if let Some(mut exif_bytes) = exif_bytes {
         buf.append(&mut exif_bytes);
@@ -1093,10 +1393,12 @@ pub fn write_tag(
     endian: &Endian,
 ) -> Result<(), Error> {
     let encoded = encode_tag_data(tag, *endian)?;
-    serialize_encoded_tag(buf, append, &encoded, last_offset, *endian)
+    serialize_encoded_tag(buf, append, &encoded, last_offset, *endian, false)
 }
 
 /// Serializes a [`TiffHeaders`] tree to a complete TIFF byte stream.
+///
+/// The stream is written as BigTIFF when `version` is [`BIGTIFF_VERSION`].
 pub fn tiff_headers_to_bytes(tags: &TiffHeaders) -> Result<Vec<u8>, Error> {
     tiff_pages_to_bytes(std::slice::from_ref(tags))
 }
@@ -1111,8 +1413,19 @@ pub(crate) fn tiff_pages_to_bytes(pages: &[TiffHeaders]) -> Result<Vec<u8>, Erro
 
     let endian = first.endian;
     let version = first.version;
+    let is_bigtiff = match version {
+        TIFF_VERSION => false,
+        BIGTIFF_VERSION => true,
+        _ => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::InvalidParameter,
+                format!("unsupported TIFF version {version}"),
+            )));
+        }
+    };
+    let header_size = if is_bigtiff { 16 } else { 8 };
     let mut offsets = Vec::with_capacity(pages.len());
-    let mut next_offset = 8usize;
+    let mut next_offset = header_size;
     for page in pages {
         if page.endian != endian {
             return Err(Box::new(ImgError::new_const(
@@ -1135,6 +1448,7 @@ pub(crate) fn tiff_pages_to_bytes(pages: &[TiffHeaders]) -> Result<Vec<u8>, Erro
             next_offset,
             0,
             endian,
+            is_bigtiff,
         )?;
         next_offset = next_offset.checked_add(ifd.len()).ok_or_else(|| {
             Box::new(ImgError::new_const(
@@ -1150,19 +1464,16 @@ pub(crate) fn tiff_pages_to_bytes(pages: &[TiffHeaders]) -> Result<Vec<u8>, Erro
         Endian::LittleEndian => write_bytes(b"II", &mut buf),
     }
     write_u16(version, &mut buf, endian);
-    write_u32(8, &mut buf, endian);
+    if is_bigtiff {
+        write_u16(8, &mut buf, endian);
+        write_u16(0, &mut buf, endian);
+        write_u64(header_size as u64, &mut buf, endian);
+    } else {
+        write_u32(header_size as u32, &mut buf, endian);
+    }
 
     for (index, page) in pages.iter().enumerate() {
-        let next_ifd_offset = if let Some(next) = offsets.get(index + 1) {
-            u32::try_from(*next).map_err(|_| {
-                Box::new(ImgError::new_const(
-                    ImgErrorKind::InvalidParameter,
-                    "TIFF next IFD offset exceeds 32-bit range".to_string(),
-                )) as Error
-            })?
-        } else {
-            0
-        };
+        let next_ifd_offset = offsets.get(index + 1).copied().unwrap_or(0);
         let mut ifd = serialize_ifd(
             &page.headers,
             page.exif.as_ref(),
@@ -1170,6 +1481,7 @@ pub(crate) fn tiff_pages_to_bytes(pages: &[TiffHeaders]) -> Result<Vec<u8>, Erro
             offsets[index],
             next_ifd_offset,
             endian,
+            is_bigtiff,
         )?;
         buf.append(&mut ifd);
     }
@@ -1192,6 +1504,7 @@ pub fn write_tags(buf: &mut Vec<u8>, tags: &TiffHeaders) -> Result<usize, Error>
         .and_then(|tag| match &tag.data {
             DataPack::Short(values) => values.first().map(|value| *value as usize),
             DataPack::Long(values) => values.first().map(|value| *value as usize),
+            DataPack::Long8(values) => values.first().map(|value| *value as usize),
             _ => None,
         })
         .unwrap_or(0);
@@ -1201,6 +1514,19 @@ pub fn write_tags(buf: &mut Vec<u8>, tags: &TiffHeaders) -> Result<usize, Error>
     Ok(image_offset)
 }
 
+/// Tags of the first IFD; `read_tags` appends later IFDs, each sorted by tag.
+#[cfg(feature = "tiff")]
+pub(crate) fn first_ifd_tags(tags: &[TiffHeader]) -> &[TiffHeader] {
+    let mut split_index = tags.len();
+    for index in 1..tags.len() {
+        if tags[index].tagid < tags[index - 1].tagid {
+            split_index = index;
+            break;
+        }
+    }
+    &tags[..split_index]
+}
+
 pub fn read_tags(reader: &mut dyn bin_rs::reader::BinaryReader) -> Result<TiffHeaders, Error> {
     let b0 = reader.read_byte()?;
     let b1 = reader.read_byte()?;
@@ -1225,260 +1551,216 @@ pub fn read_tags(reader: &mut dyn bin_rs::reader::BinaryReader) -> Result<TiffHe
         )));
     }
 
-    // version
+    // version 42 = classic TIFF, 43 = BigTIFF
     let ver = reader.read_u16()?;
-    if ver != 42 {
-        return Err(Box::new(ImgError::new_const(
-            ImgErrorKind::IllegalData,
-            "not Tiff".to_string(),
-        )));
+    let offset_ifd = match ver {
+        TIFF_VERSION => reader.read_u32()? as u64,
+        BIGTIFF_VERSION => {
+            // BigTIFF: offset bytesize (always 8), reserved (always 0), 64-bit offset
+            let bytesize = reader.read_u16()?;
+            let reserved = reader.read_u16()?;
+            if bytesize != 8 || reserved != 0 {
+                return Err(Box::new(ImgError::new_const(
+                    ImgErrorKind::IllegalData,
+                    "unsupported BigTIFF offset size".to_string(),
+                )));
+            }
+            reader.read_u64()?
+        }
+        _ => {
+            return Err(Box::new(ImgError::new_const(
+                ImgErrorKind::IllegalData,
+                "not Tiff".to_string(),
+            )));
+        }
+    };
+    read_tag(reader, offset_ifd, ver, IfdMode::BaseTiff)
+}
+
+/// Returns the number of bytes between the reader position and the end of
+/// the stream.
+fn remaining_len(reader: &mut dyn BinaryReader) -> Result<u64, Error> {
+    let current = reader.offset()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(current))?;
+    Ok(end.saturating_sub(current))
+}
+
+/// Returns the byte size of one value of a TIFF field type.
+fn type_size(datatype: usize) -> usize {
+    match datatype {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 | 16 | 17 | 18 => 8,
+        _ => 1,
     }
-    let offset_ifd = reader.read_u32()? as u64;
-    read_tag(reader, offset_ifd, IfdMode::BaseTiff)
 }
 
 fn get_data(
     reader: &mut dyn BinaryReader,
     datatype: usize,
     datalen: usize,
+    is_bigtiff: bool,
 ) -> Result<DataPack, Error> {
-    let data: DataPack;
-    match datatype {
-        1 => {
-            // 1.BYTE(u8)
-            let mut d: Vec<u8> = Vec::with_capacity(datalen);
-            if datalen <= 4 {
-                let buf = reader.read_bytes_as_vec(4)?;
-                for i in 0..datalen {
-                    d.push(buf[i]);
-                }
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_byte()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
-            }
-            data = DataPack::Bytes(d);
-        }
-        2 => {
-            // 2. ASCII(u8)
-            let s;
-            if datalen <= 4 {
-                s = reader.read_ascii_string(datalen)?;
-                reader.skip_ptr(4 - datalen)?;
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                s = reader.read_ascii_string(datalen)?;
-                reader.seek(SeekFrom::Start(current))?;
-            }
-            data = DataPack::Ascii(s);
-        }
+    // Values that fit in the entry are stored inline, otherwise the entry
+    // holds an offset. The slot is 4 bytes for TIFF and 8 bytes for BigTIFF.
+    let slot = if is_bigtiff { 8 } else { 4 };
+    let next_entry = reader.offset()? + slot as u64;
+    if datalen.saturating_mul(type_size(datatype)) > slot {
+        let offset = if is_bigtiff {
+            reader.read_u64()?
+        } else {
+            reader.read_u32()? as u64
+        };
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+    let data = read_values(reader, datatype, datalen)?;
+    reader.seek(SeekFrom::Start(next_entry))?;
+    Ok(data)
+}
+
+fn read_values(
+    reader: &mut dyn BinaryReader,
+    datatype: usize,
+    datalen: usize,
+) -> Result<DataPack, Error> {
+    // Counts come from the file, so check them before allocating.
+    let remaining = remaining_len(reader)?;
+    if datalen
+        .checked_mul(type_size(datatype))
+        .is_none_or(|size| size as u64 > remaining)
+    {
+        return Err(Box::new(ImgError::new_const(
+            ImgErrorKind::IllegalData,
+            format!(
+                "TIFF field of {} values does not fit in the remaining {} bytes",
+                datalen, remaining
+            ),
+        )));
+    }
+    let data = match datatype {
+        // 1.BYTE(u8)
+        1 => DataPack::Bytes(reader.read_bytes_as_vec(datalen)?),
+        // 2. ASCII(u8)
+        2 => DataPack::Ascii(reader.read_ascii_string(datalen)?),
         3 => {
             // SHORT (u16)
             let mut d: Vec<u16> = Vec::with_capacity(datalen);
-            if datalen * 2 <= 4 {
+            for _ in 0..datalen {
                 d.push(reader.read_u16()?);
-                if datalen == 2 {
-                    d.push(reader.read_u16()?);
-                } else {
-                    reader.skip_ptr(2)?;
-                }
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_u16()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
             }
-            data = DataPack::Short(d);
+            DataPack::Short(d)
         }
-        4 => {
-            // LONG (u32)
+        4 | 13 => {
+            // LONG (u32) and IFD
             let mut d: Vec<u32> = Vec::with_capacity(datalen);
-            if datalen * 4 <= 4 {
+            for _ in 0..datalen {
                 d.push(reader.read_u32()?);
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_u32()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
             }
-            data = DataPack::Long(d);
+            DataPack::Long(d)
         }
         5 => {
             //RATIONAL u32/u32
             let mut d: Vec<Rational> = Vec::with_capacity(datalen);
-            let offset = reader.read_u32()? as u64;
-            let current = reader.offset()?;
-            reader.seek(SeekFrom::Start(offset))?;
             for _ in 0..datalen {
                 let n = reader.read_u32()?;
                 let denomi = reader.read_u32()?;
                 d.push(Rational { n, d: denomi });
             }
-            reader.seek(SeekFrom::Start(current))?;
-            data = DataPack::Rational(d);
+            DataPack::Rational(d)
         }
         6 => {
             // 6 i8
             let mut d: Vec<i8> = Vec::with_capacity(datalen);
-            if datalen <= 4 {
-                let buf = reader.read_bytes_as_vec(4)?;
-                for i in 0..datalen {
-                    d.push(buf[i] as i8);
-                }
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_i8()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
-            }
-            data = DataPack::SByte(d);
-        }
-        7 => {
-            // 1.undef
-            let mut d: Vec<u8> = Vec::with_capacity(datalen);
-            if datalen <= 4 {
-                let buf = reader.read_bytes_as_vec(4)?;
-                for i in 0..datalen {
-                    d.push(buf[i]);
-                }
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_byte()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
+            for _ in 0..datalen {
+                d.push(reader.read_i8()?);
             }
-            data = DataPack::Undef(d);
+            DataPack::SByte(d)
         }
+        // 7 undef
+        7 => DataPack::Undef(reader.read_bytes_as_vec(datalen)?),
         8 => {
-            // 6 i16
+            // 8 i16
             let mut d: Vec<i16> = Vec::with_capacity(datalen);
-            if datalen <= 2 {
+            for _ in 0..datalen {
                 d.push(reader.read_i16()?);
-                if datalen == 2 {
-                    d.push(reader.read_i16()?);
-                } else {
-                    reader.skip_ptr(2)?;
-                }
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_i16()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
             }
-            data = DataPack::SShort(d);
+            DataPack::SShort(d)
         }
         9 => {
             // i32
             let mut d: Vec<i32> = Vec::with_capacity(datalen);
-            if datalen * 4 <= 4 {
+            for _ in 0..datalen {
                 d.push(reader.read_i32()?);
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_i32()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
             }
-            data = DataPack::SLong(d);
+            DataPack::SLong(d)
         }
-        // 7 undefined 8 s16 9 s32 10 srational u64/u64 11 float 12 double
         10 => {
-            //RATIONAL u64/u64
+            //SRATIONAL i32/i32
             let mut d: Vec<SRational> = Vec::with_capacity(datalen);
-            let offset = reader.read_u32()? as u64;
-            let current = reader.offset()?;
-            reader.seek(SeekFrom::Start(offset))?;
             for _ in 0..datalen {
                 let n_i32 = reader.read_i32()?;
                 let d_i32 = reader.read_i32()?;
                 d.push(SRational { n: n_i32, d: d_i32 });
             }
-            reader.seek(SeekFrom::Start(current))?;
-            data = DataPack::SRational(d);
+            DataPack::SRational(d)
         }
         11 => {
             // f32
             let mut d: Vec<f32> = Vec::with_capacity(datalen);
-            if datalen * 4 <= 4 {
-                for _ in 0..datalen {
-                    d.push(reader.read_f32()?);
-                }
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_f32()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
+            for _ in 0..datalen {
+                d.push(reader.read_f32()?);
             }
-            data = DataPack::Float(d);
+            DataPack::Float(d)
         }
         12 => {
             // f64
             let mut d: Vec<f64> = Vec::with_capacity(datalen);
-            let offset = reader.read_u32()? as u64;
-            let current = reader.offset()?;
-            reader.seek(SeekFrom::Start(offset))?;
             for _ in 0..datalen {
                 d.push(reader.read_f64()?);
             }
-            reader.seek(SeekFrom::Start(current))?;
-            data = DataPack::Double(d);
+            DataPack::Double(d)
         }
-        _ => {
-            let mut d: Vec<u8> = Vec::with_capacity(datalen);
-            if datalen <= 4 {
-                let buf = reader.read_bytes_as_vec(4)?;
-                for i in 0..datalen {
-                    d.push(buf[i])
-                }
-            } else {
-                let offset = reader.read_u32()? as u64;
-                let current = reader.offset()?;
-                reader.seek(SeekFrom::Start(offset))?;
-                for _ in 0..datalen {
-                    d.push(reader.read_byte()?);
-                }
-                reader.seek(SeekFrom::Start(current))?;
-            };
-            data = DataPack::Unkown(d);
+        16 | 18 => {
+            // BigTIFF LONG8 (u64) and IFD8
+            let mut d: Vec<u64> = Vec::with_capacity(datalen);
+            for _ in 0..datalen {
+                d.push(reader.read_u64()?);
+            }
+            DataPack::Long8(d)
         }
-    }
+        17 => {
+            // BigTIFF SLONG8 (i64)
+            let mut d: Vec<i64> = Vec::with_capacity(datalen);
+            for _ in 0..datalen {
+                d.push(reader.read_i64()?);
+            }
+            DataPack::SLong8(d)
+        }
+        _ => DataPack::Unkown(reader.read_bytes_as_vec(datalen)?),
+    };
     Ok(data)
 }
 
+/// Returns the first value of an offset-like field (LONG, LONG8 or IFD8).
+fn first_offset(data: &DataPack) -> Option<u64> {
+    match data {
+        DataPack::Long(d) => d.first().map(|offset| *offset as u64),
+        DataPack::Long8(d) => d.first().copied(),
+        _ => None,
+    }
+}
+
 fn read_tag(
     reader: &mut dyn BinaryReader,
     mut offset_ifd: u64,
+    version: u16,
     mode: IfdMode,
 ) -> Result<TiffHeaders, Error> {
     let endian = reader.endian();
+    let is_bigtiff = version == BIGTIFF_VERSION;
     let mut headers: TiffHeaders = TiffHeaders {
-        version: 42,
+        version,
         headers: Vec::new(),
         exif: None,
         gps: None,
@@ -1486,43 +1768,59 @@ fn read_tag(
     };
 
     loop {
-        reader.seek(SeekFrom::Start(offset_ifd as u64))?;
-        let tag = reader.read_u16()? as usize;
-        let buf = reader.read_bytes_no_move(tag * 12 + 4)?;
-        let next_ifd = bin_rs::io::read_u32(&buf, tag * 12, reader.endian());
+        reader.seek(SeekFrom::Start(offset_ifd))?;
+        let (tag, next_ifd) = if is_bigtiff {
+            // 20-byte entries followed by the 8-byte next IFD offset.
+            let tag = reader.read_u64()?;
+            let remaining = remaining_len(reader)?;
+            let size = tag
+                .checked_mul(20)
+                .and_then(|size| size.checked_add(8))
+                .filter(|size| *size <= remaining)
+                .ok_or_else(|| {
+                    Box::new(ImgError::new_const(
+                        ImgErrorKind::IllegalData,
+                        format!("BigTIFF IFD with {} entries exceeds the data", tag),
+                    )) as Error
+                })?;
+            let tag = tag as usize;
+            let buf = reader.read_bytes_no_move(size as usize)?;
+            (tag, bin_rs::io::read_u64(&buf, tag * 20, reader.endian()))
+        } else {
+            let tag = reader.read_u16()? as usize;
+            let buf = reader.read_bytes_no_move(tag * 12 + 4)?;
+            let next_ifd = bin_rs::io::read_u32(&buf, tag * 12, reader.endian());
+            (tag, next_ifd as u64)
+        };
 
         for _i in 0..tag {
             let tagid = reader.read_u16()?;
             let datatype = reader.read_u16()? as usize;
-            let datalen = reader.read_u32()? as usize;
+            let datalen = if is_bigtiff {
+                reader.read_u64()? as usize
+            } else {
+                reader.read_u32()? as usize
+            };
 
-            let data: DataPack = get_data(reader, datatype, datalen)?;
+            let data: DataPack = get_data(reader, datatype, datalen, is_bigtiff)?;
             if mode == IfdMode::BaseTiff {
                 match tagid {
                     0x8769 => {
                         // Exif
-                        match &data {
-                            DataPack::Long(d) => {
-                                let current = reader.offset()?;
-                                reader.seek(SeekFrom::Start(d[0] as u64))?;
-                                let r = read_tag(reader, d[0] as u64, IfdMode::Exif)?; // read exif
-                                headers.exif = Some(r.headers);
-                                reader.seek(SeekFrom::Start(current))?;
-                            }
-                            _ => {}
+                        if let Some(offset) = first_offset(&data) {
+                            let current = reader.offset()?;
+                            let r = read_tag(reader, offset, version, IfdMode::Exif)?; // read exif
+                            headers.exif = Some(r.headers);
+                            reader.seek(SeekFrom::Start(current))?;
                         }
                     }
                     0x8825 => {
                         // GPS
-                        match &data {
-                            DataPack::Long(d) => {
-                                let current = reader.offset()?;
-                                reader.seek(SeekFrom::Start(d[0] as u64))?;
-                                let r = read_tag(reader, d[0] as u64, IfdMode::Gps)?; // read gps
-                                reader.seek(SeekFrom::Start(current))?;
-                                headers.gps = Some(r.headers);
-                            }
-                            _ => {}
+                        if let Some(offset) = first_offset(&data) {
+                            let current = reader.offset()?;
+                            let r = read_tag(reader, offset, version, IfdMode::Gps)?; // read gps
+                            reader.seek(SeekFrom::Start(current))?;
+                            headers.gps = Some(r.headers);
                         }
                     }
                     _ => {
@@ -1530,6 +1828,9 @@ fn read_tag(
                         super::tags::tag_mapper(tagid, &data, datalen);
                     }
                 }
+            } else if mode == IfdMode::SubIfd {
+                #[cfg(debug_assertions)]
+                super::tags::tag_mapper(tagid, &data, datalen);
             } else {
                 #[cfg(debug_assertions)]
                 super::tags::gps_mapper(tagid, &data, datalen);
@@ -1543,7 +1844,7 @@ fn read_tag(
         if next_ifd == 0 || mode != IfdMode::BaseTiff {
             break;
         }
-        offset_ifd = next_ifd as u64;
+        offset_ifd = next_ifd;
     }
 
     Ok(headers)
@@ -4,6 +4,8 @@
 pub mod decoder;
 #[cfg(feature = "tiff")]
 pub mod encoder;
+#[cfg(feature = "tiff")]
+pub mod geotiff;
 #[cfg(feature = "exif")]
 pub mod header;
 #[cfg(feature = "exif")]
@@ -193,6 +193,22 @@ pub fn print_data_with_max_size(data: &DataPack, length: usize, max_size: usize)
                 s += &format!("{} ", d[i]);
             }
         }
+        DataPack::Long8(d) => {
+            if length > 1 {
+                s = format!("\nlength {}\n", length)
+            };
+            for value in d.iter().take(length) {
+                s += &format!("{} ", value);
+            }
+        }
+        DataPack::SLong8(d) => {
+            if length > 1 {
+                s = format!("\nlength {}\n", length)
+            };
+            for value in d.iter().take(length) {
+                s += &format!("{} ", value);
+            }
+        }
         _ => {}
     }
     if length > len {
@@ -352,6 +368,18 @@ pub fn convert(data: &DataPack, length: usize) -> DataMap {
             }
             DataMap::FloatAllay(data)
         }
+        DataPack::Long8(d) => {
+            if length == 1 {
+                return DataMap::UInt(d[0]);
+            }
+            DataMap::UIntAllay(d.to_vec())
+        }
+        DataPack::SLong8(d) => {
+            if length == 1 {
+                return DataMap::SInt(d[0]);
+            }
+            DataMap::SIntAllay(d.to_vec())
+        }
         DataPack::Unkown(d) => DataMap::Raw(d.to_vec()),
     }
 }
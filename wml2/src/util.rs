@@ -52,13 +52,14 @@ pub fn format_check(buffer: &[u8]) -> ImageFormat {
     }
     if buffer.len() >= 4 && buffer[0] == b'I' && buffer[1] == b'I' {
         let ver = bin_rs::io::read_u16_le(buffer, 2);
-        if ver == 42 {
+        // 42 = TIFF, 43 = BigTIFF
+        if ver == 42 || ver == 43 {
             return ImageFormat::Tiff;
         }
     }
     if buffer.len() >= 4 && buffer[0] == b'M' && buffer[1] == b'M' {
         let ver = bin_rs::io::read_u16_be(buffer, 2);
-        if ver == 42 {
+        if ver == 42 || ver == 43 {
             return ImageFormat::Tiff;
         }
         return ImageFormat::Tiff;
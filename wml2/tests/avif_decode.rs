@@ -140,12 +140,10 @@ fn avif_decoder_decodes_sample_with_active_filters() {
     let data = sample_avif();
     let mut reader = BytesReader::new(&data);
     let mut drawer = RecordingDrawer::default();
-    let mut options = DecodeOptions {
-        // The assertions below cover AV1 diagnostic probes, which are
-        // intentionally collected only when debug mode is requested.
-        debug_flag: 1,
-        drawer: &mut drawer,
-    };
+    let mut options = DecodeOptions::new(&mut drawer);
+    // The assertions below cover AV1 diagnostic probes, which are
+    // intentionally collected only when debug mode is requested.
+    options.debug_flag = 1;
 
     image_decoder(&mut reader, &mut options).expect("supported filtered AVIF should decode");
     assert!(
@@ -631,10 +629,7 @@ fn avif_decoder_keeps_callback_order_for_filter_disabled_fixture() {
     let data = filter_disabled_fixture();
     let mut reader = BytesReader::new(&data);
     let mut drawer = RecordingDrawer::default();
-    let mut options = DecodeOptions {
-        debug_flag: 0,
-        drawer: &mut drawer,
-    };
+    let mut options = DecodeOptions::new(&mut drawer);
 
     image_decoder(&mut reader, &mut options).expect("filter-disabled AVIF should decode");
 
@@ -687,10 +682,7 @@ fn avif_animation_notifies_next_before_each_full_canvas_frame() {
     };
     let mut reader = BytesReader::new(&data);
     let mut drawer = RecordingDrawer::default();
-    let mut options = DecodeOptions {
-        debug_flag: 0,
-        drawer: &mut drawer,
-    };
+    let mut options = DecodeOptions::new(&mut drawer);
 
     image_decoder(&mut reader, &mut options).expect("star AVIS should decode");
 
@@ -718,10 +710,7 @@ fn avif_animation_alpha_is_synchronized_per_frame() {
     };
     let mut reader = BytesReader::new(&data);
     let mut drawer = RecordingDrawer::default();
-    let mut options = DecodeOptions {
-        debug_flag: 0,
-        drawer: &mut drawer,
-    };
+    let mut options = DecodeOptions::new(&mut drawer);
 
     image_decoder(&mut reader, &mut options).expect("alpha AVIS should decode");
     assert_eq!(drawer.draw_buffers.len(), 5);
@@ -746,10 +735,7 @@ fn avif_animation_is_stored_as_five_full_canvas_layers() {
     };
     let mut reader = BytesReader::new(&data);
     let mut image = ImageBuffer::new();
-    let mut options = DecodeOptions {
-        debug_flag: 0,
-        drawer: &mut image,
-    };
+    let mut options = DecodeOptions::new(&mut image);
 
     image_decoder(&mut reader, &mut options).expect("animated AVIS should populate ImageBuffer");
     let layers = image
@@ -773,10 +759,7 @@ fn avif_animation_round_trips_to_five_frame_apng() {
     };
     let mut reader = BytesReader::new(&data);
     let mut image = ImageBuffer::new();
-    let mut decode = DecodeOptions {
-        debug_flag: 0,
-        drawer: &mut image,
-    };
+    let mut decode = DecodeOptions::new(&mut image);
     image_decoder(&mut reader, &mut decode).expect("animated AVIS should populate ImageBuffer");
 
     let mut encode = EncodeOptions {
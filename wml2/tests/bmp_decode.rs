@@ -0,0 +1,169 @@
+use wml2::draw::{ImageBuffer, image_load};
+use wml2::metadata::DataMap;
+
+const PAPER: [u8; 3] = [250, 240, 230];
+const INK: [u8; 3] = [10, 20, 30];
+
+/// Builds an OS/2 2.x bitmap. `header_size` cuts BITMAPINFOHEADER2 short;
+/// `os2_fields` are usUnits, usReserved, usRecording, usRendering, cSize1
+/// and cSize2.
+fn os2_bitmap(
+    header_size: u32,
+    width: u32,
+    height: u32,
+    bit_count: u16,
+    compression: u32,
+    os2_fields: [u32; 6],
+    palette: &[[u8; 3]],
+    pixels: &[u8],
+) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&header_size.to_le_bytes());
+    header.extend_from_slice(&width.to_le_bytes());
+    header.extend_from_slice(&height.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&bit_count.to_le_bytes());
+    header.extend_from_slice(&compression.to_le_bytes());
+    header.extend_from_slice(&(pixels.len() as u32).to_le_bytes());
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+    header.extend_from_slice(&[0; 4]);
+    for field in &os2_fields[..4] {
+        header.extend_from_slice(&(*field as u16).to_le_bytes());
+    }
+    header.extend_from_slice(&os2_fields[4].to_le_bytes());
+    header.extend_from_slice(&os2_fields[5].to_le_bytes());
+    header.extend_from_slice(&[0; 8]);
+    assert_eq!(header.len(), 64);
+    header.truncate(header_size as usize);
+
+    let offbits = 14 + header.len() + palette.len() * 4;
+    let mut data = Vec::new();
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&((offbits + pixels.len()) as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&(offbits as u32).to_le_bytes());
+    data.extend_from_slice(&header);
+    for color in palette {
+        data.extend_from_slice(&[color[2], color[1], color[0], 0]);
+    }
+    data.extend_from_slice(pixels);
+    data
+}
+
+/// Packs a string of '0' and '1' MSB first, ignoring spaces.
+fn bits(code: &str) -> Vec<u8> {
+    let code: Vec<u8> = code.bytes().filter(|b| *b != b' ').collect();
+    let mut buf = vec![0; code.len().div_ceil(8)];
+    for (i, bit) in code.iter().enumerate() {
+        if *bit == b'1' {
+            buf[i / 8] |= 0x80 >> (i % 8);
+        }
+    }
+    buf
+}
+
+fn rgba_rows(rows: &[&[[u8; 3]]]) -> Vec<u8> {
+    let mut rgba = Vec::new();
+    for row in rows {
+        for color in *row {
+            rgba.extend_from_slice(color);
+            rgba.push(0xff);
+        }
+    }
+    rgba
+}
+
+fn metadata(image: &ImageBuffer, key: &str) -> DataMap {
+    image
+        .metadata
+        .as_ref()
+        .unwrap()
+        .get(key)
+        .unwrap_or(&DataMap::None)
+        .clone()
+}
+
+#[test]
+fn decode_os2_huffman_1d() {
+    // Bottom row first: 3 white, 2 black, 3 white; then 8 black.
+    let pixels = bits(
+        "000000000001 1000 11 1000 \
+         000000000001 00110101 000101 \
+         000000000001 000000000001",
+    );
+    let data = os2_bitmap(64, 8, 2, 1, 3, [0; 6], &[PAPER, INK], &pixels);
+
+    let image = image_load(&data).unwrap();
+    let expected = rgba_rows(&[
+        &[INK; 8],
+        &[PAPER, PAPER, PAPER, INK, INK, PAPER, PAPER, PAPER],
+    ]);
+    assert_eq!(image.buffer.as_ref().unwrap(), &expected);
+    assert_eq!(
+        metadata(&image, "compression"),
+        DataMap::Ascii("Huffman 1D".to_string())
+    );
+
+    // OS/2 2.x bitmaps cut to 40 bytes look like BI_BITFIELDS
+    let data = os2_bitmap(40, 8, 2, 1, 3, [0; 6], &[PAPER, INK], &pixels);
+    assert_eq!(image_load(&data).unwrap().buffer.unwrap(), expected);
+}
+
+#[test]
+fn decode_os2_rle24_with_halftoning_fields() {
+    const A: [u8; 3] = [200, 10, 10];
+    const B: [u8; 3] = [10, 200, 10];
+    const C: [u8; 3] = [10, 10, 200];
+    let bgr = |color: [u8; 3]| [color[2], color[1], color[0]];
+    let mut pixels = Vec::new();
+    // bottom row: 2 x A, absolute B C A (9 bytes + pad)
+    pixels.push(2);
+    pixels.extend_from_slice(&bgr(A));
+    pixels.extend_from_slice(&[0, 3]);
+    for color in [B, C, A] {
+        pixels.extend_from_slice(&bgr(color));
+    }
+    pixels.extend_from_slice(&[0, 0, 0]);
+    // top row: 5 x C
+    pixels.push(5);
+    pixels.extend_from_slice(&bgr(C));
+    pixels.extend_from_slice(&[0, 0, 0, 1]);
+
+    let data = os2_bitmap(64, 5, 2, 24, 4, [0, 0, 0, 2, 8, 4], &[], &pixels);
+    let image = image_load(&data).unwrap();
+    assert_eq!(
+        image.buffer.as_ref().unwrap(),
+        &rgba_rows(&[&[C; 5], &[A, A, B, C, A]])
+    );
+    assert_eq!(
+        metadata(&image, "compression"),
+        DataMap::Ascii("RLE24".to_string())
+    );
+    assert_eq!(
+        metadata(&image, "halftoning"),
+        DataMap::Ascii("PANDA".to_string())
+    );
+    assert_eq!(
+        metadata(&image, "halftoning parameters"),
+        DataMap::UIntAllay(vec![8, 4])
+    );
+}
+
+#[test]
+fn decode_short_os2_headers_and_reject_unknown_row_order() {
+    // 16-byte BITMAPINFOHEADER2: no compression, 24-bit rows padded to 4 bytes
+    let pixels = [
+        INK[2], INK[1], INK[0], PAPER[2], PAPER[1], PAPER[0], 0, 0, //
+        PAPER[2], PAPER[1], PAPER[0], INK[2], INK[1], INK[0], 0, 0,
+    ];
+    let data = os2_bitmap(16, 2, 2, 24, 0, [0; 6], &[], &pixels);
+    let image = image_load(&data).unwrap();
+    assert_eq!(
+        image.buffer.unwrap(),
+        rgba_rows(&[&[PAPER, INK], &[INK, PAPER]])
+    );
+
+    let data = os2_bitmap(64, 2, 2, 24, 0, [0, 0, 1, 0, 0, 0], &[], &pixels);
+    assert!(image_load(&data).is_err());
+}
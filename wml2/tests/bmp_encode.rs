@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+
+use wml2::draw::{EncodeOptions, ImageBuffer, image_encoder, image_load};
+use wml2::metadata::DataMap;
+use wml2::util::ImageFormat;
+
+const COLORS: [[u8; 4]; 4] = [
+    [248, 0, 0, 255],
+    [0, 252, 0, 255],
+    [0, 0, 248, 255],
+    [248, 252, 248, 255],
+];
+
+/// Vertical stripes of varying width, so rows mix long runs and short
+/// literal runs.
+fn striped_rgba(width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let index = if x < 5 { 0 } else { (x + y) % COLORS.len() };
+            rgba.extend_from_slice(&COLORS[index]);
+        }
+    }
+    rgba
+}
+
+fn encode_bmp(width: usize, height: usize, rgba: Vec<u8>, options: &[(&str, DataMap)]) -> Vec<u8> {
+    let mut image = ImageBuffer::from_buffer(width, height, rgba);
+    let options: HashMap<String, DataMap> = options
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect();
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: Some(options),
+    };
+    image_encoder(&mut encode, ImageFormat::Bmp).unwrap()
+}
+
+fn encode_error(options: &[(&str, DataMap)]) -> String {
+    let mut image = ImageBuffer::from_buffer(2, 2, striped_rgba(2, 2));
+    let options: HashMap<String, DataMap> = options
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect();
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: Some(options),
+    };
+    image_encoder(&mut encode, ImageFormat::Bmp)
+        .unwrap_err()
+        .to_string()
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn decode_rgba(data: &[u8]) -> Vec<u8> {
+    image_load(data).unwrap().buffer.unwrap()
+}
+
+#[test]
+fn bmp_encoder_writes_v5_bitfields_argb() {
+    let mut rgba = striped_rgba(7, 3);
+    rgba[3] = 0;
+    rgba[7] = 128;
+    let data = encode_bmp(7, 3, rgba.clone(), &[("bit_count", DataMap::UInt(32))]);
+
+    assert_eq!(u32_at(&data, 14), 124);
+    assert_eq!(u16_at(&data, 28), 32);
+    assert_eq!(u32_at(&data, 30), 3);
+    assert_eq!(u32_at(&data, 54), 0x00ff_0000);
+    assert_eq!(u32_at(&data, 66), 0xff00_0000);
+    assert_eq!(u32_at(&data, 70), 0x7352_4742);
+    assert_eq!(decode_rgba(&data), rgba);
+
+    let data = encode_bmp(
+        7,
+        3,
+        rgba.clone(),
+        &[
+            ("bit_count", DataMap::UInt(32)),
+            ("header", DataMap::Ascii("v4".to_string())),
+        ],
+    );
+    assert_eq!(u32_at(&data, 14), 108);
+    assert_eq!(u32_at(&data, 10), 14 + 108);
+    assert_eq!(decode_rgba(&data), rgba);
+}
+
+#[test]
+fn bmp_encoder_writes_indexed_palettes() {
+    let rgba = striped_rgba(13, 5);
+    for bits in [4, 8] {
+        let data = encode_bmp(13, 5, rgba.clone(), &[("bit_count", DataMap::UInt(bits))]);
+        assert_eq!(u16_at(&data, 28), bits as u16);
+        assert_eq!(u32_at(&data, 30), 0);
+        assert_eq!(u32_at(&data, 46), 4);
+        assert_eq!(u32_at(&data, 10), 14 + 40 + 4 * 4);
+        assert_eq!(decode_rgba(&data), rgba);
+    }
+
+    let mut two = Vec::new();
+    for index in 0..9 * 3 {
+        two.extend_from_slice(&COLORS[index % 3 % 2]);
+    }
+    let data = encode_bmp(
+        9,
+        3,
+        two.clone(),
+        &[("bits", DataMap::Ascii("1".to_string()))],
+    );
+    assert_eq!(u16_at(&data, 28), 1);
+    assert_eq!(u32_at(&data, 46), 2);
+    assert_eq!(decode_rgba(&data), two);
+}
+
+#[test]
+fn bmp_encoder_compresses_rle4_and_rle8() {
+    let rgba = striped_rgba(21, 6);
+    let data = encode_bmp(
+        21,
+        6,
+        rgba.clone(),
+        &[("compression", DataMap::Ascii("rle".to_string()))],
+    );
+    assert_eq!(u16_at(&data, 28), 8);
+    assert_eq!(u32_at(&data, 30), 1);
+    assert_eq!(&data[data.len() - 2..], &[0, 1]);
+    assert_eq!(decode_rgba(&data), rgba);
+
+    let data = encode_bmp(
+        21,
+        6,
+        rgba.clone(),
+        &[("compression", DataMap::Ascii("rle4".to_string()))],
+    );
+    assert_eq!(u16_at(&data, 28), 4);
+    assert_eq!(u32_at(&data, 30), 2);
+    assert_eq!(
+        u32_at(&data, 34) as usize,
+        data.len() - u32_at(&data, 10) as usize
+    );
+    assert_eq!(decode_rgba(&data), rgba);
+}
+
+#[test]
+fn bmp_encoder_writes_16bit_555_and_565() {
+    let rgba = striped_rgba(5, 2);
+    let data = encode_bmp(5, 2, rgba.clone(), &[("bit_count", DataMap::UInt(16))]);
+    assert_eq!(u16_at(&data, 28), 16);
+    assert_eq!(u32_at(&data, 30), 0);
+    let decoded = decode_rgba(&data);
+    for (decoded, source) in decoded.chunks_exact(4).zip(rgba.chunks_exact(4)) {
+        for channel in 0..3 {
+            assert_eq!(decoded[channel] & 0xf8, source[channel] & 0xf8);
+        }
+    }
+
+    let data = encode_bmp(
+        5,
+        2,
+        rgba.clone(),
+        &[
+            ("bit_count", DataMap::UInt(16)),
+            ("rgb16", DataMap::UInt(565)),
+        ],
+    );
+    assert_eq!(u32_at(&data, 30), 3);
+    assert_eq!(u32_at(&data, 54), 0xf800);
+    assert_eq!(u32_at(&data, 58), 0x07e0);
+    assert_eq!(u32_at(&data, 62), 0x001f);
+    assert_eq!(decode_rgba(&data), rgba);
+}
+
+#[test]
+fn bmp_encoder_writes_top_down_rows() {
+    let rgba = striped_rgba(6, 4);
+    let bottom_up = encode_bmp(6, 4, rgba.clone(), &[]);
+    let top_down = encode_bmp(
+        6,
+        4,
+        rgba.clone(),
+        &[("top_down", DataMap::Ascii("true".to_string()))],
+    );
+
+    assert_eq!(u32_at(&top_down, 22) as i32, -4);
+    assert_eq!(top_down.len(), bottom_up.len());
+    // first stored row is the top row
+    assert_eq!(&top_down[54..57], &[0, 0, 248]);
+    assert_eq!(decode_rgba(&top_down), rgba);
+    // The same on/off spellings as the TIFF encoder are accepted.
+    for value in ["on", "1", "yes"] {
+        let data = encode_bmp(
+            6,
+            4,
+            rgba.clone(),
+            &[("top_down", DataMap::Ascii(value.to_string()))],
+        );
+        assert_eq!(data, top_down, "{value}");
+    }
+}
+
+#[test]
+fn bmp_encoder_rejects_inconsistent_options() {
+    assert!(encode_error(&[("bit_count", DataMap::UInt(2))]).contains("bit_count"));
+    assert!(
+        encode_error(&[
+            ("bit_count", DataMap::UInt(24)),
+            ("compression", DataMap::Ascii("rle".to_string())),
+        ])
+        .contains("RLE")
+    );
+    assert!(
+        encode_error(&[
+            ("compression", DataMap::Ascii("rle8".to_string())),
+            ("top_down", DataMap::Ascii("true".to_string())),
+        ])
+        .contains("top-down")
+    );
+    assert!(encode_error(&[("rgb16", DataMap::UInt(565))]).contains("rgb16"));
+    assert!(encode_error(&[("header", DataMap::Ascii("v2".to_string()))]).contains("header"));
+    assert!(
+        encode_error(&[
+            ("header", DataMap::Ascii("v4".to_string())),
+            ("icc_profile", DataMap::ICCProfile(vec![0; 8])),
+        ])
+        .contains("v5")
+    );
+}
+
+fn metadata(image: &ImageBuffer, key: &str) -> DataMap {
+    image
+        .metadata
+        .as_ref()
+        .unwrap()
+        .get(key)
+        .unwrap_or(&DataMap::None)
+        .clone()
+}
+
+fn ascii(value: DataMap) -> String {
+    match value {
+        DataMap::Ascii(value) => value,
+        other => panic!("expected ASCII metadata, got {other:?}"),
+    }
+}
+
+#[test]
+fn bmp_v5_embeds_and_decodes_icc_profiles() {
+    let rgba = striped_rgba(6, 3);
+    let icc: Vec<u8> = (0..=255).collect();
+    let data = encode_bmp(
+        6,
+        3,
+        rgba.clone(),
+        &[("icc_profile", DataMap::ICCProfile(icc.clone()))],
+    );
+    assert_eq!(u32_at(&data, 14), 124);
+    assert_eq!(u32_at(&data, 70), 0x4d42_4544);
+    let profile_data = u32_at(&data, 14 + 112) as usize;
+    assert_eq!(u32_at(&data, 14 + 116) as usize, icc.len());
+    assert_eq!(&data[14 + profile_data..], &icc[..]);
+    assert_eq!(u32_at(&data, 2) as usize, data.len());
+
+    let image = image_load(&data).unwrap();
+    assert_eq!(image.buffer.as_ref().unwrap(), &rgba);
+    assert_eq!(
+        metadata(&image, "ICC Profile"),
+        DataMap::ICCProfile(icc.clone())
+    );
+    assert_eq!(ascii(metadata(&image, "color space")), "Embedded profile");
+    assert_eq!(ascii(metadata(&image, "rendering intent")), "Perceptual");
+
+    // re-encoding keeps the decoded profile only with an explicit v5 header
+    let mut decoded = image_load(&data).unwrap();
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut decoded,
+        options: None,
+    };
+    let plain = image_encoder(&mut encode, ImageFormat::Bmp).unwrap();
+    assert_eq!(u32_at(&plain, 14), 40);
+    assert_eq!(
+        metadata(&image_load(&plain).unwrap(), "ICC Profile"),
+        DataMap::None
+    );
+
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut decoded,
+        options: Some(HashMap::from([(
+            "header".to_string(),
+            DataMap::Ascii("v5".to_string()),
+        )])),
+    };
+    let data = image_encoder(&mut encode, ImageFormat::Bmp).unwrap();
+    assert_eq!(u32_at(&data, 14), 124);
+    assert_eq!(
+        metadata(&image_load(&data).unwrap(), "ICC Profile"),
+        DataMap::ICCProfile(icc)
+    );
+}
+
+#[test]
+fn bmp_v5_reports_calibrated_and_linked_color_spaces() {
+    let rgba = striped_rgba(4, 2);
+    let srgb = encode_bmp(
+        4,
+        2,
+        rgba.clone(),
+        &[("header", DataMap::Ascii("v5".to_string()))],
+    );
+    assert_eq!(
+        ascii(metadata(&image_load(&srgb).unwrap(), "color space")),
+        "sRGB"
+    );
+
+    let mut calibrated = srgb.clone();
+    calibrated[70..74].copy_from_slice(&0u32.to_le_bytes());
+    // red endpoint X = 0.64, gamma 2.2 for every channel
+    calibrated[74..78].copy_from_slice(&((0.64 * (1u64 << 30) as f64) as u32).to_le_bytes());
+    for offset in [110, 114, 118] {
+        calibrated[offset..offset + 4].copy_from_slice(&((2.2 * 65536.0) as u32).to_le_bytes());
+    }
+    let image = image_load(&calibrated).unwrap();
+    assert_eq!(ascii(metadata(&image, "color space")), "Calibrated RGB");
+    let DataMap::FloatAllay(endpoints) = metadata(&image, "color space endpoints") else {
+        panic!("missing endpoints");
+    };
+    assert_eq!(endpoints.len(), 9);
+    assert!((endpoints[0] - 0.64).abs() < 1e-6);
+    let DataMap::FloatAllay(gamma) = metadata(&image, "gamma") else {
+        panic!("missing gamma");
+    };
+    assert!(gamma.iter().all(|gamma| (gamma - 2.2).abs() < 1e-4));
+
+    let mut linked = srgb.clone();
+    let name = b"C:\\profiles\\display.icm\0";
+    linked[70..74].copy_from_slice(&0x4c49_4e4bu32.to_le_bytes());
+    let profile_data = (linked.len() - 14) as u32;
+    linked[126..130].copy_from_slice(&profile_data.to_le_bytes());
+    linked[130..134].copy_from_slice(&(name.len() as u32).to_le_bytes());
+    linked.extend_from_slice(name);
+    let image = image_load(&linked).unwrap();
+    assert_eq!(ascii(metadata(&image, "color space")), "Linked profile");
+    assert_eq!(
+        ascii(metadata(&image, "linked profile")),
+        "C:\\profiles\\display.icm"
+    );
+    assert_eq!(image.buffer.unwrap(), rgba);
+
+    // a profile outside the file is skipped
+    let mut broken = srgb;
+    broken[70..74].copy_from_slice(&0x4d42_4544u32.to_le_bytes());
+    broken[126..130].copy_from_slice(&0x10000u32.to_le_bytes());
+    broken[130..134].copy_from_slice(&16u32.to_le_bytes());
+    let image = image_load(&broken).unwrap();
+    assert_eq!(metadata(&image, "ICC Profile"), DataMap::None);
+    assert_eq!(image.buffer.unwrap(), rgba);
+}
@@ -0,0 +1,258 @@
+use wml2::draw::{DecodeOptions, ImageBuffer, image_load, image_loader, image_to};
+use wml2::metadata::DataMap;
+use wml2::util::ImageFormat;
+
+/// Logical screen with a black and white global color table.
+fn gif_header(width: u16, height: u16) -> Vec<u8> {
+    let mut data = b"GIF89a".to_vec();
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.extend_from_slice(&[0x80, 0x00, 0x00]);
+    data.extend_from_slice(&[0x00, 0x00, 0x00, 0xff, 0xff, 0xff]);
+    data
+}
+
+/// A 1x1 image of color index 0.
+fn single_pixel_image() -> Vec<u8> {
+    vec![
+        0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00,
+    ]
+}
+
+/// A 1x1 image of color index 1.
+fn single_white_pixel_image() -> Vec<u8> {
+    vec![
+        0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x4c, 0x01, 0x00,
+    ]
+}
+
+/// Decodes `data`, returning the image and the warning text.
+fn decode_lenient(data: &[u8]) -> (ImageBuffer, String) {
+    let mut image = ImageBuffer::new();
+    let mut option = DecodeOptions::new(&mut image);
+    let warnings = image_loader(data, &mut option).unwrap();
+    let text = warnings.map(|warnings| warnings.to_string());
+    (image, text.unwrap_or_default())
+}
+
+fn gradient_gif(width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            rgba.extend_from_slice(&[(x * 16) as u8, (y * 16) as u8, 0x80, 0xff]);
+        }
+    }
+    let mut image = ImageBuffer::from_buffer(width, height, rgba);
+    image_to(&mut image, ImageFormat::Gif, None).unwrap()
+}
+
+fn plain_text(grid: [u16; 4], cell: (u8, u8), colors: (u8, u8), text: &[u8]) -> Vec<u8> {
+    let mut data = vec![0x21, 0x01, 0x0c];
+    for value in grid {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(&[cell.0, cell.1, colors.0, colors.1]);
+    for chunk in text.chunks(255) {
+        data.push(chunk.len() as u8);
+        data.extend_from_slice(chunk);
+    }
+    data.push(0x00);
+    data
+}
+
+fn pixel(buffer: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+    let offset = (y * width + x) * 4;
+    buffer[offset..offset + 4].try_into().unwrap()
+}
+
+const WHITE: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
+
+#[test]
+fn decode_gif_renders_plain_text_extension() {
+    let mut data = gif_header(16, 8);
+    data.extend(plain_text([0, 0, 16, 8], (8, 8), (1, 0), b"Hi"));
+    data.push(0x3b);
+
+    let image = image_load(&data).unwrap();
+    let buffer = image.buffer.as_ref().unwrap();
+    // First row of 'H' is 0x33 and of 'i' is 0x0c, bit 0 leftmost.
+    let row: Vec<bool> = (0..16).map(|x| pixel(buffer, 16, x, 0) == WHITE).collect();
+    let expected: Vec<bool> = [0x33_u8, 0x0c]
+        .iter()
+        .flat_map(|bits| (0..8).map(move |bit| (bits >> bit) & 1 == 1))
+        .collect();
+    assert_eq!(row, expected);
+    for x in 0..16 {
+        assert_eq!(pixel(buffer, 16, x, 7), BLACK);
+    }
+
+    let metadata = image.metadata.as_ref().unwrap();
+    match metadata.get("plain_text:1") {
+        Some(DataMap::Ascii(text)) => assert_eq!(text, "Hi"),
+        other => panic!("unexpected plain text metadata {other:?}"),
+    }
+}
+
+#[test]
+fn decode_gif_scales_plain_text_to_tall_cells() {
+    let mut data = gif_header(8, 16);
+    data.extend(plain_text([0, 0, 8, 16], (8, 16), (1, 0), b"H"));
+    data.push(0x3b);
+
+    let image = image_load(&data).unwrap();
+    let buffer = image.buffer.as_ref().unwrap();
+    // Each row of the 8x8 glyph covers two rows of a 16-pixel cell.
+    for y in 0..16 {
+        for x in 0..8 {
+            assert_eq!(pixel(buffer, 8, x, y), pixel(buffer, 8, x, y / 2 * 2));
+        }
+    }
+    assert_eq!(pixel(buffer, 8, 0, 1), WHITE);
+    assert_eq!(pixel(buffer, 8, 2, 1), BLACK);
+}
+
+#[test]
+fn decode_gif_plain_text_honors_transparent_background() {
+    let mut data = gif_header(8, 8);
+    data.extend_from_slice(&[0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00]);
+    data.extend(plain_text([0, 0, 8, 8], (8, 8), (1, 0), b"-"));
+    data.push(0x3b);
+
+    let image = image_load(&data).unwrap();
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(pixel(buffer, 8, 0, 3), WHITE);
+    assert_eq!(pixel(buffer, 8, 0, 0)[3], 0);
+}
+
+#[test]
+fn decode_gif_clips_plain_text_grid_to_logical_screen() {
+    let mut data = gif_header(16, 8);
+    // The grid claims 65535x65535 pixels; only the screen area is drawn.
+    data.extend(plain_text([0, 0, 0xffff, 0xffff], (8, 8), (1, 0), b"Hi"));
+    data.extend(plain_text([16, 0, 8, 8], (8, 8), (1, 0), b"H"));
+    data.push(0x3b);
+
+    let (image, warnings) = decode_lenient(&data);
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(buffer.len(), 16 * 8 * 4);
+    assert_eq!(pixel(buffer, 16, 0, 0), WHITE);
+    assert_eq!(pixel(buffer, 16, 2, 0), BLACK);
+    assert!(
+        warnings.contains("outside the logical screen"),
+        "{warnings}"
+    );
+}
+
+#[test]
+fn decode_gif_exposes_xmp_and_icc_application_extensions() {
+    let xmp = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF/></x:xmpmeta>";
+    let icc = vec![0x5a_u8; 300];
+
+    let mut data = gif_header(1, 1);
+    data.extend_from_slice(&[0x21, 0xff, 0x0b]);
+    data.extend_from_slice(b"XMP DataXMP");
+    data.extend_from_slice(xmp.as_bytes());
+    data.push(0x01);
+    data.extend((0..=0xff_u8).rev());
+    data.push(0x00);
+
+    data.extend_from_slice(&[0x21, 0xff, 0x0b]);
+    data.extend_from_slice(b"ICCRGBG1012");
+    for chunk in icc.chunks(255) {
+        data.push(chunk.len() as u8);
+        data.extend_from_slice(chunk);
+    }
+    data.push(0x00);
+
+    data.extend(single_pixel_image());
+    data.push(0x3b);
+
+    let image = image_load(&data).unwrap();
+    assert_eq!(image.buffer.as_ref().unwrap(), &BLACK.to_vec());
+    let metadata = image.metadata.as_ref().unwrap();
+    match metadata.get("XMP") {
+        Some(DataMap::Ascii(text)) => assert_eq!(text, xmp),
+        other => panic!("unexpected XMP metadata {other:?}"),
+    }
+    match metadata.get("ICC Profile") {
+        Some(DataMap::ICCProfile(profile)) => assert_eq!(profile, &icc),
+        other => panic!("unexpected ICC metadata {other:?}"),
+    }
+}
+
+#[test]
+fn decode_gif_keeps_rows_of_a_truncated_frame() {
+    let data = gradient_gif(16, 16);
+    let complete = image_load(&data).unwrap();
+    let complete = complete.buffer.as_ref().unwrap();
+
+    let truncated = &data[..data.len() - 64];
+    let (image, warnings) = decode_lenient(truncated);
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(buffer[..16 * 4], complete[..16 * 4]);
+    assert!(warnings.contains("Truncated data"), "{warnings}");
+    assert!(warnings.contains("Missing rows"), "{warnings}");
+
+    // Missing rows are filled with the logical screen background color, or
+    // left transparent without a global color table.
+    let background = if data[10] & 0x80 != 0 {
+        let palette = 13 + data[11] as usize * 3;
+        [data[palette], data[palette + 1], data[palette + 2], 0xff]
+    } else {
+        [0, 0, 0, 0]
+    };
+    for x in 0..16 {
+        assert_eq!(pixel(buffer, 16, x, 15), background);
+    }
+}
+
+#[test]
+fn decode_gif_accepts_missing_trailer() {
+    let mut data = gif_header(1, 1);
+    data.extend(single_white_pixel_image());
+
+    let (image, warnings) = decode_lenient(&data);
+    assert_eq!(image.buffer.as_ref().unwrap(), &WHITE.to_vec());
+    assert!(warnings.contains("Truncated data"), "{warnings}");
+}
+
+#[test]
+fn decode_gif_pads_frame_after_invalid_lzw_code() {
+    // Background index 1 (white); the 2x2 frame stops after one black pixel.
+    let mut data = gif_header(2, 2);
+    data[11] = 1;
+    data.extend_from_slice(&[0x2c, 0, 0, 0, 0, 2, 0, 2, 0, 0]);
+    data.extend_from_slice(&[0x02, 0x02, 0xc4, 0x01, 0x00, 0x3b]);
+
+    let (image, warnings) = decode_lenient(&data);
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(pixel(buffer, 2, 0, 0), BLACK);
+    assert_eq!(pixel(buffer, 2, 1, 0), WHITE);
+    assert_eq!(pixel(buffer, 2, 0, 1), WHITE);
+    assert!(warnings.contains("Invalid LZW code"), "{warnings}");
+    assert!(warnings.contains("Missing rows"), "{warnings}");
+}
+
+#[test]
+fn decode_gif_skips_malformed_data_between_frames() {
+    let mut data = gif_header(1, 1);
+    data.extend(single_pixel_image());
+    // A broken sub-block chain leaves stray bytes before the next frame.
+    data.extend_from_slice(&[0x07, 0x13, 0x99, 0x2c, 0x40]);
+    data.extend(single_white_pixel_image());
+    data.push(0x3b);
+
+    let (image, warnings) = decode_lenient(&data);
+    let frames = image.animation.as_ref().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[1].buffer, WHITE.to_vec());
+    assert!(warnings.contains("Malformed block"), "{warnings}");
+}
+
+#[test]
+fn decode_gif_without_frames_still_fails_on_garbage() {
+    let mut data = gif_header(1, 1);
+    data.extend_from_slice(&[0x07, 0x13, 0x99]);
+    assert!(image_load(&data).is_err());
+}
@@ -1,7 +1,10 @@
+use std::collections::HashMap;
+
 use wml2::draw::{
     AnimationLayer, EncodeOptions, ImageBuffer, ImageRect, NextBlend, NextDispose, NextOption,
     NextOptions, image_encoder, image_load,
 };
+use wml2::metadata::DataMap;
 use wml2::util::ImageFormat;
 
 fn solid_rgba(width: usize, height: usize, rgba: [u8; 4]) -> Vec<u8> {
@@ -301,3 +304,480 @@ fn encode_gif_third_animation_canvas_via_public_api() {
     assert_eq!(decoded.width, 4);
     assert_eq!(decoded.height, 4);
 }
+
+/// Composes decoded GIF layers onto full canvases following the disposal rules.
+fn compose_gif_layers(width: usize, height: usize, layers: &[AnimationLayer]) -> Vec<Vec<u8>> {
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut canvases = Vec::with_capacity(layers.len());
+    for layer in layers {
+        let previous = canvas.clone();
+        let (x, y) = (layer.start_x as usize, layer.start_y as usize);
+        let blend = matches!(layer.control.blend, Some(NextBlend::Source));
+        apply_frame(
+            &mut canvas,
+            width,
+            x,
+            y,
+            layer.width,
+            layer.height,
+            &layer.buffer,
+            blend,
+        );
+        canvases.push(canvas.clone());
+        match layer.control.dispose_option {
+            Some(NextDispose::Background) => {
+                clear_rect(&mut canvas, width, x, y, layer.width, layer.height)
+            }
+            Some(NextDispose::Previous) => canvas = previous,
+            _ => {}
+        }
+    }
+    canvases
+}
+
+fn optimize_option() -> Option<HashMap<String, DataMap>> {
+    let mut options = HashMap::new();
+    options.insert("optimize".to_string(), DataMap::UInt(1));
+    Some(options)
+}
+
+fn encode_gif_with(image: &mut ImageBuffer, options: Option<HashMap<String, DataMap>>) -> Vec<u8> {
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: image,
+        options,
+    };
+    image_encoder(&mut encode, ImageFormat::Gif).unwrap()
+}
+
+/// Full-canvas frames of a cursor moving over a static gradient.
+fn screen_recording_frames(width: usize, height: usize) -> Vec<Vec<u8>> {
+    let mut background = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            background.extend_from_slice(&[(x / 4 * 20) as u8, (y / 4 * 30) as u8, 96, 255]);
+        }
+    }
+    (0..4)
+        .map(|frame| {
+            let mut canvas = background.clone();
+            for y in 3..8 {
+                for x in frame * 6..frame * 6 + 4 {
+                    let offset = (y * width + x) * 4;
+                    canvas[offset..offset + 4].copy_from_slice(&[255, 255, 255, 255]);
+                }
+            }
+            canvas
+        })
+        .collect()
+}
+
+fn full_canvas_animation(width: usize, height: usize, canvases: &[Vec<u8>]) -> ImageBuffer {
+    let mut image = ImageBuffer::from_buffer(width, height, canvases[0].clone());
+    image.loop_count = Some(0);
+    image.animation = Some(
+        canvases
+            .iter()
+            .map(|canvas| AnimationLayer {
+                width,
+                height,
+                start_x: 0,
+                start_y: 0,
+                buffer: canvas.clone(),
+                control: frame_control(
+                    0,
+                    0,
+                    width,
+                    height,
+                    100,
+                    NextDispose::None,
+                    NextBlend::Override,
+                ),
+            })
+            .collect(),
+    );
+    image
+}
+
+#[test]
+fn encode_gif_optimize_uses_global_palette_and_changed_rectangles() {
+    let (width, height) = (40, 24);
+    let canvases = screen_recording_frames(width, height);
+
+    let plain = encode_gif_with(&mut full_canvas_animation(width, height, &canvases), None);
+    let optimized = encode_gif_with(
+        &mut full_canvas_animation(width, height, &canvases),
+        optimize_option(),
+    );
+
+    assert!(optimized.len() < plain.len());
+    // Logical screen descriptor announces a global color table.
+    assert_eq!(optimized[10] & 0x80, 0x80);
+
+    let decoded = image_load(&optimized).unwrap();
+    let frames = decoded.animation.as_ref().unwrap();
+    assert_eq!(frames.len(), 4);
+    assert!(
+        frames[1..]
+            .iter()
+            .all(|frame| frame.width * frame.height < width * height / 4)
+    );
+    assert_eq!(compose_gif_layers(width, height, frames), canvases);
+}
+
+#[test]
+fn encode_gif_optimize_clears_pixels_that_become_transparent() {
+    let expected: Vec<Vec<u8>> = expected_animation_frames()
+        .iter()
+        .map(|frame| flatten_partial_alpha_for_gif(frame))
+        .collect();
+    let data = encode_gif_with(&mut animated_image(), optimize_option());
+
+    let decoded = image_load(&data).unwrap();
+    let frames = decoded.animation.as_ref().unwrap();
+    assert_eq!(frames.len(), 3);
+    assert_eq!(compose_gif_layers(4, 4, frames), expected);
+}
+
+#[test]
+fn encode_gif_optimize_merges_identical_frames() {
+    let (width, height) = (40, 24);
+    let mut canvases = screen_recording_frames(width, height);
+    canvases.insert(1, canvases[0].clone());
+
+    let data = encode_gif_with(
+        &mut full_canvas_animation(width, height, &canvases),
+        optimize_option(),
+    );
+    let decoded = image_load(&data).unwrap();
+    let frames = decoded.animation.as_ref().unwrap();
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[0].control.await_time, 200);
+    canvases.remove(1);
+    assert_eq!(compose_gif_layers(width, height, frames), canvases);
+}
+
+#[test]
+fn encode_gif_optimize_dithers_many_colors_to_global_palette() {
+    let (width, height) = (40, 24);
+    let canvases: Vec<Vec<u8>> = (0..3)
+        .map(|frame| {
+            let mut canvas = Vec::with_capacity(width * height * 4);
+            for y in 0..height {
+                for x in 0..width {
+                    let moving = (x / 8 == frame) as usize * 128;
+                    canvas.extend_from_slice(&[(x * 6) as u8, (y * 10) as u8, moving as u8, 255]);
+                }
+            }
+            canvas
+        })
+        .collect();
+
+    let data = encode_gif_with(
+        &mut full_canvas_animation(width, height, &canvases),
+        optimize_option(),
+    );
+    let decoded = image_load(&data).unwrap();
+    let frames = decoded.animation.as_ref().unwrap();
+    assert_eq!(frames.len(), 3);
+    for (composed, expected) in compose_gif_layers(width, height, frames)
+        .iter()
+        .zip(&canvases)
+    {
+        let error: u64 = composed
+            .iter()
+            .zip(expected)
+            .map(|(left, right)| left.abs_diff(*right) as u64)
+            .sum();
+        assert!(error / (composed.len() as u64) < 16, "{error}");
+    }
+}
+
+#[test]
+fn encode_gif_applies_selected_dither() {
+    let (width, height) = (48, 32);
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            rgba.extend_from_slice(&[(x * 5) as u8, (y * 8) as u8, ((x + y) * 3) as u8, 255]);
+        }
+    }
+
+    let mut encoded = Vec::new();
+    for dither in ["none", "atkinson", "bayer4", "blue_noise"] {
+        let mut options = HashMap::new();
+        options.insert("dither".to_string(), DataMap::Ascii(dither.to_string()));
+        options.insert("serpentine".to_string(), DataMap::UInt(1));
+        let mut image = ImageBuffer::from_buffer(width, height, rgba.clone());
+        let data = encode_gif_with(&mut image, Some(options));
+        let decoded = image_load(&data).unwrap();
+        let decoded_rgba = decoded.buffer.as_ref().unwrap();
+        let error: u64 = decoded_rgba
+            .iter()
+            .zip(&rgba)
+            .map(|(left, right)| left.abs_diff(*right) as u64)
+            .sum();
+        assert!(error / (width * height * 4) as u64 <= 16, "{dither}");
+        encoded.push(data);
+    }
+    assert_ne!(encoded[0], encoded[2]);
+
+    let mut options = HashMap::new();
+    options.insert("dither".to_string(), DataMap::Ascii("stucki".to_string()));
+    let mut image = ImageBuffer::from_buffer(width, height, rgba);
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: Some(options),
+    };
+    assert!(image_encoder(&mut encode, ImageFormat::Gif).is_err());
+}
+
+fn gif_options(entries: &[(&str, DataMap)]) -> Option<HashMap<String, DataMap>> {
+    Some(
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect(),
+    )
+}
+
+/// Returns the bytes of the first graphic control extension and image
+/// descriptor.
+fn first_frame_headers(data: &[u8]) -> (&[u8], &[u8]) {
+    let gce = data
+        .windows(3)
+        .position(|window| window == [0x21, 0xf9, 0x04])
+        .unwrap();
+    let descriptor = gce + 8;
+    assert_eq!(data[descriptor], 0x2c);
+    (&data[gce..gce + 8], &data[descriptor..descriptor + 10])
+}
+
+fn eight_color_rgba(width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let color = (x + y * 3) % 8;
+            rgba.extend_from_slice(&[
+                if color & 1 != 0 { 255 } else { 0 },
+                if color & 2 != 0 { 255 } else { 0 },
+                if color & 4 != 0 { 255 } else { 0 },
+                255,
+            ]);
+        }
+    }
+    rgba
+}
+
+#[test]
+fn encode_gif_interlaced_round_trips() {
+    for (width, height) in [(7, 13), (5, 1), (3, 4), (9, 17)] {
+        let rgba = eight_color_rgba(width, height);
+        let mut image = ImageBuffer::from_buffer(width, height, rgba.clone());
+        let data = encode_gif_with(&mut image, gif_options(&[("interlace", DataMap::UInt(1))]));
+
+        let (_, descriptor) = first_frame_headers(&data);
+        assert_eq!(descriptor[9] & 0x40, 0x40);
+        let decoded = image_load(&data).unwrap();
+        assert_eq!(decoded.buffer.as_ref().unwrap(), &rgba, "{width}x{height}");
+    }
+}
+
+#[test]
+fn encode_gif_writes_comment_extension() {
+    let mut image = ImageBuffer::from_buffer(4, 4, eight_color_rgba(4, 4));
+    let comment = "wml2 ".repeat(60);
+    let data = encode_gif_with(
+        &mut image,
+        gif_options(&[("comment", DataMap::Ascii(comment.clone()))]),
+    );
+
+    assert!(data.windows(2).any(|window| window == [0x21, 0xfe]));
+    let decoded = image_load(&data).unwrap();
+    let metadata = decoded.metadata.as_ref().unwrap();
+    match metadata.get("comment:1") {
+        Some(DataMap::Ascii(text)) => assert_eq!(text, &comment),
+        other => panic!("unexpected comment metadata {other:?}"),
+    }
+}
+
+#[test]
+fn encode_gif_limits_palette_size() {
+    let rgba = eight_color_rgba(16, 16);
+    let mut image = ImageBuffer::from_buffer(16, 16, rgba);
+    let data = encode_gif_with(
+        &mut image,
+        gif_options(&[
+            ("max_colors", DataMap::UInt(4)),
+            ("dither", DataMap::Ascii("none".to_string())),
+        ]),
+    );
+
+    let (_, descriptor) = first_frame_headers(&data);
+    assert_eq!(descriptor[9] & 0x87, 0x81);
+    let decoded = image_load(&data).unwrap();
+    let mut colors: Vec<&[u8]> = decoded.buffer.as_ref().unwrap().chunks_exact(4).collect();
+    colors.sort();
+    colors.dedup();
+    assert!(colors.len() <= 4);
+}
+
+#[test]
+fn encode_gif_transparency_threshold_controls_semitransparent_pixels() {
+    let mut rgba = solid_rgba(4, 1, [200, 40, 40, 255]);
+    rgba[3] = 100;
+    rgba[7] = 200;
+    rgba[11] = 0;
+
+    let mut image = ImageBuffer::from_buffer(4, 1, rgba.clone());
+    let default = image_load(&encode_gif_with(&mut image, None)).unwrap();
+    let default = default.buffer.unwrap();
+    assert_ne!(default[3], 0);
+    assert_eq!(default[11], 0);
+
+    let mut image = ImageBuffer::from_buffer(4, 1, rgba.clone());
+    let data = encode_gif_with(
+        &mut image,
+        gif_options(&[("transparency_threshold", DataMap::UInt(128))]),
+    );
+    let decoded = image_load(&data).unwrap().buffer.unwrap();
+    assert_eq!(decoded[3], 0);
+    assert_ne!(decoded[7], 0);
+    assert_eq!(decoded[11], 0);
+
+    let mut image = ImageBuffer::from_buffer(4, 1, rgba);
+    let data = encode_gif_with(
+        &mut image,
+        gif_options(&[("transparency_threshold", DataMap::UInt(0))]),
+    );
+    let (gce, _) = first_frame_headers(&data);
+    assert_eq!(gce[3] & 0x01, 0);
+    let decoded = image_load(&data).unwrap().buffer.unwrap();
+    assert!(decoded.chunks_exact(4).all(|pixel| pixel[3] == 255));
+}
+
+#[test]
+fn encode_gif_still_transparent_index_and_disposal() {
+    let mut rgba = eight_color_rgba(4, 4);
+    rgba[3] = 0;
+    rgba[23] = 0;
+    let mut image = ImageBuffer::from_buffer(4, 4, rgba.clone());
+    let data = encode_gif_with(
+        &mut image,
+        gif_options(&[
+            ("transparent_index", DataMap::UInt(12)),
+            ("dispose", DataMap::UInt(2)),
+        ]),
+    );
+
+    let (gce, descriptor) = first_frame_headers(&data);
+    assert_eq!(gce[3], 0x01 | (2 << 2));
+    assert_eq!(gce[6], 12);
+    assert_eq!(descriptor[9] & 0x07, 3);
+    let decoded = image_load(&data).unwrap().buffer.unwrap();
+    for (decoded, source) in decoded.chunks_exact(4).zip(rgba.chunks_exact(4)) {
+        if source[3] == 0 {
+            assert_eq!(decoded[3], 0);
+        } else {
+            assert_eq!(decoded, source);
+        }
+    }
+}
+
+#[test]
+fn encode_gif_transparent_index_stays_within_max_colors() {
+    // Exact palette: 8 colors, padding and the transparent slot fill 16.
+    let mut rgba = eight_color_rgba(4, 4);
+    rgba[3] = 0;
+    let mut image = ImageBuffer::from_buffer(4, 4, rgba);
+    let data = encode_gif_with(
+        &mut image,
+        gif_options(&[
+            ("transparent_index", DataMap::UInt(15)),
+            ("max_colors", DataMap::UInt(16)),
+        ]),
+    );
+    let (gce, descriptor) = first_frame_headers(&data);
+    assert_eq!(gce[6], 15);
+    assert_eq!(descriptor[9] & 0x07, 3);
+    assert_eq!(image_load(&data).unwrap().buffer.unwrap()[3], 0);
+
+    // Quantized palette: the slot is reserved out of max_colors.
+    let mut rgba = Vec::new();
+    for index in 0..64u32 {
+        let value = (index * 4) as u8;
+        rgba.extend_from_slice(&[value, 255 - value, value / 2, 255]);
+    }
+    rgba[3] = 0;
+    let mut image = ImageBuffer::from_buffer(8, 8, rgba);
+    let data = encode_gif_with(
+        &mut image,
+        gif_options(&[
+            ("transparent_index", DataMap::UInt(3)),
+            ("max_colors", DataMap::UInt(4)),
+        ]),
+    );
+    let (gce, descriptor) = first_frame_headers(&data);
+    assert_eq!(gce[6], 3);
+    assert_eq!(descriptor[9] & 0x07, 1);
+    assert_eq!(image_load(&data).unwrap().buffer.unwrap()[3], 0);
+
+    let mut image = ImageBuffer::from_buffer(4, 4, eight_color_rgba(4, 4));
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: gif_options(&[
+            ("transparent_index", DataMap::UInt(8)),
+            ("max_colors", DataMap::UInt(8)),
+        ]),
+    };
+    assert!(image_encoder(&mut encode, ImageFormat::Gif).is_err());
+}
+
+#[test]
+fn encode_gif_overrides_loop_count() {
+    let canvases = vec![
+        solid_rgba(2, 2, [255, 0, 0, 255]),
+        solid_rgba(2, 2, [0, 0, 255, 255]),
+    ];
+    for optimize in [0, 1] {
+        let data = encode_gif_with(
+            &mut full_canvas_animation(2, 2, &canvases),
+            gif_options(&[
+                ("loop_count", DataMap::UInt(3)),
+                ("optimize", DataMap::UInt(optimize)),
+                ("interlace", DataMap::UInt(1)),
+            ]),
+        );
+        let netscape = data
+            .windows(11)
+            .position(|window| window == b"NETSCAPE2.0")
+            .unwrap();
+        assert_eq!(&data[netscape + 11..netscape + 16], &[3, 1, 3, 0, 0]);
+        let decoded = image_load(&data).unwrap();
+        assert_eq!(decoded.animation.as_ref().unwrap().len(), 2);
+    }
+}
+
+#[test]
+fn encode_gif_rejects_invalid_options() {
+    for (key, value) in [
+        ("dispose", DataMap::UInt(4)),
+        ("transparency_threshold", DataMap::UInt(256)),
+        ("max_colors", DataMap::UInt(1)),
+        ("comment", DataMap::UInt(1)),
+        ("loop_count", DataMap::SInt(-1)),
+    ] {
+        let mut image = ImageBuffer::from_buffer(2, 2, solid_rgba(2, 2, [0, 0, 0, 255]));
+        let mut encode = EncodeOptions {
+            debug_flag: 0,
+            drawer: &mut image,
+            options: gif_options(&[(key, value)]),
+        };
+        assert!(
+            image_encoder(&mut encode, ImageFormat::Gif).is_err(),
+            "{key}"
+        );
+    }
+}
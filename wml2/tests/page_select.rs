@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use wml2::draw::{
+    AnimationLayer, DecodeOptions, EncodeOptions, EncodePage, ImageBuffer, ImageRect, NextBlend,
+    NextDispose, NextOption, NextOptions, PageSource, image_encoder, image_load, image_loader,
+    image_page_count, image_to,
+};
+use wml2::metadata::DataMap;
+use wml2::util::ImageFormat;
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const GREEN: [u8; 4] = [0, 255, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+fn solid_rgba(width: usize, height: usize, rgba: [u8; 4]) -> Vec<u8> {
+    rgba.repeat(width * height)
+}
+
+fn layer(
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    rgba: [u8; 4],
+    dispose: NextDispose,
+) -> AnimationLayer {
+    let rect = ImageRect {
+        start_x: x,
+        start_y: y,
+        width,
+        height,
+    };
+    AnimationLayer {
+        width,
+        height,
+        start_x: x,
+        start_y: y,
+        buffer: solid_rgba(width, height, rgba),
+        control: NextOptions {
+            flag: NextOption::Continue,
+            await_time: 100,
+            image_rect: Some(rect),
+            dispose_option: Some(dispose),
+            blend: Some(NextBlend::Override),
+        },
+    }
+}
+
+/// Red canvas, a green square at (1, 1) that is disposed to the background,
+/// then a blue pixel at (0, 0).
+fn animated_image() -> ImageBuffer {
+    let mut image = ImageBuffer::from_buffer(4, 4, solid_rgba(4, 4, RED));
+    image.loop_count = Some(0);
+    image.animation = Some(vec![
+        layer(0, 0, 4, 4, RED, NextDispose::None),
+        layer(1, 1, 2, 2, GREEN, NextDispose::Background),
+        layer(0, 0, 1, 1, BLUE, NextDispose::None),
+    ]);
+    image
+}
+
+fn pixel(image: &ImageBuffer, x: usize, y: usize) -> [u8; 4] {
+    let offset = (y * image.width + x) * 4;
+    image.buffer.as_ref().unwrap()[offset..offset + 4]
+        .try_into()
+        .unwrap()
+}
+
+fn load_page(data: &[u8], page: u64) -> Result<ImageBuffer, Box<dyn std::error::Error>> {
+    let mut image = ImageBuffer::new();
+    let mut option = DecodeOptions::new(&mut image).with_option("page", DataMap::UInt(page));
+    image_loader(data, &mut option)?;
+    Ok(image)
+}
+
+#[test]
+fn animation_frames_are_composed_and_decoded_alone() {
+    for (format, image_format) in [("png", ImageFormat::Png), ("gif", ImageFormat::Gif)] {
+        let data = image_to(&mut animated_image(), image_format, None).unwrap();
+        assert_eq!(image_page_count(&data).unwrap(), 3, "{format}");
+
+        let first = load_page(&data, 0).unwrap();
+        assert!(first.animation.is_none());
+        assert_eq!(pixel(&first, 1, 1), RED, "{format}");
+
+        let second = load_page(&data, 1).unwrap();
+        assert_eq!((second.width, second.height), (4, 4));
+        assert_eq!(pixel(&second, 0, 0), RED, "{format}");
+        assert_eq!(pixel(&second, 2, 2), GREEN, "{format}");
+
+        let third = load_page(&data, 2).unwrap();
+        assert_eq!(pixel(&third, 0, 0), BLUE, "{format}");
+        assert_eq!(pixel(&third, 3, 3), RED, "{format}");
+        // The GIF encoder stores whole frames, so only APNG keeps the
+        // dispose-to-background of the green square.
+        if format == "png" {
+            assert_eq!(pixel(&third, 2, 2), [0, 0, 0, 0]);
+        }
+
+        assert!(load_page(&data, 3).is_err(), "{format}");
+    }
+}
+
+#[test]
+fn still_images_have_one_page() {
+    let data = image_to(
+        &mut ImageBuffer::from_buffer(2, 2, solid_rgba(2, 2, GREEN)),
+        ImageFormat::Png,
+        None,
+    )
+    .unwrap();
+    assert_eq!(image_page_count(&data).unwrap(), 1);
+    assert_eq!(pixel(&load_page(&data, 0).unwrap(), 1, 1), GREEN);
+    assert!(load_page(&data, 1).is_err());
+}
+
+#[test]
+fn tiff_page_is_decoded_directly() {
+    let pages = vec![
+        EncodePage::new(5, 3, solid_rgba(5, 3, RED)),
+        EncodePage::new(3, 2, solid_rgba(3, 2, GREEN)),
+        EncodePage::new(2, 4, solid_rgba(2, 4, BLUE))
+            .with_option("compression", DataMap::Ascii("lzw".to_string())),
+    ];
+    let mut source = PageSource::new(pages.into_iter());
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut source,
+        options: None,
+    };
+    let data = image_encoder(&mut encode, ImageFormat::Tiff).unwrap();
+    assert_eq!(image_page_count(&data).unwrap(), 3);
+
+    let page = load_page(&data, 2).unwrap();
+    assert!(page.animation.is_none());
+    assert_eq!((page.width, page.height), (2, 4));
+    assert_eq!(page.buffer.as_ref().unwrap(), &solid_rgba(2, 4, BLUE));
+    assert_eq!(
+        page.metadata.as_ref().unwrap().get("width"),
+        Some(&DataMap::UInt(2))
+    );
+
+    let page = load_page(&data, 1).unwrap();
+    assert_eq!(page.buffer.as_ref().unwrap(), &solid_rgba(3, 2, GREEN));
+    assert!(load_page(&data, 3).is_err());
+    assert_eq!(
+        image_load(&data)
+            .unwrap()
+            .animation
+            .map(|frames| frames.len()),
+        Some(2)
+    );
+}
+
+/// Builds an ICO whose entries hold PNG payloads.
+fn ico_with_pngs(images: &[(usize, [u8; 4])]) -> Vec<u8> {
+    let payloads: Vec<Vec<u8>> = images
+        .iter()
+        .map(|(size, rgba)| {
+            let mut image = ImageBuffer::from_buffer(*size, *size, solid_rgba(*size, *size, *rgba));
+            image_to(&mut image, ImageFormat::Png, None).unwrap()
+        })
+        .collect();
+    let mut data = vec![0, 0, 1, 0, images.len() as u8, 0];
+    let mut offset = 6 + 16 * images.len();
+    for ((size, _), payload) in images.iter().zip(&payloads) {
+        data.extend_from_slice(&[*size as u8, *size as u8, 0, 0, 1, 0, 32, 0]);
+        data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += payload.len();
+    }
+    for payload in payloads {
+        data.extend_from_slice(&payload);
+    }
+    data
+}
+
+#[test]
+fn ico_entry_is_selected_by_page() {
+    let data = ico_with_pngs(&[(2, RED), (4, GREEN), (3, BLUE)]);
+    assert_eq!(image_page_count(&data).unwrap(), 3);
+
+    let best = image_load(&data).unwrap();
+    assert_eq!(best.width, 4);
+
+    let page = load_page(&data, 2).unwrap();
+    assert_eq!((page.width, page.height), (3, 3));
+    assert_eq!(pixel(&page, 0, 0), BLUE);
+    assert_eq!(
+        page.metadata.as_ref().unwrap().get("ICO selected index"),
+        Some(&DataMap::UInt(2))
+    );
+    assert!(load_page(&data, 3).is_err());
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+
+use wml2::draw::{DecodeOptions, ImageBuffer, image_loader, image_to};
+use wml2::metadata::DataMap;
+use wml2::png::utils::CRC32;
+use wml2::util::ImageFormat;
+use wml2::warning::ImgWarnings;
+
+type Error = Box<dyn std::error::Error>;
+
+fn gray_png(value: u8) -> Vec<u8> {
+    let mut image = ImageBuffer::from_buffer(2, 1, vec![value, value, value, 255].repeat(2));
+    image_to(&mut image, ImageFormat::Png, None).unwrap()
+}
+
+fn chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut body = chunk_type.to_vec();
+    body.extend_from_slice(data);
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(&body);
+    chunk.extend_from_slice(&CRC32::new().crc32(&body).to_be_bytes());
+    chunk
+}
+
+/// Inserts ancillary chunks directly after IHDR.
+fn with_chunks(png: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+    let ihdr_end = 8 + 4 + 4 + 13 + 4;
+    let mut data = png[..ihdr_end].to_vec();
+    for chunk in chunks {
+        data.extend_from_slice(chunk);
+    }
+    data.extend_from_slice(&png[ihdr_end..]);
+    data
+}
+
+fn decode_with(data: &[u8], options: Option<HashMap<String, DataMap>>) -> ImageBuffer {
+    let mut image = ImageBuffer::new();
+    let mut decode = DecodeOptions::new(&mut image);
+    decode.options = options;
+    image_loader(data, &mut decode).unwrap();
+    image
+}
+
+fn srgb_option() -> Option<HashMap<String, DataMap>> {
+    let mut options = HashMap::new();
+    options.insert(
+        "color_correction".to_string(),
+        DataMap::Ascii("srgb".to_string()),
+    );
+    Some(options)
+}
+
+fn chrm(values: [u32; 8]) -> Vec<u8> {
+    let data: Vec<u8> = values
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect();
+    chunk(b"cHRM", &data)
+}
+
+#[test]
+fn png_linear_gamma_is_converted_to_srgb_when_requested() {
+    let png = with_chunks(&gray_png(128), &[chunk(b"gAMA", &100000_u32.to_be_bytes())]);
+
+    let raw = decode_with(&png, None);
+    assert_eq!(&raw.buffer.as_ref().unwrap()[..4], &[128, 128, 128, 255]);
+    let metadata = raw.metadata.as_ref().unwrap();
+    assert_eq!(metadata.get("gamma"), Some(&DataMap::Float(1.0)));
+    assert!(metadata.get("color corrected").is_none());
+
+    let corrected = decode_with(&png, srgb_option());
+    assert_eq!(
+        &corrected.buffer.as_ref().unwrap()[..4],
+        &[188, 188, 188, 255]
+    );
+    assert_eq!(
+        corrected.metadata.as_ref().unwrap().get("color corrected"),
+        Some(&DataMap::Ascii("true".to_string()))
+    );
+}
+
+#[test]
+fn png_srgb_chunk_overrides_gamma_correction() {
+    let png = with_chunks(
+        &gray_png(128),
+        &[
+            chunk(b"sRGB", &[0]),
+            chunk(b"gAMA", &100000_u32.to_be_bytes()),
+        ],
+    );
+
+    let image = decode_with(&png, srgb_option());
+    assert_eq!(&image.buffer.as_ref().unwrap()[..4], &[128, 128, 128, 255]);
+    let metadata = image.metadata.as_ref().unwrap();
+    assert_eq!(
+        metadata.get("color corrected"),
+        Some(&DataMap::Ascii("false".to_string()))
+    );
+    assert_eq!(
+        metadata.get("sRGB rendering intent"),
+        Some(&DataMap::Ascii("Perceptual".to_string()))
+    );
+}
+
+#[test]
+fn png_srgb_equivalent_chromaticity_is_left_unchanged() {
+    let png = with_chunks(
+        &gray_png(200),
+        &[
+            chunk(b"gAMA", &45455_u32.to_be_bytes()),
+            chrm([31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000]),
+        ],
+    );
+
+    let image = decode_with(&png, srgb_option());
+    assert_eq!(&image.buffer.as_ref().unwrap()[..4], &[200, 200, 200, 255]);
+    assert!(matches!(
+        image.metadata.as_ref().unwrap().get("chromaticity"),
+        Some(DataMap::FloatAllay(values)) if values.len() == 8
+    ));
+}
+
+#[test]
+fn png_wide_gamut_chromaticity_keeps_white_and_saturates_red() {
+    // Adobe RGB (1998) primaries with a D65 white point.
+    let adobe = chrm([31270, 32900, 64000, 33000, 21000, 71000, 15000, 6000]);
+    let gamma = chunk(b"gAMA", &45455_u32.to_be_bytes());
+
+    let white = with_chunks(&gray_png(255), &[gamma.clone(), adobe.clone()]);
+    let image = decode_with(&white, srgb_option());
+    let pixel = &image.buffer.as_ref().unwrap()[..4];
+    assert!(pixel[..3].iter().all(|value| *value >= 254), "{pixel:?}");
+
+    let mut red = ImageBuffer::from_buffer(1, 1, vec![200, 0, 0, 255]);
+    let red = image_to(&mut red, ImageFormat::Png, None).unwrap();
+    let red = with_chunks(&red, &[gamma, adobe]);
+    let image = decode_with(&red, srgb_option());
+    let pixel = &image.buffer.as_ref().unwrap()[..4];
+    assert!(pixel[0] > 200, "{pixel:?}");
+    assert_eq!(pixel[3], 255);
+}
+
+#[test]
+fn png_rejects_unknown_color_correction_option() {
+    let png = gray_png(128);
+    let mut image = ImageBuffer::new();
+    let mut decode = DecodeOptions::new(&mut image)
+        .with_option("color_correction", DataMap::Ascii("cmyk".to_string()));
+    assert!(image_loader(&png, &mut decode).is_err());
+}
+
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Applies PNG filter `flag` to a scanline, the inverse of the decoder step.
+fn filter_row(flag: u8, row: &[u8], prev: &[u8], bpp: usize) -> Vec<u8> {
+    let mut out = vec![flag];
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] as i32 } else { 0 };
+        let up = prev[i] as i32;
+        let upper_left = if i >= bpp { prev[i - bpp] as i32 } else { 0 };
+        let predictor = match flag {
+            1 => left,
+            2 => up,
+            3 => (left + up) / 2,
+            4 => {
+                let p = left + up - upper_left;
+                let (pa, pb, pc) = ((p - left).abs(), (p - up).abs(), (p - upper_left).abs());
+                if pa <= pb && pa <= pc {
+                    left
+                } else if pb <= pc {
+                    up
+                } else {
+                    upper_left
+                }
+            }
+            _ => 0,
+        };
+        out.push(row[i].wrapping_sub(predictor as u8));
+    }
+    out
+}
+
+/// Builds a PNG from raw samples, cycling through all filter types and
+/// splitting the zlib stream into IDAT chunks of `idat_size` bytes.
+fn raw_png(
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlace: bool,
+    samples: &[u8],
+    idat_size: usize,
+) -> Vec<u8> {
+    let channels = match color_type {
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => 1,
+    };
+    let pixel_bytes = channels * bit_depth as usize / 8;
+    let passes: Vec<(usize, usize, usize, usize)> = if interlace {
+        ADAM7.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    };
+    let mut filtered = Vec::new();
+    let mut flag = 0;
+    for (start_x, start_y, step_x, step_y) in passes {
+        let mut prev: Option<Vec<u8>> = None;
+        for y in (start_y..height).step_by(step_y) {
+            let mut row = Vec::new();
+            for x in (start_x..width).step_by(step_x) {
+                let offset = (y * width + x) * pixel_bytes;
+                row.extend_from_slice(&samples[offset..offset + pixel_bytes]);
+            }
+            if row.is_empty() {
+                break;
+            }
+            let up = prev.clone().unwrap_or_else(|| vec![0; row.len()]);
+            filtered.extend(filter_row(flag % 5, &row, &up, pixel_bytes));
+            flag += 1;
+            prev = Some(row);
+        }
+    }
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace as u8]);
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    png.extend(chunk(b"IHDR", &ihdr));
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&filtered, 6);
+    for data in compressed.chunks(idat_size) {
+        png.extend(chunk(b"IDAT", data));
+    }
+    png.extend(chunk(b"IEND", &[]));
+    png
+}
+
+fn rgb_samples(width: usize, height: usize) -> Vec<u8> {
+    (0..width * height)
+        .flat_map(|i| [(i * 37) as u8, (i * 11 + 5) as u8, (i * 3) as u8 ^ 0xff])
+        .collect()
+}
+
+fn rgba_from_rgb(samples: &[u8]) -> Vec<u8> {
+    samples
+        .chunks(3)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect()
+}
+
+#[test]
+fn png_streaming_decode_matches_across_idat_splits_and_filters() {
+    let (width, height) = (13, 9);
+    let samples = rgb_samples(width, height);
+    let expected = rgba_from_rgb(&samples);
+
+    for idat_size in [1, 7, usize::MAX] {
+        let png = raw_png(width, height, 8, 2, false, &samples, idat_size);
+        let image = decode_with(&png, None);
+        assert_eq!(image.buffer.as_ref().unwrap(), &expected, "{idat_size}");
+    }
+}
+
+#[test]
+fn png_streaming_decode_places_interlaced_pixels() {
+    let (width, height) = (13, 9);
+    let samples = rgb_samples(width, height);
+    let png = raw_png(width, height, 8, 2, true, &samples, 5);
+
+    let image = decode_with(&png, None);
+    assert_eq!(image.buffer.as_ref().unwrap(), &rgba_from_rgb(&samples));
+}
+
+#[test]
+fn png_streaming_decode_unfilters_16bit_samples() {
+    let (width, height) = (5, 4);
+    let samples: Vec<u8> = (0..width * height)
+        .flat_map(|i| ((i as u16) * 3001).to_be_bytes())
+        .collect();
+    let png = raw_png(width, height, 16, 0, false, &samples, 3);
+
+    let image = decode_with(&png, None);
+    let expected: Vec<u8> = samples
+        .chunks(2)
+        .flat_map(|sample| [sample[0], sample[0], sample[0], 255])
+        .collect();
+    assert_eq!(image.buffer.as_ref().unwrap(), &expected);
+}
+
+fn noise_samples(length: usize) -> Vec<u8> {
+    // Noise keeps the zlib stream large enough to span many IDAT chunks.
+    let mut seed = 0x2545_f491_u32;
+    (0..length)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (seed >> 24) as u8
+        })
+        .collect()
+}
+
+fn checksum_option(mode: &str) -> Option<HashMap<String, DataMap>> {
+    let mut options = HashMap::new();
+    options.insert("checksum".to_string(), DataMap::Ascii(mode.to_string()));
+    Some(options)
+}
+
+fn try_decode(
+    data: &[u8],
+    options: Option<HashMap<String, DataMap>>,
+) -> (ImageBuffer, Result<Option<ImgWarnings>, Error>) {
+    let mut image = ImageBuffer::new();
+    let mut decode = DecodeOptions::new(&mut image);
+    decode.options = options;
+    let result = image_loader(data, &mut decode);
+    (image, result)
+}
+
+#[test]
+fn png_streaming_decode_draws_rows_before_truncated_data() {
+    let (width, height) = (64, 64);
+    let samples = noise_samples(width * height * 3);
+    let png = raw_png(width, height, 8, 2, false, &samples, 64);
+    let idat_count = png.windows(4).filter(|name| name == b"IDAT").count();
+    assert!(idat_count > 16);
+
+    // Keep the signature, IHDR and the first eight IDAT chunks only.
+    let mut truncated = png[..33 + 8 * (64 + 12)].to_vec();
+    truncated.extend(chunk(b"IEND", &[]));
+
+    let (image, result) = try_decode(&truncated, checksum_option("strict"));
+    assert!(result.is_err());
+
+    let expected = rgba_from_rgb(&samples);
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(&buffer[..width * 4 * 2], &expected[..width * 4 * 2]);
+    assert_ne!(buffer, &expected);
+}
+
+#[test]
+fn png_lenient_decode_recovers_rows_from_cut_off_file() {
+    let (width, height) = (64, 64);
+    let samples = noise_samples(width * height * 3);
+    let png = raw_png(width, height, 8, 2, false, &samples, 64);
+    // Cut the file in the middle of an IDAT chunk, as in an aborted upload.
+    let cut = &png[..33 + 8 * (64 + 12) + 30];
+
+    let (_, strict) = try_decode(cut, checksum_option("strict"));
+    assert!(strict.is_err());
+
+    for mode in ["warn", "ignore"] {
+        let (image, result) = try_decode(cut, checksum_option(mode));
+        let warnings = result.unwrap().expect("truncation is reported");
+        assert!(warnings.to_string().contains("truncated"), "{warnings}");
+
+        let expected = rgba_from_rgb(&samples);
+        let buffer = image.buffer.as_ref().unwrap();
+        assert_eq!(&buffer[..width * 4 * 2], &expected[..width * 4 * 2]);
+        assert!(image.metadata.is_some());
+    }
+}
+
+#[test]
+fn png_lenient_decode_reports_chunk_cut_before_image_data() {
+    let png = with_chunks(&gray_png(128), &[chunk(b"gAMA", &45455_u32.to_be_bytes())]);
+    // Cut the file inside gAMA, before any IDAT has been read.
+    let cut = &png[..33 + 8 + 2];
+
+    let (_, strict) = try_decode(cut, checksum_option("strict"));
+    assert!(strict.is_err());
+
+    for options in [None, checksum_option("ignore")] {
+        let (image, result) = try_decode(cut, options);
+        let warnings = result.unwrap().expect("truncation is reported");
+        assert!(
+            warnings.to_string().contains("gAMA chunk is truncated"),
+            "{warnings}"
+        );
+        assert!(image.buffer.is_some());
+    }
+}
+
+#[test]
+fn png_chunk_crc_mismatch_follows_checksum_mode() {
+    let mut png = with_chunks(&gray_png(128), &[chunk(b"gAMA", &45455_u32.to_be_bytes())]);
+    // Corrupt the stored CRC of gAMA.
+    png[33 + 12 + 4 - 1] ^= 0xff;
+
+    let (_, strict) = try_decode(&png, checksum_option("strict"));
+    assert!(
+        strict
+            .unwrap_err()
+            .to_string()
+            .contains("gAMA chunk CRC mismatch")
+    );
+
+    let (image, warn) = try_decode(&png, checksum_option("warn"));
+    assert!(
+        warn.unwrap()
+            .unwrap()
+            .to_string()
+            .contains("gAMA chunk CRC mismatch")
+    );
+    assert_eq!(&image.buffer.as_ref().unwrap()[..4], &[128, 128, 128, 255]);
+
+    let (image, ignore) = try_decode(&png, checksum_option("ignore"));
+    assert!(ignore.unwrap().is_none());
+    assert_eq!(&image.buffer.as_ref().unwrap()[..4], &[128, 128, 128, 255]);
+
+    // The default reports the mismatch without failing.
+    let (_, default) = try_decode(&png, None);
+    assert!(default.unwrap().is_some());
+}
+
+#[test]
+fn png_adler32_mismatch_follows_checksum_mode() {
+    let samples = rgb_samples(4, 4);
+    let mut png = raw_png(4, 4, 8, 2, false, &samples, usize::MAX);
+    let idat = png.windows(4).position(|name| name == b"IDAT").unwrap();
+    let length = u32::from_be_bytes(png[idat - 4..idat].try_into().unwrap()) as usize;
+    let data_end = idat + 4 + length;
+    png[data_end - 1] ^= 0xff;
+    let crc = CRC32::new().crc32(&png[idat..data_end]);
+    png[data_end..data_end + 4].copy_from_slice(&crc.to_be_bytes());
+
+    let (_, strict) = try_decode(&png, checksum_option("strict"));
+    assert!(strict.unwrap_err().to_string().contains("Adler-32"));
+
+    let (image, warn) = try_decode(&png, checksum_option("warn"));
+    assert!(warn.unwrap().unwrap().to_string().contains("Adler-32"));
+    assert_eq!(image.buffer.as_ref().unwrap(), &rgba_from_rgb(&samples));
+
+    let (_, ignore) = try_decode(&png, checksum_option("ignore"));
+    assert!(ignore.unwrap().is_none());
+}
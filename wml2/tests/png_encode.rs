@@ -346,3 +346,100 @@ fn encode_viewer_error_sample_to_png_uses_full_canvas_first_frame() {
             .is_some_and(|buffer| !buffer.is_empty())
     );
 }
+
+/// Composes decoded APNG layers onto full canvases following the APNG rules.
+fn compose_apng_layers(width: usize, height: usize, layers: &[AnimationLayer]) -> Vec<Vec<u8>> {
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut canvases = Vec::with_capacity(layers.len());
+    for layer in layers {
+        let previous = canvas.clone();
+        for y in 0..layer.height {
+            for x in 0..layer.width {
+                let src = (y * layer.width + x) * 4;
+                let dst = ((layer.start_y as usize + y) * width + layer.start_x as usize + x) * 4;
+                let pixel = &layer.buffer[src..src + 4];
+                match layer.control.blend {
+                    Some(NextBlend::Source) if pixel[3] == 0 => {}
+                    Some(NextBlend::Source) if pixel[3] != 255 => {
+                        assert_eq!(canvas[dst + 3], 0, "OVER onto visible pixel");
+                        canvas[dst..dst + 4].copy_from_slice(pixel);
+                    }
+                    _ => canvas[dst..dst + 4].copy_from_slice(pixel),
+                }
+            }
+        }
+        canvases.push(canvas.clone());
+        match layer.control.dispose_option {
+            Some(NextDispose::Background) => {
+                for y in 0..layer.height {
+                    let start = ((layer.start_y as usize + y) * width + layer.start_x as usize) * 4;
+                    canvas[start..start + layer.width * 4].fill(0);
+                }
+            }
+            Some(NextDispose::Previous) => canvas = previous,
+            _ => {}
+        }
+    }
+    canvases
+}
+
+#[test]
+fn encode_apng_optimize_stores_changed_rectangles() {
+    let (width, height) = (48, 32);
+    let base = gradient_rgba(width, height);
+    let mut canvases = Vec::new();
+    for frame in 0..4 {
+        let mut canvas = base.clone();
+        // A moving cursor-sized box, as in a screen recording.
+        for y in 4..10 {
+            for x in frame * 6..frame * 6 + 5 {
+                let offset = (y * width + x) * 4;
+                canvas[offset..offset + 4].copy_from_slice(&[255, 255, 255, 255]);
+            }
+        }
+        canvases.push(canvas);
+    }
+
+    let layers = || {
+        canvases
+            .iter()
+            .map(|canvas| AnimationLayer {
+                width,
+                height,
+                start_x: 0,
+                start_y: 0,
+                buffer: canvas.clone(),
+                control: frame_control(width, height, 100),
+            })
+            .collect::<Vec<_>>()
+    };
+    let encode = |options: Option<HashMap<String, DataMap>>| {
+        let mut image = ImageBuffer::from_buffer(width, height, canvases[0].clone());
+        image.loop_count = Some(0);
+        image.animation = Some(layers());
+        let mut encode = EncodeOptions {
+            debug_flag: 0,
+            drawer: &mut image,
+            options,
+        };
+        image_encoder(&mut encode, ImageFormat::Png).unwrap()
+    };
+
+    let plain = encode(None);
+    let mut options = HashMap::new();
+    options.insert("optimize".to_string(), DataMap::UInt(1));
+    let optimized = encode(Some(options));
+
+    assert!(optimized.len() < plain.len());
+    assert_eq!(first_fctl_rect(&optimized), Some((48, 32, 0, 0)));
+
+    let decoded = image_load(&optimized).unwrap();
+    let frames = decoded.animation.as_ref().unwrap();
+    assert_eq!(frames.len(), 4);
+    assert!(
+        frames[1..]
+            .iter()
+            .all(|frame| frame.width * frame.height < width * height / 4)
+    );
+    assert_eq!(compose_apng_layers(width, height, frames), canvases);
+}
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use wml2::metadata::DataMap;
+use wml2::quantize::{
+    ColorDistance, ColorHistogram, Dither, NearestColor, PC98_16_PALETTE, QuantizeMethod,
+    QuantizeOptions, quantize, remap,
+};
+
+fn gradient_rgba(width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            rgba.extend_from_slice(&[
+                (x * 255 / (width - 1)) as u8,
+                (y * 255 / (height - 1)) as u8,
+                ((x + y) * 255 / (width + height - 2)) as u8,
+                0xff,
+            ]);
+        }
+    }
+    rgba
+}
+
+fn mean_error(rgba: &[u8], palette: &[[u8; 4]], indices: &[u8]) -> f64 {
+    let total: u64 = rgba
+        .chunks_exact(4)
+        .zip(indices)
+        .map(|(pixel, &index)| {
+            let entry = palette[index as usize];
+            (0..3)
+                .map(|channel| (pixel[channel] as i64 - entry[channel] as i64).unsigned_abs())
+                .sum::<u64>()
+        })
+        .sum();
+    total as f64 / (indices.len() * 3) as f64
+}
+
+#[test]
+fn quantize_keeps_exact_palette_when_colors_fit() {
+    let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+    let rgba: Vec<u8> = (0..12).flat_map(|index| colors[index % 3]).collect();
+
+    let quantized = quantize(&rgba, 4, 3, &QuantizeOptions::default()).unwrap();
+
+    assert!(quantized.exact);
+    assert_eq!(quantized.palette.len(), 3);
+    assert_eq!(quantized.transparent_index, None);
+    for (pixel, &index) in rgba.chunks_exact(4).zip(&quantized.indices) {
+        assert_eq!(&quantized.palette[index as usize][..], pixel);
+    }
+}
+
+#[test]
+fn quantize_methods_reduce_gradient_to_palette_size() {
+    let (width, height) = (64, 64);
+    let rgba = gradient_rgba(width, height);
+
+    for method in [
+        QuantizeMethod::MedianCut,
+        QuantizeMethod::Octree,
+        QuantizeMethod::Histogram,
+    ] {
+        let options = QuantizeOptions {
+            method,
+            max_colors: 16,
+            dither: Dither::None,
+            ..QuantizeOptions::default()
+        };
+        let quantized = quantize(&rgba, width, height, &options).unwrap();
+
+        assert!(!quantized.exact);
+        assert!(quantized.palette.len() <= 16, "{method:?}");
+        assert!(quantized.palette.len() >= 8, "{method:?}");
+        assert_eq!(quantized.indices.len(), width * height);
+        let error = mean_error(&rgba, &quantized.palette, &quantized.indices);
+        assert!(error < 20.0, "{method:?} mean error {error}");
+    }
+}
+
+#[test]
+fn kmeans_refinement_does_not_increase_error() {
+    let (width, height) = (48, 48);
+    let rgba = gradient_rgba(width, height);
+    let base = QuantizeOptions {
+        method: QuantizeMethod::Histogram,
+        max_colors: 8,
+        kmeans_iterations: 0,
+        dither: Dither::None,
+        ..QuantizeOptions::default()
+    };
+    let refined = QuantizeOptions {
+        kmeans_iterations: 8,
+        ..base.clone()
+    };
+
+    let before = quantize(&rgba, width, height, &base).unwrap();
+    let after = quantize(&rgba, width, height, &refined).unwrap();
+
+    let before = mean_error(&rgba, &before.palette, &before.indices);
+    let after = mean_error(&rgba, &after.palette, &after.indices);
+    assert!(after <= before, "k-means {after} > initial {before}");
+}
+
+#[test]
+fn quantize_reserves_index_zero_for_transparent_pixels() {
+    let (width, height) = (32, 32);
+    let mut rgba = gradient_rgba(width, height);
+    for pixel in rgba.chunks_exact_mut(4).step_by(5) {
+        pixel[3] = 0;
+    }
+    let options = QuantizeOptions {
+        max_colors: 32,
+        ..QuantizeOptions::default()
+    };
+
+    let quantized = quantize(&rgba, width, height, &options).unwrap();
+
+    assert_eq!(quantized.transparent_index, Some(0));
+    assert_eq!(quantized.palette[0], [0, 0, 0, 0]);
+    assert!(quantized.palette.len() <= 32);
+    for (pixel, &index) in rgba.chunks_exact(4).zip(&quantized.indices) {
+        assert_eq!(pixel[3] == 0, index == 0);
+    }
+}
+
+#[test]
+fn alpha_aware_quantization_keeps_translucent_entries() {
+    let mut rgba = Vec::new();
+    for alpha in [255_u8, 128, 64] {
+        for value in 0..64_u8 {
+            rgba.extend_from_slice(&[value * 4, 0, 0, alpha]);
+        }
+    }
+    let options = QuantizeOptions {
+        max_colors: 24,
+        alpha: true,
+        dither: Dither::None,
+        ..QuantizeOptions::default()
+    };
+
+    let quantized = quantize(&rgba, 64, 3, &options).unwrap();
+
+    for (pixel, &index) in rgba.chunks_exact(4).zip(&quantized.indices) {
+        let entry = quantized.palette[index as usize];
+        assert!((pixel[3] as i32 - entry[3] as i32).abs() <= 32);
+    }
+    let opaque = QuantizeOptions {
+        alpha: false,
+        ..options
+    };
+    let flattened = quantize(&rgba, 64, 3, &opaque).unwrap();
+    assert!(flattened.palette.iter().all(|color| color[3] == 0xff));
+}
+
+#[test]
+fn histogram_builds_shared_palette_across_frames() {
+    let first = gradient_rgba(16, 16);
+    let second: Vec<u8> = first
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[2], pixel[0], pixel[1], 0xff])
+        .collect();
+    let mut histogram = ColorHistogram::new(false);
+    histogram.add_rgba(&first);
+    histogram.add_rgba(&second);
+    let options = QuantizeOptions {
+        max_colors: 64,
+        reserve_transparent: true,
+        ..QuantizeOptions::default()
+    };
+
+    let palette = histogram.palette(&options);
+
+    assert!(!histogram.is_exact(&options));
+    assert_eq!(palette[0], [0, 0, 0, 0]);
+    assert!(palette.len() <= 64);
+    for frame in [&first, &second] {
+        let indices = remap(frame, 16, 16, &palette, &options).unwrap();
+        assert!(indices.iter().all(|&index| index != 0));
+    }
+}
+
+#[test]
+fn lab_distance_prefers_perceptually_closer_entry() {
+    let palette = [[0, 0, 0, 255], [255, 255, 255, 255], [0, 0, 255, 255]];
+    let mut rgb = NearestColor::new(&palette, ColorDistance::Rgb, false);
+    let mut lab = NearestColor::new(&palette, ColorDistance::Lab, false);
+
+    for (index, color) in palette.iter().enumerate() {
+        assert_eq!(lab.nearest(*color), index);
+    }
+    // A light lavender is nearest to blue by channel distance, but its
+    // CIELAB lightness puts it closer to white.
+    let color = [110, 110, 200, 255];
+    assert_eq!(rgb.nearest(color), 2);
+    assert_eq!(lab.nearest(color), 1);
+}
+
+#[test]
+fn quantize_options_parse_encoder_keys() {
+    let mut options = HashMap::new();
+    options.insert("quantize".to_string(), DataMap::Ascii("octree".to_string()));
+    options.insert("kmeans".to_string(), DataMap::UInt(0));
+    options.insert(
+        "color_distance".to_string(),
+        DataMap::Ascii("Lab".to_string()),
+    );
+
+    let parsed = QuantizeOptions::from_options(Some(&options)).unwrap();
+    assert_eq!(parsed.method, QuantizeMethod::Octree);
+    assert_eq!(parsed.kmeans_iterations, 0);
+    assert_eq!(parsed.distance, ColorDistance::Lab);
+
+    options.insert(
+        "quantize".to_string(),
+        DataMap::Ascii("popularity".to_string()),
+    );
+    assert!(QuantizeOptions::from_options(Some(&options)).is_err());
+    options.remove("quantize");
+    options.insert("kmeans".to_string(), DataMap::SInt(-1));
+    assert!(QuantizeOptions::from_options(Some(&options)).is_err());
+}
+
+#[test]
+fn remap_rejects_mismatched_buffers() {
+    let palette = [[0, 0, 0, 255]];
+    let options = QuantizeOptions::default();
+    assert!(remap(&[0; 8], 3, 1, &palette, &options).is_err());
+    assert!(remap(&[0; 4], 1, 1, &[], &options).is_err());
+}
+
+fn flat_rgba(width: usize, height: usize, color: [u8; 4]) -> Vec<u8> {
+    (0..width * height).flat_map(|_| color).collect()
+}
+
+fn dithered(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    palette: &[[u8; 4]],
+    dither: Dither,
+) -> Vec<u8> {
+    let options = QuantizeOptions {
+        dither,
+        ..QuantizeOptions::default()
+    };
+    remap(rgba, width, height, palette, &options).unwrap()
+}
+
+#[test]
+fn every_dither_keeps_average_of_flat_gray() {
+    let (width, height) = (64, 64);
+    let rgba = flat_rgba(width, height, [128, 128, 128, 255]);
+    let palette = [[0, 0, 0, 255], [255, 255, 255, 255]];
+
+    for dither in [
+        Dither::FloydSteinberg,
+        Dither::Atkinson,
+        Dither::Sierra,
+        Dither::Jarvis,
+        Dither::Bayer(2),
+        Dither::Bayer(4),
+        Dither::Bayer(8),
+        Dither::Bayer(16),
+        Dither::BlueNoise,
+    ] {
+        let indices = dithered(&rgba, width, height, &palette, dither);
+        let white = indices.iter().filter(|&&index| index == 1).count();
+        let ratio = white as f64 / indices.len() as f64;
+        assert!(
+            (0.45..=0.55).contains(&ratio),
+            "{dither:?} white ratio {ratio}"
+        );
+    }
+
+    let plain = dithered(&rgba, width, height, &palette, Dither::None);
+    assert!(plain.iter().all(|&index| index == plain[0]));
+}
+
+#[test]
+fn ordered_dither_repeats_with_matrix_size() {
+    let (width, height) = (16, 16);
+    let rgba = flat_rgba(width, height, [96, 96, 96, 255]);
+    let palette = [[0, 0, 0, 255], [255, 255, 255, 255]];
+
+    let indices = dithered(&rgba, width, height, &palette, Dither::Bayer(4));
+
+    for y in 0..height {
+        for x in 0..width {
+            assert_eq!(indices[y * width + x], indices[(y % 4) * width + x % 4]);
+        }
+    }
+}
+
+#[test]
+fn dither_strength_zero_matches_nearest_mapping() {
+    let (width, height) = (32, 32);
+    let rgba = gradient_rgba(width, height);
+    let none = dithered(&rgba, width, height, &PC98_16_PALETTE, Dither::None);
+
+    for dither in [Dither::FloydSteinberg, Dither::Bayer(8), Dither::BlueNoise] {
+        let options = QuantizeOptions {
+            dither,
+            dither_strength: 0.0,
+            ..QuantizeOptions::default()
+        };
+        let indices = remap(&rgba, width, height, &PC98_16_PALETTE, &options).unwrap();
+        assert_eq!(indices, none, "{dither:?}");
+    }
+}
+
+#[test]
+fn serpentine_scan_changes_diffusion_order() {
+    let (width, height) = (32, 32);
+    let rgba = gradient_rgba(width, height);
+    let raster = QuantizeOptions {
+        dither: Dither::Jarvis,
+        ..QuantizeOptions::default()
+    };
+    let serpentine = QuantizeOptions {
+        serpentine: true,
+        ..raster.clone()
+    };
+
+    let raster = remap(&rgba, width, height, &PC98_16_PALETTE, &raster).unwrap();
+    let serpentine = remap(&rgba, width, height, &PC98_16_PALETTE, &serpentine).unwrap();
+
+    assert_eq!(raster[..width], serpentine[..width]);
+    assert_ne!(raster, serpentine);
+    assert!(serpentine.iter().all(|&index| (index as usize) < 16));
+}
+
+#[test]
+fn fixed_palette_dithering_reduces_error_of_mean_color() {
+    let (width, height) = (64, 64);
+    let rgba = gradient_rgba(width, height);
+    let mean = |indices: &[u8]| -> [f64; 3] {
+        let mut sum = [0.0; 3];
+        for &index in indices {
+            for (channel, sum) in sum.iter_mut().enumerate() {
+                *sum += PC98_16_PALETTE[index as usize][channel] as f64;
+            }
+        }
+        sum.map(|sum| sum / indices.len() as f64)
+    };
+    let mut source = [0.0; 3];
+    for pixel in rgba.chunks_exact(4) {
+        for (channel, sum) in source.iter_mut().enumerate() {
+            *sum += pixel[channel] as f64;
+        }
+    }
+    let source = source.map(|sum| sum / (width * height) as f64);
+    let drift = |mean: [f64; 3]| -> f64 {
+        (0..3)
+            .map(|channel| (mean[channel] - source[channel]).abs())
+            .sum()
+    };
+
+    let plain = drift(mean(&dithered(
+        &rgba,
+        width,
+        height,
+        &PC98_16_PALETTE,
+        Dither::None,
+    )));
+    for dither in [Dither::FloydSteinberg, Dither::Sierra, Dither::BlueNoise] {
+        let dithered = drift(mean(&dithered(
+            &rgba,
+            width,
+            height,
+            &PC98_16_PALETTE,
+            dither,
+        )));
+        assert!(dithered < plain, "{dither:?} drift {dithered} >= {plain}");
+    }
+}
+
+#[test]
+fn dither_options_parse_encoder_keys() {
+    let mut options = HashMap::new();
+    options.insert("dither".to_string(), DataMap::Ascii("bayer8".to_string()));
+    options.insert("serpentine".to_string(), DataMap::UInt(1));
+    options.insert("dither_strength".to_string(), DataMap::Float(0.5));
+
+    let parsed = QuantizeOptions::from_options(Some(&options)).unwrap();
+    assert_eq!(parsed.dither, Dither::Bayer(8));
+    assert!(parsed.serpentine);
+    assert_eq!(parsed.dither_strength, 0.5);
+
+    assert_eq!(
+        Dither::from_name("Floyd-Steinberg"),
+        Some(Dither::FloydSteinberg)
+    );
+    assert_eq!(Dither::from_name("blue_noise"), Some(Dither::BlueNoise));
+    assert_eq!(Dither::from_name("bayer3"), None);
+
+    options.insert("dither_strength".to_string(), DataMap::Float(1.5));
+    assert!(QuantizeOptions::from_options(Some(&options)).is_err());
+    options.remove("dither_strength");
+    options.insert("dither".to_string(), DataMap::Ascii("stucki".to_string()));
+    assert!(QuantizeOptions::from_options(Some(&options)).is_err());
+}
@@ -0,0 +1,1173 @@
+use wml2::draw::{DecodeOptions, ImageBuffer, image_load, image_loader};
+use wml2::encoder::lzw::encode_tiff;
+use wml2::metadata::DataMap;
+
+/// A TIFF field value.
+enum Value {
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+}
+
+/// Where the image data blocks go.
+#[derive(Clone, Copy)]
+enum Layout {
+    Strips,
+    Tiles,
+}
+
+/// Builds a little-endian TIFF with one IFD. The offsets and byte counts for
+/// `blocks` are filled in for the given layout.
+fn build_tiff(mut fields: Vec<(u16, Value)>, blocks: &[Vec<u8>], layout: Layout) -> Vec<u8> {
+    let (offsets_tag, counts_tag) = match layout {
+        Layout::Strips => (0x0111, 0x0117),
+        Layout::Tiles => (0x0144, 0x0145),
+    };
+    fields.push((offsets_tag, Value::Long(vec![0; blocks.len()])));
+    fields.push((
+        counts_tag,
+        Value::Long(blocks.iter().map(|block| block.len() as u32).collect()),
+    ));
+    fields.sort_by_key(|field| field.0);
+
+    let ifd_len = 2 + fields.len() * 12 + 4;
+    let mut extra = vec![];
+    let extra_start = 8 + ifd_len;
+    // Out-of-line values first, then the image data.
+    let value_bytes = |value: &Value| -> Vec<u8> {
+        match value {
+            Value::Short(v) => v.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Long(v) => v.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Rational(v) => v
+                .iter()
+                .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()].concat())
+                .collect(),
+        }
+    };
+    let mut out_of_line = vec![];
+    for (_, value) in &fields {
+        let bytes = value_bytes(value);
+        if bytes.len() > 4 {
+            out_of_line.push(extra_start + extra.len());
+            extra.extend_from_slice(&bytes);
+        } else {
+            out_of_line.push(0);
+        }
+    }
+    let mut block_offsets = vec![];
+    for block in blocks {
+        block_offsets.push((extra_start + extra.len()) as u32);
+        extra.extend_from_slice(block);
+    }
+    // Patch the block offsets now that they are known.
+    for (index, (tag, value)) in fields.iter_mut().enumerate() {
+        if *tag == offsets_tag {
+            *value = Value::Long(block_offsets.clone());
+            let bytes = value_bytes(value);
+            if bytes.len() > 4 {
+                let start = out_of_line[index] - extra_start;
+                extra[start..start + bytes.len()].copy_from_slice(&bytes);
+            }
+        }
+    }
+
+    let mut data = b"II*\0".to_vec();
+    data.extend_from_slice(&8_u32.to_le_bytes());
+    data.extend_from_slice(&(fields.len() as u16).to_le_bytes());
+    for (index, (tag, value)) in fields.iter().enumerate() {
+        let (field_type, count) = match value {
+            Value::Short(v) => (3_u16, v.len()),
+            Value::Long(v) => (4_u16, v.len()),
+            Value::Rational(v) => (5_u16, v.len()),
+        };
+        data.extend_from_slice(&tag.to_le_bytes());
+        data.extend_from_slice(&field_type.to_le_bytes());
+        data.extend_from_slice(&(count as u32).to_le_bytes());
+        let mut bytes = value_bytes(value);
+        if bytes.len() > 4 {
+            bytes = (out_of_line[index] as u32).to_le_bytes().to_vec();
+        }
+        bytes.resize(4, 0);
+        data.extend_from_slice(&bytes);
+    }
+    data.extend_from_slice(&0_u32.to_le_bytes());
+    data.extend_from_slice(&extra);
+    data
+}
+
+fn image_fields(
+    width: u32,
+    height: u32,
+    bits: &[u16],
+    compression: u16,
+    photometric: u16,
+) -> Vec<(u16, Value)> {
+    vec![
+        (0x0100, Value::Long(vec![width])),
+        (0x0101, Value::Long(vec![height])),
+        (0x0102, Value::Short(bits.to_vec())),
+        (0x0103, Value::Short(vec![compression])),
+        (0x0106, Value::Short(vec![photometric])),
+        (0x0115, Value::Short(vec![bits.len() as u16])),
+    ]
+}
+
+fn tile_fields(tile_width: u32, tile_length: u32) -> Vec<(u16, Value)> {
+    vec![
+        (0x0142, Value::Long(vec![tile_width])),
+        (0x0143, Value::Long(vec![tile_length])),
+    ]
+}
+
+/// Splits an image of `samples` bytes per pixel into tiles, padding partial
+/// edge tiles with `0xee` so that stray padding would show up.
+fn split_tiles(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    samples: usize,
+    tile: usize,
+) -> Vec<Vec<u8>> {
+    let mut tiles = vec![];
+    for tile_y in (0..height).step_by(tile) {
+        for tile_x in (0..width).step_by(tile) {
+            let mut block = vec![];
+            for y in tile_y..tile_y + tile {
+                for x in tile_x..tile_x + tile {
+                    if x < width && y < height {
+                        let offset = (y * width + x) * samples;
+                        block.extend_from_slice(&pixels[offset..offset + samples]);
+                    } else {
+                        block.extend(std::iter::repeat_n(0xee, samples));
+                    }
+                }
+            }
+            tiles.push(block);
+        }
+    }
+    tiles
+}
+
+fn gray_pattern(width: usize, height: usize) -> Vec<u8> {
+    (0..width * height)
+        .map(|i| ((i % width) * 11 + (i / width) * 7) as u8)
+        .collect()
+}
+
+fn gray_to_rgba(gray: &[u8]) -> Vec<u8> {
+    gray.iter().flat_map(|&v| [v, v, v, 0xff]).collect()
+}
+
+/// PackBits with literal runs only.
+fn packbits(data: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![];
+    for chunk in data.chunks(128) {
+        encoded.push((chunk.len() - 1) as u8);
+        encoded.extend_from_slice(chunk);
+    }
+    encoded
+}
+
+const WIDTH: usize = 20;
+const HEIGHT: usize = 18;
+const TILE: usize = 16;
+
+fn tiled_gray(compression: u16, compress: fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+    let gray = gray_pattern(WIDTH, HEIGHT);
+    let tiles: Vec<Vec<u8>> = split_tiles(&gray, WIDTH, HEIGHT, 1, TILE)
+        .iter()
+        .map(|tile| compress(tile))
+        .collect();
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[8], compression, 1);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+    build_tiff(fields, &tiles, Layout::Tiles)
+}
+
+#[test]
+fn decode_tiled_tiff_for_each_compression() {
+    let expected = gray_to_rgba(&gray_pattern(WIDTH, HEIGHT));
+    let cases: [(&str, u16, fn(&[u8]) -> Vec<u8>); 5] = [
+        ("none", 1, |data| data.to_vec()),
+        ("lzw", 5, |data| encode_tiff(data, false).unwrap()),
+        ("packbits", 32773, packbits),
+        ("adobe deflate", 8, |data| {
+            miniz_oxide::deflate::compress_to_vec_zlib(data, 6)
+        }),
+        ("deflate", 32946, |data| {
+            miniz_oxide::deflate::compress_to_vec_zlib(data, 6)
+        }),
+    ];
+    for (name, compression, compress) in cases {
+        let image = image_load(&tiled_gray(compression, compress)).unwrap();
+        assert_eq!((image.width, image.height), (WIDTH, HEIGHT), "{name}");
+        assert_eq!(image.buffer.as_ref().unwrap(), &expected, "{name}");
+    }
+}
+
+#[test]
+fn decode_tiled_tiff_with_predictor() {
+    let gray = gray_pattern(WIDTH, HEIGHT);
+    let tiles: Vec<Vec<u8>> = split_tiles(&gray, WIDTH, HEIGHT, 1, TILE)
+        .iter()
+        .map(|tile| {
+            let mut diff = tile.clone();
+            for row in diff.chunks_mut(TILE) {
+                for x in (1..TILE).rev() {
+                    row[x] = row[x].wrapping_sub(row[x - 1]);
+                }
+            }
+            encode_tiff(&diff, false).unwrap()
+        })
+        .collect();
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[8], 5, 1);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+    fields.push((0x013d, Value::Short(vec![2])));
+
+    let image = image_load(&build_tiff(fields, &tiles, Layout::Tiles)).unwrap();
+    assert_eq!(image.buffer.as_ref().unwrap(), &gray_to_rgba(&gray));
+}
+
+fn rgb_pattern(width: usize, height: usize) -> Vec<u8> {
+    (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            [(x * 12) as u8, (y * 13) as u8, ((x + y) * 5) as u8]
+        })
+        .collect()
+}
+
+fn rgb_to_rgba(rgb: &[u8]) -> Vec<u8> {
+    rgb.chunks_exact(3)
+        .flat_map(|p| [p[0], p[1], p[2], 0xff])
+        .collect()
+}
+
+#[test]
+fn decode_tiled_tiff_with_separate_planes() {
+    let rgb = rgb_pattern(WIDTH, HEIGHT);
+    let mut tiles = vec![];
+    for plane in 0..3 {
+        let samples: Vec<u8> = rgb.iter().skip(plane).step_by(3).copied().collect();
+        for tile in split_tiles(&samples, WIDTH, HEIGHT, 1, TILE) {
+            tiles.push(encode_tiff(&tile, false).unwrap());
+        }
+    }
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[8, 8, 8], 5, 2);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+    fields.push((0x011c, Value::Short(vec![2])));
+
+    let image = image_load(&build_tiff(fields, &tiles, Layout::Tiles)).unwrap();
+    assert_eq!(image.buffer.as_ref().unwrap(), &rgb_to_rgba(&rgb));
+}
+
+#[test]
+fn decode_tiled_tiff_with_16_bit_separate_planes() {
+    let rgb = rgb_pattern(WIDTH, HEIGHT);
+    let mut tiles = vec![];
+    for plane in 0..3 {
+        let samples: Vec<u8> = rgb
+            .iter()
+            .skip(plane)
+            .step_by(3)
+            .flat_map(|&v| (v as u16 * 257).to_le_bytes())
+            .collect();
+        tiles.extend(split_tiles(&samples, WIDTH, HEIGHT, 2, TILE));
+    }
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[16, 16, 16], 1, 2);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+    fields.push((0x011c, Value::Short(vec![2])));
+
+    let image = image_load(&build_tiff(fields, &tiles, Layout::Tiles)).unwrap();
+    assert_eq!(image.buffer.as_ref().unwrap(), &rgb_to_rgba(&rgb));
+}
+
+/// Modified Huffman codes for the runs used below: (white 8, black 8) and
+/// white 16, each row padded to a byte.
+fn ccitt_rle_tile() -> Vec<u8> {
+    let mut data = vec![];
+    for row in 0..TILE {
+        if row % 2 == 0 {
+            // 10011 (white 8) 000101 (black 8)
+            data.extend_from_slice(&[0b1001_1000, 0b1010_0000]);
+        } else {
+            // 101010 (white 16)
+            data.push(0b1010_1000);
+        }
+    }
+    data
+}
+
+#[test]
+fn decode_tiled_tiff_with_ccitt_rle() {
+    let tiles = vec![ccitt_rle_tile(); 4];
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[1], 2, 0);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+
+    let image = image_load(&build_tiff(fields, &tiles, Layout::Tiles)).unwrap();
+    let buffer = image.buffer.as_ref().unwrap();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let black = (y % TILE) % 2 == 0 && (x % TILE) >= 8;
+            let expected = if black { 0 } else { 0xff };
+            assert_eq!(buffer[(y * WIDTH + x) * 4], expected, "({x}, {y})");
+        }
+    }
+}
+
+#[test]
+fn decode_tiled_tiff_reports_damaged_ccitt_tiles() {
+    let mut tiles = vec![ccitt_rle_tile(); 4];
+    tiles[1].truncate(TILE / 2);
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[1], 2, 0);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+    let data = build_tiff(fields, &tiles, Layout::Tiles);
+
+    let mut image = ImageBuffer::new();
+    let mut option = DecodeOptions::new(&mut image);
+    let warnings = image_loader(&data, &mut option).unwrap();
+    let text = warnings.map(|warnings| warnings.to_string());
+    assert!(
+        text.as_deref()
+            .is_some_and(|text| text.contains("CCITT data is damaged")),
+        "{text:?}"
+    );
+}
+
+#[test]
+fn decode_g4_strip_with_rows_per_strip_beyond_image() {
+    use wml2::encoder::ccitt::{CcittOptions, Encoding, encode};
+
+    // Even rows are white 8 then black 8, odd rows are white.
+    let packed: Vec<u8> = (0..4)
+        .flat_map(|row| {
+            if row % 2 == 0 {
+                [0x00, 0xff]
+            } else {
+                [0x00, 0x00]
+            }
+        })
+        .collect();
+    let strip = encode(&packed, 16, 4, &CcittOptions::new(Encoding::G4)).unwrap();
+    let mut fields = image_fields(16, 4, &[1], 4, 0);
+    fields.push((0x0116, Value::Long(vec![u32::MAX])));
+
+    let image = image_load(&build_tiff(fields, &[strip], Layout::Strips)).unwrap();
+    let buffer = image.buffer.as_ref().unwrap();
+    for y in 0..4 {
+        for x in 0..16 {
+            let expected = if y % 2 == 0 && x >= 8 { 0 } else { 0xff };
+            assert_eq!(buffer[(y * 16 + x) * 4], expected, "({x}, {y})");
+        }
+    }
+}
+
+#[cfg(feature = "tiff-jpeg")]
+#[test]
+fn decode_tiled_tiff_with_jpeg() {
+    use wml2::draw::image_to;
+    use wml2::util::ImageFormat;
+
+    let colors = [[200, 40, 40], [40, 200, 40], [40, 40, 200], [220, 220, 40]];
+    let tiles: Vec<Vec<u8>> = colors
+        .iter()
+        .map(|color| {
+            let rgba = [color[0], color[1], color[2], 0xff].repeat(TILE * TILE);
+            let mut tile = ImageBuffer::from_buffer(TILE, TILE, rgba);
+            image_to(&mut tile, ImageFormat::Jpeg, None).unwrap()
+        })
+        .collect();
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[8, 8, 8], 7, 2);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+
+    let image = image_load(&build_tiff(fields, &tiles, Layout::Tiles)).unwrap();
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(buffer.len(), WIDTH * HEIGHT * 4);
+    for (x, y, color) in [
+        (3, 3, colors[0]),
+        (WIDTH - 1, 3, colors[1]),
+        (3, HEIGHT - 1, colors[2]),
+        (WIDTH - 1, HEIGHT - 1, colors[3]),
+    ] {
+        let offset = (y * WIDTH + x) * 4;
+        for channel in 0..3 {
+            let diff = (buffer[offset + channel] as i32 - color[channel] as i32).abs();
+            assert!(diff <= 8, "({x}, {y}) {:?}", &buffer[offset..offset + 4]);
+        }
+    }
+}
+
+#[test]
+fn decode_strip_tiff_is_unchanged() {
+    let gray = gray_pattern(WIDTH, HEIGHT);
+    let strips: Vec<Vec<u8>> = gray
+        .chunks(WIDTH * 5)
+        .map(|strip| encode_tiff(strip, false).unwrap())
+        .collect();
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[8], 5, 1);
+    fields.push((0x0116, Value::Long(vec![5])));
+
+    let image = image_load(&build_tiff(fields, &strips, Layout::Strips)).unwrap();
+    assert_eq!(image.buffer.as_ref().unwrap(), &gray_to_rgba(&gray));
+}
+
+fn decode_with(data: &[u8], options: Vec<(&str, DataMap)>) -> ImageBuffer {
+    let mut image = ImageBuffer::new();
+    let mut decode = options
+        .into_iter()
+        .fold(DecodeOptions::new(&mut image), |decode, (key, value)| {
+            decode.with_option(key, value)
+        });
+    image_loader(data, &mut decode).unwrap();
+    image
+}
+
+fn metadata_floats(image: &ImageBuffer, key: &str) -> Vec<f64> {
+    match image.metadata.as_ref().unwrap().get(key) {
+        Some(DataMap::FloatAllay(values)) => values.clone(),
+        other => panic!("unexpected {key}: {other:?}"),
+    }
+}
+
+fn float_fields(bits: u16, samples: usize, photometric: u16) -> Vec<(u16, Value)> {
+    let mut fields = image_fields(4, 2, &vec![bits; samples], 1, photometric);
+    fields.push((0x0153, Value::Short(vec![3; samples])));
+    fields
+}
+
+/// Encodes rows of big-endian samples with the floating-point predictor.
+fn fp_predict(data: &[u8], row_samples: usize, bytes: usize, stride: usize) -> Vec<u8> {
+    let mut out = vec![];
+    for row in data.chunks(row_samples * bytes) {
+        let mut planes = vec![];
+        for byte in 0..bytes {
+            planes.extend(row.chunks(bytes).map(|sample| sample[byte]));
+        }
+        for i in (stride..planes.len()).rev() {
+            planes[i] = planes[i].wrapping_sub(planes[i - stride]);
+        }
+        out.extend(planes);
+    }
+    out
+}
+
+const FLOAT_VALUES: [f64; 8] = [0.0, 0.25, 0.5, 1.0, -1.0, 2.0, 0.125, 1.5];
+
+#[test]
+fn decode_float_samples_of_each_width() {
+    let encode = |bits: u16, value: f64| -> Vec<u8> {
+        match bits {
+            // 1.0 = sign 0, exponent 15/63, mantissa 0; values here are exact.
+            16 | 24 => {
+                let (exponent_bits, mantissa_bits) = if bits == 16 { (5, 10) } else { (7, 16) };
+                let bias = (1_i32 << (exponent_bits - 1)) - 1;
+                let sign = if value < 0.0 { 1_u32 } else { 0 };
+                let magnitude = value.abs();
+                let raw = if magnitude == 0.0 {
+                    0
+                } else {
+                    let exponent = magnitude.log2().floor() as i32;
+                    let mantissa = magnitude / 2_f64.powi(exponent) - 1.0;
+                    ((exponent + bias) as u32) << mantissa_bits
+                        | (mantissa * (1 << mantissa_bits) as f64) as u32
+                };
+                let raw = sign << (exponent_bits + mantissa_bits) | raw;
+                raw.to_le_bytes()[..bits as usize / 8].to_vec()
+            }
+            32 => (value as f32).to_le_bytes().to_vec(),
+            _ => value.to_le_bytes().to_vec(),
+        }
+    };
+    for bits in [16, 24, 32, 64] {
+        let strip: Vec<u8> = FLOAT_VALUES.iter().flat_map(|&v| encode(bits, v)).collect();
+        let data = build_tiff(float_fields(bits, 1, 1), &[strip], Layout::Strips);
+        let image = decode_with(&data, vec![("high_bit_depth", DataMap::UInt(1))]);
+
+        assert_eq!(
+            image.samples.as_ref().unwrap().buffer,
+            FLOAT_VALUES,
+            "{bits}"
+        );
+        assert_eq!(metadata_floats(&image, "sample range"), vec![-1.0, 2.0]);
+        let buffer = image.buffer.as_ref().unwrap();
+        // min/max normalization: -1.0 -> 0, 2.0 -> 255, 0.5 -> 128
+        assert_eq!(&buffer[16..20], &[0, 0, 0, 255], "{bits}");
+        assert_eq!(&buffer[20..24], &[255, 255, 255, 255], "{bits}");
+        assert_eq!(buffer[8], 128, "{bits}");
+    }
+}
+
+#[test]
+fn decode_float_strips_with_minmax_over_the_whole_image() {
+    // One row per strip; the range still comes from both strips.
+    let strips: Vec<Vec<u8>> = FLOAT_VALUES
+        .chunks(4)
+        .map(|row| row.iter().flat_map(|&v| (v as f32).to_le_bytes()).collect())
+        .collect();
+    let mut fields = float_fields(32, 1, 1);
+    fields.push((0x0116, Value::Long(vec![1])));
+    let data = build_tiff(fields, &strips, Layout::Strips);
+    let image = decode_with(&data, vec![("high_bit_depth", DataMap::UInt(1))]);
+
+    assert_eq!(image.samples.as_ref().unwrap().buffer, FLOAT_VALUES);
+    assert_eq!(metadata_floats(&image, "sample range"), vec![-1.0, 2.0]);
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(buffer[8], 128);
+    assert_eq!(&buffer[16..20], &[0, 0, 0, 255]);
+}
+
+#[test]
+fn decode_float_rejects_zero_width() {
+    let mut fields = image_fields(0, 2, &[32], 1, 1);
+    fields.push((0x0153, Value::Short(vec![3])));
+    let data = build_tiff(fields, &[vec![0; 8]], Layout::Strips);
+    let message = image_load(&data).err().unwrap().to_string();
+    assert!(message.contains("zero"), "{message}");
+}
+
+#[test]
+fn decode_float_with_floating_point_predictor_and_fixed_range() {
+    let values: Vec<f32> = (0..8).map(|i| i as f32 * 0.125).collect();
+    let big_endian: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+    let strip = miniz_oxide::deflate::compress_to_vec_zlib(&fp_predict(&big_endian, 4, 4, 1), 6);
+    let mut fields = float_fields(32, 1, 1);
+    fields[3] = (0x0103, Value::Short(vec![8]));
+    fields.push((0x013d, Value::Short(vec![3])));
+    let data = build_tiff(fields, &[strip], Layout::Strips);
+
+    let image = decode_with(
+        &data,
+        vec![
+            ("normalize", DataMap::Ascii("range".to_string())),
+            ("normalize_min", DataMap::Float(0.0)),
+            ("normalize_max", DataMap::Float(0.5)),
+        ],
+    );
+    let gray: Vec<u8> = image
+        .buffer
+        .as_ref()
+        .unwrap()
+        .chunks_exact(4)
+        .map(|pixel| pixel[0])
+        .collect();
+    assert_eq!(gray, vec![0, 64, 128, 191, 255, 255, 255, 255]);
+}
+
+#[test]
+fn decode_float_rgba_with_nan_as_transparent() {
+    let mut values = vec![];
+    for i in 0..8 {
+        let v = i as f32 / 7.0;
+        let red = if i == 3 { f32::NAN } else { v };
+        values.extend([red, 1.0 - v, 0.5, 0.5]);
+    }
+    let strip: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let mut fields = float_fields(32, 4, 2);
+    fields.push((0x0152, Value::Short(vec![2])));
+    let data = build_tiff(fields, &[strip], Layout::Strips);
+
+    let image = decode_with(
+        &data,
+        vec![("normalize", DataMap::Ascii("minmax".to_string()))],
+    );
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(&buffer[0..4], &[0, 255, 128, 128]);
+    assert_eq!(&buffer[12..16], &[0, 0, 0, 0]);
+    assert_eq!(&buffer[28..32], &[255, 0, 128, 128]);
+}
+
+#[test]
+fn decode_signed_samples_with_predictor() {
+    let values: [i16; 8] = [-32768, -1000, 0, 1000, 32767, -1, 1, 0];
+    let mut strip = vec![];
+    for row in values.chunks(4) {
+        let mut prev = 0_i16;
+        for &v in row {
+            strip.extend(v.wrapping_sub(prev).to_le_bytes());
+            prev = v;
+        }
+    }
+    let mut fields = image_fields(4, 2, &[16], 1, 1);
+    fields.push((0x0153, Value::Short(vec![2])));
+    fields.push((0x013d, Value::Short(vec![2])));
+    let data = build_tiff(fields, &[strip], Layout::Strips);
+
+    let image = decode_with(&data, vec![("high_bit_depth", DataMap::UInt(1))]);
+    let expected: Vec<f64> = values.iter().map(|&v| v as f64).collect();
+    let samples = image.samples.as_ref().unwrap();
+    assert_eq!(samples.channels, 1);
+    assert_eq!(samples.buffer, expected);
+    // Integer samples default to the full type range.
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(buffer[0], 0);
+    assert_eq!(buffer[8], 128);
+    assert_eq!(buffer[16], 255);
+    match image.metadata.as_ref().unwrap().get("sample format") {
+        Some(DataMap::Ascii(name)) => assert_eq!(name, "int16"),
+        other => panic!("unexpected sample format: {other:?}"),
+    }
+}
+
+#[test]
+fn decode_unsigned_16_bit_with_minmax_normalization() {
+    let values: [u16; 8] = [100, 200, 300, 400, 500, 600, 700, 1100];
+    let strip: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let data = build_tiff(image_fields(4, 2, &[16], 1, 1), &[strip], Layout::Strips);
+
+    let image = decode_with(
+        &data,
+        vec![("normalize", DataMap::Ascii("minmax".to_string()))],
+    );
+    let buffer = image.buffer.as_ref().unwrap();
+    assert_eq!(buffer[0], 0);
+    assert_eq!(buffer[4 * 4], 102);
+    assert_eq!(buffer[7 * 4], 255);
+}
+
+fn assert_near(actual: &[u8], expected: &[u8], tolerance: u8) {
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(expected) {
+        assert!(a.abs_diff(*e) <= tolerance, "{actual:?} != {expected:?}");
+    }
+}
+
+#[test]
+fn decode_cmyk_with_extra_alpha() {
+    let strip = vec![0, 0, 0, 0, 255, 255, 0, 0, 128, 200, 0, 255, 0, 0, 64];
+    let mut fields = image_fields(3, 1, &[8; 5], 1, 5);
+    fields.push((0x0152, Value::Short(vec![2])));
+    let data = build_tiff(fields, &[strip], Layout::Strips);
+
+    let image = image_load(&data).unwrap();
+    assert_eq!(
+        image.buffer.as_ref().unwrap(),
+        &[255, 255, 255, 255, 0, 127, 127, 200, 255, 0, 255, 64]
+    );
+}
+
+#[test]
+fn decode_tiled_cmyk() {
+    // Black ink only, so the image is the inverted ink pattern.
+    let gray = gray_pattern(WIDTH, HEIGHT);
+    let cmyk: Vec<u8> = gray.iter().flat_map(|&v| [0, 0, 0, 255 - v]).collect();
+    let tiles = split_tiles(&cmyk, WIDTH, HEIGHT, 4, TILE);
+    let mut fields = image_fields(WIDTH as u32, HEIGHT as u32, &[8; 4], 1, 5);
+    fields.extend(tile_fields(TILE as u32, TILE as u32));
+
+    let image = image_load(&build_tiff(fields, &tiles, Layout::Tiles)).unwrap();
+    assert_eq!(image.buffer.as_ref().unwrap(), &gray_to_rgba(&gray));
+}
+
+#[test]
+fn decode_cmyk_rejects_non_cmyk_ink_set() {
+    let mut fields = image_fields(1, 1, &[8; 4], 1, 5);
+    fields.push((0x014c, Value::Short(vec![2])));
+    let data = build_tiff(fields, &[vec![0; 4]], Layout::Strips);
+    assert!(image_load(&data).is_err());
+}
+
+#[test]
+fn decode_lab_interpretations() {
+    // White, black, mid gray and sRGB red (D50 L*a*b* 54.3, 80.8, 69.9).
+    let expected = [
+        255, 255, 255, 255, 0, 0, 0, 255, 119, 119, 119, 255, 255, 0, 0, 255,
+    ];
+    let cielab = vec![255, 0, 0, 0, 0, 0, 128, 0, 0, 138, 81, 70];
+    let icclab = vec![255, 128, 128, 0, 128, 128, 128, 128, 128, 138, 209, 198];
+    for (photometric, strip) in [(8, cielab), (9, icclab)] {
+        let fields = image_fields(4, 1, &[8; 3], 1, photometric);
+        let image = image_load(&build_tiff(fields, &[strip], Layout::Strips)).unwrap();
+        assert_near(image.buffer.as_ref().unwrap(), &expected, 4);
+    }
+
+    // 16-bit CIELab stores a* and b* in 1/256 units.
+    let strip: Vec<u8> = [35579_u16, 20685, 17894]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let fields = image_fields(1, 1, &[16; 3], 1, 8);
+    let image = image_load(&build_tiff(fields, &[strip], Layout::Strips)).unwrap();
+    assert_near(image.buffer.as_ref().unwrap(), &expected[12..], 4);
+
+    // ITULab with the default Decode ranges.
+    let fields = image_fields(2, 1, &[8; 3], 1, 10);
+    let image = image_load(&build_tiff(
+        fields,
+        &[vec![255, 128, 96, 0, 128, 96]],
+        Layout::Strips,
+    ))
+    .unwrap();
+    assert_near(image.buffer.as_ref().unwrap(), &expected[..8], 4);
+}
+
+#[test]
+fn decode_lab_with_alpha_and_white_point() {
+    let mut fields = image_fields(1, 1, &[8; 4], 1, 8);
+    fields.push((0x0152, Value::Short(vec![2])));
+    // A D65 white point maps L* 100 to white without adaptation.
+    fields.push((0x013e, Value::Rational(vec![(3127, 10000), (3290, 10000)])));
+    let image = image_load(&build_tiff(fields, &[vec![255, 0, 0, 99]], Layout::Strips)).unwrap();
+    assert_near(image.buffer.as_ref().unwrap(), &[255, 255, 255, 99], 1);
+}
+
+/// A 4x2 YCbCr image with 2x2 subsampling, two data units with flat luma and
+/// Cr of 128 and 200.
+fn subsampled_ycbcr(positioning: u16) -> Vec<u8> {
+    let strip = vec![100, 100, 100, 100, 128, 128, 100, 100, 100, 100, 128, 200];
+    let mut fields = image_fields(4, 2, &[8; 3], 1, 6);
+    fields.push((0x0212, Value::Short(vec![2, 2])));
+    fields.push((0x0213, Value::Short(vec![positioning])));
+    let image = image_load(&build_tiff(fields, &[strip], Layout::Strips)).unwrap();
+    let buffer = image.buffer.unwrap();
+    assert_eq!(&buffer[..16], &buffer[16..], "rows share the chroma");
+    buffer[..16].chunks_exact(4).map(|pixel| pixel[0]).collect()
+}
+
+#[test]
+fn decode_subsampled_ycbcr_by_positioning() {
+    // Co-sited chroma sits on even columns, centered chroma between them.
+    assert_eq!(subsampled_ycbcr(2), vec![100, 150, 201, 201]);
+    assert_eq!(subsampled_ycbcr(1), vec![100, 125, 176, 201]);
+}
+
+#[test]
+fn decode_ycbcr_with_coefficients_and_reference_range() {
+    let mut fields = image_fields(3, 1, &[8; 3], 1, 6);
+    fields.push((0x0212, Value::Short(vec![1, 1])));
+    // ITU-R BT.709 with footroom and headroom.
+    fields.push((
+        0x0211,
+        Value::Rational(vec![(2126, 10000), (7152, 10000), (722, 10000)]),
+    ));
+    let reference = [16, 235, 128, 240, 128, 240];
+    fields.push((
+        0x0214,
+        Value::Rational(reference.iter().map(|&v| (v, 1)).collect()),
+    ));
+    let strip = vec![235, 128, 128, 16, 128, 128, 126, 128, 184];
+    let image = image_load(&build_tiff(fields, &[strip], Layout::Strips)).unwrap();
+    assert_near(
+        image.buffer.as_ref().unwrap(),
+        &[255, 255, 255, 255, 0, 0, 0, 255, 228, 98, 128, 255],
+        1,
+    );
+}
+
+/// Position of the IFD entry for `tag` in a TIFF built by `build_tiff`.
+fn ifd_entry(data: &[u8], tag: u16) -> usize {
+    let count = u16::from_le_bytes([data[8], data[9]]) as usize;
+    (0..count)
+        .map(|i| 10 + i * 12)
+        .find(|&entry| u16::from_le_bytes([data[entry], data[entry + 1]]) == tag)
+        .unwrap()
+}
+
+fn read_long(data: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(data[position..position + 4].try_into().unwrap())
+}
+
+/// Appends `values` to the end of the file and points the LONG field `tag`
+/// at them, one offset per value.
+fn append_offsets(data: &mut Vec<u8>, tag: u16, values: &[Vec<u8>]) {
+    let entry = ifd_entry(data, tag);
+    let count = read_long(data, entry + 4) as usize;
+    assert_eq!(count, values.len());
+    let mut target = entry + 8;
+    if count > 1 {
+        target = read_long(data, target) as usize;
+    }
+    for (i, value) in values.iter().enumerate() {
+        let offset = (data.len() as u32).to_le_bytes();
+        data[target + i * 4..target + i * 4 + 4].copy_from_slice(&offset);
+        data.extend_from_slice(value);
+    }
+}
+
+/// A baseline JPEG split into what old-style JPEG TIFF stores separately.
+struct JpegParts {
+    stream: Vec<u8>,
+    /// Offset of the entropy-coded data after the SOS header.
+    scan_start: usize,
+    sampling: [u16; 2],
+    q_tables: Vec<Vec<u8>>,
+    dc_tables: Vec<Vec<u8>>,
+    ac_tables: Vec<Vec<u8>>,
+}
+
+fn jpeg_parts(stream: Vec<u8>) -> JpegParts {
+    let mut q = vec![vec![]; 4];
+    let mut huffman = vec![vec![vec![]; 4]; 2];
+    let mut frame = vec![];
+    let mut scan = vec![];
+    let mut i = 2;
+    loop {
+        let marker = stream[i + 1];
+        let length = u16::from_be_bytes([stream[i + 2], stream[i + 3]]) as usize;
+        let payload = &stream[i + 4..i + 2 + length];
+        match marker {
+            0xdb => {
+                for table in payload.chunks(65) {
+                    q[(table[0] & 0xf) as usize] = table[1..].to_vec();
+                }
+            }
+            0xc4 => {
+                let mut j = 0;
+                while j < payload.len() {
+                    let symbols: usize = payload[j + 1..j + 17].iter().map(|&c| c as usize).sum();
+                    huffman[(payload[j] >> 4) as usize][(payload[j] & 0xf) as usize] =
+                        payload[j + 1..j + 17 + symbols].to_vec();
+                    j += 17 + symbols;
+                }
+            }
+            0xc0 => frame = payload.to_vec(),
+            0xda => scan = payload.to_vec(),
+            _ => {}
+        }
+        i += 2 + length;
+        if marker == 0xda {
+            break;
+        }
+    }
+    // Per-component tables, in component order.
+    let components = frame[5] as usize;
+    let mut parts = JpegParts {
+        sampling: [(frame[7] >> 4) as u16, (frame[7] & 0xf) as u16],
+        q_tables: vec![],
+        dc_tables: vec![],
+        ac_tables: vec![],
+        scan_start: i,
+        stream,
+    };
+    for c in 0..components {
+        parts.q_tables.push(q[frame[8 + c * 3] as usize].clone());
+        let selector = scan[2 + c * 2];
+        parts
+            .dc_tables
+            .push(huffman[0][(selector >> 4) as usize].clone());
+        parts
+            .ac_tables
+            .push(huffman[1][(selector & 0xf) as usize].clone());
+    }
+    parts
+}
+
+fn ojpeg_sample() -> (JpegParts, Vec<u8>) {
+    use wml2::draw::image_to;
+    use wml2::util::ImageFormat;
+
+    let rgba: Vec<u8> = (0..16 * 16)
+        .flat_map(|i| [(i % 16 * 16) as u8, (i / 16 * 16) as u8, 128, 0xff])
+        .collect();
+    let mut image = ImageBuffer::from_buffer(16, 16, rgba);
+    let jpeg = image_to(&mut image, ImageFormat::Jpeg, None).unwrap();
+    let expected = image_load(&jpeg).unwrap().buffer.unwrap();
+    (jpeg_parts(jpeg), expected)
+}
+
+fn ojpeg_fields(parts: &JpegParts) -> Vec<(u16, Value)> {
+    let mut fields = image_fields(16, 16, &[8; 3], 6, 6);
+    fields.push((0x0116, Value::Long(vec![16])));
+    fields.push((0x0200, Value::Short(vec![1])));
+    fields.push((0x0212, Value::Short(parts.sampling.to_vec())));
+    fields
+}
+
+#[test]
+fn decode_old_jpeg_from_table_tags() {
+    let (parts, expected) = ojpeg_sample();
+    let scan = parts.stream[parts.scan_start..parts.stream.len() - 2].to_vec();
+    let mut fields = ojpeg_fields(&parts);
+    for tag in [0x0207, 0x0208, 0x0209] {
+        fields.push((tag, Value::Long(vec![0; 3])));
+    }
+    let mut data = build_tiff(fields, &[scan], Layout::Strips);
+    append_offsets(&mut data, 0x0207, &parts.q_tables);
+    append_offsets(&mut data, 0x0208, &parts.dc_tables);
+    append_offsets(&mut data, 0x0209, &parts.ac_tables);
+
+    let image = image_load(&data).unwrap();
+    assert_eq!(image.buffer.unwrap(), expected);
+}
+
+#[test]
+fn decode_old_jpeg_from_interchange_format() {
+    let (parts, expected) = ojpeg_sample();
+    let stream = &parts.stream;
+    let scan = stream[parts.scan_start..stream.len() - 2].to_vec();
+    // The stream holds the whole image, only its headers, or only tables.
+    let mut tables_only = stream[..2].to_vec();
+    let mut i = 2;
+    while i < parts.scan_start {
+        let length = u16::from_be_bytes([stream[i + 2], stream[i + 3]]) as usize;
+        if matches!(stream[i + 1], 0xdb | 0xc4) {
+            tables_only.extend_from_slice(&stream[i..i + 2 + length]);
+        }
+        i += 2 + length;
+    }
+    tables_only.extend_from_slice(&[0xff, 0xd9]);
+    let cases = [
+        (stream.clone(), stream.clone()),
+        (stream[..parts.scan_start].to_vec(), scan.clone()),
+        (tables_only, scan),
+    ];
+
+    for (interchange, strip) in cases {
+        let mut fields = ojpeg_fields(&parts);
+        fields.push((0x0201, Value::Long(vec![0])));
+        fields.push((0x0202, Value::Long(vec![interchange.len() as u32])));
+        let mut data = build_tiff(fields, &[strip], Layout::Strips);
+        append_offsets(&mut data, 0x0201, &[interchange]);
+
+        let image = image_load(&data).unwrap();
+        assert_eq!(image.buffer.unwrap(), expected);
+    }
+}
+
+/// One IFD of [`build_ifds`] with a single uncompressed strip.
+struct Ifd {
+    fields: Vec<(u16, Value)>,
+    strip: Vec<u8>,
+    /// Indexes of the IFDs listed in its SubIFDs tag.
+    sub_ifds: Vec<usize>,
+}
+
+fn rgb_ifd(width: u32, height: u32, subfile: u32, rgb: [u8; 3]) -> Ifd {
+    let mut fields = image_fields(width, height, &[8, 8, 8], 1, 2);
+    fields.push((0x00fe, Value::Long(vec![subfile])));
+    Ifd {
+        fields,
+        strip: rgb.repeat((width * height) as usize),
+        sub_ifds: vec![],
+    }
+}
+
+/// A 1-bit transparency mask; `rows` holds one packed byte per row.
+fn mask_ifd(width: u32, subfile: u32, rows: &[u8]) -> Ifd {
+    let mut fields = image_fields(width, rows.len() as u32, &[1], 1, 4);
+    fields.push((0x00fe, Value::Long(vec![subfile])));
+    Ifd {
+        fields,
+        strip: rows.to_vec(),
+        sub_ifds: vec![],
+    }
+}
+
+/// Builds a little-endian TIFF whose IFD chain is `chain`; the other IFDs
+/// are only reachable through SubIFDs.
+fn build_ifds(mut ifds: Vec<Ifd>, chain: &[usize]) -> Vec<u8> {
+    let value_bytes = |value: &Value| -> Vec<u8> {
+        match value {
+            Value::Short(v) => v.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Long(v) => v.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Rational(v) => v
+                .iter()
+                .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()].concat())
+                .collect(),
+        }
+    };
+    // Strips follow the header, the IFDs follow the strips.
+    let mut data = b"II*\0\0\0\0\0".to_vec();
+    for ifd in &mut ifds {
+        let strip_offset = data.len() as u32;
+        data.extend_from_slice(&ifd.strip);
+        let height = match &ifd.fields[1].1 {
+            Value::Long(v) => v[0],
+            _ => unreachable!(),
+        };
+        ifd.fields.push((0x0111, Value::Long(vec![strip_offset])));
+        ifd.fields.push((0x0116, Value::Long(vec![height])));
+        ifd.fields
+            .push((0x0117, Value::Long(vec![ifd.strip.len() as u32])));
+        if !ifd.sub_ifds.is_empty() {
+            ifd.fields
+                .push((0x014a, Value::Long(vec![0; ifd.sub_ifds.len()])));
+        }
+        ifd.fields.sort_by_key(|field| field.0);
+    }
+    let mut positions = vec![];
+    let mut position = data.len();
+    for ifd in &ifds {
+        positions.push(position);
+        let extra: usize = ifd
+            .fields
+            .iter()
+            .map(|(_, value)| value_bytes(value).len())
+            .filter(|len| *len > 4)
+            .sum();
+        position += 2 + ifd.fields.len() * 12 + 4 + extra;
+    }
+    data[4..8].copy_from_slice(&(positions[chain[0]] as u32).to_le_bytes());
+
+    for (index, ifd) in ifds.iter_mut().enumerate() {
+        let subs: Vec<u32> = ifd
+            .sub_ifds
+            .iter()
+            .map(|sub| positions[*sub] as u32)
+            .collect();
+        for (tag, value) in &mut ifd.fields {
+            if *tag == 0x014a {
+                *value = Value::Long(subs.clone());
+            }
+        }
+        let next = chain
+            .iter()
+            .position(|link| *link == index)
+            .and_then(|link| chain.get(link + 1))
+            .map_or(0, |next| positions[*next] as u32);
+
+        assert_eq!(data.len(), positions[index]);
+        let mut extra_at = positions[index] + 2 + ifd.fields.len() * 12 + 4;
+        let mut extra = vec![];
+        data.extend_from_slice(&(ifd.fields.len() as u16).to_le_bytes());
+        for (tag, value) in &ifd.fields {
+            let (field_type, count) = match value {
+                Value::Short(v) => (3_u16, v.len()),
+                Value::Long(v) => (4_u16, v.len()),
+                Value::Rational(v) => (5_u16, v.len()),
+            };
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&field_type.to_le_bytes());
+            data.extend_from_slice(&(count as u32).to_le_bytes());
+            let mut bytes = value_bytes(value);
+            if bytes.len() > 4 {
+                extra.extend_from_slice(&bytes);
+                bytes = (extra_at as u32).to_le_bytes().to_vec();
+                extra_at += value_bytes(value).len();
+            }
+            bytes.resize(4, 0);
+            data.extend_from_slice(&bytes);
+        }
+        data.extend_from_slice(&next.to_le_bytes());
+        data.extend_from_slice(&extra);
+    }
+    data
+}
+
+fn alpha_of(image: &ImageBuffer) -> Vec<u8> {
+    image
+        .buffer
+        .as_ref()
+        .unwrap()
+        .chunks_exact(4)
+        .map(|pixel| pixel[3])
+        .collect()
+}
+
+fn decode_error(data: &[u8], options: Vec<(&str, DataMap)>) -> bool {
+    let mut image = ImageBuffer::new();
+    let mut decode = options
+        .into_iter()
+        .fold(DecodeOptions::new(&mut image), |decode, (key, value)| {
+            decode.with_option(key, value)
+        });
+    image_loader(data, &mut decode).is_err()
+}
+
+/// The left half of each 4-pixel row is inside the mask.
+const HALF_MASK: [u8; 4] = [0b1100_0000; 4];
+const HALF_ALPHA: [u8; 4] = [255, 255, 0, 0];
+
+#[test]
+fn decode_overview_ifds_and_transparency_mask_in_the_chain() {
+    // A COG-style chain: the image, its mask, then a reduced-resolution copy.
+    let data = build_ifds(
+        vec![
+            rgb_ifd(4, 4, 0, [200, 0, 0]),
+            mask_ifd(4, 4, &HALF_MASK),
+            rgb_ifd(2, 2, 1, [0, 0, 200]),
+        ],
+        &[0, 1, 2],
+    );
+    assert_eq!(wml2::draw::image_page_count(&data).unwrap(), 1);
+
+    let image = image_load(&data).unwrap();
+    assert!(image.animation.is_none());
+    assert_eq!((image.width, image.height), (4, 4));
+    assert_eq!(alpha_of(&image), HALF_ALPHA.repeat(4));
+    assert_eq!(&image.buffer.as_ref().unwrap()[..3], &[200, 0, 0]);
+    assert_eq!(
+        image.metadata.as_ref().unwrap().get("image levels"),
+        Some(&DataMap::UInt(2))
+    );
+
+    let overview = decode_with(&data, vec![("level", DataMap::UInt(1))]);
+    assert_eq!((overview.width, overview.height), (2, 2));
+    assert_eq!(overview.buffer.unwrap(), [0, 0, 200, 255].repeat(4));
+    assert!(decode_error(&data, vec![("level", DataMap::UInt(2))]));
+}
+
+#[test]
+fn decode_sub_ifd_pyramid_levels_and_mask() {
+    // The image lists a 2x2 level, a mask at twice its resolution and a
+    // 1x1 level that is nested in the 2x2 one.
+    let mut image = rgb_ifd(4, 4, 0, [0, 200, 0]);
+    image.sub_ifds = vec![1, 2];
+    let mut level = rgb_ifd(2, 2, 1, [0, 0, 200]);
+    level.sub_ifds = vec![3];
+    let data = build_ifds(
+        vec![
+            image,
+            level,
+            mask_ifd(8, 4, &[0xf0; 8]),
+            rgb_ifd(1, 1, 1, [9, 9, 9]),
+        ],
+        &[0],
+    );
+    let mut reader = bin_rs::reader::BytesReader::new(&data);
+    assert_eq!(wml2::tiff::decoder::level_count(&mut reader, 0).unwrap(), 3);
+
+    let full = image_load(&data).unwrap();
+    assert_eq!((full.width, full.height), (4, 4));
+    assert_eq!(alpha_of(&full), HALF_ALPHA.repeat(4));
+
+    let level = decode_with(&data, vec![("level", DataMap::UInt(1))]);
+    assert_eq!(level.buffer.unwrap(), [0, 0, 200, 255].repeat(4));
+    let level = decode_with(
+        &data,
+        vec![("page", DataMap::UInt(0)), ("level", DataMap::UInt(2))],
+    );
+    assert_eq!(level.buffer.unwrap(), [9, 9, 9, 255]);
+}
+
+#[test]
+fn decode_sub_ifds_listed_many_times_once() {
+    // Both IFDs list the level 200 times, so without de-duplication the
+    // nested SubIFDs would be read 200^4 times.
+    let mut image = rgb_ifd(4, 4, 0, [0, 200, 0]);
+    image.sub_ifds = vec![1; 200];
+    let mut level = rgb_ifd(2, 2, 1, [0, 0, 200]);
+    level.sub_ifds = vec![1; 200];
+    let data = build_ifds(vec![image, level], &[0]);
+
+    let mut reader = bin_rs::reader::BytesReader::new(&data);
+    assert_eq!(wml2::tiff::decoder::level_count(&mut reader, 0).unwrap(), 2);
+    let level = decode_with(&data, vec![("level", DataMap::UInt(1))]);
+    assert_eq!(level.buffer.unwrap(), [0, 0, 200, 255].repeat(4));
+}
+
+/// Little-endian BigTIFF header followed by one IFD at offset 16.
+fn bigtiff_with_ifd(ifd: &[u8]) -> Vec<u8> {
+    let mut data = b"II".to_vec();
+    data.extend_from_slice(&43_u16.to_le_bytes());
+    data.extend_from_slice(&8_u16.to_le_bytes());
+    data.extend_from_slice(&0_u16.to_le_bytes());
+    data.extend_from_slice(&16_u64.to_le_bytes());
+    data.extend_from_slice(ifd);
+    data
+}
+
+#[test]
+fn bigtiff_rejects_counts_larger_than_the_file() {
+    for count in [u64::MAX, 1 << 60, 2] {
+        let data = bigtiff_with_ifd(&count.to_le_bytes());
+        assert!(image_load(&data).is_err(), "{count}");
+    }
+
+    // One ImageWidth entry claiming 2^40 SHORT values.
+    let mut ifd = 1_u64.to_le_bytes().to_vec();
+    ifd.extend_from_slice(&0x0100_u16.to_le_bytes());
+    ifd.extend_from_slice(&3_u16.to_le_bytes());
+    ifd.extend_from_slice(&(1_u64 << 40).to_le_bytes());
+    ifd.extend_from_slice(&0_u64.to_le_bytes());
+    ifd.extend_from_slice(&0_u64.to_le_bytes());
+    assert!(image_load(&bigtiff_with_ifd(&ifd)).is_err());
+}
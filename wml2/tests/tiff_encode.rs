@@ -6,12 +6,14 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use bin_rs::Endian;
 use bin_rs::reader::BytesReader;
 use wml2::draw::{
-    AnimationLayer, EncodeOptions, ImageBuffer, ImageRect, NextBlend, NextDispose, NextOption,
-    NextOptions, convert, image_encoder, image_load,
+    AnimationLayer, EncodeOptions, EncodePage, ImageBuffer, ImageRect, NextBlend, NextDispose,
+    NextOption, NextOptions, PageSource, convert, image_encoder, image_load,
 };
 use wml2::metadata::{DataMap, get_exif};
-use wml2::tiff::header::{DataPack, Rational, TiffHeader, TiffHeaders, exif_to_bytes, read_tags};
-use wml2::util::ImageFormat;
+use wml2::tiff::header::{
+    BIGTIFF_VERSION, DataPack, Rational, TiffHeader, TiffHeaders, exif_to_bytes, read_tags,
+};
+use wml2::util::{ImageFormat, format_check};
 
 fn temp_path(name: &str, extension: &str) -> PathBuf {
     let unique = SystemTime::now()
@@ -414,6 +416,101 @@ fn encode_tiff_via_public_api_roundtrips_pixels_and_metadata() {
     );
 }
 
+#[test]
+fn bigtiff_writer_roundtrips_exif_and_gps() {
+    let mut headers = exif_fixture();
+    headers.version = BIGTIFF_VERSION;
+    let bytes = exif_to_bytes(&headers).unwrap();
+    assert!(bytes.starts_with(b"II+\0\x08\0\0\0"));
+    assert!(matches!(format_check(&bytes), ImageFormat::Tiff));
+
+    let mut reader = BytesReader::new(&bytes);
+    let parsed = read_tags(&mut reader).unwrap();
+    assert_eq!(parsed.version, BIGTIFF_VERSION);
+    let make = first_ifd_tags(&parsed.headers)
+        .iter()
+        .find(|tag| tag.tagid == 0x010f)
+        .unwrap();
+    match &make.data {
+        DataPack::Ascii(value) => assert_eq!(value.trim_end_matches('\0'), "wml2"),
+        other => panic!("unexpected Make tag: {other:?}"),
+    }
+    assert!(
+        parsed
+            .exif
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|tag| tag.tagid == 0x9000)
+    );
+    assert!(
+        parsed
+            .gps
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|tag| tag.tagid == 0x0000)
+    );
+}
+
+#[test]
+fn encode_bigtiff_via_public_api_roundtrips_pixels() {
+    let mut rgba = Vec::with_capacity(9 * 6 * 4);
+    for y in 0..6 {
+        for x in 0..9 {
+            rgba.push((x * 23 + y * 5) as u8);
+            rgba.push((x * 11 + y * 19) as u8);
+            rgba.push((x * 3 + y * 41) as u8);
+            rgba.push(255);
+        }
+    }
+
+    for compression in ["none", "lzw"] {
+        let mut image = ImageBuffer::from_buffer(9, 6, rgba.clone());
+        let mut options = HashMap::new();
+        options.insert(
+            "compression".to_string(),
+            DataMap::Ascii(compression.to_string()),
+        );
+        options.insert("bigtiff".to_string(), DataMap::Ascii("true".to_string()));
+        let mut encode = EncodeOptions {
+            debug_flag: 0,
+            drawer: &mut image,
+            options: Some(options),
+        };
+        let data = image_encoder(&mut encode, ImageFormat::Tiff).unwrap();
+        assert!(data.starts_with(b"II+\0"), "{compression}");
+
+        let mut reader = BytesReader::new(&data);
+        let parsed = read_tags(&mut reader).unwrap();
+        let offsets = parsed
+            .headers
+            .iter()
+            .find(|tag| tag.tagid == 0x0111)
+            .unwrap();
+        assert!(matches!(offsets.data, DataPack::Long8(_)), "{compression}");
+
+        let decoded = image_load(&data).unwrap();
+        assert_eq!(decoded.width, 9);
+        assert_eq!(decoded.height, 6);
+        assert_eq!(decoded.buffer.as_ref().unwrap(), &rgba, "{compression}");
+    }
+}
+
+#[test]
+fn encode_tiff_defaults_to_classic_tiff() {
+    let mut image = ImageBuffer::from_buffer(2, 2, solid_rgba(2, 2, [1, 2, 3, 255]));
+    let mut options = HashMap::new();
+    options.insert("bigtiff".to_string(), DataMap::Ascii("auto".to_string()));
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: Some(options),
+    };
+    let data = image_encoder(&mut encode, ImageFormat::Tiff).unwrap();
+    assert!(data.starts_with(b"II*\0"));
+}
+
 #[test]
 fn encode_lzw_tiff_via_public_api_roundtrips_pixels() {
     let mut rgba = Vec::with_capacity(13 * 9 * 4);
@@ -596,3 +693,491 @@ fn convert_animated_png_file_to_tiff_via_public_api() {
     let _ = fs::remove_file(input_path);
     let _ = fs::remove_file(output_path);
 }
+
+fn encode_tiff_with_map(
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    options: HashMap<String, DataMap>,
+) -> Vec<u8> {
+    let mut image = ImageBuffer::from_buffer(width, height, rgba);
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: Some(options),
+    };
+    image_encoder(&mut encode, ImageFormat::Tiff).unwrap()
+}
+
+fn encode_tiff_with(
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+    options: &[(&str, &str)],
+) -> Vec<u8> {
+    let options = options
+        .iter()
+        .map(|(key, value)| (key.to_string(), DataMap::Ascii(value.to_string())))
+        .collect();
+    encode_tiff_with_map(width, height, rgba, options)
+}
+
+fn encode_tiff_error(options: &[(&str, &str)]) -> bool {
+    let mut image = ImageBuffer::from_buffer(2, 2, solid_rgba(2, 2, [1, 2, 3, 255]));
+    let options = options
+        .iter()
+        .map(|(key, value)| (key.to_string(), DataMap::Ascii(value.to_string())))
+        .collect();
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut image,
+        options: Some(options),
+    };
+    image_encoder(&mut encode, ImageFormat::Tiff).is_err()
+}
+
+fn short_tag_value(data: &[u8], tagid: usize) -> Option<u32> {
+    let mut reader = BytesReader::new(data);
+    let headers = read_tags(&mut reader).unwrap();
+    first_ifd_tags(&headers.headers)
+        .iter()
+        .find(|tag| tag.tagid == tagid)
+        .and_then(|tag| match &tag.data {
+            DataPack::Short(values) => values.first().map(|value| *value as u32),
+            DataPack::Long(values) => values.first().copied(),
+            _ => None,
+        })
+}
+
+fn gradient_rgba(width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            rgba.push((x * 19 + y * 3) as u8);
+            rgba.push((x * 7 + y * 23) as u8);
+            rgba.push((x * 29 + y * 11) as u8);
+            rgba.push((255 - x * 5) as u8);
+        }
+    }
+    rgba
+}
+
+/// Black and white runs of growing length with an odd width.
+fn bilevel_rgba(width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let value = if (x * x + y * 3) / 17 % 2 == 0 {
+                255
+            } else {
+                0
+            };
+            rgba.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+    rgba
+}
+
+#[test]
+fn encode_deflate_and_packbits_tiff_roundtrip_pixels() {
+    let rgba = gradient_rgba(13, 9);
+    for (compression, code, predictor) in [
+        ("deflate", 8, "none"),
+        ("deflate", 8, "horizontal"),
+        ("lzw", 5, "horizontal"),
+        ("packbits", 32773, "none"),
+    ] {
+        let data = encode_tiff_with(
+            13,
+            9,
+            rgba.clone(),
+            &[("compression", compression), ("predictor", predictor)],
+        );
+        assert_eq!(short_tag_value(&data, 0x0103), Some(code));
+        let expected_predictor = if predictor == "horizontal" {
+            Some(2)
+        } else {
+            None
+        };
+        assert_eq!(short_tag_value(&data, 0x013d), expected_predictor);
+
+        let decoded = image_load(&data).unwrap();
+        assert_eq!(
+            decoded.buffer.as_ref().unwrap(),
+            &rgba,
+            "{compression} {predictor}"
+        );
+    }
+}
+
+#[test]
+fn encode_gray_tiff_keeps_luminance_and_alpha() {
+    let mut rgba = Vec::new();
+    for index in 0..(11 * 7) {
+        let value = (index * 37 % 256) as u8;
+        rgba.extend_from_slice(&[value, value, value, (index * 3 % 256) as u8]);
+    }
+    for (compression, predictor) in [("none", "none"), ("deflate", "horizontal")] {
+        let data = encode_tiff_with(
+            11,
+            7,
+            rgba.clone(),
+            &[
+                ("photometric", "gray"),
+                ("compression", compression),
+                ("predictor", predictor),
+            ],
+        );
+        assert_eq!(short_tag_value(&data, 0x0106), Some(1));
+        assert_eq!(short_tag_value(&data, 0x0115), Some(2));
+        let decoded = image_load(&data).unwrap();
+        assert_eq!(decoded.buffer.as_ref().unwrap(), &rgba, "{compression}");
+    }
+}
+
+#[test]
+fn encode_palette_tiff_roundtrips_exact_colors() {
+    let colors = [[200, 10, 10], [10, 200, 10], [10, 10, 200], [250, 250, 0]];
+    let mut rgba = Vec::new();
+    for index in 0..(9 * 6) {
+        let color = colors[index * 7 % colors.len()];
+        rgba.extend_from_slice(&[color[0], color[1], color[2], 255]);
+    }
+    let data = encode_tiff_with(
+        9,
+        6,
+        rgba.clone(),
+        &[("photometric", "palette"), ("compression", "packbits")],
+    );
+    assert_eq!(short_tag_value(&data, 0x0106), Some(3));
+    assert_eq!(short_tag_value(&data, 0x0102), Some(8));
+    let decoded = image_load(&data).unwrap();
+    assert_eq!(decoded.buffer.as_ref().unwrap(), &rgba);
+}
+
+#[test]
+fn encode_bilevel_tiff_roundtrips_for_every_compression() {
+    let rgba = bilevel_rgba(37, 11);
+    for (compression, code) in [
+        ("none", 1),
+        ("lzw", 5),
+        ("packbits", 32773),
+        ("deflate", 8),
+        ("ccitt_rle", 2),
+        ("ccitt_g3", 3),
+        ("ccitt_g4", 4),
+    ] {
+        let data = encode_tiff_with(
+            37,
+            11,
+            rgba.clone(),
+            &[("photometric", "bilevel"), ("compression", compression)],
+        );
+        assert_eq!(short_tag_value(&data, 0x0103), Some(code));
+        assert_eq!(short_tag_value(&data, 0x0102), Some(1));
+        assert_eq!(short_tag_value(&data, 0x0106), Some(0));
+        let decoded = image_load(&data).unwrap();
+        assert_eq!(decoded.buffer.as_ref().unwrap(), &rgba, "{compression}");
+    }
+}
+
+#[test]
+fn encode_group3_tiff_writes_t4_options() {
+    let rgba = bilevel_rgba(37, 11);
+    for (two_d, fill_bits, t4_options) in [
+        ("false", "false", 0),
+        ("true", "false", 1),
+        ("false", "true", 4),
+        ("true", "true", 5),
+        ("1", "0", 1),
+        ("off", "on", 4),
+    ] {
+        let mut options = HashMap::new();
+        options.insert(
+            "compression".to_string(),
+            DataMap::Ascii("ccitt_g3".to_string()),
+        );
+        options.insert("ccitt_2d".to_string(), DataMap::Ascii(two_d.to_string()));
+        options.insert("ccitt_k".to_string(), DataMap::UInt(3));
+        options.insert(
+            "ccitt_fill_bits".to_string(),
+            DataMap::Ascii(fill_bits.to_string()),
+        );
+        let data = encode_tiff_with_map(37, 11, rgba.clone(), options);
+        assert_eq!(short_tag_value(&data, 0x0124), Some(t4_options));
+        let decoded = image_load(&data).unwrap();
+        assert_eq!(decoded.buffer.as_ref().unwrap(), &rgba, "{t4_options}");
+    }
+}
+
+#[test]
+fn encode_ccitt_tiff_thresholds_color_input() {
+    let mut rgba = Vec::new();
+    for x in 0..16 {
+        let value = (x * 16) as u8;
+        rgba.extend_from_slice(&[value, value, value, 255]);
+    }
+    let data = encode_tiff_with(16, 1, rgba, &[("compression", "g4")]);
+    let decoded = image_load(&data).unwrap();
+    let buffer = decoded.buffer.as_ref().unwrap();
+    for x in 0..16 {
+        let expected = if x < 8 { 0 } else { 255 };
+        assert_eq!(buffer[x * 4], expected, "pixel {x}");
+    }
+}
+
+#[test]
+fn encode_tiff_rejects_invalid_photometric_combinations() {
+    assert!(encode_tiff_error(&[
+        ("compression", "g3"),
+        ("photometric", "rgb")
+    ]));
+    assert!(encode_tiff_error(&[
+        ("compression", "packbits"),
+        ("predictor", "horizontal")
+    ]));
+    assert!(encode_tiff_error(&[
+        ("photometric", "bilevel"),
+        ("predictor", "horizontal"),
+        ("compression", "lzw")
+    ]));
+    assert!(encode_tiff_error(&[("photometric", "cmyk")]));
+    assert!(encode_tiff_error(&[
+        ("compression", "g4"),
+        ("ccitt_2d", "true")
+    ]));
+}
+
+/// Splits `read_tags` output into IFDs, which are each sorted by tag.
+fn ifd_tags(data: &[u8]) -> Vec<Vec<TiffHeader>> {
+    let mut reader = BytesReader::new(data);
+    let headers = read_tags(&mut reader).unwrap();
+    let mut pages: Vec<Vec<TiffHeader>> = Vec::new();
+    for tag in headers.headers {
+        match pages.last_mut() {
+            Some(page) if page.last().unwrap().tagid < tag.tagid => page.push(tag),
+            _ => pages.push(vec![tag]),
+        }
+    }
+    pages
+}
+
+fn tag_data(tags: &[TiffHeader], tagid: usize) -> Option<&DataPack> {
+    tags.iter()
+        .find(|tag| tag.tagid == tagid)
+        .map(|tag| &tag.data)
+}
+
+fn tag_number(tags: &[TiffHeader], tagid: usize) -> Option<u32> {
+    match tag_data(tags, tagid)? {
+        DataPack::Short(values) => values.first().map(|value| *value as u32),
+        DataPack::Long(values) => values.first().copied(),
+        _ => None,
+    }
+}
+
+#[test]
+fn encode_multipage_tiff_from_page_source_with_per_page_settings() {
+    let cover = gradient_rgba(5, 3);
+    let fax = bilevel_rgba(37, 11);
+    let gray = solid_rgba(2, 4, [90, 90, 90, 255]);
+    let pages = vec![
+        EncodePage::new(5, 3, cover.clone())
+            .with_option("description", DataMap::Ascii("cover".to_string()))
+            .with_option("resolution", DataMap::UInt(300)),
+        EncodePage::new(37, 11, fax.clone())
+            .with_option("compression", DataMap::Ascii("ccitt_g4".to_string()))
+            .with_option("resolution", DataMap::Ascii("204x196".to_string())),
+        EncodePage::new(2, 4, gray.clone())
+            .with_option("photometric", DataMap::Ascii("gray".to_string())),
+    ];
+    let mut source = PageSource::new(pages.into_iter());
+    let mut options = HashMap::new();
+    options.insert(
+        "compression".to_string(),
+        DataMap::Ascii("deflate".to_string()),
+    );
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut source,
+        options: Some(options),
+    };
+    let data = image_encoder(&mut encode, ImageFormat::Tiff).unwrap();
+
+    let ifds = ifd_tags(&data);
+    assert_eq!(ifds.len(), 3);
+    for (index, (width, compression, photometric)) in
+        [(5, 8, 2), (37, 4, 0), (2, 8, 1)].into_iter().enumerate()
+    {
+        let tags = &ifds[index];
+        assert_eq!(tag_number(tags, 0x0100), Some(width));
+        assert_eq!(tag_number(tags, 0x0103), Some(compression));
+        assert_eq!(tag_number(tags, 0x0106), Some(photometric));
+        assert_eq!(tag_number(tags, 0x00fe), Some(2));
+        assert_eq!(
+            tag_data(tags, 0x0129),
+            Some(&DataPack::Short(vec![index as u16, 3]))
+        );
+    }
+    assert_eq!(
+        tag_data(&ifds[0], 0x010e),
+        Some(&DataPack::Ascii("cover".to_string()))
+    );
+    assert_eq!(tag_data(&ifds[1], 0x010e), None);
+    assert_eq!(
+        tag_data(&ifds[0], 0x011a),
+        Some(&DataPack::Rational(vec![Rational { n: 300, d: 1 }]))
+    );
+    assert_eq!(
+        tag_data(&ifds[1], 0x011b),
+        Some(&DataPack::Rational(vec![Rational { n: 196, d: 1 }]))
+    );
+    assert_eq!(
+        tag_data(&ifds[2], 0x011a),
+        Some(&DataPack::Rational(vec![Rational { n: 72, d: 1 }]))
+    );
+
+    let decoded = image_load(&data).unwrap();
+    assert_eq!(decoded.buffer.as_ref().unwrap(), &cover);
+    let frames = decoded.animation.as_ref().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!((frames[0].width, frames[0].height), (37, 11));
+    assert_eq!(frames[0].buffer, fax);
+    assert_eq!((frames[1].width, frames[1].height), (2, 4));
+    assert_eq!(frames[1].buffer, gray);
+}
+
+#[test]
+fn page_source_feeds_single_image_encoders_the_first_page() {
+    let first = gradient_rgba(3, 2);
+    let pages = vec![
+        EncodePage::new(3, 2, first.clone()),
+        EncodePage::new(1, 1, solid_rgba(1, 1, [0, 0, 0, 255])),
+    ];
+    let mut source = PageSource::new(pages.into_iter());
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut source,
+        options: None,
+    };
+    let data = image_encoder(&mut encode, ImageFormat::Png).unwrap();
+
+    let decoded = image_load(&data).unwrap();
+    assert_eq!((decoded.width, decoded.height), (3, 2));
+    assert_eq!(decoded.buffer.as_ref().unwrap(), &first);
+}
+
+#[test]
+fn encode_tiff_rejects_invalid_page_options() {
+    let pages = vec![
+        EncodePage::new(2, 2, solid_rgba(2, 2, [1, 2, 3, 255]))
+            .with_option("resolution", DataMap::Ascii("0x300".to_string())),
+    ];
+    let mut source = PageSource::new(pages.into_iter());
+    let mut encode = EncodeOptions {
+        debug_flag: 0,
+        drawer: &mut source,
+        options: None,
+    };
+    assert!(image_encoder(&mut encode, ImageFormat::Tiff).is_err());
+    assert!(encode_tiff_error(&[("resolution_unit", "furlong")]));
+    assert!(encode_tiff_error(&[("resolution", "abc")]));
+}
+
+fn geotiff_fixture() -> TiffHeaders {
+    let mut headers = TiffHeaders::empty(Endian::LittleEndian);
+    let tag = |tagid, data| TiffHeader {
+        tagid,
+        data,
+        length: 0,
+    };
+    headers.headers = vec![
+        tag(0x830e, DataPack::Double(vec![30.0, 30.0, 0.0])),
+        tag(
+            0x8482,
+            DataPack::Double(vec![0.0, 0.0, 0.0, 440720.0, 3751320.0, 0.0]),
+        ),
+        tag(
+            0x87af,
+            DataPack::Short(vec![
+                1, 1, 0, 4, 1024, 0, 1, 1, 1025, 0, 1, 1, 1026, 0x87b1, 21, 0, 3072, 0, 1, 32611,
+            ]),
+        ),
+        tag(
+            0x87b1,
+            DataPack::Ascii("WGS 84 / UTM zone 11N|".to_string()),
+        ),
+        tag(0xa481, DataPack::Ascii("-9999".to_string())),
+    ];
+    headers
+}
+
+fn geotiff_json(data: &[u8]) -> String {
+    let image = image_load(data).unwrap();
+    match image.metadata.unwrap().get("GeoTIFF") {
+        Some(DataMap::JSON(json)) => json.clone(),
+        other => panic!("GeoTIFF metadata missing: {other:?}"),
+    }
+}
+
+#[test]
+fn geotiff_tags_are_parsed_and_survive_reencoding() {
+    let mut options = HashMap::new();
+    options.insert("exif".to_string(), DataMap::Exif(geotiff_fixture()));
+    let data = encode_tiff_with_map(3, 2, solid_rgba(3, 2, [10, 20, 30, 255]), options);
+
+    let json = geotiff_json(&data);
+    assert!(json.contains("\"model_type_name\":\"Projected\""), "{json}");
+    assert!(json.contains("\"epsg\":32611"), "{json}");
+    assert!(
+        json.contains("\"citation\":\"WGS 84 / UTM zone 11N\""),
+        "{json}"
+    );
+    assert!(json.contains("\"pixel_scale\":[30,30,0]"), "{json}");
+    assert!(
+        json.contains("\"tie_points\":[{\"raster\":[0,0,0],\"model\":[440720,3751320,0]}]"),
+        "{json}"
+    );
+    assert!(json.contains("\"nodata\":\"-9999\""), "{json}");
+
+    // A plain conversion copies the decoded tags.
+    let mut image = image_load(&data).unwrap();
+    let copied = image_encoder(
+        &mut EncodeOptions {
+            debug_flag: 0,
+            drawer: &mut image,
+            options: Some(HashMap::from([(
+                "compression".to_string(),
+                DataMap::Ascii("lzw".to_string()),
+            )])),
+        },
+        ImageFormat::Tiff,
+    )
+    .unwrap();
+    assert_eq!(geotiff_json(&copied), json);
+
+    // Replacing the EXIF block keeps the georeferencing.
+    let mut image = image_load(&data).unwrap();
+    let replaced = image_encoder(
+        &mut EncodeOptions {
+            debug_flag: 0,
+            drawer: &mut image,
+            options: Some(HashMap::from([(
+                "exif".to_string(),
+                DataMap::Exif(exif_fixture()),
+            )])),
+        },
+        ImageFormat::Tiff,
+    )
+    .unwrap();
+    assert_eq!(geotiff_json(&replaced), json);
+    let tags = &ifd_tags(&replaced)[0];
+    assert_eq!(
+        tag_data(tags, 0x010f),
+        Some(&DataPack::Ascii("wml2".to_string()))
+    );
+    assert_eq!(
+        tag_data(tags, 0x830e),
+        Some(&DataPack::Double(vec![30.0, 30.0, 0.0]))
+    );
+}
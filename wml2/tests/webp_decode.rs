@@ -298,10 +298,7 @@ fn webp_decoder_keeps_draw_callback_contract_for_still_images() {
     let webp = image_to(&mut source, ImageFormat::Webp, None).unwrap();
     let mut reader = BytesReader::new(&webp);
     let mut drawer = RecordingDrawer::default();
-    let mut options = DecodeOptions {
-        debug_flag: 0,
-        drawer: &mut drawer,
-    };
+    let mut options = DecodeOptions::new(&mut drawer);
 
     image_decoder(&mut reader, &mut options).unwrap();
 
@@ -323,10 +320,7 @@ fn webp_decoder_keeps_draw_callback_contract_for_animation() {
     let bytes = animated_sample_bytes();
     let mut reader = BytesReader::new(&bytes);
     let mut drawer = RecordingDrawer::default();
-    let mut options = DecodeOptions {
-        debug_flag: 0,
-        drawer: &mut drawer,
-    };
+    let mut options = DecodeOptions::new(&mut drawer);
 
     image_decoder(&mut reader, &mut options).unwrap();
 